@@ -0,0 +1,793 @@
+use std::collections::HashMap;
+
+use crate::allocator::Allocation;
+use crate::frame;
+use crate::ir::{
+    self, BinaryOp, CompareOp, ConvertOp, StaticValue, Temp, Terminator, Type,
+    UnaryOp,
+};
+use crate::token::Span;
+
+// Integer arguments go in x0 to x7 and floating ones in v0 to v7
+const ARGUMENT_REGISTERS: usize = 8;
+
+// A variadic function saves the argument registers at the top of its frame,
+// where va_arg can find them: x0 to x7 then q0 to q7 below them. Below that
+// is the va_list state, {__stack, __gr_top, __vr_top, __gr_offs,
+// __vr_offs}, which va_start fills in
+const GENERAL_SAVE_AREA: i32 = -64;
+const VECTOR_SAVE_AREA: i32 = -192;
+const VA_LIST_STATE: i32 = -224;
+const VARIADIC_FRAME_SIZE: i32 = 224;
+
+// Generates AArch64 assembly from the IR, following AAPCS64. Every
+// temporary is kept in the frame; instructions load their operands into x0
+// and x1, or d0 and d1 if they're floating, and store the result back.
+// x29 is the frame pointer, and x16 and x17 are used to reach far offsets
+pub struct Generator {
+    lines: Vec<String>,
+    file_numbers: HashMap<String, usize>,
+    // The last .loc of the current function, which isn't repeated
+    last_location: Option<(usize, usize, usize)>,
+    function_name: String,
+    local_labels: usize,
+    temps: Vec<Type>,
+    layout: frame::FrameLayout,
+    // x29 offset of the space block arguments are copied through
+    copy_area: i32,
+    // For a variadic function, the initial __gr_offs and __vr_offs of its
+    // va_list and where the arguments passed on the stack continue
+    va_start_state: Option<(i32, i32, i32)>,
+}
+
+// Whether a load extends a narrow integer with its sign or with zeros
+#[derive(Clone, Copy, PartialEq)]
+enum Extend {
+    Sign,
+    Zero,
+}
+
+// Name of register `number` holding a value of the type: w for integers of
+// up to 32 bits, x for 64, s for float and d for double
+fn register(number: usize, value_type: &Type) -> String {
+    match value_type {
+        Type::I8 | Type::I16 | Type::I32 => return format!("w{}", number),
+        Type::I64 => return format!("x{}", number),
+        Type::F32 => return format!("s{}", number),
+        Type::F64 => return format!("d{}", number),
+    }
+}
+
+// The integer type of the same size, for moving the bits of a value
+fn bits_type(value_type: &Type) -> Type {
+    match value_type {
+        Type::F32 => return Type::I32,
+        Type::F64 => return Type::I64,
+        _ => return *value_type,
+    }
+}
+
+// Store instruction and register for the bits of a value of the type
+fn store_of(number: usize, value_type: &Type) -> (&'static str, String) {
+    let value_type = bits_type(value_type);
+    let mnemonic = match value_type {
+        Type::I8 => "strb",
+        Type::I16 => "strh",
+        _ => "str",
+    };
+    return (mnemonic, register(number, &value_type));
+}
+
+// Load instruction and register for an integer of the type, extended to 64
+// bits
+fn load_of(
+    number: usize,
+    value_type: &Type,
+    extend: Extend,
+) -> (&'static str, String) {
+    let value_type = bits_type(value_type);
+    match (value_type, extend) {
+        (Type::I8, Extend::Sign) => return ("ldrsb", format!("x{}", number)),
+        (Type::I16, Extend::Sign) => return ("ldrsh", format!("x{}", number)),
+        (Type::I32, Extend::Sign) => return ("ldrsw", format!("x{}", number)),
+        // Writing a w register clears the upper half
+        (Type::I8, Extend::Zero) => return ("ldrb", format!("w{}", number)),
+        (Type::I16, Extend::Zero) => return ("ldrh", format!("w{}", number)),
+        _ => return ("ldr", register(number, &value_type)),
+    }
+}
+
+fn condition(op: CompareOp) -> &'static str {
+    match op {
+        CompareOp::Equal => return "eq",
+        CompareOp::NotEqual => return "ne",
+        CompareOp::Less => return "lt",
+        CompareOp::LessOrEqual => return "le",
+        CompareOp::Greater => return "gt",
+        CompareOp::GreaterOrEqual => return "ge",
+        CompareOp::UnsignedLess => return "lo",
+        CompareOp::UnsignedLessOrEqual => return "ls",
+        CompareOp::UnsignedGreater => return "hi",
+        CompareOp::UnsignedGreaterOrEqual => return "hs",
+    }
+}
+
+fn is_signed(op: CompareOp) -> bool {
+    match op {
+        CompareOp::Less
+        | CompareOp::LessOrEqual
+        | CompareOp::Greater
+        | CompareOp::GreaterOrEqual => return true,
+        _ => return false,
+    }
+}
+
+// Storage for a variable with static storage duration
+fn static_data(data: &ir::Data) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    if data.m_global {
+        lines.push(format!("\t.globl\t{}", data.m_name));
+    }
+    let bytes = data.m_type.size();
+    // Addresses are relocated when a position independent program is
+    // loaded, so are kept writable, as gcc does in .data.rel.ro
+    let section = match data.m_value {
+        Some(StaticValue::Constant(_)) if data.m_read_only => {
+            ".section\t.rodata"
+        }
+        Some(_) => ".data",
+        None => ".bss",
+    };
+    lines.push(format!("\t{}", section));
+    lines.push(format!("\t.balign\t{}", bytes));
+    lines.push(format!("{}:", data.m_name));
+    let directive = match bytes {
+        1 => ".byte",
+        2 => ".hword",
+        4 => ".word",
+        _ => ".xword",
+    };
+    match &data.m_value {
+        Some(StaticValue::Constant(v)) => {
+            lines.push(format!("\t{}\t{}", directive, v))
+        }
+        Some(StaticValue::Address(symbol, offset)) => lines.push(format!(
+            "\t{}\t{}",
+            directive,
+            ir::address_text(symbol, *offset)
+        )),
+        None => lines.push(format!("\t.zero\t{}", bytes)),
+    }
+    return lines;
+}
+
+impl Generator {
+    pub fn new() -> Self {
+        Generator {
+            lines: Vec::new(),
+            file_numbers: HashMap::new(),
+            last_location: None,
+            function_name: String::new(),
+            local_labels: 0,
+            temps: Vec::new(),
+            layout: frame::FrameLayout {
+                m_slots: Vec::new(),
+                m_temps: Vec::new(),
+                m_saves: Vec::new(),
+                m_size: 0,
+            },
+            copy_area: 0,
+            va_start_state: None,
+        }
+    }
+
+    fn emit(&mut self, instruction: String) {
+        self.lines.push(format!("\t{}", instruction));
+    }
+
+    fn label(&mut self, label: String) {
+        self.lines.push(format!("{}:", label));
+    }
+
+    fn generate_loc(&mut self, span: &Span) {
+        let file_number = match self.file_numbers.get(&*span.m_file) {
+            Some(&n) => n,
+            None => panic!("No .file for {}", span.m_file),
+        };
+        let location = (file_number, span.m_line, span.m_column);
+        if self.last_location == Some(location) {
+            return;
+        }
+        self.last_location = Some(location);
+        self.lines.push(format!(
+            "\t.loc\t{} {} {}",
+            file_number, span.m_line, span.m_column
+        ));
+    }
+
+    fn block_label(&self, block: usize) -> String {
+        return format!(".L{}.{}", self.function_name, block);
+    }
+
+    fn local_label(&mut self) -> String {
+        self.local_labels += 1;
+        return format!(".L{}.local{}", self.function_name, self.local_labels);
+    }
+
+    // Puts any 64 bit value in a register, 16 bits at a time where it
+    // doesn't fit one instruction
+    fn move_immediate(&mut self, destination: &str, value: i64) {
+        if (-65535..=65535).contains(&value) {
+            self.emit(format!("mov\t{}, #{}", destination, value));
+            return;
+        }
+        let bits = value as u64;
+        let mut first = true;
+        for shift in [0, 16, 32, 48] {
+            let chunk = (bits >> shift) & 0xffff;
+            if chunk == 0 {
+                continue;
+            }
+            let mnemonic = if first { "movz" } else { "movk" };
+            self.emit(format!(
+                "{}\t{}, #{}, lsl #{}",
+                mnemonic, destination, chunk, shift
+            ));
+            first = false;
+        }
+    }
+
+    // destination = source + value. Immediates only reach 4095, so larger
+    // values go through x17
+    fn add_immediate(&mut self, destination: &str, source: &str, value: i64) {
+        let (mnemonic, magnitude) = match value < 0 {
+            true => ("sub", -value),
+            false => ("add", value),
+        };
+        if magnitude < 4096 {
+            self.emit(format!(
+                "{}\t{}, {}, #{}",
+                mnemonic, destination, source, magnitude
+            ));
+            return;
+        }
+        self.move_immediate("x17", magnitude);
+        self.emit(format!("{}\t{}, {}, x17", mnemonic, destination, source));
+    }
+
+    // Address operand of the bytes at an offset from x29. Loads and stores
+    // reach 256 bytes either way, and further ones go through x16
+    fn frame(&mut self, offset: i32) -> String {
+        if (-256..256).contains(&offset) {
+            return format!("[x29, #{}]", offset);
+        }
+        self.add_immediate("x16", "x29", offset as i64);
+        return String::from("[x16]");
+    }
+
+    fn home(&mut self, temp: Temp) -> String {
+        match self.layout.m_temps[temp] {
+            Some(offset) => return self.frame(offset),
+            None => panic!("Temporary {} has no home in the frame", temp),
+        }
+    }
+
+    // Loads an integer temporary into x`number`, extended to 64 bits.
+    // Floating values are loaded as their bits
+    fn load_integer(&mut self, temp: Temp, number: usize, extend: Extend) {
+        let (mnemonic, register) = load_of(number, &self.temps[temp], extend);
+        let home = self.home(temp);
+        self.emit(format!("{}\t{}, {}", mnemonic, register, home));
+    }
+
+    // Loads a temporary into register `number` of its class
+    fn load(&mut self, temp: Temp, number: usize) {
+        let temp_type = self.temps[temp];
+        if !temp_type.is_floating() {
+            self.load_integer(temp, number, Extend::Zero);
+            return;
+        }
+        let home = self.home(temp);
+        self.emit(format!("ldr\t{}, {}", register(number, &temp_type), home));
+    }
+
+    // Stores the bits of the temporary's type from x`number`
+    fn store_bits(&mut self, temp: Temp, number: usize) {
+        let (mnemonic, register) = store_of(number, &self.temps[temp]);
+        let home = self.home(temp);
+        self.emit(format!("{}\t{}, {}", mnemonic, register, home));
+    }
+
+    // Stores register `number` of the temporary's class to it
+    fn store(&mut self, temp: Temp, number: usize) {
+        let temp_type = self.temps[temp];
+        if !temp_type.is_floating() {
+            self.store_bits(temp, number);
+            return;
+        }
+        let home = self.home(temp);
+        self.emit(format!("str\t{}, {}", register(number, &temp_type), home));
+    }
+
+    pub fn generate(&mut self, program: &ir::Program) -> String {
+        for (i, file) in program.source_files().into_iter().enumerate() {
+            self.lines.push(format!("\t.file\t{} \"{}\"", i + 1, file));
+            self.file_numbers.insert(file, i + 1);
+        }
+        for function in &program.m_functions {
+            self.generate_function(function);
+        }
+        for data in &program.m_data {
+            self.lines.extend(static_data(data));
+        }
+        let mut text = std::mem::take(&mut self.lines).join("\n");
+        text.push('\n');
+        return text;
+    }
+
+    fn generate_function(&mut self, function: &ir::Function) {
+        self.function_name = function.m_name.clone();
+        self.local_labels = 0;
+        self.last_location = None;
+        self.temps = function.m_temps.clone();
+
+        // Block arguments are copied through the frame, below the save areas
+        // of a variadic function
+        let mut copied = 0;
+        for block in &function.m_blocks {
+            match &block.m_terminator {
+                Terminator::Jump { m_arguments, .. } => {
+                    copied = copied.max(m_arguments.len() as i32)
+                }
+                _ => (),
+            }
+        }
+        let variadic_size = match function.m_variadic {
+            true => VARIADIC_FRAME_SIZE,
+            false => 0,
+        };
+        self.copy_area = -(variadic_size + 8 * copied);
+        let allocation = Allocation {
+            m_registers: vec![None; function.m_temps.len()],
+            m_saved_across: HashMap::new(),
+        };
+        self.layout = frame::layout_function(
+            function,
+            &allocation,
+            variadic_size + 8 * copied,
+        );
+
+        self.lines.push(String::from("\t.text"));
+        if function.m_global {
+            self.lines.push(format!("\t.globl\t{}", function.m_name));
+        }
+        self.lines.push(String::from("\t.p2align\t2"));
+        self.lines.push(format!("\t.type\t{}, %function", function.m_name));
+        self.label(function.m_name.clone());
+        // The frame record of x29 and the return address, which x29 then
+        // points at
+        self.emit(String::from("stp\tx29, x30, [sp, #-16]!"));
+        self.emit(String::from("mov\tx29, sp"));
+        self.generate_loc(&function.m_span);
+        if self.layout.m_size > 0 {
+            self.add_immediate("sp", "sp", -(self.layout.m_size as i64));
+        }
+        if function.m_variadic {
+            for i in 0..ARGUMENT_REGISTERS {
+                let slot = self.frame(GENERAL_SAVE_AREA + 8 * i as i32);
+                self.emit(format!("str\tx{}, {}", i, slot));
+            }
+            for i in 0..ARGUMENT_REGISTERS {
+                let slot = self.frame(VECTOR_SAVE_AREA + 16 * i as i32);
+                self.emit(format!("str\tq{}, {}", i, slot));
+            }
+        }
+
+        // Parameters past the registers of their class are on the stack
+        // above the frame record, 8 bytes each
+        let mut stack_offset = 16;
+        let mut int_index = 0;
+        let mut float_index = 0;
+        for parameter in &function.m_parameters {
+            let parameter_type = self.temps[*parameter];
+            if parameter_type.is_floating() && float_index < ARGUMENT_REGISTERS
+            {
+                self.store(*parameter, float_index);
+                float_index += 1;
+            } else if !parameter_type.is_floating()
+                && int_index < ARGUMENT_REGISTERS
+            {
+                self.store(*parameter, int_index);
+                int_index += 1;
+            } else {
+                let slot = self.frame(stack_offset);
+                self.emit(format!("ldr\tx9, {}", slot));
+                self.store_bits(*parameter, 9);
+                stack_offset += 8;
+            }
+        }
+        self.va_start_state = match function.m_variadic {
+            true => Some((
+                -8 * (ARGUMENT_REGISTERS - int_index) as i32,
+                -16 * (ARGUMENT_REGISTERS - float_index) as i32,
+                stack_offset,
+            )),
+            false => None,
+        };
+
+        for (i, block) in function.m_blocks.iter().enumerate() {
+            if i > 0 {
+                self.label(self.block_label(i));
+            }
+            for instruction in &block.m_instructions {
+                self.generate_instruction(instruction);
+            }
+            self.generate_terminator(function, &block.m_terminator, i + 1);
+        }
+        self.lines.push(format!(
+            "\t.size\t{}, .-{}",
+            function.m_name, function.m_name
+        ));
+    }
+
+    fn generate_terminator(
+        &mut self,
+        function: &ir::Function,
+        terminator: &Terminator,
+        next_block: usize,
+    ) {
+        match terminator {
+            Terminator::Jump { m_target, m_arguments } => {
+                // A parameter may also be an argument, so every argument is
+                // copied out before any parameter is written
+                let parameters = &function.m_blocks[*m_target].m_parameters;
+                for (i, argument) in m_arguments.iter().enumerate() {
+                    self.load_integer(*argument, 9, Extend::Zero);
+                    let slot = self.frame(self.copy_area + 8 * i as i32);
+                    self.emit(format!("str\tx9, {}", slot));
+                }
+                for (i, parameter) in parameters.iter().enumerate() {
+                    let slot = self.frame(self.copy_area + 8 * i as i32);
+                    self.emit(format!("ldr\tx9, {}", slot));
+                    self.store_bits(*parameter, 9);
+                }
+                if *m_target != next_block {
+                    self.emit(format!("b\t{}", self.block_label(*m_target)));
+                }
+            }
+            Terminator::Branch { m_condition, m_true, m_false } => {
+                self.load_integer(*m_condition, 0, Extend::Zero);
+                self.emit(format!("cbnz\tx0, {}", self.block_label(*m_true)));
+                if *m_false != next_block {
+                    self.emit(format!("b\t{}", self.block_label(*m_false)));
+                }
+            }
+            Terminator::Return { m_value } => {
+                self.load(*m_value, 0);
+                self.emit(String::from("mov\tsp, x29"));
+                self.emit(String::from("ldp\tx29, x30, [sp], #16"));
+                self.emit(String::from("ret"));
+            }
+        }
+    }
+
+    fn generate_instruction(&mut self, instruction: &ir::Instruction) {
+        match instruction {
+            // Floating constants are moved as their bits
+            ir::Instruction::Constant { m_dest, m_type: _, m_value } => {
+                self.move_immediate("x0", *m_value);
+                self.store_bits(*m_dest, 0);
+            }
+            ir::Instruction::SlotAddress { m_dest, m_slot } => {
+                let offset = self.layout.m_slots[*m_slot];
+                self.add_immediate("x0", "x29", offset as i64);
+                self.store(*m_dest, 0);
+            }
+            ir::Instruction::GlobalAddress { m_dest, m_name } => {
+                self.emit(format!("adrp\tx0, {}", m_name));
+                self.emit(format!("add\tx0, x0, :lo12:{}", m_name));
+                self.store(*m_dest, 0);
+            }
+            // Each access is one instruction, so volatile ones need nothing
+            // more. Floating values are moved as their bits
+            ir::Instruction::Load {
+                m_dest,
+                m_type,
+                m_address,
+                m_volatile: _,
+            } => {
+                self.load_integer(*m_address, 1, Extend::Zero);
+                let (mnemonic, register) = load_of(0, m_type, Extend::Zero);
+                self.emit(format!("{}\t{}, [x1]", mnemonic, register));
+                self.store_bits(*m_dest, 0);
+            }
+            ir::Instruction::Store {
+                m_type,
+                m_address,
+                m_value,
+                m_volatile: _,
+            } => {
+                self.load_integer(*m_address, 1, Extend::Zero);
+                self.load_integer(*m_value, 0, Extend::Zero);
+                let (mnemonic, register) = store_of(0, m_type);
+                self.emit(format!("{}\t{}, [x1]", mnemonic, register));
+            }
+            ir::Instruction::Unary { m_dest, m_op, m_type, m_operand } => {
+                self.load(*m_operand, 0);
+                let r = register(0, m_type);
+                let mnemonic = match (m_op, m_type.is_floating()) {
+                    (UnaryOp::Negate, true) => "fneg",
+                    (UnaryOp::Negate, false) => "neg",
+                    (UnaryOp::Not, _) => "mvn",
+                };
+                self.emit(format!("{}\t{}, {}", mnemonic, r, r));
+                self.store(*m_dest, 0);
+            }
+            ir::Instruction::Binary {
+                m_dest,
+                m_op,
+                m_type,
+                m_left,
+                m_right,
+            } => {
+                self.generate_binary(*m_op, m_type, *m_left, *m_right);
+                self.store(*m_dest, 0);
+            }
+            ir::Instruction::Compare {
+                m_dest,
+                m_op,
+                m_type,
+                m_left,
+                m_right,
+            } => {
+                self.generate_compare(*m_op, m_type, *m_left, *m_right);
+                self.store(*m_dest, 0);
+            }
+            ir::Instruction::Convert {
+                m_dest,
+                m_op,
+                m_from,
+                m_to,
+                m_operand,
+            } => {
+                self.generate_convert(*m_op, m_from, m_to, *m_operand);
+                self.store(*m_dest, 0);
+            }
+            ir::Instruction::Call {
+                m_dest,
+                m_function,
+                m_arguments,
+                m_variadic: _,
+            } => {
+                self.generate_call(m_function, m_arguments);
+                self.store(*m_dest, 0);
+            }
+            ir::Instruction::VaStart { m_list } => {
+                let (gr_offs, vr_offs, stack) = match self.va_start_state {
+                    Some(s) => s,
+                    None => panic!("va_start outside a variadic function"),
+                };
+                self.add_immediate("x0", "x29", stack as i64);
+                let slot = self.frame(VA_LIST_STATE);
+                self.emit(format!("str\tx0, {}", slot));
+                let slot = self.frame(VA_LIST_STATE + 8);
+                self.emit(format!("str\tx29, {}", slot));
+                self.add_immediate("x0", "x29", GENERAL_SAVE_AREA as i64);
+                let slot = self.frame(VA_LIST_STATE + 16);
+                self.emit(format!("str\tx0, {}", slot));
+                self.move_immediate("x0", gr_offs as i64);
+                let slot = self.frame(VA_LIST_STATE + 24);
+                self.emit(format!("str\tw0, {}", slot));
+                self.move_immediate("x0", vr_offs as i64);
+                let slot = self.frame(VA_LIST_STATE + 28);
+                self.emit(format!("str\tw0, {}", slot));
+                self.add_immediate("x0", "x29", VA_LIST_STATE as i64);
+                self.load_integer(*m_list, 1, Extend::Zero);
+                self.emit(String::from("str\tx0, [x1]"));
+            }
+            ir::Instruction::VaArg { m_dest, m_type, m_list } => {
+                self.generate_va_arg(m_type, *m_list);
+                self.store_bits(*m_dest, 0);
+            }
+            ir::Instruction::Location { m_span } => {
+                self.generate_loc(m_span);
+            }
+        }
+    }
+
+    // Takes the next saved register of the argument's class while its
+    // offset from the top of the save area is negative, then continues on
+    // the stack. Leaves the bits of the argument in x0
+    fn generate_va_arg(&mut self, arg_type: &Type, list: Temp) {
+        let (offset_field, top_field, step) = match arg_type.is_floating() {
+            true => (28, 16, 16),
+            false => (24, 8, 8),
+        };
+        let on_stack = self.local_label();
+        let done = self.local_label();
+        self.load_integer(list, 1, Extend::Zero);
+        self.emit(format!("ldrsw\tx2, [x1, #{}]", offset_field));
+        self.emit(String::from("cmp\tx2, #0"));
+        self.emit(format!("b.ge\t{}", on_stack));
+        self.emit(format!("add\tw3, w2, #{}", step));
+        self.emit(format!("str\tw3, [x1, #{}]", offset_field));
+        self.emit(format!("ldr\tx0, [x1, #{}]", top_field));
+        self.emit(String::from("add\tx0, x0, x2"));
+        self.emit(format!("b\t{}", done));
+        self.label(on_stack);
+        self.emit(String::from("ldr\tx0, [x1]"));
+        self.emit(String::from("add\tx3, x0, #8"));
+        self.emit(String::from("str\tx3, [x1]"));
+        self.label(done);
+        let (mnemonic, register) = load_of(0, arg_type, Extend::Zero);
+        self.emit(format!("{}\t{}, [x0]", mnemonic, register));
+    }
+
+    // Leaves the result in x0 or d0
+    fn generate_binary(
+        &mut self,
+        op: BinaryOp,
+        operand_type: &Type,
+        left: Temp,
+        right: Temp,
+    ) {
+        let r0 = register(0, operand_type);
+        let r1 = register(1, operand_type);
+        if operand_type.is_floating() {
+            self.load(left, 0);
+            self.load(right, 1);
+            let mnemonic = match op {
+                BinaryOp::Add => "fadd",
+                BinaryOp::Subtract => "fsub",
+                BinaryOp::Multiply => "fmul",
+                _ => "fdiv",
+            };
+            self.emit(format!("{}\t{}, {}, {}", mnemonic, r0, r0, r1));
+            return;
+        }
+        // Narrow operands of a division are extended by their signedness
+        let extend = match op {
+            BinaryOp::Divide | BinaryOp::Remainder => Extend::Sign,
+            _ => Extend::Zero,
+        };
+        self.load_integer(left, 0, extend);
+        self.load_integer(right, 1, extend);
+        let mnemonic = match op {
+            BinaryOp::Add => "add",
+            BinaryOp::Subtract => "sub",
+            BinaryOp::Multiply => "mul",
+            BinaryOp::Divide | BinaryOp::Remainder => "sdiv",
+            BinaryOp::UnsignedDivide | BinaryOp::UnsignedRemainder => "udiv",
+        };
+        if op != BinaryOp::Remainder && op != BinaryOp::UnsignedRemainder {
+            self.emit(format!("{}\t{}, {}, {}", mnemonic, r0, r0, r1));
+            return;
+        }
+        // There's no remainder instruction: left - (left / right) * right
+        let r2 = register(2, operand_type);
+        self.emit(format!("{}\t{}, {}, {}", mnemonic, r2, r0, r1));
+        self.emit(format!("msub\t{}, {}, {}, {}", r0, r2, r1, r0));
+    }
+
+    // Leaves 0 or 1 in w0
+    fn generate_compare(
+        &mut self,
+        op: CompareOp,
+        operand_type: &Type,
+        left: Temp,
+        right: Temp,
+    ) {
+        let r0 = register(0, operand_type);
+        let r1 = register(1, operand_type);
+        if operand_type.is_floating() {
+            // An unordered compare (NaN) sets C and V, so "less" is taken
+            // from N alone and "less or equal" from C and Z, which are
+            // false for it
+            self.load(left, 0);
+            self.load(right, 1);
+            self.emit(format!("fcmp\t{}, {}", r0, r1));
+            let condition = match op {
+                CompareOp::Less => "mi",
+                CompareOp::LessOrEqual => "ls",
+                _ => condition(op),
+            };
+            self.emit(format!("cset\tw0, {}", condition));
+            return;
+        }
+        let extend = match is_signed(op) {
+            true => Extend::Sign,
+            false => Extend::Zero,
+        };
+        self.load_integer(left, 0, extend);
+        self.load_integer(right, 1, extend);
+        self.emit(format!("cmp\t{}, {}", r0, r1));
+        self.emit(format!("cset\tw0, {}", condition(op)));
+    }
+
+    // Leaves the result in x0 or d0
+    fn generate_convert(
+        &mut self,
+        op: ConvertOp,
+        from: &Type,
+        to: &Type,
+        operand: Temp,
+    ) {
+        match op {
+            ConvertOp::SignExtend => {
+                self.load_integer(operand, 0, Extend::Sign)
+            }
+            // The low bytes already hold the narrower value
+            ConvertOp::ZeroExtend | ConvertOp::Truncate => {
+                self.load_integer(operand, 0, Extend::Zero)
+            }
+            ConvertOp::SignedToFloat => {
+                self.load_integer(operand, 0, Extend::Sign);
+                self.emit(format!("scvtf\t{}, x0", register(0, to)));
+            }
+            ConvertOp::UnsignedToFloat => {
+                self.load_integer(operand, 0, Extend::Zero);
+                self.emit(format!("ucvtf\t{}, x0", register(0, to)));
+            }
+            ConvertOp::FloatToSigned => {
+                self.load(operand, 0);
+                self.emit(format!("fcvtzs\tx0, {}", register(0, from)));
+            }
+            ConvertOp::FloatToUnsigned => {
+                self.load(operand, 0);
+                self.emit(format!("fcvtzu\tx0, {}", register(0, from)));
+            }
+            ConvertOp::FloatToFloat => {
+                self.load(operand, 0);
+                self.emit(format!(
+                    "fcvt\t{}, {}",
+                    register(0, to),
+                    register(0, from)
+                ));
+            }
+        }
+    }
+
+    // AAPCS64 call: integer arguments go in x0 to x7 and floating ones in
+    // v0 to v7, and any others on the stack in 8 bytes each with the first
+    // at the lowest address. Arguments of variadic functions are passed the
+    // same way. Leaves the result in x0 or d0
+    fn generate_call(&mut self, id: &String, arguments: &Vec<Temp>) {
+        let mut int_count = 0;
+        let mut float_count = 0;
+        let mut in_register: Vec<Option<usize>> = Vec::new();
+        for argument in arguments {
+            let count = match self.temps[*argument].is_floating() {
+                true => &mut float_count,
+                false => &mut int_count,
+            };
+            if *count < ARGUMENT_REGISTERS {
+                in_register.push(Some(*count));
+            } else {
+                in_register.push(None);
+            }
+            *count += 1;
+        }
+
+        let stack_arguments =
+            in_register.iter().filter(|r| r.is_none()).count();
+        let stack_size = (8 * stack_arguments as i64 + 15) / 16 * 16;
+        if stack_size > 0 {
+            self.add_immediate("sp", "sp", -stack_size);
+        }
+        let mut stack_offset = 0;
+        for (i, argument) in arguments.iter().enumerate() {
+            match in_register[i] {
+                Some(number) => self.load(*argument, number),
+                None => {
+                    self.load_integer(*argument, 9, Extend::Zero);
+                    self.emit(format!("str\tx9, [sp, #{}]", stack_offset));
+                    stack_offset += 8;
+                }
+            }
+        }
+        self.emit(format!("bl\t{}", id));
+        if stack_size > 0 {
+            self.add_immediate("sp", "sp", stack_size);
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::assembly::Register;
+use crate::ir::{self, Instruction, Temp};
+
+// The registers temporaries can be kept in. The generator works in %rax,
+// %rcx, %rdx, %xmm0 and %xmm1 and passes arguments in the argument
+// registers, so none of those are handed out and putting the arguments of a
+// call in place never overwrites a temporary. Callee saved registers come
+// first, so values live across calls go there
+const INTEGER_REGISTERS: [Register; 7] = [
+    Register::Rbx,
+    Register::R12,
+    Register::R13,
+    Register::R14,
+    Register::R15,
+    Register::R10,
+    Register::R11,
+];
+const SSE_REGISTERS: [Register; 8] = [
+    Register::Xmm(8),
+    Register::Xmm(9),
+    Register::Xmm(10),
+    Register::Xmm(11),
+    Register::Xmm(12),
+    Register::Xmm(13),
+    Register::Xmm(14),
+    Register::Xmm(15),
+];
+
+// Where the temporaries of a function are kept
+pub struct Allocation {
+    // Register of each temporary, or None for those spilled to the frame
+    pub m_registers: Vec<Option<Register>>,
+    // Caller saved registers holding values needed after each call, by
+    // block and instruction index
+    pub m_saved_across: HashMap<(usize, usize), Vec<Register>>,
+}
+
+// The range of positions from the definition of a temporary to its last
+// use, covering every block it's live through. Holes aren't tracked
+struct Interval {
+    m_temp: Temp,
+    m_start: usize,
+    m_end: usize,
+}
+
+// Temporaries live on entry to each block, worked out backwards until
+// nothing changes
+fn live_in(function: &ir::Function) -> Vec<HashSet<Temp>> {
+    let blocks = &function.m_blocks;
+    let mut live_in: Vec<HashSet<Temp>> = vec![HashSet::new(); blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (i, block) in blocks.iter().enumerate().rev() {
+            let mut live: HashSet<Temp> = HashSet::new();
+            for successor in block.m_terminator.successors() {
+                live.extend(live_in[successor].iter());
+            }
+            live.extend(block.m_terminator.operands());
+            for instruction in block.m_instructions.iter().rev() {
+                match instruction.dest() {
+                    Some(dest) => {
+                        live.remove(&dest);
+                    }
+                    None => (),
+                }
+                live.extend(instruction.operands());
+            }
+            for parameter in &block.m_parameters {
+                live.remove(parameter);
+            }
+            if live != live_in[i] {
+                live_in[i] = live;
+                changed = true;
+            }
+        }
+    }
+    return live_in;
+}
+
+// Numbers the blocks' starts, instructions and terminators in order and
+// works out the interval of every temporary, along with the position of
+// each call
+fn intervals(function: &ir::Function) -> (Vec<Interval>, Vec<usize>) {
+    let live_in = live_in(function);
+    let mut ranges: Vec<Option<(usize, usize)>> =
+        vec![None; function.m_temps.len()];
+    let mut cover = |temp: Temp, position: usize| {
+        ranges[temp] = match ranges[temp] {
+            Some((start, end)) => {
+                Some((start.min(position), end.max(position)))
+            }
+            None => Some((position, position)),
+        };
+    };
+    for parameter in &function.m_parameters {
+        cover(*parameter, 0);
+    }
+
+    let mut calls: Vec<usize> = Vec::new();
+    let mut position = 0;
+    for block in &function.m_blocks {
+        let start = position;
+        for parameter in &block.m_parameters {
+            cover(*parameter, start);
+        }
+        for (k, instruction) in block.m_instructions.iter().enumerate() {
+            let at = start + 1 + k;
+            for operand in instruction.operands() {
+                cover(operand, at);
+            }
+            match instruction.dest() {
+                Some(dest) => cover(dest, at),
+                None => (),
+            }
+            match instruction {
+                Instruction::Call { .. } => calls.push(at),
+                _ => (),
+            }
+        }
+        let end = start + 1 + block.m_instructions.len();
+        for operand in block.m_terminator.operands() {
+            cover(operand, end);
+        }
+        position = end + 1;
+    }
+
+    // A temporary live into a block is live from its start, and one live
+    // into a successor is live until the end
+    let mut position = 0;
+    for (i, block) in function.m_blocks.iter().enumerate() {
+        let start = position;
+        let end = start + 1 + block.m_instructions.len();
+        for temp in &live_in[i] {
+            cover(*temp, start);
+        }
+        for successor in block.m_terminator.successors() {
+            for temp in &live_in[successor] {
+                cover(*temp, end);
+            }
+        }
+        position = end + 1;
+    }
+
+    let mut intervals: Vec<Interval> = Vec::new();
+    for (temp, range) in ranges.iter().enumerate() {
+        match range {
+            Some((start, end)) => intervals.push(Interval {
+                m_temp: temp,
+                m_start: *start,
+                m_end: *end,
+            }),
+            None => (),
+        }
+    }
+    intervals.sort_by_key(|i| (i.m_start, i.m_end));
+    return (intervals, calls);
+}
+
+// Linear scan: intervals are given registers in order of their start, and
+// when there are none left the one ending last is spilled to the frame.
+// Values live across a call take callee saved registers where they can, and
+// others leave those for them
+pub fn allocate(function: &ir::Function) -> Allocation {
+    let (intervals, calls) = intervals(function);
+    let crosses_call = |interval: &Interval| {
+        return calls
+            .iter()
+            .any(|c| interval.m_start < *c && *c < interval.m_end);
+    };
+
+    let mut registers: Vec<Option<Register>> =
+        vec![None; function.m_temps.len()];
+    // (end, temp) of the intervals holding a register
+    let mut active: Vec<(usize, Temp)> = Vec::new();
+    for interval in &intervals {
+        active.retain(|(end, _)| *end >= interval.m_start);
+        let sse = function.m_temps[interval.m_temp].is_floating();
+        let class: &[Register] =
+            if sse { &SSE_REGISTERS } else { &INTEGER_REGISTERS };
+        let taken: HashSet<Register> =
+            active.iter().filter_map(|(_, t)| registers[*t]).collect();
+        let mut free = class.iter().filter(|r| !taken.contains(r));
+        let register =
+            if crosses_call(interval) { free.next() } else { free.last() };
+        match register {
+            Some(r) => {
+                registers[interval.m_temp] = Some(*r);
+                active.push((interval.m_end, interval.m_temp));
+                continue;
+            }
+            None => (),
+        }
+
+        // Take the register of the active interval of the same class which
+        // ends last, if it ends after this one
+        let victim = active
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, t))| function.m_temps[*t].is_floating() == sse)
+            .max_by_key(|(_, (end, _))| *end)
+            .map(|(i, (end, t))| (i, *end, *t));
+        match victim {
+            Some((i, end, temp)) if end > interval.m_end => {
+                registers[interval.m_temp] = registers[temp];
+                registers[temp] = None;
+                active.remove(i);
+                active.push((interval.m_end, interval.m_temp));
+            }
+            _ => (),
+        }
+    }
+
+    // Caller saved registers are kept across a call by the caller
+    let mut saved_at: HashMap<usize, Vec<Register>> = HashMap::new();
+    for interval in &intervals {
+        let register = match registers[interval.m_temp] {
+            Some(r) if !r.is_callee_saved() => r,
+            _ => continue,
+        };
+        for call in &calls {
+            if interval.m_start < *call && *call < interval.m_end {
+                saved_at.entry(*call).or_insert(Vec::new()).push(register);
+            }
+        }
+    }
+    let mut saved_across: HashMap<(usize, usize), Vec<Register>> =
+        HashMap::new();
+    let mut position = 0;
+    for (i, block) in function.m_blocks.iter().enumerate() {
+        for k in 0..block.m_instructions.len() {
+            match saved_at.remove(&(position + 1 + k)) {
+                Some(mut saved) => {
+                    saved.sort();
+                    saved_across.insert((i, k), saved);
+                }
+                None => (),
+            }
+        }
+        position += block.m_instructions.len() + 2;
+    }
+
+    return Allocation { m_registers: registers, m_saved_across: saved_across };
+}
@@ -1,11 +1,15 @@
+use crate::evaluator::{self, Constant, EvaluationError};
 use crate::parser::{
-    AdditiveExpression, BlockItem, ConditionalExpression, Declaration,
-    EqualityExpression, Expression, Factor, Function, FunctionType,
-    LogicalAndExpression, LogicalOrExpresson, Program, RelationalExpression,
-    Statement, Term, VarType,
+    AdditiveExpression, AdditiveOperator, BlockItem, ConditionalExpression,
+    Declaration, EqualityExpression, Expression, Factor, Function,
+    LogicalAndExpression, LogicalOrExpresson, MultiplicativeOperator,
+    Parameter, Program, Qualifiers, RelationalExpression, Statement,
+    StorageClass, Term, TopLevelItem, UnaryOperator, VarType,
 };
+use crate::types::{self, TypeContext};
 
-use std::{collections::HashMap, hash::Hash};
+use crate::token::Span;
+use std::collections::HashMap;
 
 #[derive(Debug)]
 pub enum AnalysisError {
@@ -16,12 +20,20 @@ pub enum AnalysisError {
     AssignmentError(String, String),
     DuplicateDeclaration(String, String),
     FunctionError(String, String),
+    LinkageError(String, String),
+    StorageClassError(String, String),
+    InitializerError(String, String),
+    JumpError(String, String),
 }
 
 #[derive(Debug)]
 struct FunctionDef {
-    pub m_type: FunctionType,
-    pub m_parameters: Vec<(VarType, String)>,
+    pub m_type: VarType,
+    pub m_parameters: Vec<Parameter>,
+    pub m_variadic: bool,
+    // Calls to a function without a prototype aren't checked. A definition
+    // with "()" has one, taking no parameters
+    pub m_prototype: bool,
 }
 
 impl FunctionDef {
@@ -29,25 +41,236 @@ impl FunctionDef {
         return FunctionDef {
             m_type: self.m_type.clone(),
             m_parameters: self.m_parameters.clone(),
+            m_variadic: self.m_variadic,
+            m_prototype: self.m_prototype,
         };
     }
 }
 
+// Linkage of a file scope identifier
+#[derive(Debug, Clone, PartialEq)]
+enum Linkage {
+    Internal,
+    External,
+}
+
 #[derive(Debug)]
 enum Symbol {
     Func(FunctionDef),
-    Var(VarType),
+    // The storage class is kept for register, whose address can't be taken
+    Var(VarType, Qualifiers, Option<StorageClass>),
 }
 
 const DEBUG: bool = false;
 
 pub struct Analyser {
     context: Vec<HashMap<String, Symbol>>,
+    // Span of the statement being analysed, for errors in nodes without one
+    current_span: Span,
+    // Linkage of every identifier declared at file scope or with extern
+    linkage: HashMap<String, Linkage>,
+    // Where each file scope name was first declared and where it was
+    // defined, for notes pointing back at them
+    declared_at: HashMap<String, Span>,
+    defined_at: HashMap<String, Span>,
+    // First declaration of every function in the file, to tell a call to a
+    // function declared further down from a call to an unknown one
+    later_functions: HashMap<String, Span>,
+    // Notes explaining the last error
+    notes: Vec<(String, Span)>,
+    // Warnings about code which is valid but probably wrong
+    warnings: Vec<(String, Span)>,
+    // Return type of the function being analysed
+    return_type: VarType,
+    // Parameters of the function being analysed, for va_start
+    parameters: Vec<Parameter>,
+    variadic: bool,
+    // Number of loops around the statement being analysed, for break and
+    // continue
+    loop_depth: usize,
+}
+
+impl TypeContext for Analyser {
+    fn variable_type(&self, name: &String) -> Option<VarType> {
+        for context in self.context.iter().rev() {
+            match context.get(name) {
+                Some(Symbol::Var(var_type, _, _)) => {
+                    return Some(var_type.clone())
+                }
+                Some(Symbol::Func(_)) => return None,
+                None => (),
+            }
+        }
+        return None;
+    }
+
+    fn function_type(&self, name: &String) -> Option<VarType> {
+        for context in self.context.iter().rev() {
+            match context.get(name) {
+                Some(Symbol::Func(f_def)) => return Some(f_def.m_type.clone()),
+                Some(Symbol::Var(..)) => return None,
+                None => (),
+            }
+        }
+        return None;
+    }
+
+    fn variable_qualifiers(&self, name: &String) -> Qualifiers {
+        for context in self.context.iter().rev() {
+            match context.get(name) {
+                Some(Symbol::Var(_, qualifiers, _)) => {
+                    return qualifiers.clone()
+                }
+                Some(Symbol::Func(_)) => return Qualifiers::default(),
+                None => (),
+            }
+        }
+        return Qualifiers::default();
+    }
+}
+
+// Every declaration of a function has to give the same return type and the
+// same parameter types, ignoring qualifiers on the parameters themselves. A
+// declaration without a prototype matches any prototype its arguments could
+// be passed to after the default promotions
+fn prototype_mismatch(
+    previous: &FunctionDef,
+    function: &Function,
+) -> Option<String> {
+    if previous.m_type != function.m_type {
+        return Some(format!(
+            "Conflicting return type '{}', previously declared as '{}'",
+            function.m_type, previous.m_type
+        ));
+    }
+    match (previous.m_prototype, has_prototype(function)) {
+        (true, true) => (),
+        (true, false) => {
+            return unpromoted_mismatch(
+                &previous.m_parameters,
+                previous.m_variadic,
+            )
+        }
+        (false, _) => {
+            return unpromoted_mismatch(&function.m_params, function.m_variadic)
+        }
+    }
+    if previous.m_parameters.len() != function.m_params.len() {
+        return Some(format!(
+            "Declared with {} parameters, previously declared with {}",
+            function.m_params.len(),
+            previous.m_parameters.len()
+        ));
+    }
+    if previous.m_variadic != function.m_variadic {
+        let (now, before) = if function.m_variadic {
+            ("variadic", "fixed")
+        } else {
+            ("fixed", "variadic")
+        };
+        return Some(format!(
+            "Declared with {} arguments, previously declared with {}",
+            now, before
+        ));
+    }
+    for (i, (old, new)) in
+        previous.m_parameters.iter().zip(&function.m_params).enumerate()
+    {
+        if old.m_type != new.m_type {
+            return Some(format!(
+                "Conflicting type '{}' for parameter {}, previously declared \
+                as '{}'",
+                new.m_type,
+                i + 1,
+                old.m_type
+            ));
+        }
+    }
+    return None;
+}
+
+fn has_prototype(function: &Function) -> bool {
+    return function.m_prototype || function.m_items.is_some();
+}
+
+// A prototype matching a declaration without one can't be variadic, or have
+// parameters which the default promotions change
+fn unpromoted_mismatch(
+    parameters: &Vec<Parameter>,
+    variadic: bool,
+) -> Option<String> {
+    if variadic {
+        return Some(String::from(
+            "Variadic, which a declaration without a prototype can't match",
+        ));
+    }
+    for (i, parameter) in parameters.iter().enumerate() {
+        let t = &parameter.m_type;
+        if types::promote(t) != *t || *t == VarType::Float {
+            return Some(format!(
+                "Parameter {} has type '{}', which a declaration without a \
+                prototype can't match",
+                i + 1,
+                t
+            ));
+        }
+    }
+    return None;
 }
 
 impl Analyser {
     pub fn new() -> Self {
-        Analyser { context: Vec::new() }
+        Analyser {
+            context: Vec::new(),
+            current_span: Span {
+                m_file: "<input>".into(),
+                m_line: 0,
+                m_column: 0,
+            },
+            linkage: HashMap::new(),
+            declared_at: HashMap::new(),
+            defined_at: HashMap::new(),
+            later_functions: HashMap::new(),
+            notes: Vec::new(),
+            warnings: Vec::new(),
+            return_type: VarType::Int,
+            parameters: Vec::new(),
+            variadic: false,
+            loop_depth: 0,
+        }
+    }
+
+    // Notes to print after an error, pointing at other declarations involved
+    pub fn notes(&self) -> &Vec<(String, Span)> {
+        return &self.notes;
+    }
+
+    pub fn warnings(&self) -> &Vec<(String, Span)> {
+        return &self.warnings;
+    }
+
+    fn warn(&mut self, warning: &str) {
+        self.warnings.push((String::from(warning), self.current_span.clone()));
+    }
+
+    fn note_previous_declaration(&mut self, id: &String) {
+        match self.declared_at.get(id) {
+            Some(span) => self.notes.push((
+                format!("previous declaration of '{}' is here", id),
+                span.clone(),
+            )),
+            None => (),
+        }
+    }
+
+    fn note_previous_definition(&mut self, id: &String) {
+        match self.defined_at.get(id) {
+            Some(span) => self.notes.push((
+                format!("previous definition of '{}' is here", id),
+                span.clone(),
+            )),
+            None => (),
+        }
     }
 
     fn open_scope(&mut self) {
@@ -62,8 +285,76 @@ impl Analyser {
         self.context.last_mut().unwrap().insert(id, Symbol::Func(func));
     }
 
-    fn add_var(&mut self, id: String, var_type: VarType) {
-        self.context.last_mut().unwrap().insert(id, Symbol::Var(var_type));
+    fn add_var(
+        &mut self,
+        id: String,
+        var_type: VarType,
+        qualifiers: Qualifiers,
+        storage: Option<StorageClass>,
+    ) {
+        self.context
+            .last_mut()
+            .unwrap()
+            .insert(id, Symbol::Var(var_type, qualifiers, storage));
+    }
+
+    // The variable a factor names, if it was declared register
+    fn register_variable(&self, factor: &Factor) -> Option<String> {
+        let name = match factor {
+            Factor::Variable { m_var, m_span: _ } => m_var,
+            Factor::Braced { m_expression } => {
+                match types::single_factor(m_expression) {
+                    Some(f) => return self.register_variable(f),
+                    None => return None,
+                }
+            }
+            _ => return None,
+        };
+        for context in self.context.iter().rev() {
+            match context.get(name) {
+                Some(Symbol::Var(_, _, Some(StorageClass::Register))) => {
+                    return Some(name.clone())
+                }
+                Some(_) => return None,
+                None => (),
+            }
+        }
+        return None;
+    }
+
+    fn function_parameters(&self, id: &String) -> Option<Vec<Parameter>> {
+        for context in self.context.iter().rev() {
+            match context.get(id) {
+                Some(Symbol::Func(f_def)) => {
+                    return Some(f_def.m_parameters.clone())
+                }
+                Some(Symbol::Var(..)) => return None,
+                None => (),
+            }
+        }
+        return None;
+    }
+
+    fn function_prototyped(&self, id: &String) -> bool {
+        for context in self.context.iter().rev() {
+            match context.get(id) {
+                Some(Symbol::Func(f_def)) => return f_def.m_prototype,
+                Some(Symbol::Var(..)) => return false,
+                None => (),
+            }
+        }
+        return false;
+    }
+
+    fn function_variadic(&self, id: &String) -> bool {
+        for context in self.context.iter().rev() {
+            match context.get(id) {
+                Some(Symbol::Func(f_def)) => return f_def.m_variadic,
+                Some(Symbol::Var(..)) => return false,
+                None => (),
+            }
+        }
+        return false;
     }
 
     fn num_arguments(&self, id: &String) -> Option<usize> {
@@ -73,7 +364,7 @@ impl Analyser {
                     Symbol::Func(f_def) => {
                         return Some(f_def.m_parameters.len());
                     }
-                    Symbol::Var(_) => return None,
+                    Symbol::Var(..) => return None,
                 },
                 None => (),
             }
@@ -84,43 +375,333 @@ impl Analyser {
     pub fn analyse_program(
         &mut self,
         program: &Program,
-    ) -> Result<bool, AnalysisError> {
+    ) -> Result<bool, (AnalysisError, Span)> {
         self.open_scope();
-        for func in &program.m_functions {
-            match self.analyse_function(&func) {
-                Ok(_) => (),
-                Err(e) => return Err(e),
+        for item in &program.m_items {
+            match item {
+                TopLevelItem::Function(func) => {
+                    if !self.later_functions.contains_key(&func.m_id) {
+                        self.later_functions
+                            .insert(func.m_id.clone(), func.m_span.clone());
+                    }
+                }
+                TopLevelItem::Declaration(_) => (),
+            }
+        }
+        for item in &program.m_items {
+            match item {
+                TopLevelItem::Function(func) => {
+                    match self.analyse_function(&func) {
+                        Ok(_) => (),
+                        Err(e) => return Err(e),
+                    }
+                }
+                TopLevelItem::Declaration(declaration) => {
+                    match self.analyse_global_declaration(&declaration) {
+                        Ok(_) => (),
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+        return Ok(true);
+    }
+
+    // Works out the linkage of a declaration of an object or function with
+    // linkage, which has to agree with any earlier declaration of it
+    fn declare_linkage(
+        &mut self,
+        id: &String,
+        storage: &Option<StorageClass>,
+        is_function: bool,
+    ) -> Result<bool, (AnalysisError, Span)> {
+        let previous = self.linkage.get(id).cloned();
+        let linkage =
+            match storage {
+                Some(StorageClass::Static) => Linkage::Internal,
+                Some(StorageClass::Extern) => {
+                    previous.clone().unwrap_or(Linkage::External)
+                }
+                None if is_function => {
+                    previous.clone().unwrap_or(Linkage::External)
+                }
+                None => Linkage::External,
+                Some(_) => return Err((
+                    AnalysisError::StorageClassError(
+                        id.clone(),
+                        String::from(
+                            "auto and register are not allowed at file scope",
+                        ),
+                    ),
+                    self.current_span.clone(),
+                )),
+            };
+        match previous {
+            Some(p) if p != linkage => {
+                let message = match linkage {
+                    Linkage::Internal => {
+                        "static declaration follows non-static declaration"
+                    }
+                    Linkage::External => {
+                        "non-static declaration follows static declaration"
+                    }
+                };
+                self.note_previous_declaration(id);
+                return Err((
+                    AnalysisError::LinkageError(
+                        id.clone(),
+                        String::from(message),
+                    ),
+                    self.current_span.clone(),
+                ));
+            }
+            _ => (),
+        }
+        self.linkage.insert(id.clone(), linkage);
+        if !self.declared_at.contains_key(id) {
+            self.declared_at.insert(id.clone(), self.current_span.clone());
+        }
+        return Ok(true);
+    }
+
+    // A variable declared at file scope must agree with any earlier file
+    // scope declaration of the same name
+    fn check_global_var(
+        &mut self,
+        declaration: &Declaration,
+    ) -> Result<bool, (AnalysisError, Span)> {
+        let id = &declaration.m_id;
+        let message = match self.context.first().and_then(|c| c.get(id)) {
+            Some(Symbol::Var(t, q, _))
+                if *t != declaration.m_type
+                    || *q != declaration.m_qualifiers =>
+            {
+                "Conflicting types"
+            }
+            Some(Symbol::Func(_)) => "Redeclared as a different kind of symbol",
+            _ => return Ok(true),
+        };
+        self.note_previous_declaration(id);
+        return Err((
+            AnalysisError::TypeError(id.clone(), String::from(message)),
+            self.current_span.clone(),
+        ));
+    }
+
+    // Static storage is initialized before the program starts, so only
+    // constants can be used
+    fn check_static_initializer(
+        &mut self,
+        declaration: &Declaration,
+    ) -> Result<bool, (AnalysisError, Span)> {
+        match &declaration.m_value {
+            Some(e) => {
+                match self.analyse_expression(e) {
+                    Ok(_) => (),
+                    Err(e) => return Err(e),
+                }
+                match self.check_conversion(e, &declaration.m_type) {
+                    Ok(_) => (),
+                    Err(e) => return Err(e),
+                }
+                let message = match evaluator::evaluate(self, e) {
+                    Ok(evaluation) => {
+                        if evaluation.m_overflow {
+                            self.warnings.push((
+                                String::from("integer overflow in expression"),
+                                declaration.m_value_span.clone(),
+                            ));
+                        }
+                        return Ok(true);
+                    }
+                    Err(EvaluationError::NotConstant)
+                        if self.static_address(declaration) =>
+                    {
+                        return Ok(true)
+                    }
+                    Err(EvaluationError::NotConstant) => {
+                        "Initializer element is not constant"
+                    }
+                    Err(EvaluationError::DivisionByZero) => {
+                        "Division by zero in initializer"
+                    }
+                };
+                return Err((
+                    AnalysisError::InitializerError(
+                        declaration.m_id.clone(),
+                        String::from(message),
+                    ),
+                    self.current_span.clone(),
+                ));
             }
+            None => (),
+        }
+        return Ok(true);
+    }
+
+    // A pointer can also start as the address of a variable with static
+    // storage, which doesn't move
+    fn static_address(&self, declaration: &Declaration) -> bool {
+        let address = match (&declaration.m_type, &declaration.m_value) {
+            (VarType::Pointer(..), Some(e)) => {
+                match evaluator::address_constant(self, e) {
+                    Some(a) => a,
+                    None => return false,
+                }
+            }
+            _ => return false,
+        };
+        for (depth, context) in self.context.iter().enumerate().rev() {
+            match context.get(&address.m_variable) {
+                Some(Symbol::Var(_, _, storage)) => match storage {
+                    _ if depth == 0 => return true,
+                    Some(StorageClass::Static) | Some(StorageClass::Extern) => {
+                        return true
+                    }
+                    _ => return false,
+                },
+                Some(Symbol::Func(_)) => return false,
+                None => (),
+            }
+        }
+        return false;
+    }
+
+    fn analyse_global_declaration(
+        &mut self,
+        declaration: &Declaration,
+    ) -> Result<bool, (AnalysisError, Span)> {
+        if DEBUG {
+            println!("Analyzing Global Declaration: {:?}", &declaration);
+        }
+        self.current_span = declaration.m_span.clone();
+        match self.declare_linkage(
+            &declaration.m_id,
+            &declaration.m_storage,
+            false,
+        ) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        match self.check_global_var(declaration) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        match self.check_static_initializer(declaration) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        if declaration.m_value.is_some()
+            && self.defined_at.contains_key(&declaration.m_id)
+        {
+            self.note_previous_definition(&declaration.m_id);
+            return Err((
+                AnalysisError::DuplicateDeclaration(
+                    declaration.m_id.clone(),
+                    String::from("Redefinition of variable"),
+                ),
+                self.current_span.clone(),
+            ));
+        }
+        if declaration.m_value.is_some() {
+            self.defined_at
+                .insert(declaration.m_id.clone(), declaration.m_span.clone());
         }
+        self.add_var(
+            declaration.m_id.clone(),
+            declaration.m_type.clone(),
+            declaration.m_qualifiers.clone(),
+            declaration.m_storage.clone(),
+        );
         return Ok(true);
     }
 
     pub fn analyse_function(
         &mut self,
         function: &Function,
-    ) -> Result<bool, AnalysisError> {
+    ) -> Result<bool, (AnalysisError, Span)> {
         if DEBUG {
             println!("Analyzing Function: {:?}", &function);
         }
-        match self.num_arguments(&function.m_id) {
-            Some(n) => {
-                if function.m_params.len() != n {
-                    return Err(AnalysisError::FunctionError(
-                        function.m_id.clone(),
-                        String::from("Function declaration type error"),
-                    ));
-                }
+        self.current_span = function.m_span.clone();
+        match self.declare_linkage(&function.m_id, &function.m_storage, true) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        let id = &function.m_id;
+        let mismatch = match self.context.first().and_then(|c| c.get(id)) {
+            Some(Symbol::Var(..)) => {
+                self.note_previous_declaration(id);
+                return Err((
+                    AnalysisError::TypeError(
+                        id.clone(),
+                        String::from(
+                            "Redeclared as a different kind of symbol",
+                        ),
+                    ),
+                    function.m_span.clone(),
+                ));
             }
-            None => {
-                let new_function = FunctionDef {
-                    m_type: function.m_type.clone(),
-                    m_parameters: function.m_params.clone(),
-                };
-                self.add_function(function.m_id.clone(), new_function);
+            Some(Symbol::Func(previous)) => {
+                prototype_mismatch(previous, function)
+            }
+            None => None,
+        };
+        match mismatch {
+            Some(m) => {
+                self.note_previous_declaration(id);
+                return Err((
+                    AnalysisError::FunctionError(id.clone(), m),
+                    function.m_span.clone(),
+                ));
+            }
+            None => (),
+        }
+        if function.m_items.is_some() {
+            if self.defined_at.contains_key(id) {
+                self.note_previous_definition(id);
+                return Err((
+                    AnalysisError::DuplicateDeclaration(
+                        id.clone(),
+                        String::from("Redefinition of function"),
+                    ),
+                    function.m_span.clone(),
+                ));
             }
+            self.defined_at.insert(id.clone(), function.m_span.clone());
+        }
+        // A declaration without a prototype leaves the earlier one in place
+        let replaces = match self.context.first().and_then(|c| c.get(id)) {
+            Some(Symbol::Func(_)) => has_prototype(function),
+            _ => true,
+        };
+        if replaces {
+            let new_function = FunctionDef {
+                m_type: function.m_type.clone(),
+                m_parameters: function.m_params.clone(),
+                m_variadic: function.m_variadic,
+                m_prototype: has_prototype(function),
+            };
+            self.add_function(id.clone(), new_function);
         }
 
         self.open_scope();
+        self.current_span = function.m_span.clone();
+
+        self.return_type = function.m_type.clone();
+        self.parameters = function.m_params.clone();
+        self.variadic = function.m_variadic;
+        for parameter in &function.m_params {
+            match &parameter.m_id {
+                Some(id) => self.add_var(
+                    id.clone(),
+                    parameter.m_type.clone(),
+                    parameter.m_qualifiers.clone(),
+                    parameter.m_storage.clone(),
+                ),
+                None => (),
+            }
+        }
 
         match &function.m_items {
             Some(b) => {
@@ -142,13 +723,14 @@ impl Analyser {
     fn analyse_block_item(
         &mut self,
         item: &BlockItem,
-    ) -> Result<bool, AnalysisError> {
+    ) -> Result<bool, (AnalysisError, Span)> {
         if DEBUG {
             println!("Analyzing BlockItem: {:?}", &item);
         }
         match item {
-            BlockItem::Statement(statement) => {
-                return self.analyse_statement(&statement)
+            BlockItem::Statement(statement, span) => {
+                self.current_span = span.clone();
+                return self.analyse_statement(&statement);
             }
             BlockItem::Declaration(declaration) => {
                 return self.analyse_declaration(&declaration)
@@ -159,31 +741,179 @@ impl Analyser {
     fn analyse_declaration(
         &mut self,
         declaration: &Declaration,
-    ) -> Result<bool, AnalysisError> {
+    ) -> Result<bool, (AnalysisError, Span)> {
         if DEBUG {
             println!("Analyzing Declaration: {:?}", &declaration);
         }
-        match &declaration.m_value {
-            Some(e) => return self.analyse_expression(e),
-            None => return Ok(true),
+        self.current_span = declaration.m_span.clone();
+        match declaration.m_storage {
+            Some(StorageClass::Extern) => {
+                if declaration.m_value.is_some() {
+                    return Err((
+                        AnalysisError::StorageClassError(
+                            declaration.m_id.clone(),
+                            String::from(
+                                "Block scope extern declaration has an initializer",
+                            ),
+                        ),
+                        self.current_span.clone(),
+                    ));
+                }
+                match self.declare_linkage(
+                    &declaration.m_id,
+                    &declaration.m_storage,
+                    false,
+                ) {
+                    Ok(_) => (),
+                    Err(e) => return Err(e),
+                }
+                match self.check_global_var(declaration) {
+                    Ok(_) => (),
+                    Err(e) => return Err(e),
+                }
+            }
+            Some(StorageClass::Static) => {
+                match self.check_static_initializer(declaration) {
+                    Ok(_) => (),
+                    Err(e) => return Err(e),
+                }
+            }
+            _ => match &declaration.m_value {
+                Some(e) => {
+                    match self.analyse_expression(e) {
+                        Ok(_) => (),
+                        Err(e) => return Err(e),
+                    }
+                    for warning in evaluator::warnings(self, e) {
+                        self.warnings.push((
+                            String::from(warning),
+                            declaration.m_value_span.clone(),
+                        ));
+                    }
+                    match self.check_conversion(e, &declaration.m_type) {
+                        Ok(_) => (),
+                        Err(e) => return Err(e),
+                    }
+                }
+                None => (),
+            },
+        }
+        let redeclared = match self.context.last() {
+            Some(scope) => scope.contains_key(&declaration.m_id),
+            None => false,
+        };
+        if redeclared && declaration.m_storage != Some(StorageClass::Extern) {
+            return Err((
+                AnalysisError::DuplicateDeclaration(
+                    declaration.m_id.clone(),
+                    String::from("Redeclaration in the same scope"),
+                ),
+                self.current_span.clone(),
+            ));
         }
+        self.add_var(
+            declaration.m_id.clone(),
+            declaration.m_type.clone(),
+            declaration.m_qualifiers.clone(),
+            declaration.m_storage.clone(),
+        );
+        return Ok(true);
+    }
+
+    // Checks that a value can be implicitly converted to a type, as in an
+    // assignment. Pointers may gain qualifiers on the pointed to type but
+    // not lose them
+    fn check_conversion(
+        &self,
+        value: &Expression,
+        to: &VarType,
+    ) -> Result<bool, (AnalysisError, Span)> {
+        let from = types::expression_type(self, value);
+        let message = match (&from, to) {
+            (VarType::Pointer(f, fq), VarType::Pointer(t, tq)) => {
+                if !tq.contains(fq) {
+                    "Conversion discards qualifiers from pointer target type"
+                } else if f != t {
+                    "Incompatible pointer types"
+                } else {
+                    return Ok(true);
+                }
+            }
+            (VarType::VaList, VarType::VaList) => return Ok(true),
+            (VarType::VaList, _) | (_, VarType::VaList) => {
+                "Incompatible conversion of a va_list"
+            }
+            (VarType::Pointer(_, _), t) if t.is_floating() => {
+                "Pointer converted to a floating type"
+            }
+            (f, VarType::Pointer(_, _)) if f.is_floating() => {
+                "Floating value converted to a pointer"
+            }
+            (VarType::Pointer(_, _), _) => "Pointer converted to an integer",
+            (_, VarType::Pointer(_, _)) => {
+                // Only a null pointer constant converts to a pointer
+                if evaluator::constant_value(self, value)
+                    == Some(Constant::Int(0))
+                {
+                    return Ok(true);
+                }
+                "Integer converted to a pointer"
+            }
+            _ => return Ok(true),
+        };
+        return Err((
+            AnalysisError::TypeError(
+                format!("'{}' to '{}'", from, to),
+                String::from(message),
+            ),
+            self.current_span.clone(),
+        ));
+    }
+
+    // The va_list argument of a variadic argument builtin
+    fn check_va_list(
+        &mut self,
+        list: &Expression,
+    ) -> Result<bool, (AnalysisError, Span)> {
+        match self.analyse_expression(list) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        let list_type = types::expression_type(self, list);
+        if list_type != VarType::VaList {
+            return Err((
+                AnalysisError::TypeError(
+                    format!("'{}'", list_type),
+                    String::from("Argument is not a va_list"),
+                ),
+                self.current_span.clone(),
+            ));
+        }
+        return Ok(true);
     }
 
     fn analyse_statement(
         &mut self,
         statement: &Statement,
-    ) -> Result<bool, AnalysisError> {
+    ) -> Result<bool, (AnalysisError, Span)> {
         if DEBUG {
             println!("Analyzing Statement: {:?}", &statement);
         }
         match statement {
             Statement::Return(e) => match e {
                 None => return Ok(true),
-                Some(exp) => return self.analyse_expression(exp),
+                Some(exp) => {
+                    match self.analyse_full_expression(exp) {
+                        Ok(_) => (),
+                        Err(e) => return Err(e),
+                    }
+                    let return_type = self.return_type.clone();
+                    return self.check_conversion(exp, &return_type);
+                }
             },
             Statement::Expression(e) => match e {
                 None => return Ok(true),
-                Some(exp) => return self.analyse_expression(exp),
+                Some(exp) => return self.analyse_full_expression(exp),
             },
             Statement::If {
                 m_condition,
@@ -199,7 +929,7 @@ impl Analyser {
                     }
                     None => (),
                 }
-                match self.analyse_expression(m_condition) {
+                match self.analyse_full_expression(m_condition) {
                     Ok(_) => {
                         return self.analyse_statement(&m_true_statement);
                     }
@@ -227,7 +957,7 @@ impl Analyser {
                 match m_initial_expression {
                     None => (),
                     Some(e) => {
-                        match self.analyse_expression(&e) {
+                        match self.analyse_full_expression(&e) {
                             Ok(_) => (),
                             Err(e) => return Err(e),
                         };
@@ -235,16 +965,16 @@ impl Analyser {
                 }
                 match m_post_expression {
                     None => (),
-                    Some(e) => match self.analyse_expression(&e) {
+                    Some(e) => match self.analyse_full_expression(&e) {
                         Ok(_) => (),
                         Err(e) => return Err(e),
                     },
                 }
-                match self.analyse_expression(&m_condition) {
+                match self.analyse_full_expression(&m_condition) {
                     Ok(_) => (),
                     Err(e) => return Err(e),
                 }
-                match self.analyse_statement(&m_statement) {
+                match self.analyse_loop_body(&m_statement) {
                     Ok(_) => (),
                     Err(e) => return Err(e),
                 }
@@ -258,22 +988,36 @@ impl Analyser {
                 m_statement,
             } => {
                 self.open_scope();
+                match m_initial_declaration.m_storage {
+                    Some(StorageClass::Static) | Some(StorageClass::Extern) => {
+                        return Err((
+                            AnalysisError::StorageClassError(
+                                m_initial_declaration.m_id.clone(),
+                                String::from(
+                                    "Only auto and register are allowed in a for loop declaration",
+                                ),
+                            ),
+                            m_initial_declaration.m_span.clone(),
+                        ))
+                    }
+                    _ => (),
+                }
+                match self.analyse_declaration(&m_initial_declaration) {
+                    Ok(_) => (),
+                    Err(e) => return Err(e),
+                }
                 match m_post_expression {
                     None => (),
-                    Some(e) => match self.analyse_expression(&e) {
+                    Some(e) => match self.analyse_full_expression(&e) {
                         Ok(_) => (),
                         Err(e) => return Err(e),
                     },
                 }
-                match self.analyse_expression(&m_condition) {
-                    Ok(_) => (),
-                    Err(e) => return Err(e),
-                }
-                match self.analyse_declaration(&m_initial_declaration) {
+                match self.analyse_full_expression(&m_condition) {
                     Ok(_) => (),
                     Err(e) => return Err(e),
                 }
-                match self.analyse_statement(&m_statement) {
+                match self.analyse_loop_body(&m_statement) {
                     Ok(_) => (),
                     Err(e) => return Err(e),
                 }
@@ -281,42 +1025,105 @@ impl Analyser {
                 return Ok(true);
             }
             Statement::While { m_condition, m_statement } => {
-                match self.analyse_expression(&m_condition) {
+                match self.analyse_full_expression(&m_condition) {
                     Ok(_) => (),
                     Err(e) => return Err(e),
                 }
-                match self.analyse_statement(m_statement) {
+                match self.analyse_loop_body(m_statement) {
                     Ok(_) => (),
                     Err(e) => return Err(e),
                 }
                 return Ok(true);
             }
             Statement::Do { m_statement, m_condition } => {
-                match self.analyse_expression(&m_condition) {
+                match self.analyse_full_expression(&m_condition) {
                     Ok(_) => (),
                     Err(e) => return Err(e),
                 }
-                match self.analyse_statement(m_statement) {
+                match self.analyse_loop_body(m_statement) {
                     Ok(_) => (),
                     Err(e) => return Err(e),
                 }
                 return Ok(true);
             }
-            Statement::Break => return Ok(true),
-            Statement::Continue => return Ok(true),
+            Statement::Break => return self.check_jump("break"),
+            Statement::Continue => return self.check_jump("continue"),
         }
     }
 
+    fn analyse_loop_body(
+        &mut self,
+        statement: &Statement,
+    ) -> Result<bool, (AnalysisError, Span)> {
+        self.loop_depth += 1;
+        let result = self.analyse_statement(statement);
+        self.loop_depth -= 1;
+        return result;
+    }
+
+    fn check_jump(&self, keyword: &str) -> Result<bool, (AnalysisError, Span)> {
+        if self.loop_depth > 0 {
+            return Ok(true);
+        }
+        return Err((
+            AnalysisError::JumpError(
+                String::from(keyword),
+                format!("'{}' statement not in a loop", keyword),
+            ),
+            self.current_span.clone(),
+        ));
+    }
+
+    // An expression which isn't part of a larger one, where arithmetic on
+    // constants is checked once
+    fn analyse_full_expression(
+        &mut self,
+        expression: &Expression,
+    ) -> Result<bool, (AnalysisError, Span)> {
+        match self.analyse_expression(expression) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        for warning in evaluator::warnings(self, expression) {
+            self.warn(warning);
+        }
+        return Ok(true);
+    }
+
     fn analyse_expression(
         &mut self,
         expression: &Expression,
-    ) -> Result<bool, AnalysisError> {
+    ) -> Result<bool, (AnalysisError, Span)> {
         if DEBUG {
             println!("Analyzing Expression: {:?}", &expression);
         }
         match expression {
-            Expression::Assignment { m_name, m_value } => {
-                return self.analyse_expression(m_value);
+            Expression::Assignment { m_target, m_value, m_span } => {
+                match self.analyse_factor(m_target) {
+                    Ok(_) => (),
+                    Err(e) => return Err(e),
+                }
+                match self.analyse_expression(m_value) {
+                    Ok(_) => (),
+                    Err(e) => return Err(e),
+                }
+                let message = match types::lvalue_qualifiers(self, m_target) {
+                    None => "Expression is not assignable",
+                    Some(q) if q.m_const => {
+                        "Assignment to const-qualified lvalue"
+                    }
+                    Some(_) => {
+                        let target_type = types::factor_type(self, m_target);
+                        return self.check_conversion(m_value, &target_type);
+                    }
+                };
+                return Err((
+                    AnalysisError::AssignmentError(
+                        String::from("="),
+                        String::from(message),
+                    ),
+                    m_span.clone(),
+                ));
             }
             Expression::Operation(conditional_expression) => {
                 return self
@@ -328,7 +1135,7 @@ impl Analyser {
     fn analyse_conditional_expression(
         &mut self,
         conditional_expression: &ConditionalExpression,
-    ) -> Result<bool, AnalysisError> {
+    ) -> Result<bool, (AnalysisError, Span)> {
         if DEBUG {
             println!(
                 "Analyzing ConditionalExpression: {:?}",
@@ -357,7 +1164,7 @@ impl Analyser {
     fn analyse_logical_or_expression(
         &mut self,
         logical_or_expression: &LogicalOrExpresson,
-    ) -> Result<bool, AnalysisError> {
+    ) -> Result<bool, (AnalysisError, Span)> {
         if DEBUG {
             println!(
                 "Analyzing LogicalOrExpression: {:?}",
@@ -382,7 +1189,7 @@ impl Analyser {
     fn analyse_logical_and_expression(
         &mut self,
         logical_and_expression: &LogicalAndExpression,
-    ) -> Result<bool, AnalysisError> {
+    ) -> Result<bool, (AnalysisError, Span)> {
         if DEBUG {
             println!(
                 "Analyzing LogicalAndExpression: {:?}",
@@ -406,7 +1213,7 @@ impl Analyser {
     fn analyse_equality_expression(
         &mut self,
         equality_expession: &EqualityExpression,
-    ) -> Result<bool, AnalysisError> {
+    ) -> Result<bool, (AnalysisError, Span)> {
         match self.analyse_relational_expression(&equality_expession.m_first) {
             Ok(_) => (),
             Err(e) => return Err(e),
@@ -423,7 +1230,7 @@ impl Analyser {
     fn analyse_relational_expression(
         &mut self,
         relational_expession: &RelationalExpression,
-    ) -> Result<bool, AnalysisError> {
+    ) -> Result<bool, (AnalysisError, Span)> {
         if DEBUG {
             println!(
                 "Analyzing RelationalExpression: {:?}",
@@ -446,7 +1253,7 @@ impl Analyser {
     fn analyse_additive_expression(
         &mut self,
         additive_expression: &AdditiveExpression,
-    ) -> Result<bool, AnalysisError> {
+    ) -> Result<bool, (AnalysisError, Span)> {
         if DEBUG {
             println!(
                 "Analyzing AdditiveExpression: {:?}",
@@ -463,10 +1270,61 @@ impl Analyser {
                 Err(e) => return Err(e),
             }
         }
+        // A pointer can have an integer added or subtracted, or be
+        // subtracted from a pointer to the same type
+        let mut left_type =
+            types::term_type(self, &additive_expression.m_first_term);
+        for next in &additive_expression.m_rest {
+            let right_type = types::term_type(self, &next.1);
+            let message = match (&left_type, &next.0, &right_type) {
+                (VarType::Pointer(_, _), _, t)
+                | (t, _, VarType::Pointer(_, _))
+                    if t.is_floating() =>
+                {
+                    Some("Pointer arithmetic with a floating operand")
+                }
+                (
+                    VarType::Pointer(l, _),
+                    AdditiveOperator::Minus,
+                    VarType::Pointer(r, _),
+                ) if l != r => Some("Subtraction of incompatible pointers"),
+                (
+                    VarType::Pointer(_, _),
+                    AdditiveOperator::Addition,
+                    VarType::Pointer(_, _),
+                ) => Some("Addition of two pointers"),
+                (_, AdditiveOperator::Minus, VarType::Pointer(_, _))
+                    if !left_type.is_pointer() =>
+                {
+                    Some("Subtraction of a pointer from an integer")
+                }
+                _ => None,
+            };
+            match message {
+                Some(m) => {
+                    return Err((
+                        AnalysisError::TypeError(
+                            format!("{:?}", next.0),
+                            String::from(m),
+                        ),
+                        self.current_span.clone(),
+                    ))
+                }
+                None => (),
+            }
+            if left_type.is_pointer() && right_type.is_pointer() {
+                left_type = VarType::Long;
+            } else {
+                left_type = types::common_type(&left_type, &right_type);
+            }
+        }
         return Ok(true);
     }
 
-    fn analyse_term(&mut self, term: &Term) -> Result<bool, AnalysisError> {
+    fn analyse_term(
+        &mut self,
+        term: &Term,
+    ) -> Result<bool, (AnalysisError, Span)> {
         if DEBUG {
             println!("Analyzing Term: {:?}", &term);
         }
@@ -480,45 +1338,264 @@ impl Analyser {
                 Err(e) => return Err(e),
             }
         }
+        let mut left_type = types::factor_type(self, &term.m_first_factor);
+        for next in &term.m_rest {
+            let right_type = types::factor_type(self, &next.1);
+            if left_type.is_pointer() || right_type.is_pointer() {
+                return Err((
+                    AnalysisError::TypeError(
+                        format!("{:?}", next.0),
+                        String::from("Operands must be arithmetic"),
+                    ),
+                    self.current_span.clone(),
+                ));
+            }
+            match next.0 {
+                MultiplicativeOperator::Modulo => {
+                    if left_type.is_floating() || right_type.is_floating() {
+                        return Err((
+                            AnalysisError::TypeError(
+                                String::from("%"),
+                                String::from("Operands of % must be integers"),
+                            ),
+                            self.current_span.clone(),
+                        ));
+                    }
+                }
+                _ => (),
+            }
+            left_type = types::common_type(&left_type, &right_type);
+        }
         return Ok(true);
     }
 
     fn analyse_factor(
         &mut self,
         factor: &Factor,
-    ) -> Result<bool, AnalysisError> {
+    ) -> Result<bool, (AnalysisError, Span)> {
         if DEBUG {
             println!("Analyzing Factor: {:?}", &factor);
         }
         match factor {
-            Factor::FunCall { m_id, m_arguments } => {
+            Factor::FunCall { m_id, m_arguments, m_span } => {
                 match self.num_arguments(&m_id) {
                     None => {
-                        return Err(AnalysisError::FunctionError(
-                            m_id.clone(),
-                            String::from("Undefined identifier"),
+                        // Calls need a declaration in scope, there are no
+                        // implicit declarations
+                        let message = match self.later_functions.get(m_id) {
+                            Some(span) => {
+                                self.notes.push((
+                                    format!("'{}' is declared here", m_id),
+                                    span.clone(),
+                                ));
+                                "Called before it is declared, without a \
+                                prototype"
+                            }
+                            None => "Undefined identifier",
+                        };
+                        return Err((
+                            AnalysisError::FunctionError(
+                                m_id.clone(),
+                                String::from(message),
+                            ),
+                            m_span.clone(),
                         ));
                     }
                     Some(n) => {
-                        if m_arguments.len() != n {
-                            return Err(AnalysisError::FunctionError(
-                                m_id.clone(),
-                                String::from("Invalid Arguments"),
-                            ));
+                        // A variadic function takes any number of arguments
+                        // after the fixed ones, and one without a prototype
+                        // any number at all
+                        let variadic = self.function_variadic(m_id)
+                            || !self.function_prototyped(m_id);
+                        let message = if variadic {
+                            "Too few arguments for the fixed parameters"
                         } else {
-                            return Ok(true);
+                            "Invalid Arguments"
+                        };
+                        let supplied = m_arguments.len();
+                        if supplied < n || (supplied > n && !variadic) {
+                            match self.declared_at.get(m_id) {
+                                Some(span) => self.notes.push((
+                                    format!("'{}' is declared here", m_id),
+                                    span.clone(),
+                                )),
+                                None => (),
+                            }
+                            return Err((
+                                AnalysisError::FunctionError(
+                                    m_id.clone(),
+                                    String::from(message),
+                                ),
+                                m_span.clone(),
+                            ));
                         }
                     }
                 }
+                let parameters = match self.function_parameters(m_id) {
+                    Some(p) => p,
+                    None => Vec::new(),
+                };
+                for (i, argument) in m_arguments.iter().enumerate() {
+                    match self.analyse_expression(argument) {
+                        Ok(_) => (),
+                        Err(e) => return Err(e),
+                    }
+                    let parameter = match parameters.get(i) {
+                        Some(p) => p,
+                        None => continue,
+                    };
+                    match self.check_conversion(argument, &parameter.m_type) {
+                        Ok(_) => (),
+                        Err(e) => return Err(e),
+                    }
+                }
+                return Ok(true);
             }
-            Factor::Constant { m_value } => return Ok(true),
+            Factor::Constant { m_value: _, m_type: _ } => return Ok(true),
+            Factor::FloatConstant { m_value: _, m_type: _ } => return Ok(true),
             Factor::UnaryOperation { m_opertator, m_factor } => {
-                return self.analyse_factor(&m_factor)
+                match (m_opertator, self.register_variable(m_factor)) {
+                    (UnaryOperator::AddressOf, Some(name)) => {
+                        return Err((
+                            AnalysisError::StorageClassError(
+                                name,
+                                String::from(
+                                    "Address of register variable requested",
+                                ),
+                            ),
+                            self.current_span.clone(),
+                        ))
+                    }
+                    _ => (),
+                }
+                let operand_type = types::factor_type(self, m_factor);
+                let message = match m_opertator {
+                    UnaryOperator::Dereference
+                        if !operand_type.is_pointer() =>
+                    {
+                        Some("Operand of * must be a pointer")
+                    }
+                    UnaryOperator::AddressOf
+                        if types::lvalue_qualifiers(self, m_factor)
+                            .is_none() =>
+                    {
+                        Some("Operand of & must be an lvalue")
+                    }
+                    UnaryOperator::Minus | UnaryOperator::Complement
+                        if operand_type.is_pointer() =>
+                    {
+                        Some("Operand must be arithmetic")
+                    }
+                    _ => None,
+                };
+                match message {
+                    Some(m) => {
+                        return Err((
+                            AnalysisError::TypeError(
+                                format!("{:?}", m_opertator),
+                                String::from(m),
+                            ),
+                            self.current_span.clone(),
+                        ))
+                    }
+                    None => (),
+                }
+                match m_opertator {
+                    UnaryOperator::Complement => {
+                        if types::factor_type(self, m_factor).is_floating() {
+                            return Err((
+                                AnalysisError::TypeError(
+                                    String::from("~"),
+                                    String::from(
+                                        "Operand of ~ must be an integer",
+                                    ),
+                                ),
+                                self.current_span.clone(),
+                            ));
+                        }
+                    }
+                    _ => (),
+                }
+                return self.analyse_factor(&m_factor);
             }
             Factor::Braced { m_expression } => {
                 return self.analyse_expression(&m_expression)
             }
-            Factor::Variable { m_var } => return Ok(true),
+            Factor::Variable { m_var, m_span } => {
+                if self.variable_type(m_var).is_none() {
+                    return Err((
+                        AnalysisError::UndeclaredIdentifier(
+                            m_var.clone(),
+                            String::from("Use of undeclared identifier"),
+                        ),
+                        m_span.clone(),
+                    ));
+                }
+                return Ok(true);
+            }
+            Factor::Cast { m_type, m_factor } => {
+                match self.analyse_factor(m_factor) {
+                    Ok(_) => (),
+                    Err(e) => return Err(e),
+                }
+                // Every scalar converts to every other, except that pointers
+                // and floating values don't mix
+                let from = types::factor_type(self, m_factor);
+                if (from.is_pointer() && m_type.is_floating())
+                    || (from.is_floating() && m_type.is_pointer())
+                {
+                    return Err((
+                        AnalysisError::TypeError(
+                            format!("'{}' to '{}'", from, m_type),
+                            String::from("Invalid cast"),
+                        ),
+                        self.current_span.clone(),
+                    ));
+                }
+                return Ok(true);
+            }
+            // The operand of sizeof is never evaluated, but it still has to
+            // be a valid expression
+            Factor::SizeOfExpression { m_factor } => {
+                return self.analyse_factor(m_factor)
+            }
+            Factor::SizeOfType { m_type: _ }
+            | Factor::AlignOf { m_type: _ } => return Ok(true),
+            Factor::VaStart { m_list, m_last } => {
+                match self.check_va_list(m_list) {
+                    Ok(_) => (),
+                    Err(e) => return Err(e),
+                }
+                let message = if !self.variadic {
+                    Some("va_start used in a function with fixed arguments")
+                } else if self.parameters.last().and_then(|p| p.m_id.as_ref())
+                    != Some(m_last)
+                {
+                    Some("Second argument of va_start is not the last named parameter")
+                } else if types::single_factor(m_list)
+                    .and_then(|f| types::lvalue_qualifiers(self, f))
+                    .is_none()
+                {
+                    Some("First argument of va_start is not an lvalue")
+                } else {
+                    None
+                };
+                match message {
+                    Some(m) => {
+                        return Err((
+                            AnalysisError::FunctionError(
+                                String::from("va_start"),
+                                String::from(m),
+                            ),
+                            self.current_span.clone(),
+                        ))
+                    }
+                    None => return Ok(true),
+                }
+            }
+            Factor::VaArg { m_list, m_type: _ } | Factor::VaEnd { m_list } => {
+                return self.check_va_list(m_list)
+            }
         }
     }
 }
@@ -0,0 +1,712 @@
+// x86-64 instructions as the generator builds them, printed as AT&T or
+// Intel assembly
+
+use crate::ir;
+
+// General purpose and SSE registers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Register {
+    Rax,
+    Rbx,
+    Rcx,
+    Rdx,
+    Rsi,
+    Rdi,
+    Rbp,
+    Rsp,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+    Xmm(u8),
+}
+
+// Size of an integer operand. SSE instructions take the size of the
+// floating value, Long for float and Quad for double
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Size {
+    Byte,
+    Word,
+    Long,
+    Quad,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Immediate(i64),
+    Register(Register),
+    // offset(base)
+    Memory { m_base: Register, m_offset: i32 },
+    // A symbol addressed relative to %rip
+    Symbol(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Below,
+    BelowOrEqual,
+    Above,
+    AboveOrEqual,
+    Parity,
+    NoParity,
+    Sign,
+}
+
+// Integer instructions of the form op source, destination
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IntegerOp {
+    Add,
+    Subtract,
+    Multiply,
+    And,
+    Or,
+    Xor,
+    Compare,
+    Test,
+    ShiftRight,
+    // Complements the bit numbered by the source
+    BitComplement,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FloatOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+// Operands are in AT&T order, source before destination
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    // Printed as movabsq for a 64 bit immediate which doesn't fit 32 bits
+    Mov(Size, Operand, Operand),
+    // From the first size to the second
+    MovSignExtend(Size, Size, Operand, Operand),
+    MovZeroExtend(Size, Size, Operand, Operand),
+    Lea(Operand, Operand),
+    Push(Operand),
+    Pop(Operand),
+    Integer(IntegerOp, Size, Operand, Operand),
+    Negate(Size, Operand),
+    Not(Size, Operand),
+    // Divide %rdx:%rax by the operand
+    Divide(Size, Operand),
+    SignedDivide(Size, Operand),
+    // Sign extends %eax into %edx, or %rax into %rdx
+    ExtendAccumulator(Size),
+    Set(Condition, Operand),
+    Jump(String),
+    JumpIf(Condition, String),
+    Call(String),
+    Return,
+    // Into the kernel, with the call number in %rax
+    SystemCall,
+    // Between SSE registers and memory
+    MovFloat(Size, Operand, Operand),
+    // The bits of a value between a general purpose and an SSE register
+    MovBits(Size, Operand, Operand),
+    Float(FloatOp, Size, Operand, Operand),
+    // Unordered compare, setting the parity flag for NaN
+    CompareFloat(Size, Operand, Operand),
+    // From a 64 bit integer to the size given
+    IntegerToFloat(Size, Operand, Operand),
+    // From the size given to a 64 bit integer, truncating
+    FloatToInteger(Size, Operand, Operand),
+    // From the size given to the other one
+    FloatToFloat(Size, Operand, Operand),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Section {
+    Text,
+    Data,
+    ReadOnly,
+    Bss,
+}
+
+// Anything for the assembler rather than the processor
+#[derive(Debug, Clone, PartialEq)]
+pub enum Directive {
+    Global(String),
+    Section(Section),
+    Align(i32),
+    Zero(i32),
+    Value(Size, i64),
+    // A quad holding the address of a symbol plus a number of bytes
+    Address(String, i64),
+    // Number and name of a source file, and a file, line and column
+    // instructions come from
+    File(usize, String),
+    Location(usize, usize, usize),
+}
+
+// The two syntaxes GNU as reads. AT&T puts the source first and sizes in
+// the mnemonic, Intel the destination first and sizes on memory operands
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Syntax {
+    Att,
+    Intel,
+}
+
+// One line of assembly
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line {
+    Label(String),
+    Directive(Directive),
+    Instruction(Instruction),
+}
+
+impl Register {
+    // Kept by calls, so a function using one saves it for its caller
+    pub fn is_callee_saved(&self) -> bool {
+        match self {
+            Register::Rbx
+            | Register::Rbp
+            | Register::Rsp
+            | Register::R12
+            | Register::R13
+            | Register::R14
+            | Register::R15 => return true,
+            _ => return false,
+        }
+    }
+
+    pub fn is_sse(&self) -> bool {
+        match self {
+            Register::Xmm(_) => return true,
+            _ => return false,
+        }
+    }
+
+    // The name of the part of the register holding values of the size. SSE
+    // registers have one name whatever the size
+    pub fn name(&self, size: Size) -> String {
+        let legacy = match self {
+            Register::Rax => "a",
+            Register::Rbx => "b",
+            Register::Rcx => "c",
+            Register::Rdx => "d",
+            Register::Rsi => "si",
+            Register::Rdi => "di",
+            Register::Rbp => "bp",
+            Register::Rsp => "sp",
+            Register::Xmm(n) => return format!("xmm{}", n),
+            Register::R8 => "8",
+            Register::R9 => "9",
+            Register::R10 => "10",
+            Register::R11 => "11",
+            Register::R12 => "12",
+            Register::R13 => "13",
+            Register::R14 => "14",
+            Register::R15 => "15",
+        };
+        // The numbered registers add a letter for the size
+        if legacy.starts_with(|c: char| c.is_ascii_digit()) {
+            let suffix = match size {
+                Size::Byte => "b",
+                Size::Word => "w",
+                Size::Long => "d",
+                Size::Quad => "",
+            };
+            return format!("r{}{}", legacy, suffix);
+        }
+        // The a, b, c and d registers put an x after their letter
+        let wide = match legacy.len() {
+            1 => format!("{}x", legacy),
+            _ => String::from(legacy),
+        };
+        match size {
+            Size::Byte => return format!("{}l", legacy),
+            Size::Word => return wide,
+            Size::Long => return format!("e{}", wide),
+            Size::Quad => return format!("r{}", wide),
+        }
+    }
+
+    // Number of the register in instruction encodings, the top bit of which
+    // goes in the REX prefix
+    pub fn number(&self) -> u8 {
+        match self {
+            Register::Rax => return 0,
+            Register::Rcx => return 1,
+            Register::Rdx => return 2,
+            Register::Rbx => return 3,
+            Register::Rsp => return 4,
+            Register::Rbp => return 5,
+            Register::Rsi => return 6,
+            Register::Rdi => return 7,
+            Register::R8 => return 8,
+            Register::R9 => return 9,
+            Register::R10 => return 10,
+            Register::R11 => return 11,
+            Register::R12 => return 12,
+            Register::R13 => return 13,
+            Register::R14 => return 14,
+            Register::R15 => return 15,
+            Register::Xmm(n) => return *n,
+        }
+    }
+}
+
+impl Size {
+    // Size of an integer of the given number of bytes
+    pub fn of(bytes: i32) -> Size {
+        match bytes {
+            1 => return Size::Byte,
+            2 => return Size::Word,
+            4 => return Size::Long,
+            _ => return Size::Quad,
+        }
+    }
+
+    fn suffix(&self) -> &'static str {
+        match self {
+            Size::Byte => return "b",
+            Size::Word => return "w",
+            Size::Long => return "l",
+            Size::Quad => return "q",
+        }
+    }
+
+    // Suffix of the SSE scalar instruction for floating values of the size
+    fn sse_suffix(&self) -> &'static str {
+        match self {
+            Size::Long => return "ss",
+            _ => return "sd",
+        }
+    }
+}
+
+impl Operand {
+    pub fn is_memory(&self) -> bool {
+        match self {
+            Operand::Memory { .. } | Operand::Symbol(_) => return true,
+            _ => return false,
+        }
+    }
+}
+
+impl Condition {
+    fn suffix(&self) -> &'static str {
+        match self {
+            Condition::Equal => return "e",
+            Condition::NotEqual => return "ne",
+            Condition::Less => return "l",
+            Condition::LessOrEqual => return "le",
+            Condition::Greater => return "g",
+            Condition::GreaterOrEqual => return "ge",
+            Condition::Below => return "b",
+            Condition::BelowOrEqual => return "be",
+            Condition::Above => return "a",
+            Condition::AboveOrEqual => return "ae",
+            Condition::Parity => return "p",
+            Condition::NoParity => return "np",
+            Condition::Sign => return "s",
+        }
+    }
+}
+
+impl Instruction {
+    // The move back the other way, for moves which copy a value unchanged
+    pub fn reversed(&self) -> Option<Instruction> {
+        match self {
+            Instruction::Mov(size, source, destination) => {
+                return Some(Instruction::Mov(
+                    *size,
+                    destination.clone(),
+                    source.clone(),
+                ))
+            }
+            Instruction::MovFloat(size, source, destination) => {
+                return Some(Instruction::MovFloat(
+                    *size,
+                    destination.clone(),
+                    source.clone(),
+                ))
+            }
+            Instruction::MovBits(size, source, destination) => {
+                return Some(Instruction::MovBits(
+                    *size,
+                    destination.clone(),
+                    source.clone(),
+                ))
+            }
+            _ => return None,
+        }
+    }
+
+    // Whether control never continues to the next instruction
+    pub fn is_unconditional_jump(&self) -> bool {
+        match self {
+            Instruction::Jump(_) | Instruction::Return => return true,
+            _ => return false,
+        }
+    }
+}
+
+impl Line {
+    // Directives, line information included, are seen by neither the
+    // processor nor control flow
+    pub fn is_directive(&self) -> bool {
+        match self {
+            Line::Directive(_) => return true,
+            _ => return false,
+        }
+    }
+}
+
+fn print_operand(operand: &Operand, size: Size, syntax: Syntax) -> String {
+    match syntax {
+        Syntax::Att => return print_att_operand(operand, size),
+        Syntax::Intel => return print_intel_operand(operand, size),
+    }
+}
+
+fn print_att_operand(operand: &Operand, size: Size) -> String {
+    match operand {
+        Operand::Immediate(value) => return format!("${}", value),
+        Operand::Register(register) => {
+            return format!("%{}", register.name(size))
+        }
+        Operand::Memory { m_base, m_offset: 0 } => {
+            return format!("(%{})", m_base.name(Size::Quad))
+        }
+        Operand::Memory { m_base, m_offset } => {
+            return format!("{}(%{})", m_offset, m_base.name(Size::Quad))
+        }
+        Operand::Symbol(name) => return format!("{}(%rip)", name),
+    }
+}
+
+// [base+offset], or [rip+symbol]
+fn intel_address(operand: &Operand) -> String {
+    match operand {
+        Operand::Memory { m_base, m_offset: 0 } => {
+            return format!("[{}]", m_base.name(Size::Quad))
+        }
+        Operand::Memory { m_base, m_offset } => {
+            return format!("[{}{:+}]", m_base.name(Size::Quad), m_offset)
+        }
+        Operand::Symbol(name) => return format!("[rip+{}]", name),
+        _ => panic!("{:?} is not in memory", operand),
+    }
+}
+
+// Memory operands always say their size, which the registers would give in
+// AT&T syntax
+fn print_intel_operand(operand: &Operand, size: Size) -> String {
+    match operand {
+        Operand::Immediate(value) => return format!("{}", value),
+        Operand::Register(register) => return register.name(size),
+        _ => (),
+    }
+    let pointer = match size {
+        Size::Byte => "BYTE",
+        Size::Word => "WORD",
+        Size::Long => "DWORD",
+        Size::Quad => "QWORD",
+    };
+    return format!("{} PTR {}", pointer, intel_address(operand));
+}
+
+// The mnemonic of an instruction and its operands with the size each is
+// printed at
+fn parts(instruction: &Instruction) -> (String, Vec<(&Operand, Size)>) {
+    match instruction {
+        Instruction::Mov(size, source, destination) => {
+            let mnemonic = match source {
+                Operand::Immediate(v)
+                    if *size == Size::Quad && i32::try_from(*v).is_err() =>
+                {
+                    String::from("movabsq")
+                }
+                _ => format!("mov{}", size.suffix()),
+            };
+            return (mnemonic, vec![(source, *size), (destination, *size)]);
+        }
+        Instruction::MovSignExtend(from, to, source, destination) => {
+            // movsl is written movslq
+            return (
+                format!("movs{}{}", from.suffix(), to.suffix()),
+                vec![(source, *from), (destination, *to)],
+            );
+        }
+        Instruction::MovZeroExtend(from, to, source, destination) => {
+            return (
+                format!("movz{}{}", from.suffix(), to.suffix()),
+                vec![(source, *from), (destination, *to)],
+            );
+        }
+        Instruction::Lea(source, destination) => {
+            return (
+                String::from("leaq"),
+                vec![(source, Size::Quad), (destination, Size::Quad)],
+            );
+        }
+        Instruction::Push(operand) => {
+            return (String::from("pushq"), vec![(operand, Size::Quad)]);
+        }
+        Instruction::Pop(operand) => {
+            return (String::from("popq"), vec![(operand, Size::Quad)]);
+        }
+        Instruction::Integer(op, size, source, destination) => {
+            let name = match op {
+                IntegerOp::Add => "add",
+                IntegerOp::Subtract => "sub",
+                IntegerOp::Multiply => "imul",
+                IntegerOp::And => "and",
+                IntegerOp::Or => "or",
+                IntegerOp::Xor => "xor",
+                IntegerOp::Compare => "cmp",
+                IntegerOp::Test => "test",
+                IntegerOp::ShiftRight => "shr",
+                IntegerOp::BitComplement => "btc",
+            };
+            // Shift and bit counts are bytes
+            let source_size = match op {
+                IntegerOp::ShiftRight | IntegerOp::BitComplement => Size::Byte,
+                _ => *size,
+            };
+            return (
+                format!("{}{}", name, size.suffix()),
+                vec![(source, source_size), (destination, *size)],
+            );
+        }
+        Instruction::Negate(size, operand) => {
+            return (format!("neg{}", size.suffix()), vec![(operand, *size)]);
+        }
+        Instruction::Not(size, operand) => {
+            return (format!("not{}", size.suffix()), vec![(operand, *size)]);
+        }
+        Instruction::Divide(size, operand) => {
+            return (format!("div{}", size.suffix()), vec![(operand, *size)]);
+        }
+        Instruction::SignedDivide(size, operand) => {
+            return (format!("idiv{}", size.suffix()), vec![(operand, *size)]);
+        }
+        Instruction::ExtendAccumulator(size) => {
+            let mnemonic = match size {
+                Size::Quad => "cqo",
+                _ => "cltd",
+            };
+            return (String::from(mnemonic), Vec::new());
+        }
+        Instruction::Set(condition, operand) => {
+            return (
+                format!("set{}", condition.suffix()),
+                vec![(operand, Size::Byte)],
+            );
+        }
+        Instruction::Jump(_)
+        | Instruction::JumpIf(..)
+        | Instruction::Call(_)
+        | Instruction::Return
+        | Instruction::SystemCall => {
+            panic!("Control flow instructions take labels, not operands")
+        }
+        Instruction::MovFloat(size, source, destination) => {
+            return (
+                format!("mov{}", size.sse_suffix()),
+                vec![(source, *size), (destination, *size)],
+            );
+        }
+        Instruction::MovBits(size, source, destination) => {
+            let mnemonic = match size {
+                Size::Quad => "movq",
+                _ => "movd",
+            };
+            return (
+                String::from(mnemonic),
+                vec![(source, *size), (destination, *size)],
+            );
+        }
+        Instruction::Float(op, size, source, destination) => {
+            let name = match op {
+                FloatOp::Add => "add",
+                FloatOp::Subtract => "sub",
+                FloatOp::Multiply => "mul",
+                FloatOp::Divide => "div",
+            };
+            return (
+                format!("{}{}", name, size.sse_suffix()),
+                vec![(source, *size), (destination, *size)],
+            );
+        }
+        Instruction::CompareFloat(size, source, destination) => {
+            return (
+                format!("ucomi{}", size.sse_suffix()),
+                vec![(source, *size), (destination, *size)],
+            );
+        }
+        Instruction::IntegerToFloat(size, source, destination) => {
+            return (
+                format!("cvtsi2{}q", size.sse_suffix()),
+                vec![(source, Size::Quad), (destination, *size)],
+            );
+        }
+        Instruction::FloatToInteger(size, source, destination) => {
+            return (
+                format!("cvtt{}2siq", size.sse_suffix()),
+                vec![(source, *size), (destination, Size::Quad)],
+            );
+        }
+        Instruction::FloatToFloat(size, source, destination) => {
+            let mnemonic = match size {
+                Size::Long => "cvtss2sd",
+                _ => "cvtsd2ss",
+            };
+            return (
+                String::from(mnemonic),
+                vec![(source, *size), (destination, *size)],
+            );
+        }
+    }
+}
+
+fn is_sse(operand: &Operand) -> bool {
+    match operand {
+        Operand::Register(r) => return r.is_sse(),
+        _ => return false,
+    }
+}
+
+// Intel syntax names instructions without the size suffixes, which its
+// operands give instead
+fn intel_mnemonic(instruction: &Instruction, att: String) -> String {
+    match instruction {
+        Instruction::Mov(..) if att == "movabsq" => {
+            return String::from("movabs")
+        }
+        // Moves to or from an SSE register are movq in both syntaxes
+        Instruction::Mov(_, source, destination)
+            if is_sse(source) || is_sse(destination) =>
+        {
+            return String::from("movq")
+        }
+        Instruction::Mov(..) => return String::from("mov"),
+        Instruction::MovSignExtend(Size::Long, ..) => {
+            return String::from("movsxd")
+        }
+        Instruction::MovSignExtend(..) => return String::from("movsx"),
+        Instruction::MovZeroExtend(..) => return String::from("movzx"),
+        Instruction::ExtendAccumulator(Size::Quad) => {
+            return String::from("cqo")
+        }
+        Instruction::ExtendAccumulator(_) => return String::from("cdq"),
+        Instruction::Lea(..)
+        | Instruction::Push(_)
+        | Instruction::Pop(_)
+        | Instruction::Integer(..)
+        | Instruction::Negate(..)
+        | Instruction::Not(..)
+        | Instruction::Divide(..)
+        | Instruction::SignedDivide(..)
+        | Instruction::IntegerToFloat(..)
+        | Instruction::FloatToInteger(..) => {
+            let mut mnemonic = att;
+            mnemonic.pop();
+            return mnemonic;
+        }
+        _ => return att,
+    }
+}
+
+fn print_instruction(instruction: &Instruction, syntax: Syntax) -> String {
+    match instruction {
+        Instruction::Jump(label) => return format!("jmp\t{}", label),
+        Instruction::JumpIf(condition, label) => {
+            return format!("j{}\t{}", condition.suffix(), label)
+        }
+        Instruction::Call(name) => return format!("call\t{}", name),
+        Instruction::Return => return String::from("ret"),
+        Instruction::SystemCall => return String::from("syscall"),
+        _ => (),
+    }
+    let (mut mnemonic, mut operands) = parts(instruction);
+    if syntax == Syntax::Intel {
+        mnemonic = intel_mnemonic(instruction, mnemonic);
+        operands.reverse();
+    }
+    if operands.is_empty() {
+        return mnemonic;
+    }
+    let operands: Vec<String> = match instruction {
+        // An address is worked out, not read, so it has no size
+        Instruction::Lea(source, destination) if syntax == Syntax::Intel => {
+            vec![
+                print_intel_operand(destination, Size::Quad),
+                intel_address(source),
+            ]
+        }
+        _ => operands
+            .iter()
+            .map(|(operand, size)| print_operand(operand, *size, syntax))
+            .collect(),
+    };
+    return format!("{}\t{}", mnemonic, operands.join(", "));
+}
+
+fn print_directive(directive: &Directive) -> String {
+    match directive {
+        Directive::Global(name) => return format!(".globl\t{}", name),
+        Directive::Section(Section::Text) => return String::from(".text"),
+        Directive::Section(Section::Data) => return String::from(".data"),
+        Directive::Section(Section::ReadOnly) => {
+            return String::from(".section\t.rodata")
+        }
+        Directive::Section(Section::Bss) => return String::from(".bss"),
+        Directive::Align(align) => return format!(".align\t{}", align),
+        Directive::Zero(size) => return format!(".zero\t{}", size),
+        Directive::Value(size, value) => {
+            let name = match size {
+                Size::Byte => ".byte",
+                Size::Word => ".value",
+                Size::Long => ".long",
+                Size::Quad => ".quad",
+            };
+            return format!("{}\t{}", name, value);
+        }
+        Directive::Address(symbol, offset) => {
+            return format!(".quad\t{}", ir::address_text(symbol, *offset))
+        }
+        Directive::File(number, name) => {
+            return format!(".file\t{} \"{}\"", number, name)
+        }
+        Directive::Location(file, line, column) => {
+            return format!(".loc\t{} {} {}", file, line, column)
+        }
+    }
+}
+
+// The assembly text of the lines, one per line of text
+pub fn print(lines: &Vec<Line>, syntax: Syntax) -> String {
+    let mut text = String::new();
+    if syntax == Syntax::Intel {
+        text.push_str("\t.intel_syntax noprefix\n");
+    }
+    for line in lines {
+        let printed = match line {
+            Line::Label(name) => format!("{}:", name),
+            Line::Directive(directive) => {
+                format!("\t{}", print_directive(directive))
+            }
+            Line::Instruction(instruction) => {
+                format!("\t{}", print_instruction(instruction, syntax))
+            }
+        };
+        text.push_str(&printed);
+        text.push('\n');
+    }
+    return text;
+}
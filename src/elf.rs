@@ -0,0 +1,625 @@
+use std::collections::HashMap;
+use std::fmt;
+
+// The sections of an object file holding code and data, in the order their
+// contents are kept in an Object
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ObjectSection {
+    Text,
+    Data,
+    ReadOnly,
+    Bss,
+}
+
+pub const OBJECT_SECTIONS: [ObjectSection; 4] = [
+    ObjectSection::Text,
+    ObjectSection::Data,
+    ObjectSection::ReadOnly,
+    ObjectSection::Bss,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelocationKind {
+    // The symbol's address relative to where it's stored
+    Pc32,
+    // As Pc32, but through the procedure linkage table for functions which
+    // may be in a shared library
+    Plt32,
+    // The symbol's whole address, for pointers in data
+    Abs64,
+}
+
+// A place in a section to fill in with the address of a symbol once it's
+// known
+#[derive(Debug, Clone)]
+pub struct Relocation {
+    pub m_section: ObjectSection,
+    pub m_offset: u64,
+    pub m_symbol: String,
+    pub m_kind: RelocationKind,
+    pub m_addend: i64,
+}
+
+// A symbol defined in one of the sections at an offset, or left undefined
+// for the linker to find elsewhere
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub m_name: String,
+    pub m_section: Option<ObjectSection>,
+    pub m_offset: u64,
+    pub m_global: bool,
+}
+
+// What goes in a relocatable object file
+pub struct Object {
+    // Contents of each section, by the order of OBJECT_SECTIONS. .bss is
+    // kept as zeros but takes no room in the file
+    pub m_contents: [Vec<u8>; 4],
+    pub m_alignments: [u64; 4],
+    pub m_symbols: Vec<Symbol>,
+    pub m_relocations: Vec<Relocation>,
+}
+
+const SECTION_NAMES: [&str; 4] = [".text", ".data", ".rodata", ".bss"];
+
+const HEADER_SIZE: u64 = 64;
+const SECTION_HEADER_SIZE: u64 = 64;
+const SYMBOL_SIZE: u64 = 24;
+const RELOCATION_SIZE: u64 = 24;
+
+// Section header types and flags
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHT_NOBITS: u32 = 8;
+const SHF_WRITE: u64 = 1;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+// Symbol bindings and types
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+const R_X86_64_64: u64 = 1;
+const R_X86_64_PC32: u64 = 2;
+const R_X86_64_PLT32: u64 = 4;
+
+// Section header indexes. The null section then the code and data sections
+// and the relocations for .text, .data and .rodata come before these
+const SYMTAB_INDEX: u32 = 8;
+const STRTAB_INDEX: u32 = 9;
+const SHSTRTAB_INDEX: u32 = 10;
+const SECTION_COUNT: u16 = 12;
+
+// The sections which can have relocations, with the name of the section
+// holding them
+const RELOCATED_SECTIONS: [(ObjectSection, &str); 3] = [
+    (ObjectSection::Text, ".rela.text"),
+    (ObjectSection::Data, ".rela.data"),
+    (ObjectSection::ReadOnly, ".rela.rodata"),
+];
+
+impl ObjectSection {
+    // Index of the section's header
+    pub fn index(&self) -> u16 {
+        match self {
+            ObjectSection::Text => return 1,
+            ObjectSection::Data => return 2,
+            ObjectSection::ReadOnly => return 3,
+            ObjectSection::Bss => return 4,
+        }
+    }
+
+    // Position of the section's contents in an Object
+    pub fn position(&self) -> usize {
+        return self.index() as usize - 1;
+    }
+}
+
+// Names, each ending with a 0 byte, and where each starts
+struct StringTable {
+    m_bytes: Vec<u8>,
+}
+
+impl StringTable {
+    fn new() -> Self {
+        return StringTable { m_bytes: vec![0] };
+    }
+
+    fn add(&mut self, name: &str) -> u32 {
+        let offset = self.m_bytes.len() as u32;
+        self.m_bytes.extend_from_slice(name.as_bytes());
+        self.m_bytes.push(0);
+        return offset;
+    }
+}
+
+fn pad(bytes: &mut Vec<u8>, align: u64) {
+    while bytes.len() as u64 % align != 0 {
+        bytes.push(0);
+    }
+}
+
+struct SectionHeader {
+    m_name: u32,
+    m_type: u32,
+    m_flags: u64,
+    m_offset: u64,
+    m_size: u64,
+    m_link: u32,
+    m_info: u32,
+    m_align: u64,
+    m_entry_size: u64,
+}
+
+impl SectionHeader {
+    fn write(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.m_name.to_le_bytes());
+        bytes.extend_from_slice(&self.m_type.to_le_bytes());
+        bytes.extend_from_slice(&self.m_flags.to_le_bytes());
+        // Not loaded anywhere until linked
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        bytes.extend_from_slice(&self.m_offset.to_le_bytes());
+        bytes.extend_from_slice(&self.m_size.to_le_bytes());
+        bytes.extend_from_slice(&self.m_link.to_le_bytes());
+        bytes.extend_from_slice(&self.m_info.to_le_bytes());
+        bytes.extend_from_slice(&self.m_align.to_le_bytes());
+        bytes.extend_from_slice(&self.m_entry_size.to_le_bytes());
+    }
+}
+
+// Lays out an ELF64 relocatable object for x86-64: the header, then the
+// contents of each section, then the section headers
+pub fn write(object: &Object) -> Vec<u8> {
+    let mut bytes: Vec<u8> = vec![0; HEADER_SIZE as usize];
+    let mut section_names = StringTable::new();
+    let mut headers: Vec<SectionHeader> = vec![SectionHeader {
+        m_name: 0,
+        m_type: 0,
+        m_flags: 0,
+        m_offset: 0,
+        m_size: 0,
+        m_link: 0,
+        m_info: 0,
+        m_align: 0,
+        m_entry_size: 0,
+    }];
+
+    for section in OBJECT_SECTIONS {
+        let contents = &object.m_contents[section.position()];
+        let align = object.m_alignments[section.position()].max(1);
+        pad(&mut bytes, align);
+        let (section_type, flags) = match section {
+            ObjectSection::Text => (SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR),
+            ObjectSection::Data => (SHT_PROGBITS, SHF_ALLOC | SHF_WRITE),
+            ObjectSection::ReadOnly => (SHT_PROGBITS, SHF_ALLOC),
+            ObjectSection::Bss => (SHT_NOBITS, SHF_ALLOC | SHF_WRITE),
+        };
+        headers.push(SectionHeader {
+            m_name: section_names.add(SECTION_NAMES[section.position()]),
+            m_type: section_type,
+            m_flags: flags,
+            m_offset: bytes.len() as u64,
+            m_size: contents.len() as u64,
+            m_link: 0,
+            m_info: 0,
+            m_align: align,
+            m_entry_size: 0,
+        });
+        if section != ObjectSection::Bss {
+            bytes.extend_from_slice(contents);
+        }
+    }
+
+    // Local symbols have to come before global ones, after the null symbol
+    let mut symbols: Vec<&Symbol> =
+        object.m_symbols.iter().filter(|s| !s.m_global).collect();
+    let first_global = symbols.len() as u32 + 1;
+    symbols.extend(object.m_symbols.iter().filter(|s| s.m_global));
+    let mut indexes: HashMap<&str, u64> = HashMap::new();
+    for (i, symbol) in symbols.iter().enumerate() {
+        indexes.insert(symbol.m_name.as_str(), i as u64 + 1);
+    }
+
+    pad(&mut bytes, 8);
+    for (section, name) in RELOCATED_SECTIONS {
+        let relocations_start = bytes.len() as u64;
+        for relocation in &object.m_relocations {
+            if relocation.m_section != section {
+                continue;
+            }
+            let symbol = match indexes.get(relocation.m_symbol.as_str()) {
+                Some(i) => *i,
+                None => {
+                    panic!("Relocation against unknown {}", relocation.m_symbol)
+                }
+            };
+            let kind = match relocation.m_kind {
+                RelocationKind::Pc32 => R_X86_64_PC32,
+                RelocationKind::Plt32 => R_X86_64_PLT32,
+                RelocationKind::Abs64 => R_X86_64_64,
+            };
+            bytes.extend_from_slice(&relocation.m_offset.to_le_bytes());
+            bytes.extend_from_slice(&(symbol << 32 | kind).to_le_bytes());
+            bytes.extend_from_slice(&relocation.m_addend.to_le_bytes());
+        }
+        headers.push(SectionHeader {
+            m_name: section_names.add(name),
+            m_type: SHT_RELA,
+            m_flags: SHF_INFO_LINK,
+            m_offset: relocations_start,
+            m_size: bytes.len() as u64 - relocations_start,
+            m_link: SYMTAB_INDEX,
+            m_info: section.index() as u32,
+            m_align: 8,
+            m_entry_size: RELOCATION_SIZE,
+        });
+    }
+
+    let mut names = StringTable::new();
+    let symbols_start = bytes.len() as u64;
+    bytes.extend_from_slice(&[0; SYMBOL_SIZE as usize]);
+    for symbol in &symbols {
+        let binding = if symbol.m_global { STB_GLOBAL } else { STB_LOCAL };
+        let (symbol_type, section) = match symbol.m_section {
+            Some(ObjectSection::Text) => {
+                (STT_FUNC, ObjectSection::Text.index())
+            }
+            Some(s) => (STT_OBJECT, s.index()),
+            None => (STT_NOTYPE, 0),
+        };
+        bytes.extend_from_slice(&names.add(&symbol.m_name).to_le_bytes());
+        bytes.push(binding << 4 | symbol_type);
+        bytes.push(0);
+        bytes.extend_from_slice(&section.to_le_bytes());
+        bytes.extend_from_slice(&symbol.m_offset.to_le_bytes());
+        // Sizes are left unknown
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+    }
+    headers.push(SectionHeader {
+        m_name: section_names.add(".symtab"),
+        m_type: SHT_SYMTAB,
+        m_flags: 0,
+        m_offset: symbols_start,
+        m_size: bytes.len() as u64 - symbols_start,
+        m_link: STRTAB_INDEX,
+        m_info: first_global,
+        m_align: 8,
+        m_entry_size: SYMBOL_SIZE,
+    });
+
+    headers.push(SectionHeader {
+        m_name: section_names.add(".strtab"),
+        m_type: SHT_STRTAB,
+        m_flags: 0,
+        m_offset: bytes.len() as u64,
+        m_size: names.m_bytes.len() as u64,
+        m_link: 0,
+        m_info: 0,
+        m_align: 1,
+        m_entry_size: 0,
+    });
+    bytes.extend_from_slice(&names.m_bytes);
+
+    // Its name has to be in the table before the table is written. The
+    // empty .note.GNU-stack marks the stack as not executable
+    let shstrtab_name = section_names.add(".shstrtab");
+    let note_name = section_names.add(".note.GNU-stack");
+    headers.push(SectionHeader {
+        m_name: shstrtab_name,
+        m_type: SHT_STRTAB,
+        m_flags: 0,
+        m_offset: bytes.len() as u64,
+        m_size: section_names.m_bytes.len() as u64,
+        m_link: 0,
+        m_info: 0,
+        m_align: 1,
+        m_entry_size: 0,
+    });
+    bytes.extend_from_slice(&section_names.m_bytes);
+    headers.push(SectionHeader {
+        m_name: note_name,
+        m_type: SHT_PROGBITS,
+        m_flags: 0,
+        m_offset: bytes.len() as u64,
+        m_size: 0,
+        m_link: 0,
+        m_info: 0,
+        m_align: 1,
+        m_entry_size: 0,
+    });
+
+    pad(&mut bytes, 8);
+    let section_headers_start = bytes.len() as u64;
+    for header in &headers {
+        header.write(&mut bytes);
+    }
+
+    let mut header: Vec<u8> = Vec::new();
+    // Magic number, 64 bit, little endian, version 1, System V
+    header.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    header.extend_from_slice(&[0; 8]);
+    // Relocatable, x86-64, version 1
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&62u16.to_le_bytes());
+    header.extend_from_slice(&1u32.to_le_bytes());
+    // No entry point or program headers
+    header.extend_from_slice(&0u64.to_le_bytes());
+    header.extend_from_slice(&0u64.to_le_bytes());
+    header.extend_from_slice(&section_headers_start.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes());
+    header.extend_from_slice(&(SECTION_HEADER_SIZE as u16).to_le_bytes());
+    header.extend_from_slice(&SECTION_COUNT.to_le_bytes());
+    header.extend_from_slice(&(SHSTRTAB_INDEX as u16).to_le_bytes());
+    bytes[..HEADER_SIZE as usize].copy_from_slice(&header);
+    return bytes;
+}
+
+#[derive(Debug)]
+pub enum ReadError {
+    NotRelocatable,
+    Truncated,
+    // Something the object uses which the built-in linker doesn't support
+    Unsupported(String),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadError::NotRelocatable => {
+                write!(f, "not an x86-64 ELF relocatable object")
+            }
+            ReadError::Truncated => write!(f, "object file is truncated"),
+            ReadError::Unsupported(what) => {
+                write!(f, "unsupported by the built-in linker: {}", what)
+            }
+        }
+    }
+}
+
+const SHT_REL: u32 = 9;
+const STT_SECTION: u8 = 3;
+const STT_FILE: u8 = 4;
+const SHN_UNDEF: u16 = 0;
+
+fn read_bytes(bytes: &[u8], at: u64, size: u64) -> Result<&[u8], ReadError> {
+    let start = at as usize;
+    let end = start.checked_add(size as usize);
+    match end {
+        Some(end) if end <= bytes.len() => return Ok(&bytes[start..end]),
+        _ => return Err(ReadError::Truncated),
+    }
+}
+
+fn read_u16(bytes: &[u8], at: u64) -> Result<u16, ReadError> {
+    let b = read_bytes(bytes, at, 2)?;
+    return Ok(u16::from_le_bytes([b[0], b[1]]));
+}
+
+fn read_u32(bytes: &[u8], at: u64) -> Result<u32, ReadError> {
+    let b = read_bytes(bytes, at, 4)?;
+    return Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+}
+
+fn read_u64(bytes: &[u8], at: u64) -> Result<u64, ReadError> {
+    let b = read_bytes(bytes, at, 8)?;
+    let mut word = [0; 8];
+    word.copy_from_slice(b);
+    return Ok(u64::from_le_bytes(word));
+}
+
+fn read_name(table: &[u8], at: u32) -> String {
+    let start = (at as usize).min(table.len());
+    let length = match table[start..].iter().position(|b| *b == 0) {
+        Some(l) => l,
+        None => table.len() - start,
+    };
+    return String::from_utf8_lossy(&table[start..start + length]).into_owned();
+}
+
+// Which section of an Object a section of a file is gathered into, going by
+// its name. gcc splits sections up, as in .rodata.str1.1 and .text.startup
+fn gathered_section(name: &str) -> Option<ObjectSection> {
+    for section in OBJECT_SECTIONS {
+        let base = SECTION_NAMES[section.position()];
+        if name == base || name.starts_with(&format!("{}.", base)) {
+            return Some(section);
+        }
+    }
+    return None;
+}
+
+// Reads an ELF64 relocatable object for x86-64, as written by write or by
+// gcc -c, back into an Object. Sections the program doesn't need at run
+// time, like .eh_frame and notes, are left out
+pub fn read(bytes: &[u8]) -> Result<Object, ReadError> {
+    let identity = read_bytes(bytes, 0, 8)?;
+    if identity[..6] != [0x7f, b'E', b'L', b'F', 2, 1]
+        || read_u16(bytes, 16)? != 1
+        || read_u16(bytes, 18)? != 62
+    {
+        return Err(ReadError::NotRelocatable);
+    }
+    let headers_start = read_u64(bytes, 0x28)?;
+    let header_size = read_u16(bytes, 0x3a)? as u64;
+    let count = read_u16(bytes, 0x3c)? as u64;
+    let names_index = read_u16(bytes, 0x3e)? as u64;
+
+    let mut headers: Vec<SectionHeader> = Vec::new();
+    for i in 0..count {
+        let at = headers_start + i * header_size;
+        headers.push(SectionHeader {
+            m_name: read_u32(bytes, at)?,
+            m_type: read_u32(bytes, at + 4)?,
+            m_flags: read_u64(bytes, at + 8)?,
+            m_offset: read_u64(bytes, at + 24)?,
+            m_size: read_u64(bytes, at + 32)?,
+            m_link: read_u32(bytes, at + 40)?,
+            m_info: read_u32(bytes, at + 44)?,
+            m_align: read_u64(bytes, at + 48)?,
+            m_entry_size: read_u64(bytes, at + 56)?,
+        });
+    }
+    let section_names = match headers.get(names_index as usize) {
+        Some(h) => read_bytes(bytes, h.m_offset, h.m_size)?,
+        None => return Err(ReadError::Truncated),
+    };
+
+    let mut object = Object {
+        m_contents: [Vec::new(), Vec::new(), Vec::new(), Vec::new()],
+        m_alignments: [1; 4],
+        m_symbols: Vec::new(),
+        m_relocations: Vec::new(),
+    };
+    // Where each section of the file went: (section, offset of its start)
+    let mut placed: Vec<Option<(ObjectSection, u64)>> = Vec::new();
+    for header in &headers {
+        let name = read_name(section_names, header.m_name);
+        let section = match gathered_section(&name) {
+            Some(s) if header.m_flags & SHF_ALLOC != 0 => s,
+            _ => {
+                if header.m_flags & SHF_ALLOC != 0
+                    && name != ".eh_frame"
+                    && !name.starts_with(".note")
+                {
+                    return Err(ReadError::Unsupported(name));
+                }
+                placed.push(None);
+                continue;
+            }
+        };
+        let position = section.position();
+        let align = header.m_align.max(1);
+        object.m_alignments[position] =
+            object.m_alignments[position].max(align);
+        let contents = &mut object.m_contents[position];
+        pad(contents, align);
+        placed.push(Some((section, contents.len() as u64)));
+        if header.m_type == SHT_NOBITS {
+            contents.resize(contents.len() + header.m_size as usize, 0);
+        } else {
+            contents.extend_from_slice(read_bytes(
+                bytes,
+                header.m_offset,
+                header.m_size,
+            )?);
+        }
+    }
+
+    // Names of the symbols by their index, for the relocations
+    let mut symbol_names: Vec<Option<String>> = Vec::new();
+    for header in headers.iter().filter(|h| h.m_type == SHT_SYMTAB) {
+        let names = match headers.get(header.m_link as usize) {
+            Some(h) => read_bytes(bytes, h.m_offset, h.m_size)?,
+            None => return Err(ReadError::Truncated),
+        };
+        let entries = header.m_size / SYMBOL_SIZE;
+        for i in 0..entries {
+            let at = header.m_offset + i * SYMBOL_SIZE;
+            let info = read_bytes(bytes, at + 4, 1)?[0];
+            let index = read_u16(bytes, at + 6)?;
+            let value = read_u64(bytes, at + 8)?;
+            let symbol_type = info & 0xf;
+            let mut name = read_name(names, read_u32(bytes, at)?);
+            if i == 0 || symbol_type == STT_FILE {
+                symbol_names.push(None);
+                continue;
+            }
+            if symbol_type == STT_SECTION {
+                // Named after their section, which locals can't be
+                let header = match headers.get(index as usize) {
+                    Some(h) => h,
+                    None => return Err(ReadError::Truncated),
+                };
+                name = read_name(section_names, header.m_name);
+            }
+            let section = if index == SHN_UNDEF {
+                None
+            } else {
+                match placed.get(index as usize) {
+                    Some(Some((section, start))) => Some((*section, *start)),
+                    Some(None) if symbol_type == STT_SECTION => {
+                        symbol_names.push(None);
+                        continue;
+                    }
+                    _ => {
+                        return Err(ReadError::Unsupported(format!(
+                            "section of symbol {}",
+                            name
+                        )))
+                    }
+                }
+            };
+            object.m_symbols.push(Symbol {
+                m_name: name.clone(),
+                m_section: section.map(|(s, _)| s),
+                m_offset: match section {
+                    Some((_, start)) => start + value,
+                    None => 0,
+                },
+                m_global: info >> 4 != STB_LOCAL,
+            });
+            symbol_names.push(Some(name));
+        }
+    }
+
+    for header in &headers {
+        if header.m_type != SHT_RELA && header.m_type != SHT_REL {
+            continue;
+        }
+        let (section, start) = match placed.get(header.m_info as usize) {
+            Some(Some((ObjectSection::Bss, _))) | None => {
+                let name = read_name(section_names, header.m_name);
+                return Err(ReadError::Unsupported(name));
+            }
+            Some(Some(place)) => *place,
+            // Relocations in sections which were left out
+            Some(None) => continue,
+        };
+        if header.m_type == SHT_REL {
+            let name = read_name(section_names, header.m_name);
+            return Err(ReadError::Unsupported(name));
+        }
+        let entries = header.m_size / RELOCATION_SIZE;
+        for i in 0..entries {
+            let at = header.m_offset + i * RELOCATION_SIZE;
+            let info = read_u64(bytes, at + 8)?;
+            let kind = match info & 0xffffffff {
+                R_X86_64_64 => RelocationKind::Abs64,
+                R_X86_64_PC32 => RelocationKind::Pc32,
+                R_X86_64_PLT32 => RelocationKind::Plt32,
+                other => {
+                    return Err(ReadError::Unsupported(format!(
+                        "relocation type {}",
+                        other
+                    )))
+                }
+            };
+            let symbol = match symbol_names.get((info >> 32) as usize) {
+                Some(Some(name)) => name.clone(),
+                _ => {
+                    return Err(ReadError::Unsupported(format!(
+                        "relocation against symbol {}",
+                        info >> 32
+                    )))
+                }
+            };
+            object.m_relocations.push(Relocation {
+                m_section: section,
+                m_offset: start + read_u64(bytes, at)?,
+                m_symbol: symbol,
+                m_kind: kind,
+                m_addend: read_u64(bytes, at + 16)? as i64,
+            });
+        }
+    }
+    return Ok(object);
+}
@@ -0,0 +1,792 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::assembly::{
+    Condition, Directive, FloatOp, Instruction, IntegerOp, Line, Operand,
+    Register, Section, Size,
+};
+use crate::elf::{Object, ObjectSection, Relocation, RelocationKind, Symbol};
+
+// Turns assembly into machine code and data, the way the assembler would.
+// Jumps are always given 32 bit displacements, and line information is
+// left out
+struct Encoder {
+    section: ObjectSection,
+    contents: [Vec<u8>; 4],
+    alignments: [u64; 4],
+    // Labels in the order they're defined, with where they are
+    labels: Vec<(String, ObjectSection, u64)>,
+    globals: HashSet<String>,
+    // Offsets in .text of jump displacements and the label each jumps to
+    jumps: Vec<(usize, String)>,
+    relocations: Vec<Relocation>,
+}
+
+// The REX prefix bits and the part of the ModRM byte, SIB byte and
+// displacement which give an operand in a register or memory
+struct RegisterOrMemory {
+    m_rex: u8,
+    m_bytes: Vec<u8>,
+    // Symbol the 32 bit displacement at the end of m_bytes is relative to
+    m_symbol: Option<String>,
+}
+
+fn condition_code(condition: Condition) -> u8 {
+    match condition {
+        Condition::Below => return 0x2,
+        Condition::AboveOrEqual => return 0x3,
+        Condition::Equal => return 0x4,
+        Condition::NotEqual => return 0x5,
+        Condition::BelowOrEqual => return 0x6,
+        Condition::Above => return 0x7,
+        Condition::Sign => return 0x8,
+        Condition::Parity => return 0xa,
+        Condition::NoParity => return 0xb,
+        Condition::Less => return 0xc,
+        Condition::GreaterOrEqual => return 0xd,
+        Condition::LessOrEqual => return 0xe,
+        Condition::Greater => return 0xf,
+    }
+}
+
+fn object_section(section: Section) -> ObjectSection {
+    match section {
+        Section::Text => return ObjectSection::Text,
+        Section::Data => return ObjectSection::Data,
+        Section::ReadOnly => return ObjectSection::ReadOnly,
+        Section::Bss => return ObjectSection::Bss,
+    }
+}
+
+// The low bytes of a value, little endian
+fn immediate(value: i64, bytes: usize) -> Vec<u8> {
+    return value.to_le_bytes()[..bytes].to_vec();
+}
+
+// Bytes of an immediate for an instruction of the size. 64 bit
+// instructions take 32 bits, sign extended
+fn immediate_of(value: i64, size: Size) -> Vec<u8> {
+    match size {
+        Size::Byte => return immediate(value, 1),
+        Size::Word => return immediate(value, 2),
+        _ => return immediate(value, 4),
+    }
+}
+
+// %spl, %bpl, %sil and %dil can only be named with a REX prefix, without
+// which the same numbers are %ah, %ch, %dh and %bh
+fn needs_rex(operand: &Operand, size: Size) -> bool {
+    match operand {
+        Operand::Register(r) => {
+            return size == Size::Byte
+                && !r.is_sse()
+                && (4..8).contains(&r.number())
+        }
+        _ => return false,
+    }
+}
+
+fn fits_byte(value: i64) -> bool {
+    return i8::try_from(value).is_ok();
+}
+
+// Operand size prefix and REX.W for integer instructions of the size
+fn size_prefix(size: Size) -> (Vec<u8>, bool) {
+    match size {
+        Size::Word => return (vec![0x66], false),
+        Size::Quad => return (Vec::new(), true),
+        _ => return (Vec::new(), false),
+    }
+}
+
+// Mandatory prefix of the scalar SSE instructions: F3 for float, F2 for
+// double
+fn sse_prefix(size: Size) -> Vec<u8> {
+    match size {
+        Size::Long => return vec![0xf3],
+        _ => return vec![0xf2],
+    }
+}
+
+fn register(operand: &Operand) -> Register {
+    match operand {
+        Operand::Register(r) => return *r,
+        _ => panic!("{:?} has to be a register", operand),
+    }
+}
+
+fn register_or_memory(operand: &Operand) -> RegisterOrMemory {
+    match operand {
+        Operand::Register(r) => {
+            let number = r.number();
+            return RegisterOrMemory {
+                m_rex: number >> 3,
+                m_bytes: vec![0xc0 | (number & 7)],
+                m_symbol: None,
+            };
+        }
+        Operand::Memory { m_base, m_offset } => {
+            let base = m_base.number();
+            // %rbp and %r13 as a base with no displacement would mean
+            // %rip relative, so they always have one
+            let (mode, displacement) = if *m_offset == 0 && base & 7 != 5 {
+                (0x00, Vec::new())
+            } else if fits_byte(*m_offset as i64) {
+                (0x40, immediate(*m_offset as i64, 1))
+            } else {
+                (0x80, immediate(*m_offset as i64, 4))
+            };
+            let mut bytes = vec![mode | (base & 7)];
+            // %rsp and %r12 need a SIB byte naming them as the base
+            if base & 7 == 4 {
+                bytes.push(0x24);
+            }
+            bytes.extend(displacement);
+            return RegisterOrMemory {
+                m_rex: base >> 3,
+                m_bytes: bytes,
+                m_symbol: None,
+            };
+        }
+        Operand::Symbol(name) => {
+            return RegisterOrMemory {
+                m_rex: 0,
+                m_bytes: vec![0x05, 0, 0, 0, 0],
+                m_symbol: Some(name.clone()),
+            };
+        }
+        Operand::Immediate(_) => {
+            panic!("An immediate can't be a register or memory operand")
+        }
+    }
+}
+
+impl Encoder {
+    fn new() -> Self {
+        return Encoder {
+            section: ObjectSection::Text,
+            contents: [Vec::new(), Vec::new(), Vec::new(), Vec::new()],
+            alignments: [1; 4],
+            labels: Vec::new(),
+            globals: HashSet::new(),
+            jumps: Vec::new(),
+            relocations: Vec::new(),
+        };
+    }
+
+    fn bytes(&mut self) -> &mut Vec<u8> {
+        return &mut self.contents[self.section.position()];
+    }
+
+    fn text(&mut self) -> &mut Vec<u8> {
+        return &mut self.contents[ObjectSection::Text.position()];
+    }
+
+    // Writes an instruction with a ModRM byte: prefixes, REX prefix,
+    // opcode, ModRM and what follows it, then any immediate. `reg` is the
+    // register or opcode extension in the ModRM byte's reg field
+    fn encode(
+        &mut self,
+        prefixes: &[u8],
+        wide: bool,
+        force_rex: bool,
+        opcode: &[u8],
+        reg: u8,
+        operand: &Operand,
+        immediate: &[u8],
+    ) {
+        let operand = register_or_memory(operand);
+        let rex = 0x40 | (wide as u8) << 3 | (reg >> 3) << 2 | operand.m_rex;
+        let text = self.text();
+        text.extend_from_slice(prefixes);
+        if rex != 0x40 || force_rex {
+            text.push(rex);
+        }
+        text.extend_from_slice(opcode);
+        let mut modrm = operand.m_bytes;
+        modrm[0] |= (reg & 7) << 3;
+        text.extend(modrm);
+        let end = text.len() + immediate.len();
+        text.extend_from_slice(immediate);
+        // The displacement is from the end of the instruction
+        match operand.m_symbol {
+            Some(symbol) => {
+                let offset = end - immediate.len() - 4;
+                self.relocations.push(Relocation {
+                    m_section: ObjectSection::Text,
+                    m_offset: offset as u64,
+                    m_symbol: symbol,
+                    m_kind: RelocationKind::Pc32,
+                    m_addend: offset as i64 - end as i64,
+                });
+            }
+            None => (),
+        }
+    }
+
+    // A jump or call taking a 32 bit displacement after the opcode
+    fn encode_jump(&mut self, opcode: &[u8], label: &String) {
+        self.text().extend_from_slice(opcode);
+        let offset = self.text().len();
+        self.text().extend_from_slice(&[0; 4]);
+        self.jumps.push((offset, label.clone()));
+    }
+
+    // add, or, and, sub, xor and cmp share their forms, with opcodes
+    // counting up from `base` and the immediate forms told apart by `digit`
+    fn encode_arithmetic(
+        &mut self,
+        base: u8,
+        digit: u8,
+        size: Size,
+        source: &Operand,
+        destination: &Operand,
+    ) {
+        for operand in [source, destination] {
+            match operand {
+                Operand::Register(r) if r.is_sse() => {
+                    panic!("mov of {:?}, which needs an SSE move", r)
+                }
+                _ => (),
+            }
+        }
+        let (prefixes, wide) = size_prefix(size);
+        let byte = size == Size::Byte;
+        let force_rex = needs_rex(source, size) || needs_rex(destination, size);
+        match (source, destination) {
+            (Operand::Immediate(value), _) => {
+                let (opcode, bytes) = if byte {
+                    (0x80, immediate(*value, 1))
+                } else if fits_byte(*value) {
+                    (0x83, immediate(*value, 1))
+                } else {
+                    (0x81, immediate_of(*value, size))
+                };
+                self.encode(
+                    &prefixes,
+                    wide,
+                    force_rex,
+                    &[opcode],
+                    digit,
+                    destination,
+                    &bytes,
+                );
+            }
+            (Operand::Register(r), _) => {
+                let opcode = if byte { base } else { base + 1 };
+                self.encode(
+                    &prefixes,
+                    wide,
+                    force_rex,
+                    &[opcode],
+                    r.number(),
+                    destination,
+                    &[],
+                );
+            }
+            (_, Operand::Register(r)) => {
+                let opcode = if byte { base + 2 } else { base + 3 };
+                self.encode(
+                    &prefixes,
+                    wide,
+                    force_rex,
+                    &[opcode],
+                    r.number(),
+                    source,
+                    &[],
+                );
+            }
+            _ => panic!("No {:?} from memory to memory", destination),
+        }
+    }
+
+    // neg, not, div and idiv, told apart by `digit`
+    fn encode_unary(&mut self, digit: u8, size: Size, operand: &Operand) {
+        let (prefixes, wide) = size_prefix(size);
+        let opcode = if size == Size::Byte { 0xf6 } else { 0xf7 };
+        let force_rex = needs_rex(operand, size);
+        self.encode(&prefixes, wide, force_rex, &[opcode], digit, operand, &[]);
+    }
+
+    // SSE instructions with the xmm register or integer destination in the
+    // reg field and the source as the other operand
+    fn encode_sse(
+        &mut self,
+        prefixes: &[u8],
+        wide: bool,
+        opcode: u8,
+        source: &Operand,
+        destination: &Operand,
+    ) {
+        self.encode(
+            prefixes,
+            wide,
+            false,
+            &[0x0f, opcode],
+            register(destination).number(),
+            source,
+            &[],
+        );
+    }
+
+    fn encode_mov(
+        &mut self,
+        size: Size,
+        source: &Operand,
+        destination: &Operand,
+    ) {
+        let (prefixes, wide) = size_prefix(size);
+        let byte = size == Size::Byte;
+        let force_rex = needs_rex(source, size) || needs_rex(destination, size);
+        match (source, destination) {
+            // movabsq
+            (Operand::Immediate(value), Operand::Register(r))
+                if size == Size::Quad && i32::try_from(*value).is_err() =>
+            {
+                let number = r.number();
+                let text = self.text();
+                text.push(0x48 | number >> 3);
+                text.push(0xb8 + (number & 7));
+                text.extend(immediate(*value, 8));
+            }
+            (Operand::Immediate(value), _) => {
+                let opcode = if byte { 0xc6 } else { 0xc7 };
+                self.encode(
+                    &prefixes,
+                    wide,
+                    force_rex,
+                    &[opcode],
+                    0,
+                    destination,
+                    &immediate_of(*value, size),
+                );
+            }
+            (Operand::Register(r), _) => {
+                let opcode = if byte { 0x88 } else { 0x89 };
+                self.encode(
+                    &prefixes,
+                    wide,
+                    force_rex,
+                    &[opcode],
+                    r.number(),
+                    destination,
+                    &[],
+                );
+            }
+            (_, Operand::Register(r)) => {
+                let opcode = if byte { 0x8a } else { 0x8b };
+                self.encode(
+                    &prefixes,
+                    wide,
+                    force_rex,
+                    &[opcode],
+                    r.number(),
+                    source,
+                    &[],
+                );
+            }
+            _ => panic!("No move from memory to memory"),
+        }
+    }
+
+    fn encode_instruction(&mut self, instruction: &Instruction) {
+        match instruction {
+            Instruction::Mov(size, source, destination) => {
+                self.encode_mov(*size, source, destination)
+            }
+            Instruction::MovSignExtend(from, to, source, destination)
+            | Instruction::MovZeroExtend(from, to, source, destination) => {
+                let signed = match instruction {
+                    Instruction::MovSignExtend(..) => true,
+                    _ => false,
+                };
+                let opcode: &[u8] = match (from, signed) {
+                    (Size::Byte, true) => &[0x0f, 0xbe],
+                    (Size::Word, true) => &[0x0f, 0xbf],
+                    (_, true) => &[0x63],
+                    (Size::Byte, false) => &[0x0f, 0xb6],
+                    (_, false) => &[0x0f, 0xb7],
+                };
+                let (prefixes, wide) = size_prefix(*to);
+                self.encode(
+                    &prefixes,
+                    wide,
+                    needs_rex(source, *from),
+                    opcode,
+                    register(destination).number(),
+                    source,
+                    &[],
+                );
+            }
+            Instruction::Lea(source, destination) => self.encode(
+                &[],
+                true,
+                false,
+                &[0x8d],
+                register(destination).number(),
+                source,
+                &[],
+            ),
+            Instruction::Push(Operand::Register(r)) => {
+                let number = r.number();
+                if number >= 8 {
+                    self.text().push(0x41);
+                }
+                self.text().push(0x50 + (number & 7));
+            }
+            Instruction::Push(operand) => {
+                self.encode(&[], false, false, &[0xff], 6, operand, &[])
+            }
+            Instruction::Pop(Operand::Register(r)) => {
+                let number = r.number();
+                if number >= 8 {
+                    self.text().push(0x41);
+                }
+                self.text().push(0x58 + (number & 7));
+            }
+            Instruction::Pop(operand) => {
+                self.encode(&[], false, false, &[0x8f], 0, operand, &[])
+            }
+            Instruction::Integer(op, size, source, destination) => {
+                self.encode_integer(*op, *size, source, destination)
+            }
+            Instruction::Negate(size, operand) => {
+                self.encode_unary(3, *size, operand)
+            }
+            Instruction::Not(size, operand) => {
+                self.encode_unary(2, *size, operand)
+            }
+            Instruction::Divide(size, operand) => {
+                self.encode_unary(6, *size, operand)
+            }
+            Instruction::SignedDivide(size, operand) => {
+                self.encode_unary(7, *size, operand)
+            }
+            Instruction::ExtendAccumulator(Size::Quad) => {
+                self.text().extend_from_slice(&[0x48, 0x99])
+            }
+            Instruction::ExtendAccumulator(_) => self.text().push(0x99),
+            Instruction::Set(condition, operand) => self.encode(
+                &[],
+                false,
+                needs_rex(operand, Size::Byte),
+                &[0x0f, 0x90 + condition_code(*condition)],
+                0,
+                operand,
+                &[],
+            ),
+            Instruction::Jump(label) => self.encode_jump(&[0xe9], label),
+            Instruction::JumpIf(condition, label) => self
+                .encode_jump(&[0x0f, 0x80 + condition_code(*condition)], label),
+            // Calls are left to the linker, which may send them through the
+            // procedure linkage table
+            Instruction::Call(name) => {
+                self.text().push(0xe8);
+                let offset = self.text().len();
+                self.text().extend_from_slice(&[0; 4]);
+                self.relocations.push(Relocation {
+                    m_section: ObjectSection::Text,
+                    m_offset: offset as u64,
+                    m_symbol: name.clone(),
+                    m_kind: RelocationKind::Plt32,
+                    m_addend: -4,
+                });
+            }
+            Instruction::Return => self.text().push(0xc3),
+            Instruction::SystemCall => {
+                self.text().extend_from_slice(&[0x0f, 0x05])
+            }
+            Instruction::MovFloat(size, source, destination) => {
+                match destination {
+                    Operand::Register(_) => self.encode_sse(
+                        &sse_prefix(*size),
+                        false,
+                        0x10,
+                        source,
+                        destination,
+                    ),
+                    _ => self.encode(
+                        &sse_prefix(*size),
+                        false,
+                        false,
+                        &[0x0f, 0x11],
+                        register(source).number(),
+                        destination,
+                        &[],
+                    ),
+                }
+            }
+            Instruction::MovBits(size, source, destination) => {
+                let wide = *size == Size::Quad;
+                match destination {
+                    Operand::Register(r) if r.is_sse() => self.encode_sse(
+                        &[0x66],
+                        wide,
+                        0x6e,
+                        source,
+                        destination,
+                    ),
+                    _ => self.encode(
+                        &[0x66],
+                        wide,
+                        false,
+                        &[0x0f, 0x7e],
+                        register(source).number(),
+                        destination,
+                        &[],
+                    ),
+                }
+            }
+            Instruction::Float(op, size, source, destination) => {
+                let opcode = match op {
+                    FloatOp::Add => 0x58,
+                    FloatOp::Multiply => 0x59,
+                    FloatOp::Subtract => 0x5c,
+                    FloatOp::Divide => 0x5e,
+                };
+                self.encode_sse(
+                    &sse_prefix(*size),
+                    false,
+                    opcode,
+                    source,
+                    destination,
+                );
+            }
+            Instruction::CompareFloat(size, source, destination) => {
+                let prefixes: &[u8] =
+                    if *size == Size::Long { &[] } else { &[0x66] };
+                self.encode_sse(prefixes, false, 0x2e, source, destination);
+            }
+            Instruction::IntegerToFloat(size, source, destination) => self
+                .encode_sse(
+                    &sse_prefix(*size),
+                    true,
+                    0x2a,
+                    source,
+                    destination,
+                ),
+            Instruction::FloatToInteger(size, source, destination) => self
+                .encode_sse(
+                    &sse_prefix(*size),
+                    true,
+                    0x2c,
+                    source,
+                    destination,
+                ),
+            Instruction::FloatToFloat(size, source, destination) => self
+                .encode_sse(
+                    &sse_prefix(*size),
+                    false,
+                    0x5a,
+                    source,
+                    destination,
+                ),
+        }
+    }
+
+    fn encode_integer(
+        &mut self,
+        op: IntegerOp,
+        size: Size,
+        source: &Operand,
+        destination: &Operand,
+    ) {
+        let (prefixes, wide) = size_prefix(size);
+        let force_rex = needs_rex(source, size) || needs_rex(destination, size);
+        match op {
+            IntegerOp::Add => {
+                self.encode_arithmetic(0x00, 0, size, source, destination)
+            }
+            IntegerOp::Or => {
+                self.encode_arithmetic(0x08, 1, size, source, destination)
+            }
+            IntegerOp::And => {
+                self.encode_arithmetic(0x20, 4, size, source, destination)
+            }
+            IntegerOp::Subtract => {
+                self.encode_arithmetic(0x28, 5, size, source, destination)
+            }
+            IntegerOp::Xor => {
+                self.encode_arithmetic(0x30, 6, size, source, destination)
+            }
+            IntegerOp::Compare => {
+                self.encode_arithmetic(0x38, 7, size, source, destination)
+            }
+            IntegerOp::Multiply => self.encode(
+                &prefixes,
+                wide,
+                false,
+                &[0x0f, 0xaf],
+                register(destination).number(),
+                source,
+                &[],
+            ),
+            IntegerOp::Test => {
+                let opcode = if size == Size::Byte { 0x84 } else { 0x85 };
+                self.encode(
+                    &prefixes,
+                    wide,
+                    force_rex,
+                    &[opcode],
+                    register(source).number(),
+                    destination,
+                    &[],
+                );
+            }
+            IntegerOp::ShiftRight => {
+                let count = match source {
+                    Operand::Immediate(count) => *count,
+                    _ => panic!("Shifts are by an immediate count"),
+                };
+                let byte = size == Size::Byte;
+                let (opcode, bytes) = match (byte, count) {
+                    (true, 1) => (0xd0, Vec::new()),
+                    (false, 1) => (0xd1, Vec::new()),
+                    (true, _) => (0xc0, immediate(count, 1)),
+                    (false, _) => (0xc1, immediate(count, 1)),
+                };
+                self.encode(
+                    &prefixes,
+                    wide,
+                    force_rex,
+                    &[opcode],
+                    5,
+                    destination,
+                    &bytes,
+                );
+            }
+            IntegerOp::BitComplement => {
+                let bit = match source {
+                    Operand::Immediate(bit) => *bit,
+                    _ => panic!("Bits are numbered by an immediate"),
+                };
+                self.encode(
+                    &prefixes,
+                    wide,
+                    false,
+                    &[0x0f, 0xba],
+                    7,
+                    destination,
+                    &immediate(bit, 1),
+                );
+            }
+        }
+    }
+
+    fn encode_directive(&mut self, directive: &Directive) {
+        match directive {
+            Directive::Global(name) => {
+                self.globals.insert(name.clone());
+            }
+            Directive::Section(section) => {
+                self.section = object_section(*section);
+            }
+            Directive::Align(align) => {
+                let align = *align as u64;
+                let position = self.section.position();
+                self.alignments[position] =
+                    self.alignments[position].max(align);
+                let bytes = self.bytes();
+                while bytes.len() as u64 % align != 0 {
+                    bytes.push(0);
+                }
+            }
+            Directive::Zero(size) => {
+                let size = *size as usize;
+                self.bytes().extend(vec![0; size]);
+            }
+            Directive::Value(size, value) => {
+                let bytes = match size {
+                    Size::Byte => immediate(*value, 1),
+                    Size::Word => immediate(*value, 2),
+                    Size::Long => immediate(*value, 4),
+                    Size::Quad => immediate(*value, 8),
+                };
+                self.bytes().extend(bytes);
+            }
+            Directive::Address(symbol, offset) => {
+                let place = self.bytes().len();
+                self.bytes().extend_from_slice(&[0; 8]);
+                self.relocations.push(Relocation {
+                    m_section: self.section,
+                    m_offset: place as u64,
+                    m_symbol: symbol.clone(),
+                    m_kind: RelocationKind::Abs64,
+                    m_addend: *offset,
+                });
+            }
+            Directive::File(..) | Directive::Location(..) => (),
+        }
+    }
+
+    // Fills in the jumps to labels in .text and collects the symbols:
+    // every label but the assembler's local .L ones, and the names used but
+    // not defined here
+    fn finish(mut self) -> Object {
+        let mut defined: HashMap<String, (ObjectSection, u64)> = HashMap::new();
+        for (name, section, offset) in &self.labels {
+            defined.insert(name.clone(), (*section, *offset));
+        }
+        let text = ObjectSection::Text.position();
+        for (offset, label) in &self.jumps {
+            let target = match defined.get(label) {
+                Some((ObjectSection::Text, target)) => *target as i64,
+                _ => panic!("Jump to {}, which isn't in .text", label),
+            };
+            let displacement = target - (*offset as i64 + 4);
+            self.contents[text][*offset..*offset + 4]
+                .copy_from_slice(&immediate(displacement, 4));
+        }
+
+        let mut symbols: Vec<Symbol> = Vec::new();
+        for (name, section, offset) in &self.labels {
+            if name.starts_with(".L") {
+                continue;
+            }
+            symbols.push(Symbol {
+                m_name: name.clone(),
+                m_section: Some(*section),
+                m_offset: *offset,
+                m_global: self.globals.contains(name),
+            });
+        }
+        let mut undefined: HashSet<&String> = HashSet::new();
+        for relocation in &self.relocations {
+            if !defined.contains_key(&relocation.m_symbol)
+                && undefined.insert(&relocation.m_symbol)
+            {
+                symbols.push(Symbol {
+                    m_name: relocation.m_symbol.clone(),
+                    m_section: None,
+                    m_offset: 0,
+                    m_global: true,
+                });
+            }
+        }
+        return Object {
+            m_contents: self.contents,
+            m_alignments: self.alignments,
+            m_symbols: symbols,
+            m_relocations: self.relocations,
+        };
+    }
+}
+
+pub fn encode(lines: &Vec<Line>) -> Object {
+    let mut encoder = Encoder::new();
+    for line in lines {
+        match line {
+            Line::Label(name) => {
+                let offset = encoder.bytes().len() as u64;
+                encoder.labels.push((name.clone(), encoder.section, offset));
+            }
+            Line::Directive(directive) => encoder.encode_directive(directive),
+            Line::Instruction(instruction) => {
+                if encoder.section != ObjectSection::Text {
+                    panic!("Instruction outside .text: {:?}", instruction);
+                }
+                encoder.encode_instruction(instruction);
+            }
+        }
+    }
+    return encoder.finish();
+}
@@ -0,0 +1,677 @@
+use std::cmp::Ordering;
+
+use crate::parser::{
+    AdditiveExpression, AdditiveOperator, ConditionalExpression,
+    EqualityExpression, EqualityOperator, Expression, Factor,
+    LogicalAndExpression, LogicalOrExpresson, MultiplicativeOperator,
+    RelationalExpression, RelationalOperator, Term, UnaryOperator, VarType,
+};
+use crate::types::{self, TypeContext};
+
+// Value of a constant expression. Integer values are kept as the bits of
+// their type, sign or zero extended to 64 bits
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Int(i64),
+    Floating(f64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvaluationError {
+    // Part of the expression is only known at run time
+    NotConstant,
+    // Integer division or remainder by zero, which traps at run time
+    DivisionByZero,
+}
+
+// A constant expression worked out at compile time. Signed arithmetic which
+// overflows wraps around the way it would on x86-64, and is noted so that it
+// can be warned about
+#[derive(Debug)]
+pub struct Evaluation {
+    pub m_value: Constant,
+    pub m_overflow: bool,
+}
+
+#[derive(Clone, Copy)]
+enum Arithmetic {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+struct Evaluator<'a> {
+    ctx: &'a dyn TypeContext,
+    overflow: bool,
+    division_by_zero: bool,
+}
+
+pub fn evaluate(
+    ctx: &dyn TypeContext,
+    expression: &Expression,
+) -> Result<Evaluation, EvaluationError> {
+    let mut evaluator =
+        Evaluator { ctx, overflow: false, division_by_zero: false };
+    match evaluator.expression(expression) {
+        Ok(v) => {
+            return Ok(Evaluation {
+                m_value: v,
+                m_overflow: evaluator.overflow,
+            })
+        }
+        Err(e) => return Err(e),
+    }
+}
+
+// Warnings about arithmetic on constants anywhere in the expression, even
+// when the expression as a whole isn't constant. Operands which are never
+// evaluated aren't warned about
+pub fn warnings(
+    ctx: &dyn TypeContext,
+    expression: &Expression,
+) -> Vec<&'static str> {
+    let mut evaluator =
+        Evaluator { ctx, overflow: false, division_by_zero: false };
+    let _ = evaluator.expression(expression);
+    let mut warnings = Vec::new();
+    if evaluator.division_by_zero {
+        warnings.push("division by zero");
+    }
+    if evaluator.overflow {
+        warnings.push("integer overflow in expression");
+    }
+    return warnings;
+}
+
+pub fn constant_value(
+    ctx: &dyn TypeContext,
+    expression: &Expression,
+) -> Option<Constant> {
+    match evaluate(ctx, expression) {
+        Ok(evaluation) => return Some(evaluation.m_value),
+        Err(_) => return None,
+    }
+}
+
+// The address of a variable with static storage, converted to any pointer
+// type and moved by a constant number of elements, which is known once the
+// program is linked. The offset is in bytes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Address {
+    pub m_variable: String,
+    pub m_offset: i64,
+}
+
+pub fn address_constant(
+    ctx: &dyn TypeContext,
+    expression: &Expression,
+) -> Option<Address> {
+    let additive = match types::single_additive(expression) {
+        Some(a) => a,
+        None => return None,
+    };
+    let mut evaluator =
+        Evaluator { ctx, overflow: false, division_by_zero: false };
+    let mut address: Option<(Address, VarType)> = None;
+    let mut elements: i64 = 0;
+    let terms =
+        std::iter::once((&AdditiveOperator::Addition, &*additive.m_first_term))
+            .chain(additive.m_rest.iter().map(|(o, t)| (o, t)));
+    for (operator, term) in terms {
+        let term_type = types::term_type(ctx, term);
+        // Only one address, which can't be subtracted
+        if term_type.is_pointer() {
+            match (&address, operator, term.m_rest.len()) {
+                (None, AdditiveOperator::Addition, 0) => (),
+                _ => return None,
+            }
+            match address_factor(ctx, &term.m_first_factor) {
+                Some(a) => address = Some((a, term_type)),
+                None => return None,
+            }
+            continue;
+        }
+        let value = match evaluator.term(term) {
+            Ok(Constant::Int(v)) => v,
+            _ => return None,
+        };
+        match operator {
+            AdditiveOperator::Addition => {
+                elements = elements.wrapping_add(value)
+            }
+            AdditiveOperator::Minus => elements = elements.wrapping_sub(value),
+        }
+    }
+    match address {
+        Some((a, VarType::Pointer(t, _))) => {
+            let step = elements.wrapping_mul(types::size_of(&t));
+            return Some(Address {
+                m_variable: a.m_variable,
+                m_offset: a.m_offset.wrapping_add(step),
+            });
+        }
+        _ => return None,
+    }
+}
+
+fn address_factor(ctx: &dyn TypeContext, factor: &Factor) -> Option<Address> {
+    match factor {
+        Factor::UnaryOperation {
+            m_opertator: UnaryOperator::AddressOf,
+            m_factor,
+        } => {
+            let inner = match &**m_factor {
+                Factor::Braced { m_expression } => {
+                    types::single_factor(m_expression)
+                }
+                f => Some(f),
+            };
+            match inner {
+                Some(Factor::Variable { m_var, m_span: _ }) => {
+                    return Some(Address {
+                        m_variable: m_var.clone(),
+                        m_offset: 0,
+                    })
+                }
+                _ => return None,
+            }
+        }
+        Factor::Braced { m_expression } => {
+            return address_constant(ctx, m_expression)
+        }
+        Factor::Cast { m_type: VarType::Pointer(..), m_factor } => {
+            match types::factor_type(ctx, m_factor).is_pointer() {
+                true => return address_factor(ctx, m_factor),
+                false => return None,
+            }
+        }
+        _ => return None,
+    }
+}
+
+pub fn constant_factor(
+    ctx: &dyn TypeContext,
+    factor: &Factor,
+) -> Option<Constant> {
+    let mut evaluator =
+        Evaluator { ctx, overflow: false, division_by_zero: false };
+    match evaluator.factor(factor) {
+        Ok(v) => return Some(v),
+        Err(_) => return None,
+    }
+}
+
+fn truth(value: &Constant) -> bool {
+    match value {
+        Constant::Int(v) => return *v != 0,
+        Constant::Floating(v) => return *v != 0.0,
+    }
+}
+
+// Both operands of a binary operator, which are evaluated even when the
+// first isn't constant so that overflow in the second is still noticed
+fn operands(
+    left: Result<Constant, EvaluationError>,
+    right: Result<Constant, EvaluationError>,
+) -> Result<(Constant, Constant), EvaluationError> {
+    match (left, right) {
+        (Ok(l), Ok(r)) => return Ok((l, r)),
+        (Err(e), _) | (_, Err(e)) => return Err(e),
+    }
+}
+
+// Orders two values after the usual arithmetic conversions, or None if one
+// of them is NaN
+fn compare(
+    left: &Constant,
+    left_type: &VarType,
+    right: &Constant,
+    right_type: &VarType,
+) -> Option<Ordering> {
+    let common = types::common_type(left_type, right_type);
+    let left = convert_constant(left, left_type, &common);
+    let right = convert_constant(right, right_type, &common);
+    match (left, right) {
+        (Constant::Floating(a), Constant::Floating(b)) => {
+            return a.partial_cmp(&b)
+        }
+        (Constant::Int(a), Constant::Int(b)) if common.is_pointer() => {
+            return Some((a as u64).cmp(&(b as u64)))
+        }
+        (Constant::Int(a), Constant::Int(b)) if common.is_unsigned() => {
+            return Some((a as u64).cmp(&(b as u64)))
+        }
+        (Constant::Int(a), Constant::Int(b)) => return Some(a.cmp(&b)),
+        _ => return None,
+    }
+}
+
+impl<'a> Evaluator<'a> {
+    fn expression(
+        &mut self,
+        expression: &Expression,
+    ) -> Result<Constant, EvaluationError> {
+        match expression {
+            Expression::Assignment { m_target: _, m_value: _, m_span: _ } => {
+                return Err(EvaluationError::NotConstant)
+            }
+            Expression::Operation(c) => return self.conditional(c),
+        }
+    }
+
+    fn conditional(
+        &mut self,
+        conditional_expression: &ConditionalExpression,
+    ) -> Result<Constant, EvaluationError> {
+        let condition =
+            match self.logical_or(&conditional_expression.m_condition) {
+                Ok(v) => v,
+                Err(e) => return Err(e),
+            };
+        let (true_expression, false_expression) = match (
+            &conditional_expression.m_true,
+            &conditional_expression.m_false,
+        ) {
+            (Some(t), Some(f)) => (t, f),
+            _ => return Ok(condition),
+        };
+        let result_type =
+            types::conditional_type(self.ctx, conditional_expression);
+        // Only the branch which is taken is evaluated
+        let (value, value_type) = if truth(&condition) {
+            (
+                self.expression(true_expression),
+                types::expression_type(self.ctx, true_expression),
+            )
+        } else {
+            (
+                self.conditional(false_expression),
+                types::conditional_type(self.ctx, false_expression),
+            )
+        };
+        match value {
+            Ok(v) => {
+                return Ok(convert_constant(&v, &value_type, &result_type))
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    fn logical_or(
+        &mut self,
+        logical_or_expression: &LogicalOrExpresson,
+    ) -> Result<Constant, EvaluationError> {
+        let first = match self.logical_and(&logical_or_expression.m_first) {
+            Ok(v) => v,
+            Err(e) => return Err(e),
+        };
+        if logical_or_expression.m_rest.len() == 0 {
+            return Ok(first);
+        }
+        if truth(&first) {
+            return Ok(Constant::Int(1));
+        }
+        for next in &logical_or_expression.m_rest {
+            match self.logical_and(next) {
+                Ok(v) if truth(&v) => return Ok(Constant::Int(1)),
+                Ok(_) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        return Ok(Constant::Int(0));
+    }
+
+    fn logical_and(
+        &mut self,
+        logical_and_expression: &LogicalAndExpression,
+    ) -> Result<Constant, EvaluationError> {
+        let first = match self.equality(&logical_and_expression.m_first) {
+            Ok(v) => v,
+            Err(e) => return Err(e),
+        };
+        if logical_and_expression.m_rest.len() == 0 {
+            return Ok(first);
+        }
+        if !truth(&first) {
+            return Ok(Constant::Int(0));
+        }
+        for next in &logical_and_expression.m_rest {
+            match self.equality(next) {
+                Ok(v) if !truth(&v) => return Ok(Constant::Int(0)),
+                Ok(_) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        return Ok(Constant::Int(1));
+    }
+
+    fn equality(
+        &mut self,
+        equality_expression: &EqualityExpression,
+    ) -> Result<Constant, EvaluationError> {
+        let mut value = self.relational(&equality_expression.m_first);
+        let mut value_type =
+            types::relational_type(self.ctx, &equality_expression.m_first);
+        for (operator, next) in &equality_expression.m_rest {
+            let right = self.relational(next);
+            let right_type = types::relational_type(self.ctx, next);
+            value = match operands(value, right) {
+                Ok((l, r)) => {
+                    let equal = compare(&l, &value_type, &r, &right_type)
+                        == Some(Ordering::Equal);
+                    match operator {
+                        EqualityOperator::Equal => {
+                            Ok(Constant::Int(equal as i64))
+                        }
+                        EqualityOperator::NotEqual => {
+                            Ok(Constant::Int(!equal as i64))
+                        }
+                    }
+                }
+                Err(e) => Err(e),
+            };
+            value_type = VarType::Int;
+        }
+        return value;
+    }
+
+    fn relational(
+        &mut self,
+        relational_expression: &RelationalExpression,
+    ) -> Result<Constant, EvaluationError> {
+        let mut value = self.additive(&relational_expression.m_first);
+        let mut value_type =
+            types::additive_type(self.ctx, &relational_expression.m_first);
+        for (operator, next) in &relational_expression.m_rest {
+            let right = self.additive(next);
+            let right_type = types::additive_type(self.ctx, next);
+            value = match operands(value, right) {
+                Ok((l, r)) => {
+                    let order = compare(&l, &value_type, &r, &right_type);
+                    let result = match (operator, order) {
+                        (_, None) => false,
+                        (RelationalOperator::Less, Some(o)) => o.is_lt(),
+                        (RelationalOperator::LessOrEqual, Some(o)) => o.is_le(),
+                        (RelationalOperator::Greater, Some(o)) => o.is_gt(),
+                        (RelationalOperator::GreaterOrEqual, Some(o)) => {
+                            o.is_ge()
+                        }
+                    };
+                    Ok(Constant::Int(result as i64))
+                }
+                Err(e) => Err(e),
+            };
+            value_type = VarType::Int;
+        }
+        return value;
+    }
+
+    fn additive(
+        &mut self,
+        additive_expression: &AdditiveExpression,
+    ) -> Result<Constant, EvaluationError> {
+        let mut value = self.term(&additive_expression.m_first_term);
+        let mut value_type =
+            types::term_type(self.ctx, &additive_expression.m_first_term);
+        for (operator, next) in &additive_expression.m_rest {
+            let right = self.term(next);
+            let right_type = types::term_type(self.ctx, next);
+            let operation = match operator {
+                AdditiveOperator::Addition => Arithmetic::Add,
+                AdditiveOperator::Minus => Arithmetic::Subtract,
+            };
+            value = match operands(value, right) {
+                // Addresses aren't known until link time
+                Ok(_) if value_type.is_pointer() || right_type.is_pointer() => {
+                    Err(EvaluationError::NotConstant)
+                }
+                Ok((l, r)) => {
+                    self.arithmetic(operation, &l, &value_type, &r, &right_type)
+                }
+                Err(e) => Err(e),
+            };
+            value_type = types::common_type(&value_type, &right_type);
+        }
+        return value;
+    }
+
+    fn term(&mut self, term: &Term) -> Result<Constant, EvaluationError> {
+        let mut value = self.factor(&term.m_first_factor);
+        let mut value_type = types::factor_type(self.ctx, &term.m_first_factor);
+        for (operator, next) in &term.m_rest {
+            let right = self.factor(next);
+            let right_type = types::factor_type(self.ctx, next);
+            let operation = match operator {
+                MultiplicativeOperator::Multiplication => Arithmetic::Multiply,
+                MultiplicativeOperator::Division => Arithmetic::Divide,
+                MultiplicativeOperator::Modulo => Arithmetic::Remainder,
+            };
+            // Noted even when the dividend isn't constant
+            match (operation, &right) {
+                (Arithmetic::Divide, Ok(Constant::Int(0)))
+                | (Arithmetic::Remainder, Ok(Constant::Int(0)))
+                    if types::common_type(&value_type, &right_type)
+                        .is_integer() =>
+                {
+                    self.division_by_zero = true;
+                }
+                _ => (),
+            }
+            value = match operands(value, right) {
+                Ok((l, r)) => {
+                    self.arithmetic(operation, &l, &value_type, &r, &right_type)
+                }
+                Err(e) => Err(e),
+            };
+            value_type = types::common_type(&value_type, &right_type);
+        }
+        return value;
+    }
+
+    // A binary arithmetic operator applied after the usual arithmetic
+    // conversions
+    fn arithmetic(
+        &mut self,
+        operation: Arithmetic,
+        left: &Constant,
+        left_type: &VarType,
+        right: &Constant,
+        right_type: &VarType,
+    ) -> Result<Constant, EvaluationError> {
+        let result_type = types::common_type(left_type, right_type);
+        let left = convert_constant(left, left_type, &result_type);
+        let right = convert_constant(right, right_type, &result_type);
+        match (left, right) {
+            (Constant::Floating(a), Constant::Floating(b)) => {
+                let value = match operation {
+                    Arithmetic::Add => a + b,
+                    Arithmetic::Subtract => a - b,
+                    Arithmetic::Multiply => a * b,
+                    Arithmetic::Divide => a / b,
+                    Arithmetic::Remainder => {
+                        return Err(EvaluationError::NotConstant)
+                    }
+                };
+                // A float result is rounded back to float
+                return Ok(convert_constant(
+                    &Constant::Floating(value),
+                    &result_type,
+                    &result_type,
+                ));
+            }
+            (Constant::Int(a), Constant::Int(b)) => {
+                match operation {
+                    Arithmetic::Divide | Arithmetic::Remainder if b == 0 => {
+                        return Err(EvaluationError::DivisionByZero)
+                    }
+                    _ => (),
+                }
+                if result_type.is_unsigned() {
+                    let (a, b) = (a as u64, b as u64);
+                    let value = match operation {
+                        Arithmetic::Add => a.wrapping_add(b),
+                        Arithmetic::Subtract => a.wrapping_sub(b),
+                        Arithmetic::Multiply => a.wrapping_mul(b),
+                        Arithmetic::Divide => a / b,
+                        Arithmetic::Remainder => a % b,
+                    };
+                    return Ok(convert_constant(
+                        &Constant::Int(value as i64),
+                        &result_type,
+                        &result_type,
+                    ));
+                }
+                // Wide enough that no operation on two longs overflows
+                let (a, b) = (a as i128, b as i128);
+                let value = match operation {
+                    Arithmetic::Add => a + b,
+                    Arithmetic::Subtract => a - b,
+                    Arithmetic::Multiply => a * b,
+                    Arithmetic::Divide => a / b,
+                    Arithmetic::Remainder => a % b,
+                };
+                return Ok(self.wrap(value, &result_type));
+            }
+            _ => return Err(EvaluationError::NotConstant),
+        }
+    }
+
+    // Truncates a signed result to its type, noting if it didn't fit
+    fn wrap(&mut self, value: i128, var_type: &VarType) -> Constant {
+        let wrapped =
+            convert_constant(&Constant::Int(value as i64), var_type, var_type);
+        if wrapped != Constant::Int(value as i64)
+            || value as i64 as i128 != value
+        {
+            self.overflow = true;
+        }
+        return wrapped;
+    }
+
+    fn factor(&mut self, factor: &Factor) -> Result<Constant, EvaluationError> {
+        match factor {
+            Factor::Constant { m_value, m_type: _ } => {
+                return Ok(Constant::Int(*m_value))
+            }
+            Factor::FloatConstant { m_value, m_type: _ } => {
+                return Ok(Constant::Floating(*m_value))
+            }
+            Factor::UnaryOperation { m_opertator, m_factor } => {
+                let operand_type = types::factor_type(self.ctx, m_factor);
+                let result_type = types::promote(&operand_type);
+                let value = match self.factor(m_factor) {
+                    Ok(v) => convert_constant(&v, &operand_type, &result_type),
+                    Err(e) => return Err(e),
+                };
+                match (m_opertator, value) {
+                    (UnaryOperator::Minus, Constant::Int(v))
+                        if result_type.is_unsigned() =>
+                    {
+                        return Ok(convert_constant(
+                            &Constant::Int(v.wrapping_neg()),
+                            &result_type,
+                            &result_type,
+                        ))
+                    }
+                    (UnaryOperator::Minus, Constant::Int(v)) => {
+                        return Ok(self.wrap(-(v as i128), &result_type))
+                    }
+                    (UnaryOperator::Minus, Constant::Floating(v)) => {
+                        return Ok(Constant::Floating(-v))
+                    }
+                    (UnaryOperator::Complement, Constant::Int(v)) => {
+                        return Ok(convert_constant(
+                            &Constant::Int(!v),
+                            &result_type,
+                            &result_type,
+                        ))
+                    }
+                    (UnaryOperator::Complement, Constant::Floating(_)) => {
+                        return Err(EvaluationError::NotConstant)
+                    }
+                    (UnaryOperator::Negation, v) => {
+                        return Ok(Constant::Int(!truth(&v) as i64))
+                    }
+                    (UnaryOperator::Dereference, _)
+                    | (UnaryOperator::AddressOf, _) => {
+                        return Err(EvaluationError::NotConstant)
+                    }
+                }
+            }
+            Factor::Braced { m_expression } => {
+                return self.expression(m_expression)
+            }
+            Factor::Cast { m_type, m_factor } => {
+                // Only arithmetic values and null pointers are known at
+                // compile time
+                match self.factor(m_factor) {
+                    Ok(v) => {
+                        let from = types::factor_type(self.ctx, m_factor);
+                        if m_type.is_pointer() && v != Constant::Int(0) {
+                            return Err(EvaluationError::NotConstant);
+                        }
+                        return Ok(convert_constant(&v, &from, m_type));
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            Factor::SizeOfType { m_type } => {
+                return Ok(Constant::Int(types::size_of(m_type)))
+            }
+            // The operand is never evaluated
+            Factor::SizeOfExpression { m_factor } => {
+                let operand_type = types::factor_type(self.ctx, m_factor);
+                return Ok(Constant::Int(types::size_of(&operand_type)));
+            }
+            Factor::AlignOf { m_type } => {
+                return Ok(Constant::Int(types::align_of(m_type)))
+            }
+            Factor::FunCall { m_id: _, m_arguments: _, m_span: _ }
+            | Factor::Variable { m_var: _, m_span: _ }
+            | Factor::VaStart { m_list: _, m_last: _ }
+            | Factor::VaArg { m_list: _, m_type: _ }
+            | Factor::VaEnd { m_list: _ } => {
+                return Err(EvaluationError::NotConstant)
+            }
+        }
+    }
+}
+
+// Converts a constant of type from to type to, the way a cast would at run
+// time. Integers are truncated to the width of the new type and then sign or
+// zero extended, floating values are truncated towards zero
+pub fn convert_constant(
+    value: &Constant,
+    from: &VarType,
+    to: &VarType,
+) -> Constant {
+    if to.is_floating() {
+        let v = match value {
+            Constant::Int(i) if from.is_unsigned() => *i as u64 as f64,
+            Constant::Int(i) => *i as f64,
+            Constant::Floating(f) => *f,
+        };
+        if *to == VarType::Float {
+            return Constant::Floating(v as f32 as f64);
+        }
+        return Constant::Floating(v);
+    }
+
+    let bits = match value {
+        Constant::Int(i) => *i,
+        Constant::Floating(f) if *to == VarType::ULong => *f as u64 as i64,
+        Constant::Floating(f) => *f as i64,
+    };
+    let value = match (types::size_of(to), to.is_unsigned()) {
+        (1, false) => bits as i8 as i64,
+        (1, true) => bits as u8 as i64,
+        (2, false) => bits as i16 as i64,
+        (2, true) => bits as u16 as i64,
+        (4, false) => bits as i32 as i64,
+        (4, true) => bits as u32 as i64,
+        _ => bits,
+    };
+    return Constant::Int(value);
+}
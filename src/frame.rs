@@ -0,0 +1,65 @@
+use std::collections::BTreeSet;
+
+use crate::allocator::Allocation;
+use crate::assembly::Register;
+use crate::ir;
+
+// Where the slots and temporaries of a function live in its stack frame,
+// worked out before any code is generated so the prologue can reserve the
+// whole frame at once
+pub struct FrameLayout {
+    // %rbp offset of each slot and of each temporary which isn't kept in a
+    // register
+    pub m_slots: Vec<i32>,
+    pub m_temps: Vec<Option<i32>>,
+    // Where each register the function uses is saved: callee saved ones
+    // for the whole function, caller saved ones around calls
+    pub m_saves: Vec<(Register, i32)>,
+    // Bytes reserved below %rbp, a multiple of 16 so calls stay aligned
+    pub m_size: i32,
+}
+
+// Bytes below %rbp after placing size bytes at the given alignment under
+// depth bytes already used
+fn allocate(depth: i32, size: i32, align: i32) -> i32 {
+    return (depth + size + align - 1) / align * align;
+}
+
+// Lays out the frame of a function. `reserved` bytes directly below %rbp are
+// left alone. Spilled temporaries and saved registers each get 8 bytes,
+// whatever their type
+pub fn layout_function(
+    function: &ir::Function,
+    allocation: &Allocation,
+    reserved: i32,
+) -> FrameLayout {
+    let mut depth = reserved;
+    let mut slots: Vec<i32> = Vec::new();
+    for slot in &function.m_slots {
+        depth = allocate(depth, slot.m_size, slot.m_align);
+        slots.push(-depth);
+    }
+    let mut temps: Vec<Option<i32>> = Vec::new();
+    for register in &allocation.m_registers {
+        match register {
+            Some(_) => temps.push(None),
+            None => {
+                depth = allocate(depth, 8, 8);
+                temps.push(Some(-depth));
+            }
+        }
+    }
+    let used: BTreeSet<Register> =
+        allocation.m_registers.iter().filter_map(|r| *r).collect();
+    let mut saves: Vec<(Register, i32)> = Vec::new();
+    for register in used {
+        depth = allocate(depth, 8, 8);
+        saves.push((register, -depth));
+    }
+    return FrameLayout {
+        m_slots: slots,
+        m_temps: temps,
+        m_saves: saves,
+        m_size: (depth + 15) / 16 * 16,
+    };
+}
@@ -1,870 +1,1086 @@
-use std::os::linux::raw::stat;
-use std::panic::Location;
-use std::{collections::HashMap, task::Context};
-
-use crate::parser::{
-    AdditiveExpression, AdditiveOperator, BlockItem, ConditionalExpression,
-    Declaration, EqualityExpression, EqualityOperator, Expression, Factor,
-    Function, LogicalAndExpression, LogicalOrExpresson, MultiplicativeOperator,
-    Program, RelationalExpression, RelationalOperator, Statement, Term,
-    UnaryOperator,
+use std::collections::HashMap;
+
+use crate::allocator::{self, Allocation};
+use crate::assembly::{
+    Condition, Directive, FloatOp, Instruction, IntegerOp, Line, Operand,
+    Register, Section, Size,
+};
+use crate::frame;
+use crate::ir::{
+    self, BinaryOp, CompareOp, ConvertOp, StaticValue, Temp, Terminator, Type,
+    UnaryOp,
 };
+use crate::token::Span;
+
+const INT_ARGUMENT_REGISTERS: [Register; 6] = [
+    Register::Rdi,
+    Register::Rsi,
+    Register::Rdx,
+    Register::Rcx,
+    Register::R8,
+    Register::R9,
+];
+const SSE_ARGUMENT_REGISTERS: usize = 8;
+
+// The registers instructions work in
+const RAX: Operand = Operand::Register(Register::Rax);
+const RCX: Operand = Operand::Register(Register::Rcx);
+const RDX: Operand = Operand::Register(Register::Rdx);
+const RSP: Operand = Operand::Register(Register::Rsp);
+const RBP: Operand = Operand::Register(Register::Rbp);
+const XMM0: Operand = Operand::Register(Register::Xmm(0));
+const XMM1: Operand = Operand::Register(Register::Xmm(1));
+
+// A variadic function saves the argument registers in its frame, where
+// va_arg can find them: 6 integer registers of 8 bytes then 8 SSE registers
+// of 16 bytes. Below that is the va_list state, {gp_offset, fp_offset,
+// overflow_arg_area, reg_save_area}, which va_start fills in
+const REGISTER_SAVE_AREA: i32 = -176;
+const VA_LIST_STATE: i32 = -200;
+const VARIADIC_FRAME_SIZE: i32 = 200;
+
+// Generates x86-64 assembly from the IR. Temporaries are kept in registers
+// where the allocator finds room for them and in the frame otherwise;
+// instructions load their operands into %rax / %xmm0 and store the result
+// back to where their destination is kept
+pub struct Generator {
+    lines: Vec<Line>,
+    file_numbers: HashMap<String, usize>,
+    // The last .loc of the current function, which isn't repeated
+    last_location: Option<(usize, usize, usize)>,
+    function_name: String,
+    // Labels made so far for jumps within the current function's
+    // instructions
+    local_labels: usize,
+    // Types of the current function's temporaries, the registers they are
+    // kept in and where the rest of them and its slots live in the frame
+    temps: Vec<Type>,
+    allocation: Allocation,
+    layout: frame::FrameLayout,
+    // For a variadic function, the initial gp_offset and fp_offset of its
+    // va_list and where the arguments passed on the stack continue
+    va_start_state: Option<(i32, i32, i32)>,
+}
 
-static mut LABEL_NUMBER: i32 = 0;
+// Whether each argument of a call with the given types is passed in a
+// register, integers in the first six and floating values in the first eight
+// SSE registers
+fn argument_registers(arg_types: &Vec<Type>) -> Vec<bool> {
+    let mut in_register: Vec<bool> = Vec::new();
+    let mut int_count = 0;
+    let mut sse_count = 0;
+    for arg_type in arg_types {
+        if arg_type.is_floating() {
+            in_register.push(sse_count < SSE_ARGUMENT_REGISTERS);
+            sse_count += 1;
+        } else {
+            in_register.push(int_count < INT_ARGUMENT_REGISTERS.len());
+            int_count += 1;
+        }
+    }
+    return in_register;
+}
 
-#[derive(Debug, Clone)]
-pub struct LoopContext {
-    start_label: String,
-    end_label: String,
-    scope_count: usize,
+// Operand for the bytes at an offset from %rbp
+fn frame(offset: i32) -> Operand {
+    return Operand::Memory { m_base: Register::Rbp, m_offset: offset };
 }
 
-pub struct Generator {
-    label_number: i32,
-    context: Vec<HashMap<String, i32>>,
-    manual_vars_size: Vec<i32>,
-    stack_index: i32,
-    returned: bool,
-    loop_contexts: Vec<LoopContext>,
+// Saving or restoring all 8 bytes of a caller saved register around a call.
+// SSE registers need an SSE move, as mov would take the general purpose
+// register with the same number
+fn save_move(register: Register, from: Operand, to: Operand) -> Instruction {
+    if register.is_sse() {
+        return Instruction::MovFloat(Size::Quad, from, to);
+    }
+    return Instruction::Mov(Size::Quad, from, to);
+}
+
+fn size(value_type: &Type) -> Size {
+    return Size::of(value_type.size());
+}
+
+// Storage for a variable with static storage duration
+fn static_data(data: &ir::Data) -> Vec<Line> {
+    let mut lines: Vec<Line> = Vec::new();
+    if data.m_global {
+        lines.push(Line::Directive(Directive::Global(data.m_name.clone())));
+    }
+    let bytes = data.m_type.size();
+    // Addresses are relocated when a position independent program is
+    // loaded, so are kept writable, as gcc does in .data.rel.ro
+    let (section, contents) = match &data.m_value {
+        Some(StaticValue::Constant(v)) if data.m_read_only => {
+            (Section::ReadOnly, Directive::Value(size(&data.m_type), *v))
+        }
+        Some(StaticValue::Constant(v)) => {
+            (Section::Data, Directive::Value(size(&data.m_type), *v))
+        }
+        Some(StaticValue::Address(symbol, offset)) => {
+            (Section::Data, Directive::Address(symbol.clone(), *offset))
+        }
+        None => (Section::Bss, Directive::Zero(bytes)),
+    };
+    lines.push(Line::Directive(Directive::Section(section)));
+    lines.push(Line::Directive(Directive::Align(bytes)));
+    lines.push(Line::Label(data.m_name.clone()));
+    lines.push(Line::Directive(contents));
+    return lines;
 }
 
 impl Generator {
     pub fn new() -> Self {
         Generator {
-            label_number: 0,
-            context: Vec::new(),
-            manual_vars_size: Vec::new(),
-            stack_index: -8,
-            returned: false,
-            loop_contexts: Vec::new(),
+            lines: Vec::new(),
+            file_numbers: HashMap::new(),
+            last_location: None,
+            function_name: String::new(),
+            local_labels: 0,
+            temps: Vec::new(),
+            allocation: Allocation {
+                m_registers: Vec::new(),
+                m_saved_across: HashMap::new(),
+            },
+            layout: frame::FrameLayout {
+                m_slots: Vec::new(),
+                m_temps: Vec::new(),
+                m_saves: Vec::new(),
+                m_size: 0,
+            },
+            va_start_state: None,
         }
     }
 
-    fn enter_loop(&mut self, start_label: String, end_label: String) {
-        let scope_count = self.context.len();
-        self.loop_contexts.push(LoopContext {
-            start_label,
-            end_label,
-            scope_count,
-        });
+    fn emit(&mut self, instruction: Instruction) {
+        self.lines.push(Line::Instruction(instruction));
     }
 
-    fn last_end_label(&self) -> String {
-        if let Some(&loop_context) = &self.loop_contexts.last().as_ref() {
-            return loop_context.end_label.clone();
-        } else {
-            panic!("Got label not in a loop context")
-        }
+    fn label(&mut self, label: String) {
+        self.lines.push(Line::Label(label));
     }
 
-    fn last_start_label(&self) -> String {
-        if let Some(&loop_context) = self.loop_contexts.last().as_ref() {
-            return loop_context.start_label.clone();
-        } else {
-            panic!("Got label not in a loop context")
+    // Line information for the assembler, so debuggers map instructions back
+    // to the original source file and line
+    fn generate_loc(&mut self, span: &Span) {
+        let file_number = match self.file_numbers.get(&*span.m_file) {
+            Some(&n) => n,
+            None => panic!("No .file for {}", span.m_file),
+        };
+        let location = (file_number, span.m_line, span.m_column);
+        if self.last_location == Some(location) {
+            return;
         }
+        self.last_location = Some(location);
+        self.lines.push(Line::Directive(Directive::Location(
+            file_number,
+            span.m_line,
+            span.m_column,
+        )));
     }
 
-    fn leave_loop(&mut self) {
-        self.loop_contexts.pop();
-    }
-
-    fn open_scope(&mut self) {
-        self.context.push(HashMap::new());
-        self.manual_vars_size.push(0);
+    // Operand holding a temporary: its register, or its home in the frame
+    fn operand(&self, temp: Temp) -> Operand {
+        match self.allocation.m_registers[temp] {
+            Some(r) => return Operand::Register(r),
+            None => return self.home(temp),
+        }
     }
 
-    fn add_var(&mut self, var_name: &String) {
-        let current_context = self.context.last_mut().unwrap();
-        match current_context.get(var_name) {
-            Some(_) => {
-                panic!("Variable {} already declared in this scope!", &var_name)
-            }
-            None => (),
+    // Memory operand of the home of a spilled temporary
+    fn home(&self, temp: Temp) -> Operand {
+        match self.layout.m_temps[temp] {
+            Some(offset) => return frame(offset),
+            None => panic!("Temporary {} is kept in a register", temp),
         }
-        current_context.insert(var_name.clone(), self.stack_index);
-        self.stack_index -= 8;
     }
 
-    fn add_manual_var(&mut self, var_name: &String, rbp_offest: i32) {
-        let current_context = self.context.last_mut().unwrap();
-        match current_context.get(var_name) {
-            Some(_) => {
-                panic!("Variable {} already declared in this scope!", &var_name)
+    // Where a register is saved in the frame
+    fn save_slot(&self, register: Register) -> Operand {
+        for (saved, offset) in &self.layout.m_saves {
+            if *saved == register {
+                return frame(*offset);
             }
-            None => (),
-        }
-        current_context.insert(var_name.clone(), rbp_offest);
-        if let Some(last) = self.manual_vars_size.last_mut() {
-            *last += 1;
         }
+        panic!("{:?} has no save slot", register);
     }
 
-    fn query_var(&mut self, var_name: &String) -> Option<i32> {
-        for scope in self.context.iter().rev() {
-            if let Some(&value) = scope.get(var_name) {
-                return Some(value);
+    // The memory operand at the address in a temporary. Addresses in
+    // registers are used directly, others are loaded into %rcx first
+    fn address(&mut self, temp: Temp) -> Operand {
+        match self.allocation.m_registers[temp] {
+            Some(r) => return Operand::Memory { m_base: r, m_offset: 0 },
+            None => {
+                self.emit(Instruction::Mov(Size::Quad, self.home(temp), RCX));
+                return Operand::Memory { m_base: Register::Rcx, m_offset: 0 };
             }
         }
-        return None;
     }
 
-    fn close_scope(&mut self) -> i32 {
-        let cur_context = match self.context.pop() {
-            Some(c) => c,
-            None => return 0,
-        };
-        let man_size = self.manual_vars_size.pop().unwrap();
-        let size = cur_context.len() as i32 - man_size;
-        self.stack_index = self.stack_index + 8 * size;
-        return size * 8;
+    fn block_label(&self, block: usize) -> String {
+        return format!(".L{}.{}", self.function_name, block);
     }
 
-    fn generate_label(&mut self) -> String {
-        let label = format!("label_{}", self.label_number);
-        self.label_number += 1;
-        return label;
+    // A new label for jumps within the code of one instruction
+    fn local_label(&mut self) -> String {
+        self.local_labels += 1;
+        return format!(".L{}.local{}", self.function_name, self.local_labels);
     }
 
-    pub fn generate(&mut self, program: &Program) -> String {
-        let mut gen_s: String = String::new();
-
-        for function in &program.m_functions {
-            gen_s.push_str(&self.generate_function(&function));
+    // Loads a temporary into %rax, or %xmm0 if it's floating
+    fn load(&mut self, temp: Temp) {
+        let temp_type = self.temps[temp];
+        if temp_type.is_floating() {
+            self.emit(Instruction::MovFloat(
+                size(&temp_type),
+                self.operand(temp),
+                XMM0,
+            ));
+            return;
         }
-
-        return gen_s;
+        self.emit(Instruction::Mov(size(&temp_type), self.operand(temp), RAX));
     }
 
-    fn generate_function(&mut self, function: &Function) -> String {
-        let mut gen_s: String = String::new();
-
-        match &function.m_items {
-            Some(items) => {
-                self.open_scope();
-
-                gen_s.push_str(
-                    format!(
-                        ".globl {0}\n\
-                {0}:\n\
-                \tpushq\t%rbp\n\
-                \tmovq\t%rsp, %rbp\n",
-                        function.m_id
-                    )
-                    .as_str(),
-                );
-
-                let mut cur_offset = 16;
-                for i in 0..function.m_params.len() {
-                    match i {
-                        0 => {
-                            gen_s.push_str(format!("\tpushq\t%rdi\n").as_str());
-                            self.add_var(&function.m_params.get(0).unwrap().1);
-                        }
-                        1 => {
-                            gen_s.push_str(format!("\tpushq\t%rsi\n").as_str());
-                            self.add_var(&function.m_params.get(1).unwrap().1);
-                        }
-                        2 => {
-                            gen_s.push_str(format!("\tpushq\t%rdx\n").as_str());
-                            self.add_var(&function.m_params.get(2).unwrap().1);
-                        }
-                        3 => {
-                            gen_s.push_str(format!("\tpushq\t%rcx\n").as_str());
-                            self.add_var(&function.m_params.get(3).unwrap().1);
-                        }
-                        4 => {
-                            gen_s.push_str(format!("\tpushq\t%r8\n").as_str());
-                            self.add_var(&function.m_params.get(4).unwrap().1);
-                        }
-                        5 => {
-                            gen_s.push_str(format!("\tpushq\t%r9\n").as_str());
-                            self.add_var(&function.m_params.get(5).unwrap().1);
-                        }
-                        x => {
-                            self.add_manual_var(
-                                &function.m_params.get(x).unwrap().1,
-                                cur_offset,
-                            );
-                            cur_offset += 8;
-                        }
-                    }
-                }
+    // Stores %rax, or %xmm0 if it's floating, to a temporary
+    fn store(&mut self, temp: Temp) {
+        let temp_type = self.temps[temp];
+        if temp_type.is_floating() {
+            self.emit(Instruction::MovFloat(
+                size(&temp_type),
+                XMM0,
+                self.operand(temp),
+            ));
+            return;
+        }
+        self.emit(Instruction::Mov(size(&temp_type), RAX, self.operand(temp)));
+    }
 
-                for block_item in items {
-                    gen_s.push_str(&self.generate_block_item(&block_item));
-                }
-                if !self.returned {
-                    gen_s.push_str(
-                        format!(
-                            "\tmovq\t%rbp, %rsp\n\
-                    \tpop \t%rbp\n\
-                    \tmovq\t$0, %rax\n\
-                    \tret\n"
-                        )
-                        .as_str(),
-                    );
-                }
+    // Loads the bits of a temporary into %rax, whatever its type
+    fn load_bits(&mut self, temp: Temp) {
+        let size = size(&self.temps[temp]);
+        match self.allocation.m_registers[temp] {
+            Some(r) if r.is_sse() => {
+                self.emit(Instruction::MovBits(size, Operand::Register(r), RAX))
+            }
+            _ => self.emit(Instruction::Mov(size, self.operand(temp), RAX)),
+        }
+    }
 
-                self.close_scope();
+    // Stores the bits in %rax to a temporary, whatever its type
+    fn store_bits(&mut self, temp: Temp) {
+        let size = size(&self.temps[temp]);
+        match self.allocation.m_registers[temp] {
+            Some(r) if r.is_sse() => {
+                self.emit(Instruction::MovBits(size, RAX, Operand::Register(r)))
             }
-            None => (),
+            _ => self.emit(Instruction::Mov(size, RAX, self.operand(temp))),
         }
+    }
 
-        return gen_s;
+    pub fn generate(&mut self, program: &ir::Program) -> Vec<Line> {
+        for (i, file) in program.source_files().into_iter().enumerate() {
+            self.lines
+                .push(Line::Directive(Directive::File(i + 1, file.clone())));
+            self.file_numbers.insert(file, i + 1);
+        }
+        for function in &program.m_functions {
+            self.generate_function(function);
+        }
+        for data in &program.m_data {
+            self.lines.extend(static_data(data));
+        }
+        return std::mem::take(&mut self.lines);
     }
 
-    fn generate_block_item(&mut self, block_item: &BlockItem) -> String {
-        let mut gen_s = String::new();
-        match &block_item {
-            BlockItem::Declaration(declaration) => {
-                gen_s.push_str(&self.generate_declaration(&declaration))
+    fn generate_function(&mut self, function: &ir::Function) {
+        self.function_name = function.m_name.clone();
+        self.local_labels = 0;
+        self.last_location = None;
+        self.temps = function.m_temps.clone();
+        let reserved = match function.m_variadic {
+            true => VARIADIC_FRAME_SIZE,
+            false => 0,
+        };
+        self.allocation = allocator::allocate(function);
+        self.layout =
+            frame::layout_function(function, &self.allocation, reserved);
+
+        self.lines.push(Line::Directive(Directive::Section(Section::Text)));
+        if function.m_global {
+            self.lines.push(Line::Directive(Directive::Global(
+                function.m_name.clone(),
+            )));
+        }
+        self.label(function.m_name.clone());
+        self.emit(Instruction::Push(RBP));
+        self.emit(Instruction::Mov(Size::Quad, RSP, RBP));
+        self.generate_loc(&function.m_span);
+        if self.layout.m_size > 0 {
+            self.emit(Instruction::Integer(
+                IntegerOp::Subtract,
+                Size::Quad,
+                Operand::Immediate(self.layout.m_size as i64),
+                RSP,
+            ));
+        }
+        for (register, offset) in self.layout.m_saves.clone() {
+            if register.is_callee_saved() {
+                self.emit(Instruction::Mov(
+                    Size::Quad,
+                    Operand::Register(register),
+                    frame(offset),
+                ));
             }
-            BlockItem::Statement(statement) => {
-                gen_s.push_str(&self.generate_statement(&statement))
+        }
+        if function.m_variadic {
+            self.generate_register_save();
+        }
+
+        // Parameters arrive in registers by class, integers in %rdi to %r9
+        // and floating values in %xmm0 to %xmm7; the rest are on the stack
+        // above the return address
+        let param_types: Vec<Type> =
+            function.m_parameters.iter().map(|p| self.temps[*p]).collect();
+        let in_register = argument_registers(&param_types);
+        let mut cur_offset = 16;
+        let mut int_index = 0;
+        let mut sse_index = 0;
+        for (i, parameter) in function.m_parameters.iter().enumerate() {
+            let home = self.operand(*parameter);
+            if !in_register[i] {
+                self.emit(Instruction::Mov(Size::Quad, frame(cur_offset), RAX));
+                self.store_bits(*parameter);
+                cur_offset += 8;
+            } else if param_types[i].is_floating() {
+                self.emit(Instruction::MovFloat(
+                    Size::Quad,
+                    Operand::Register(Register::Xmm(sse_index)),
+                    home,
+                ));
+                sse_index += 1;
+            } else {
+                self.emit(Instruction::Mov(
+                    Size::Quad,
+                    Operand::Register(INT_ARGUMENT_REGISTERS[int_index]),
+                    home,
+                ));
+                int_index += 1;
             }
         }
+        self.va_start_state = match function.m_variadic {
+            true => Some((
+                8 * int_index as i32,
+                8 * INT_ARGUMENT_REGISTERS.len() as i32 + 16 * sse_index as i32,
+                cur_offset,
+            )),
+            false => None,
+        };
 
-        return gen_s;
+        // Blocks are placed in order, so a jump to the next one is left out
+        for (i, block) in function.m_blocks.iter().enumerate() {
+            if i > 0 {
+                self.label(self.block_label(i));
+            }
+            for (k, instruction) in block.m_instructions.iter().enumerate() {
+                self.generate_instruction(instruction, (i, k));
+            }
+            self.generate_terminator(function, &block.m_terminator, i + 1);
+        }
     }
 
-    fn generate_statement(&mut self, statement: &Statement) -> String {
-        let mut gen_s: String = String::new();
+    // Saves the argument registers of a variadic function in the register
+    // save area at the top of its frame. %al holds an upper bound on the
+    // number of SSE registers used, so they are skipped when it's 0
+    fn generate_register_save(&mut self) {
+        for (i, register) in INT_ARGUMENT_REGISTERS.iter().enumerate() {
+            self.emit(Instruction::Mov(
+                Size::Quad,
+                Operand::Register(*register),
+                frame(REGISTER_SAVE_AREA + 8 * i as i32),
+            ));
+        }
+        let skip = self.local_label();
+        self.emit(Instruction::Integer(IntegerOp::Test, Size::Byte, RAX, RAX));
+        self.emit(Instruction::JumpIf(Condition::Equal, skip.clone()));
+        let sse_start =
+            REGISTER_SAVE_AREA + 8 * INT_ARGUMENT_REGISTERS.len() as i32;
+        for i in 0..SSE_ARGUMENT_REGISTERS {
+            self.emit(Instruction::MovFloat(
+                Size::Quad,
+                Operand::Register(Register::Xmm(i as u8)),
+                frame(sse_start + 16 * i as i32),
+            ));
+        }
+        self.label(skip);
+    }
 
-        match statement {
-            Statement::Continue => {
-                if let Some(loop_context) = self.loop_contexts.last().cloned() {
-                    let mut size_to_deallocate: i32 = 0;
-                    while self.context.len() > loop_context.scope_count {
-                        size_to_deallocate += self.close_scope();
+    fn generate_terminator(
+        &mut self,
+        function: &ir::Function,
+        terminator: &Terminator,
+        next_block: usize,
+    ) {
+        match terminator {
+            Terminator::Jump { m_target, m_arguments } => {
+                // A parameter may also be an argument, so every argument is
+                // read before any parameter is written. SSE registers can't
+                // be pushed, so go through %rax
+                let parameters = &function.m_blocks[*m_target].m_parameters;
+                for argument in m_arguments {
+                    match self.allocation.m_registers[*argument] {
+                        Some(r) if r.is_sse() => {
+                            self.emit(Instruction::MovBits(
+                                Size::Quad,
+                                Operand::Register(r),
+                                RAX,
+                            ));
+                            self.emit(Instruction::Push(RAX));
+                        }
+                        _ => self
+                            .emit(Instruction::Push(self.operand(*argument))),
                     }
-                    gen_s.push_str(
-                        format!("\taddq\t${}, %rsp\n", size_to_deallocate * 8)
-                            .as_str(),
-                    );
-                } else {
-                    panic!("continue statement not in loop context");
                 }
-            }
-            Statement::Break => {
-                if let Some(loop_context) = self.loop_contexts.last().cloned() {
-                    let mut size_to_deallocate: i32 = 0;
-                    while self.context.len() > loop_context.scope_count {
-                        size_to_deallocate += self.close_scope();
+                for parameter in parameters.iter().rev() {
+                    match self.allocation.m_registers[*parameter] {
+                        Some(r) if r.is_sse() => {
+                            self.emit(Instruction::Pop(RAX));
+                            self.emit(Instruction::MovBits(
+                                Size::Quad,
+                                RAX,
+                                Operand::Register(r),
+                            ));
+                        }
+                        _ => self
+                            .emit(Instruction::Pop(self.operand(*parameter))),
                     }
-                    gen_s.push_str(
-                        format!("\taddq\t${}, %rsp\n", size_to_deallocate * 8)
-                            .as_str(),
-                    );
-                } else {
-                    panic!("break statement not in loop context");
                 }
-            }
-            Statement::ForDecl {
-                m_initial_declaration,
-                m_condition,
-                m_post_expression,
-                m_statement,
-            } => {
-                let start_label = self.generate_label();
-                let end_label = self.generate_label();
-                self.enter_loop(start_label, end_label);
-                self.open_scope();
-                let condition_label = self.generate_label();
-                gen_s.push_str(
-                    &self.generate_declaration(&m_initial_declaration),
-                );
-                gen_s.push_str(format!("{}:\n", &condition_label).as_str());
-                gen_s.push_str(&self.generate_expression(&m_condition));
-                gen_s.push_str(
-                    format!(
-                        "\tcmpq\t$0, %rax\n\
-                    je\t{0}\n",
-                        &self.last_end_label()
-                    )
-                    .as_str(),
-                );
-                gen_s.push_str(&self.generate_statement(&m_statement));
-                gen_s.push_str(
-                    format!("{}:\n", &self.last_start_label()).as_str(),
-                );
-                match m_post_expression {
-                    Some(e) => gen_s.push_str(&self.generate_expression(&e)),
-                    None => (),
+                if *m_target != next_block {
+                    self.emit(Instruction::Jump(self.block_label(*m_target)));
                 }
-                gen_s.push_str(
-                    format!("\tjmp\t{}\n", &condition_label).as_str(),
-                );
-                gen_s.push_str(
-                    format!("{}:\n", &self.last_end_label()).as_str(),
-                );
-                self.leave_loop();
-                let size_to_deallocate = self.close_scope();
-                gen_s.push_str(
-                    format!("\taddq\t${}, %rsp\n", size_to_deallocate).as_str(),
-                );
             }
-
-            Statement::For {
-                m_initial_expression,
-                m_condition,
-                m_post_expression,
-                m_statement,
-            } => {
-                let start_label = self.generate_label();
-                let end_label = self.generate_label();
-                self.enter_loop(start_label, end_label);
-                self.open_scope();
-                let condition_label = self.generate_label();
-                match m_initial_expression {
-                    Some(e) => gen_s.push_str(&self.generate_expression(&e)),
-                    None => (),
+            Terminator::Branch { m_condition, m_true, m_false } => {
+                self.emit(Instruction::Integer(
+                    IntegerOp::Compare,
+                    size(&self.temps[*m_condition]),
+                    Operand::Immediate(0),
+                    self.operand(*m_condition),
+                ));
+                self.emit(Instruction::JumpIf(
+                    Condition::NotEqual,
+                    self.block_label(*m_true),
+                ));
+                if *m_false != next_block {
+                    self.emit(Instruction::Jump(self.block_label(*m_false)));
                 }
-                gen_s.push_str(format!("{}:\n", &condition_label).as_str());
-                gen_s.push_str(&self.generate_expression(&m_condition));
-                gen_s.push_str(
-                    format!(
-                        "\tcmpq\t$0, %rax\n\
-                    je\t{0}\n",
-                        &self.last_end_label()
-                    )
-                    .as_str(),
-                );
-                gen_s.push_str(&self.generate_statement(&m_statement));
-                gen_s.push_str(
-                    format!("{}:\n", &self.last_start_label()).as_str(),
-                );
-                match m_post_expression {
-                    Some(e) => gen_s.push_str(&self.generate_expression(&e)),
-                    None => (),
+            }
+            Terminator::Return { m_value } => {
+                self.load(*m_value);
+                for (register, offset) in self.layout.m_saves.clone() {
+                    if register.is_callee_saved() {
+                        self.emit(Instruction::Mov(
+                            Size::Quad,
+                            frame(offset),
+                            Operand::Register(register),
+                        ));
+                    }
                 }
-                gen_s.push_str(
-                    format!("\tjmp\t{}\n", &condition_label).as_str(),
-                );
-                gen_s.push_str(
-                    format!("{}:\n", &self.last_end_label()).as_str(),
-                );
-                self.leave_loop();
-                let size_to_deallocate = self.close_scope();
-                gen_s.push_str(
-                    format!("\taddq\t${}, %rsp\n", size_to_deallocate).as_str(),
-                );
+                self.emit(Instruction::Mov(Size::Quad, RBP, RSP));
+                self.emit(Instruction::Pop(RBP));
+                self.emit(Instruction::Return);
             }
-            Statement::While { m_condition, m_statement } => {
-                let start_label = self.generate_label();
-                let end_label = self.generate_label();
-                self.enter_loop(start_label, end_label);
-                self.open_scope();
-                gen_s.push_str(
-                    format!("{}:\n", &self.last_start_label()).as_str(),
-                );
-                gen_s.push_str(&self.generate_expression(&m_condition));
-                gen_s.push_str(
-                    format!(
-                        "\tcmpq\t$0, %rax\n\
-                    je\t{0}\n",
-                        &self.last_end_label()
-                    )
-                    .as_str(),
-                );
-                gen_s.push_str(&self.generate_statement(&m_statement));
-                gen_s.push_str(
-                    format!("\tjmp\t{}\n", &self.last_start_label()).as_str(),
-                );
-                gen_s.push_str(
-                    format!("{}:\n", &self.last_end_label()).as_str(),
-                );
-
-                self.leave_loop();
-                let size_to_deallocate = self.close_scope();
+        }
+    }
 
-                gen_s.push_str(
-                    format!("\taddq\t${}, %rsp\n", size_to_deallocate).as_str(),
-                );
+    // `at` is the block and index of the instruction
+    fn generate_instruction(
+        &mut self,
+        instruction: &ir::Instruction,
+        at: (usize, usize),
+    ) {
+        match instruction {
+            ir::Instruction::Constant { m_dest, m_type, m_value } => {
+                let size = size(m_type);
+                let wide =
+                    size == Size::Quad && i32::try_from(*m_value).is_err();
+                let sse = match self.allocation.m_registers[*m_dest] {
+                    Some(r) => r.is_sse(),
+                    None => false,
+                };
+                let value = Operand::Immediate(*m_value);
+                if wide || sse {
+                    // Only a 32 bit immediate can be sign extended by movq,
+                    // and SSE registers take no immediates. Floating
+                    // constants are moved as their bits too
+                    self.emit(Instruction::Mov(size, value, RAX));
+                    self.store_bits(*m_dest);
+                } else {
+                    self.emit(Instruction::Mov(
+                        size,
+                        value,
+                        self.operand(*m_dest),
+                    ));
+                }
             }
-            Statement::Do { m_statement, m_condition } => {
-                let start_label = self.generate_label();
-                let end_label = self.generate_label();
-                self.enter_loop(start_label, end_label);
-                self.open_scope();
-                gen_s.push_str(
-                    format!("{}:\n", &self.last_start_label()).as_str(),
-                );
-                gen_s.push_str(&self.generate_statement(&m_statement));
-                gen_s.push_str(&self.generate_expression(&m_condition));
-                gen_s.push_str(
-                    format!(
-                        "\tcmpq\t$0, %rax\n\
-                    je\t{0}\n",
-                        &self.last_end_label()
-                    )
-                    .as_str(),
-                );
-                gen_s.push_str(
-                    format!("\tjmp\t{}\n", &self.last_start_label()).as_str(),
-                );
-                gen_s.push_str(
-                    format!("{}:\n", &self.last_end_label()).as_str(),
-                );
-
-                self.leave_loop();
-
-                let size_to_deallocate = self.close_scope();
-
-                gen_s.push_str(
-                    format!("\taddq\t${}, %rsp\n", size_to_deallocate).as_str(),
-                );
+            ir::Instruction::SlotAddress { m_dest, m_slot } => {
+                self.emit(Instruction::Lea(
+                    frame(self.layout.m_slots[*m_slot]),
+                    RAX,
+                ));
+                self.store(*m_dest);
             }
-            Statement::Compound { m_block_items } => {
-                self.open_scope();
-
-                for block_item in m_block_items {
-                    gen_s.push_str(&self.generate_block_item(block_item));
-                }
-
-                let size_to_deallocate = self.close_scope();
-
-                gen_s.push_str(
-                    format!("\taddq\t${}, %rsp\n", size_to_deallocate).as_str(),
-                );
+            ir::Instruction::GlobalAddress { m_dest, m_name } => {
+                self.emit(Instruction::Lea(
+                    Operand::Symbol(m_name.clone()),
+                    RAX,
+                ));
+                self.store(*m_dest);
             }
-            Statement::Expression(expression) => match expression {
-                Some(e) => gen_s.push_str(&self.generate_expression(&e)),
-                None => (),
-            },
-
-            Statement::Return(expression) => {
-                self.returned = true;
-                match expression {
-                    Some(e) => gen_s.push_str(&self.generate_expression(&e)),
-                    None => (),
-                }
-                gen_s.push_str(
-                    format!(
-                        "\tmovq\t%rbp, %rsp\n\
-                        \tpop \t%rbp\n\
-                        \tret\n"
-                    )
-                    .as_str(),
-                );
+            // Each access is one instruction, so volatile ones need nothing
+            // more. Floating values are moved as their bits
+            ir::Instruction::Load {
+                m_dest,
+                m_type,
+                m_address,
+                m_volatile: _,
+            } => {
+                let memory = self.address(*m_address);
+                self.emit(Instruction::Mov(size(m_type), memory, RAX));
+                self.store_bits(*m_dest);
             }
-
-            Statement::If {
-                m_condition,
-                m_true_statement,
-                m_else_statement,
+            ir::Instruction::Store {
+                m_type,
+                m_address,
+                m_value,
+                m_volatile: _,
             } => {
-                let false_label = &self.generate_label();
-                let end_label = &self.generate_label();
-                gen_s.push_str(&self.generate_expression(&m_condition));
-                gen_s.push_str(
-                    format!(
-                        "\tcmpq\t$0, %rax\n\
-                    je\t{0}\n",
-                        &false_label
-                    )
-                    .as_str(),
-                );
-                gen_s.push_str(&self.generate_statement(&m_true_statement));
-                gen_s.push_str(
-                    format!(
-                        "\tjmp\t\t{0}\n\
-                    {1}:\n",
-                        &end_label, &false_label,
-                    )
-                    .as_str(),
-                );
-                match m_else_statement {
-                    Some(s) => gen_s.push_str(&self.generate_statement(&s)),
-                    None => (),
+                let memory = self.address(*m_address);
+                self.load_bits(*m_value);
+                self.emit(Instruction::Mov(size(m_type), RAX, memory));
+            }
+            ir::Instruction::Unary { m_dest, m_op, m_type, m_operand } => {
+                self.load(*m_operand);
+                let size = size(m_type);
+                match (m_op, m_type) {
+                    // Flip the sign bit
+                    (UnaryOp::Negate, Type::F64) => {
+                        self.emit(Instruction::MovBits(size, XMM0, RAX));
+                        self.emit(Instruction::Integer(
+                            IntegerOp::BitComplement,
+                            Size::Quad,
+                            Operand::Immediate(63),
+                            RAX,
+                        ));
+                        self.emit(Instruction::MovBits(size, RAX, XMM0));
+                    }
+                    (UnaryOp::Negate, Type::F32) => {
+                        self.emit(Instruction::MovBits(size, XMM0, RAX));
+                        self.emit(Instruction::Integer(
+                            IntegerOp::Xor,
+                            Size::Long,
+                            Operand::Immediate(0x80000000),
+                            RAX,
+                        ));
+                        self.emit(Instruction::MovBits(size, RAX, XMM0));
+                    }
+                    (UnaryOp::Negate, _) => {
+                        self.emit(Instruction::Negate(size, RAX))
+                    }
+                    (UnaryOp::Not, _) => self.emit(Instruction::Not(size, RAX)),
                 }
-                gen_s.push_str(format!("{}:", &end_label).as_str());
+                self.store(*m_dest);
             }
-        }
-
-        return gen_s;
-    }
-
-    fn generate_declaration(&mut self, declaration: &Declaration) -> String {
-        let mut gen_s = String::new();
-
-        match &declaration.m_value {
-            Some(e) => {
-                gen_s.push_str(&self.generate_expression(&e));
-                gen_s.push_str("\tpushq\t%rax\n");
+            ir::Instruction::Binary {
+                m_dest,
+                m_op,
+                m_type,
+                m_left,
+                m_right,
+            } => {
+                self.generate_binary(*m_op, m_type, *m_left, *m_right);
+                self.store(*m_dest);
             }
-            None => (),
-        }
-        self.add_var(&declaration.m_id);
-
-        return gen_s;
-    }
-
-    fn generate_expression(&mut self, expression: &Expression) -> String {
-        let mut gen_s = String::new();
-        match expression {
-            Expression::Assignment { m_name, m_value } => {
-                gen_s.push_str(&self.generate_expression(&m_value));
-                let var_offset = match self.query_var(&m_name) {
-                    Some(i) => i,
-                    None => panic!("Use of undeclared variable {}", m_name),
+            ir::Instruction::Compare {
+                m_dest,
+                m_op,
+                m_type,
+                m_left,
+                m_right,
+            } => {
+                self.generate_compare(*m_op, m_type, *m_left, *m_right);
+                self.store(*m_dest);
+            }
+            ir::Instruction::Convert {
+                m_dest,
+                m_op,
+                m_from,
+                m_to,
+                m_operand,
+            } => {
+                self.generate_convert(*m_op, m_from, m_to, *m_operand);
+                self.store(*m_dest);
+            }
+            ir::Instruction::Call {
+                m_dest,
+                m_function,
+                m_arguments,
+                m_variadic,
+            } => {
+                let saved = match self.allocation.m_saved_across.get(&at) {
+                    Some(registers) => registers.clone(),
+                    None => Vec::new(),
                 };
-                gen_s.push_str(
-                    format!("\tmovq\t%rax, {}(%rbp)\n", &var_offset).as_str(),
+                self.generate_call(
+                    m_function,
+                    m_arguments,
+                    m_variadic.is_some(),
+                    &saved,
                 );
+                self.store(*m_dest);
             }
-            Expression::Operation(conditional_expression) => gen_s.push_str(
-                &self.generate_conditional_expression(&conditional_expression),
-            ),
-        }
-
-        return gen_s;
-    }
-
-    fn generate_conditional_expression(
-        &mut self,
-        conditional_expression: &ConditionalExpression,
-    ) -> String {
-        let mut gen_s = String::new();
-
-        gen_s.push_str(&self.generate_logical_or_expression(
-            &conditional_expression.m_condition,
-        ));
-
-        match &conditional_expression.m_true {
-            Some(exp) => {
-                let false_lable = &self.generate_label();
-                let end_label = &self.generate_label();
-                gen_s.push_str(
-                    format!(
-                        "\tcmpq\t$0, %rax\n\
-                        \tje\t\t{}\n",
-                        &false_lable
-                    )
-                    .as_str(),
-                );
-                gen_s.push_str(&self.generate_expression(&exp));
-                gen_s.push_str(
-                    format!(
-                        "\tjmp\t\t{0}\n\
-                        {1}:\n",
-                        &end_label, &false_lable
-                    )
-                    .as_str(),
-                );
-                gen_s.push_str(&self.generate_conditional_expression(
-                    &conditional_expression.m_false.as_ref().unwrap(),
+            ir::Instruction::VaStart { m_list } => {
+                let (gp_offset, fp_offset, overflow) = match self.va_start_state
+                {
+                    Some(s) => s,
+                    None => panic!("va_start outside a variadic function"),
+                };
+                self.emit(Instruction::Mov(
+                    Size::Long,
+                    Operand::Immediate(gp_offset as i64),
+                    frame(VA_LIST_STATE),
+                ));
+                self.emit(Instruction::Mov(
+                    Size::Long,
+                    Operand::Immediate(fp_offset as i64),
+                    frame(VA_LIST_STATE + 4),
+                ));
+                self.emit(Instruction::Lea(frame(overflow), RAX));
+                self.emit(Instruction::Mov(
+                    Size::Quad,
+                    RAX,
+                    frame(VA_LIST_STATE + 8),
                 ));
-                gen_s.push_str(format!("{}:\n", &end_label).as_str());
+                self.emit(Instruction::Lea(frame(REGISTER_SAVE_AREA), RAX));
+                self.emit(Instruction::Mov(
+                    Size::Quad,
+                    RAX,
+                    frame(VA_LIST_STATE + 16),
+                ));
+                self.emit(Instruction::Lea(frame(VA_LIST_STATE), RAX));
+                self.emit(Instruction::Mov(
+                    Size::Quad,
+                    self.operand(*m_list),
+                    RCX,
+                ));
+                self.emit(Instruction::Mov(
+                    Size::Quad,
+                    RAX,
+                    Operand::Memory { m_base: Register::Rcx, m_offset: 0 },
+                ));
+            }
+            ir::Instruction::VaArg { m_dest, m_type, m_list } => {
+                self.generate_va_arg(m_type, *m_list);
+                self.store_bits(*m_dest);
+            }
+            ir::Instruction::Location { m_span } => {
+                self.generate_loc(m_span);
             }
-            None => (),
         }
-
-        return gen_s;
     }
 
-    fn generate_logical_or_expression(
-        &mut self,
-        logical_or_expression: &LogicalOrExpresson,
-    ) -> String {
-        let mut gen_s = String::new();
-
-        gen_s.push_str(
-            &self.generate_logical_and_expression(
-                &logical_or_expression.m_first,
-            ),
-        );
-
-        for next_op in &logical_or_expression.m_rest {
-            let e2_label = &self.generate_label();
-            let end_label = &self.generate_label();
-            gen_s.push_str(
-                format!(
-                    "\tcmpq\t$0, %rax\n\
-                \tje\t{0}\n\
-                \tmovq\t$1, %rax\n\
-                \tjmp\t{1}\n\
-                {0}:\n",
-                    &e2_label, &end_label,
-                )
-                .as_str(),
-            );
-            gen_s.push_str(&self.generate_logical_and_expression(&next_op));
-            gen_s.push_str(
-                format!(
-                    "\tcmpq\t$0, %rax\n\
-                \tmovq\t$0, %rax\n\
-                \tsetne\t%al\n\
-                {0}:",
-                    &end_label
-                )
-                .as_str(),
-            );
-        }
-
-        return gen_s;
-    }
-
-    fn generate_logical_and_expression(
-        &mut self,
-        logical_and_expresson: &LogicalAndExpression,
-    ) -> String {
-        let mut gen_s = String::new();
-
-        gen_s.push_str(
-            &self.generate_equality_expression(&logical_and_expresson.m_first),
-        );
-
-        for next_op in &logical_and_expresson.m_rest {
-            let e2_label = &self.generate_label();
-            let end_label = &self.generate_label();
-            // if e1 true jump to e2 else jump to end
-            gen_s.push_str(
-                format!(
-                    "\tcmpq\t$0, %rax\n\
-                \tjne\t{0}\n\
-                \tjmp\t{1}\n\
-                {0}:\n",
-                    &e2_label, &end_label
-                )
-                .as_str(),
-            );
-            gen_s.push_str(&self.generate_equality_expression(&next_op));
-            // if e2 true set %al to true
-            gen_s.push_str(
-                format!(
-                    "\tcmpq\t$0, %rax\n\
-                \tmovq\t$0, %rax\n\
-                \tsetne %al\n\
-                {0}:",
-                    &end_label
-                )
-                .as_str(),
-            );
-        }
-
-        return gen_s;
+    // Takes the next saved register of the argument's class while there are
+    // any left, then continues on the stack. Leaves the argument in %rax
+    fn generate_va_arg(&mut self, arg_type: &Type, list: Temp) {
+        let (offset_field, limit, step) = if arg_type.is_floating() {
+            (
+                4,
+                8 * INT_ARGUMENT_REGISTERS.len() + 16 * SSE_ARGUMENT_REGISTERS,
+                16,
+            )
+        } else {
+            (0, 8 * INT_ARGUMENT_REGISTERS.len(), 8)
+        };
+        let state = |offset: i32| {
+            return Operand::Memory { m_base: Register::Rcx, m_offset: offset };
+        };
+        let on_stack = self.local_label();
+        let done = self.local_label();
+        self.emit(Instruction::Mov(Size::Quad, self.operand(list), RCX));
+        self.emit(Instruction::Mov(Size::Long, state(offset_field), RDX));
+        self.emit(Instruction::Integer(
+            IntegerOp::Compare,
+            Size::Long,
+            Operand::Immediate(limit as i64),
+            RDX,
+        ));
+        self.emit(Instruction::JumpIf(
+            Condition::AboveOrEqual,
+            on_stack.clone(),
+        ));
+        self.emit(Instruction::Mov(Size::Quad, state(16), RAX));
+        self.emit(Instruction::Integer(IntegerOp::Add, Size::Quad, RDX, RAX));
+        self.emit(Instruction::Integer(
+            IntegerOp::Add,
+            Size::Long,
+            Operand::Immediate(step),
+            state(offset_field),
+        ));
+        self.emit(Instruction::Jump(done.clone()));
+        self.label(on_stack);
+        self.emit(Instruction::Mov(Size::Quad, state(8), RAX));
+        self.emit(Instruction::Lea(
+            Operand::Memory { m_base: Register::Rax, m_offset: 8 },
+            RDX,
+        ));
+        self.emit(Instruction::Mov(Size::Quad, RDX, state(8)));
+        self.label(done);
+        self.emit(Instruction::Mov(
+            size(arg_type),
+            Operand::Memory { m_base: Register::Rax, m_offset: 0 },
+            RAX,
+        ));
     }
 
-    fn generate_equality_expression(
+    // Leaves the result in %rax or %xmm0
+    fn generate_binary(
         &mut self,
-        equality_expession: &EqualityExpression,
-    ) -> String {
-        let mut gen_s = String::new();
-
-        gen_s.push_str(
-            &self.generate_relational_expression(&equality_expession.m_first),
-        );
-
-        for next_op in &equality_expession.m_rest {
-            gen_s.push_str("\tpushq\t%rax\n");
-            gen_s.push_str(&self.generate_relational_expression(&next_op.1));
-            gen_s.push_str("\tpop\t%rcx\n");
-            match next_op.0 {
-                EqualityOperator::NotEqual => {
-                    gen_s.push_str(
-                        "\tcmpq\t%rax, %rcx\n\
-                \tmovq\t$0, %rax\n\
-                \tsetne\t%al\n",
-                    );
+        op: BinaryOp,
+        operand_type: &Type,
+        left: Temp,
+        right: Temp,
+    ) {
+        self.load(left);
+        let right = self.operand(right);
+        let size = size(operand_type);
+        if operand_type.is_floating() {
+            let op = match op {
+                BinaryOp::Add => FloatOp::Add,
+                BinaryOp::Subtract => FloatOp::Subtract,
+                BinaryOp::Multiply => FloatOp::Multiply,
+                _ => FloatOp::Divide,
+            };
+            self.emit(Instruction::Float(op, size, right, XMM0));
+            return;
+        }
+        let op = match op {
+            BinaryOp::Add => IntegerOp::Add,
+            BinaryOp::Subtract => IntegerOp::Subtract,
+            BinaryOp::Multiply => IntegerOp::Multiply,
+            // Divides %rdx:%rax, or %edx:%eax, giving the quotient in %rax
+            // and the remainder in %rdx
+            _ => {
+                let signed =
+                    op == BinaryOp::Divide || op == BinaryOp::Remainder;
+                if signed {
+                    self.emit(Instruction::ExtendAccumulator(size));
+                    self.emit(Instruction::SignedDivide(size, right));
+                } else {
+                    self.emit(Instruction::Integer(
+                        IntegerOp::Xor,
+                        Size::Long,
+                        RDX,
+                        RDX,
+                    ));
+                    self.emit(Instruction::Divide(size, right));
                 }
-                EqualityOperator::Equal => {
-                    gen_s.push_str(
-                        "\tcmpq\t%rax, %rcx\n\
-                \tmovq\t$0, %rax\n\
-                \tsete\t%al\n",
-                    );
+                if op == BinaryOp::Remainder
+                    || op == BinaryOp::UnsignedRemainder
+                {
+                    self.emit(Instruction::Mov(Size::Quad, RDX, RAX));
                 }
+                return;
             }
-        }
-
-        return gen_s;
+        };
+        self.emit(Instruction::Integer(op, size, right, RAX));
     }
 
-    fn generate_relational_expression(
+    // Leaves 0 or 1 in %eax
+    fn generate_compare(
         &mut self,
-        relational_expression: &RelationalExpression,
-    ) -> String {
-        let mut gen_s = String::new();
-        gen_s.push_str(
-            &self.generate_additive_expression(&relational_expression.m_first),
-        );
-
-        for next_op in &relational_expression.m_rest {
-            gen_s.push_str("\tpushq\t%rax\n");
-            gen_s.push_str(&self.generate_additive_expression(&next_op.1));
-            gen_s.push_str("\tpop\t%rcx\n");
-            match next_op.0 {
-                RelationalOperator::Less => {
-                    gen_s.push_str(
-                        "\tcmpq\t%rax, %rcx\n\
-                \tmovq\t$0, %rax\n\
-                \tsetl\t%al\n",
-                    );
+        op: CompareOp,
+        operand_type: &Type,
+        left: Temp,
+        right: Temp,
+    ) {
+        let size = size(operand_type);
+        if operand_type.is_floating() {
+            // Only "above" style conditions are false for unordered
+            // operands, so < and <= compare the other way round. An
+            // unordered compare (NaN) sets the parity flag
+            let (first, second) = match op {
+                CompareOp::Less | CompareOp::LessOrEqual => (right, left),
+                _ => (left, right),
+            };
+            self.load(first);
+            self.emit(Instruction::CompareFloat(
+                size,
+                self.operand(second),
+                XMM0,
+            ));
+            match op {
+                CompareOp::Equal => {
+                    self.emit(Instruction::Set(Condition::Equal, RAX));
+                    self.emit(Instruction::Set(Condition::NoParity, RCX));
+                    self.emit(Instruction::Integer(
+                        IntegerOp::And,
+                        Size::Byte,
+                        RCX,
+                        RAX,
+                    ));
                 }
-                RelationalOperator::LessOrEqual => {
-                    gen_s.push_str(
-                        "\tcmpq\t%rax, %rcx\n\
-                \tmovq\t$0, %rax\n\
-                \tsetle\t%al\n",
-                    );
+                CompareOp::NotEqual => {
+                    self.emit(Instruction::Set(Condition::NotEqual, RAX));
+                    self.emit(Instruction::Set(Condition::Parity, RCX));
+                    self.emit(Instruction::Integer(
+                        IntegerOp::Or,
+                        Size::Byte,
+                        RCX,
+                        RAX,
+                    ));
                 }
-                RelationalOperator::Greater => {
-                    gen_s.push_str(
-                        "\tcmpq\t%rax, %rcx\n\
-                \tmovq\t$0, %rax\n\
-                \tsetg\t%al\n",
-                    );
-                }
-                RelationalOperator::GreaterOrEqual => {
-                    gen_s.push_str(
-                        "\tcmpq\t%rax, %rcx\n\
-                \tmovq\t$0, %rax\n\
-                \tsetge\t%al\n",
-                    );
+                CompareOp::Less | CompareOp::Greater => {
+                    self.emit(Instruction::Set(Condition::Above, RAX))
                 }
+                _ => self.emit(Instruction::Set(Condition::AboveOrEqual, RAX)),
             }
+            self.emit(Instruction::MovZeroExtend(
+                Size::Byte,
+                Size::Long,
+                RAX,
+                RAX,
+            ));
+            return;
         }
-        return gen_s;
+        // Unsigned numbers and addresses compare without sign
+        let condition = match op {
+            CompareOp::Equal => Condition::Equal,
+            CompareOp::NotEqual => Condition::NotEqual,
+            CompareOp::Less => Condition::Less,
+            CompareOp::LessOrEqual => Condition::LessOrEqual,
+            CompareOp::Greater => Condition::Greater,
+            CompareOp::GreaterOrEqual => Condition::GreaterOrEqual,
+            CompareOp::UnsignedLess => Condition::Below,
+            CompareOp::UnsignedLessOrEqual => Condition::BelowOrEqual,
+            CompareOp::UnsignedGreater => Condition::Above,
+            CompareOp::UnsignedGreaterOrEqual => Condition::AboveOrEqual,
+        };
+        self.load(left);
+        self.emit(Instruction::Integer(
+            IntegerOp::Compare,
+            size,
+            self.operand(right),
+            RAX,
+        ));
+        self.emit(Instruction::Set(condition, RAX));
+        self.emit(Instruction::MovZeroExtend(Size::Byte, Size::Long, RAX, RAX));
     }
 
-    fn generate_additive_expression(
-        &mut self,
-        additive_expression: &AdditiveExpression,
-    ) -> String {
-        let mut gen_s = String::new();
-
-        gen_s.push_str(&self.generate_term(&additive_expression.m_first_term));
-
-        for next_op in &additive_expression.m_rest {
-            gen_s.push_str("\tpushq\t%rax\n");
-            gen_s.push_str(&self.generate_term(&next_op.1));
-            gen_s.push_str("\tpop\t%rcx\n");
-            match next_op.0 {
-                AdditiveOperator::Minus => {
-                    gen_s.push_str(
-                        "\tsubq\t%rax, %rcx\n\
-                    \tmovq\t%rcx, %rax\n",
-                    ); // calc rax - rcx store in rcx
-                }
-                AdditiveOperator::Addition => {
-                    gen_s.push_str("\taddq\t%rcx, %rax\n"); // calc rcx + rax store in rax
-                }
-                _ => (),
+    // Loads an integer into %rax, sign or zero extending it to 64 bits
+    fn extend_to_rax(&mut self, operand: Temp, from: &Type, signed: bool) {
+        let source = self.operand(operand);
+        match (from, signed) {
+            (Type::I8 | Type::I16 | Type::I32, true) => self.emit(
+                Instruction::MovSignExtend(size(from), Size::Quad, source, RAX),
+            ),
+            // Writing a 32 bit register clears the upper half
+            (Type::I32, false) => {
+                self.emit(Instruction::Mov(Size::Long, source, RAX))
             }
+            (Type::I8 | Type::I16, false) => self.emit(
+                Instruction::MovZeroExtend(size(from), Size::Quad, source, RAX),
+            ),
+            _ => self.emit(Instruction::Mov(Size::Quad, source, RAX)),
         }
-
-        return gen_s;
     }
 
-    fn generate_term(&mut self, term: &Term) -> String {
-        let mut gen_s = String::new();
-
-        gen_s.push_str(&self.generate_factor(&term.m_first_factor));
-
-        for next_op in &term.m_rest {
-            gen_s.push_str("\tpushq\t%rax\n");
-            gen_s.push_str(&self.generate_factor(&next_op.1));
-            gen_s.push_str("\tpop\t%rcx\n");
-            match next_op.0 {
-                MultiplicativeOperator::Multiplication => {
-                    gen_s.push_str("\timul %rcx, %rax\n"); // calc rax * rcx store in rax
-                }
-                MultiplicativeOperator::Division => {
-                    gen_s.push_str(
-                        "\tmovq\t%rax, %rbx\n\
-                    \tmovq\t%rcx, %rax\n\
-                    \tcqo\n\
-                    \tidivq\t%rbx\n",
-                    ); // mov rax to rbx; mov rcx to rax; sign extend rax to rdx; calc [rdx:rax]/rbx
-                       // stores quotient in rax and remainder in rdx // rax / rbx store in rax
-                }
-                MultiplicativeOperator::Modulo => {
-                    gen_s.push_str(
-                        "\tmovq\t%rax, %rbx\n\
-                    \tmovq\t%rcx, %rax\n\
-                    \tcqo\n\
-                    \tidivq\t%rbx\n\
-                    \tmovq\t%rdx, %rax\n",
-                    ); // mov rax to rbx; mov rcx to rax; sign extend rax to rdx; calc [rdx:rax]/rbx
-                       // stores quotient in rax and remainder in rdx // rax / rbx store in rax
-                }
-
-                _ => (),
-            }
+    // Converts the 64 bit integer in %rax to floating in %xmm0
+    fn integer_to_float(&mut self, to: &Type, unsigned: bool) {
+        let size = size(to);
+        if !unsigned {
+            self.emit(Instruction::IntegerToFloat(size, RAX, XMM0));
+            return;
         }
-
-        return gen_s;
+        // Halve values with the top bit set, keeping the lowest bit so that
+        // rounding is right, then double the result
+        let halve = self.local_label();
+        let done = self.local_label();
+        self.emit(Instruction::Integer(IntegerOp::Test, Size::Quad, RAX, RAX));
+        self.emit(Instruction::JumpIf(Condition::Sign, halve.clone()));
+        self.emit(Instruction::IntegerToFloat(size, RAX, XMM0));
+        self.emit(Instruction::Jump(done.clone()));
+        self.label(halve);
+        self.emit(Instruction::Mov(Size::Quad, RAX, RCX));
+        self.emit(Instruction::Integer(
+            IntegerOp::ShiftRight,
+            Size::Quad,
+            Operand::Immediate(1),
+            RCX,
+        ));
+        self.emit(Instruction::Integer(
+            IntegerOp::And,
+            Size::Long,
+            Operand::Immediate(1),
+            RAX,
+        ));
+        self.emit(Instruction::Integer(IntegerOp::Or, Size::Quad, RAX, RCX));
+        self.emit(Instruction::IntegerToFloat(size, RCX, XMM0));
+        self.emit(Instruction::Float(FloatOp::Add, size, XMM0, XMM0));
+        self.label(done);
     }
 
-    fn generate_factor(&mut self, factor: &Factor) -> String {
-        let mut gen_s = String::new();
-
-        match factor {
-            Factor::FunCall { m_id, m_arguments } => {
-                for arg in m_arguments.iter().rev() {
-                    gen_s.push_str(&self.generate_expression(arg));
-                    gen_s.push_str("\tpushq\t%rax\n");
-                }
-                for i in 0..m_arguments.len() {
-                    match i {
-                        0 => gen_s.push_str(format!("\tpopq\t%rdi\n").as_str()),
-                        1 => gen_s.push_str(format!("\tpopq\t%rsi\n").as_str()),
-                        2 => gen_s.push_str(format!("\tpopq\t%rdx\n").as_str()),
-                        3 => gen_s.push_str(format!("\tpopq\t%rcx\n").as_str()),
-                        4 => gen_s.push_str(format!("\tpopq\t%r8\n").as_str()),
-                        5 => gen_s.push_str(format!("\tpopq\t%r9\n").as_str()),
-                        _ => {
-                            break;
-                        }
-                    }
-                }
+    // Converts the floating value in %xmm0 to a 64 bit integer in %rax
+    fn float_to_integer(&mut self, from: &Type, unsigned: bool) {
+        let size = size(from);
+        if !unsigned {
+            self.emit(Instruction::FloatToInteger(size, XMM0, RAX));
+            return;
+        }
+        // Values from 2^63 up don't fit a signed conversion, so take 2^63 off
+        // first and put the top bit back afterwards
+        let limit: i64 = match size {
+            Size::Long => 0x5f000000,
+            _ => 0x43e0000000000000,
+        };
+        let large = self.local_label();
+        let done = self.local_label();
+        self.emit(Instruction::Mov(size, Operand::Immediate(limit), RCX));
+        self.emit(Instruction::MovBits(size, RCX, XMM1));
+        self.emit(Instruction::CompareFloat(size, XMM1, XMM0));
+        self.emit(Instruction::JumpIf(Condition::AboveOrEqual, large.clone()));
+        self.emit(Instruction::FloatToInteger(size, XMM0, RAX));
+        self.emit(Instruction::Jump(done.clone()));
+        self.label(large);
+        self.emit(Instruction::Float(FloatOp::Subtract, size, XMM1, XMM0));
+        self.emit(Instruction::FloatToInteger(size, XMM0, RAX));
+        self.emit(Instruction::Integer(
+            IntegerOp::BitComplement,
+            Size::Quad,
+            Operand::Immediate(63),
+            RAX,
+        ));
+        self.label(done);
+    }
 
-                gen_s.push_str(format!("\tcall\t{}\n", m_id).as_str());
-                if m_arguments.len() > 6 {
-                    let mut size_to_deallocate = 0;
-                    size_to_deallocate = (m_arguments.len() - 6) * 8;
-                    gen_s.push_str(
-                        format!("\taddq\t${}, %rsp\n", size_to_deallocate)
-                            .as_str(),
-                    );
-                }
+    // Leaves the result in %rax or %xmm0
+    fn generate_convert(
+        &mut self,
+        op: ConvertOp,
+        from: &Type,
+        to: &Type,
+        operand: Temp,
+    ) {
+        match op {
+            ConvertOp::SignExtend => self.extend_to_rax(operand, from, true),
+            ConvertOp::ZeroExtend => self.extend_to_rax(operand, from, false),
+            // The low bytes already hold the narrower value
+            ConvertOp::Truncate => self.load(operand),
+            ConvertOp::SignedToFloat | ConvertOp::UnsignedToFloat => {
+                // Narrower unsigned values are zero extended, so they convert
+                // correctly as signed 64 bit numbers
+                let unsigned = op == ConvertOp::UnsignedToFloat;
+                self.extend_to_rax(operand, from, !unsigned);
+                self.integer_to_float(to, unsigned && from.size() == 8);
             }
-            Factor::Variable { m_var } => {
-                let var_offset = self.query_var(m_var).unwrap();
-                gen_s.push_str(
-                    format!("\tmovq\t{}(%rbp), %rax\n", var_offset).as_str(),
-                );
+            ConvertOp::FloatToSigned | ConvertOp::FloatToUnsigned => {
+                let unsigned = op == ConvertOp::FloatToUnsigned;
+                self.load(operand);
+                self.float_to_integer(from, unsigned && to.size() == 8);
             }
-            Factor::Constant { m_value } => {
-                gen_s.push_str(
-                    format!("\tmovq\t${0}, %rax\n", &m_value).as_str(),
-                );
+            ConvertOp::FloatToFloat => {
+                self.load(operand);
+                self.emit(Instruction::FloatToFloat(size(from), XMM0, XMM0));
             }
-            Factor::UnaryOperation { m_opertator, m_factor } => {
-                gen_s.push_str(&self.generate_factor(&m_factor));
-                match m_opertator {
-                    UnaryOperator::Complement => {
-                        let s = "\tnot\t%rax\n";
-                        gen_s.push_str(s);
-                    }
-                    UnaryOperator::Negation => {
-                        let s = "\tcmpq\t$0, %rax\n\
-                    \tmovq\t$0, %rax\n\
-                    \tsete %al\n";
-                        gen_s.push_str(s);
-                    }
-                    UnaryOperator::Minus => {
-                        let s = "\tneg\t%rax\n";
-                        gen_s.push_str(s);
+        }
+    }
+
+    // System V call: integer arguments go in %rdi, %rsi, %rdx, %rcx, %r8 and
+    // %r9, floating arguments in %xmm0 to %xmm7, and any others on the stack
+    // with the first of them at the lowest address. The frame is a multiple
+    // of 16 bytes, so padding the stack arguments to one keeps %rsp aligned.
+    // The caller saved registers in `saved` are kept across the call in
+    // their save slots. Leaves the result in %rax or %xmm0
+    fn generate_call(
+        &mut self,
+        id: &String,
+        arguments: &Vec<Temp>,
+        variadic: bool,
+        saved: &Vec<Register>,
+    ) {
+        let arg_types: Vec<Type> =
+            arguments.iter().map(|a| self.temps[*a]).collect();
+        let in_register = argument_registers(&arg_types);
+
+        let stack_arguments = in_register.iter().filter(|r| !**r).count();
+        let stack_size = (8 * stack_arguments as i64 + 15) / 16 * 16;
+        if stack_size > 0 {
+            self.emit(Instruction::Integer(
+                IntegerOp::Subtract,
+                Size::Quad,
+                Operand::Immediate(stack_size),
+                RSP,
+            ));
+        }
+        let mut stack_offset = 0;
+        let mut int_index = 0;
+        let mut sse_index = 0;
+        for (i, argument) in arguments.iter().enumerate() {
+            let home = self.operand(*argument);
+            if !in_register[i] {
+                // Floating values in registers are moved as their bits
+                let to_rax = match home {
+                    Operand::Register(r) if r.is_sse() => {
+                        Instruction::MovBits(Size::Quad, home, RAX)
                     }
-                }
-            }
-            Factor::Braced { m_expression } => {
-                gen_s.push_str(&self.generate_expression(m_expression));
+                    _ => Instruction::Mov(Size::Quad, home, RAX),
+                };
+                self.emit(to_rax);
+                self.emit(Instruction::Mov(
+                    Size::Quad,
+                    RAX,
+                    Operand::Memory {
+                        m_base: Register::Rsp,
+                        m_offset: stack_offset,
+                    },
+                ));
+                stack_offset += 8;
+            } else if arg_types[i].is_floating() {
+                self.emit(Instruction::MovFloat(
+                    size(&arg_types[i]),
+                    home,
+                    Operand::Register(Register::Xmm(sse_index)),
+                ));
+                sse_index += 1;
+            } else {
+                self.emit(Instruction::Mov(
+                    Size::Quad,
+                    home,
+                    Operand::Register(INT_ARGUMENT_REGISTERS[int_index]),
+                ));
+                int_index += 1;
             }
         }
 
-        return gen_s;
+        if variadic {
+            self.emit(Instruction::Mov(
+                Size::Long,
+                Operand::Immediate(sse_index as i64),
+                RAX,
+            ));
+        }
+        for register in saved {
+            let slot = self.save_slot(*register);
+            self.emit(save_move(*register, Operand::Register(*register), slot));
+        }
+        self.emit(Instruction::Call(id.clone()));
+        if stack_size > 0 {
+            self.emit(Instruction::Integer(
+                IntegerOp::Add,
+                Size::Quad,
+                Operand::Immediate(stack_size),
+                RSP,
+            ));
+        }
+        for register in saved {
+            let slot = self.save_slot(*register);
+            self.emit(save_move(*register, slot, Operand::Register(*register)));
+        }
     }
 }
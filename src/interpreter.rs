@@ -0,0 +1,792 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::Write;
+
+use crate::ir::{
+    self, BinaryOp, BlockId, CompareOp, ConvertOp, Instruction, StaticValue,
+    Temp, Terminator, Type, UnaryOp,
+};
+use crate::token::Span;
+
+#[derive(Debug)]
+pub enum RunError {
+    UndefinedFunction(String),
+    NoMain,
+    DivisionByZero,
+    // An access outside the program's data and stack
+    BadAddress(i64),
+    StackOverflow,
+    Output(String),
+    // printf conversions, written out as in the format
+    UnsupportedConversion(String),
+    MissingArgument(String),
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunError::UndefinedFunction(name) => {
+                write!(f, "undefined reference to `{}`", name)
+            }
+            RunError::NoMain => write!(f, "no main function"),
+            RunError::DivisionByZero => write!(f, "integer division by zero"),
+            RunError::BadAddress(address) => {
+                write!(f, "bad memory access at {:#x}", address)
+            }
+            RunError::StackOverflow => write!(f, "stack overflow"),
+            RunError::Output(e) => write!(f, "failed to write output: {}", e),
+            RunError::UnsupportedConversion(conversion) => {
+                write!(f, "unsupported printf conversion `{}`", conversion)
+            }
+            RunError::MissingArgument(conversion) => {
+                write!(f, "missing printf argument for `{}`", conversion)
+            }
+        }
+    }
+}
+
+// Functions provided by the interpreter when the program doesn't define them
+const BUILTINS: [&str; 2] = ["putchar", "printf"];
+
+// Memory starts away from 0 so that null pointers don't reach anything.
+// Static data comes first, then the stack, growing down from the end
+const MEMORY_START: i64 = 0x10000;
+const STACK_SIZE: i64 = 1 << 20;
+
+fn align_to(value: i64, align: i64) -> i64 {
+    return (value + align - 1) / align * align;
+}
+
+// Values are kept as the bits of their type, zero extended to 64 bits
+fn truncate(t: Type, value: u64) -> u64 {
+    match t.size() {
+        8 => return value,
+        size => return value & ((1u64 << (8 * size)) - 1),
+    }
+}
+
+fn sign_extend(t: Type, value: u64) -> i64 {
+    let shift = 64 - 8 * t.size();
+    return ((value << shift) as i64) >> shift;
+}
+
+// Floats are exactly representable as doubles, so both are worked on as f64
+// and rounded back to their type
+fn float_of(t: Type, bits: u64) -> f64 {
+    match t {
+        Type::F32 => return f32::from_bits(bits as u32) as f64,
+        _ => return f64::from_bits(bits),
+    }
+}
+
+fn bits_of(t: Type, value: f64) -> u64 {
+    match t {
+        Type::F32 => return (value as f32).to_bits() as u64,
+        _ => return value.to_bits(),
+    }
+}
+
+struct Memory {
+    bytes: Vec<u8>,
+}
+
+impl Memory {
+    fn range(&self, address: i64, size: i32) -> Result<usize, RunError> {
+        let start = address.wrapping_sub(MEMORY_START);
+        if start < 0 || start + size as i64 > self.bytes.len() as i64 {
+            return Err(RunError::BadAddress(address));
+        }
+        return Ok(start as usize);
+    }
+
+    fn load(&self, t: Type, address: i64) -> Result<u64, RunError> {
+        let start = self.range(address, t.size())?;
+        let mut bytes = [0u8; 8];
+        bytes[..t.size() as usize]
+            .copy_from_slice(&self.bytes[start..start + t.size() as usize]);
+        return Ok(u64::from_le_bytes(bytes));
+    }
+
+    fn store(
+        &mut self,
+        t: Type,
+        address: i64,
+        value: u64,
+    ) -> Result<(), RunError> {
+        let start = self.range(address, t.size())?;
+        self.bytes[start..start + t.size() as usize]
+            .copy_from_slice(&value.to_le_bytes()[..t.size() as usize]);
+        return Ok(());
+    }
+
+    // The bytes of a null terminated string
+    fn string(&self, address: i64) -> Result<Vec<u8>, RunError> {
+        let mut bytes: Vec<u8> = Vec::new();
+        loop {
+            let start = self.range(address + bytes.len() as i64, 1)?;
+            match self.bytes[start] {
+                0 => return Ok(bytes),
+                b => bytes.push(b),
+            }
+        }
+    }
+}
+
+// A call being run. Its slots are at the bottom of its part of the stack,
+// above them the pointer to its next variable argument, and above that its
+// variable arguments, 8 bytes each
+struct Frame<'a> {
+    function: &'a ir::Function,
+    values: Vec<u64>,
+    block: BlockId,
+    // Index of the next instruction in the block
+    next: usize,
+    slots: Vec<i64>,
+    va_list_state: i64,
+    // Stack pointer of the caller, restored on return
+    caller_stack: i64,
+    // Where the result of the call this frame is making goes
+    call_dest: Temp,
+    span: Option<Span>,
+}
+
+struct Interpreter<'a> {
+    functions: HashMap<&'a str, &'a ir::Function>,
+    addresses: HashMap<&'a str, i64>,
+    memory: Memory,
+    stack_pointer: i64,
+    stack_limit: i64,
+    frames: Vec<Frame<'a>>,
+}
+
+// Runs the program from main the way the generated code would, writing what
+// it prints to out, and gives main's result. Functions which aren't defined
+// have to be builtins
+pub fn run(
+    program: &ir::Program,
+    out: &mut dyn Write,
+) -> Result<i32, (RunError, Option<Span>)> {
+    let mut functions: HashMap<&str, &ir::Function> = HashMap::new();
+    for function in &program.m_functions {
+        functions.insert(&function.m_name, function);
+    }
+    for function in &program.m_functions {
+        for block in &function.m_blocks {
+            for instruction in &block.m_instructions {
+                match instruction {
+                    Instruction::Call { m_function, .. }
+                        if !functions.contains_key(m_function.as_str())
+                            && !BUILTINS.contains(&m_function.as_str()) =>
+                    {
+                        return Err((
+                            RunError::UndefinedFunction(m_function.clone()),
+                            Some(function.m_span.clone()),
+                        ));
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+    let main = match functions.get("main") {
+        Some(f) => *f,
+        None => return Err((RunError::NoMain, None)),
+    };
+
+    let mut addresses: HashMap<&str, i64> = HashMap::new();
+    let mut end = MEMORY_START;
+    for data in &program.m_data {
+        end = align_to(end, data.m_type.size() as i64);
+        addresses.insert(&data.m_name, end);
+        end += data.m_type.size() as i64;
+    }
+    let stack_limit = align_to(end, 16);
+    let stack_top = stack_limit + STACK_SIZE;
+    let mut memory =
+        Memory { bytes: vec![0; (stack_top - MEMORY_START) as usize] };
+    for data in &program.m_data {
+        let value = match &data.m_value {
+            Some(StaticValue::Constant(v)) => *v,
+            Some(StaticValue::Address(symbol, offset)) => {
+                match addresses.get(symbol.as_str()) {
+                    Some(a) => *a + offset,
+                    None => panic!("No static data named {}", symbol),
+                }
+            }
+            None => continue,
+        };
+        match memory.store(data.m_type, addresses[&*data.m_name], value as u64)
+        {
+            Ok(_) => (),
+            Err(e) => return Err((e, None)),
+        }
+    }
+
+    let mut interpreter = Interpreter {
+        functions,
+        addresses,
+        memory,
+        stack_pointer: stack_top,
+        stack_limit,
+        frames: Vec::new(),
+    };
+    let arguments: Vec<(Type, u64)> =
+        main.m_parameters.iter().map(|p| (main.m_temps[*p], 0)).collect();
+    let result = match interpreter.call(main, &arguments, arguments.len()) {
+        Ok(_) => interpreter.execute(out),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(value) => return Ok(value as i32),
+        Err(e) => {
+            let span = match interpreter.frames.last() {
+                Some(frame) => frame.span.clone(),
+                None => None,
+            };
+            return Err((e, span));
+        }
+    }
+}
+
+impl<'a> Interpreter<'a> {
+    // Starts a call of a function the program defines
+    fn call(
+        &mut self,
+        function: &'a ir::Function,
+        arguments: &[(Type, u64)],
+        fixed: usize,
+    ) -> Result<(), RunError> {
+        let variable = &arguments[fixed..];
+        let mut size = 0;
+        let mut slots: Vec<i64> = Vec::new();
+        for slot in &function.m_slots {
+            size = align_to(size, slot.m_align.max(1) as i64);
+            slots.push(size);
+            size += slot.m_size as i64;
+        }
+        size = align_to(size, 8);
+        let va_list_state = size;
+        size += 8 + 8 * variable.len() as i64;
+
+        let caller_stack = self.stack_pointer;
+        let base = (self.stack_pointer - size) / 16 * 16;
+        if base < self.stack_limit {
+            return Err(RunError::StackOverflow);
+        }
+        self.stack_pointer = base;
+        for (i, (t, value)) in variable.iter().enumerate() {
+            let address = base + va_list_state + 8 + 8 * i as i64;
+            self.memory.store(*t, address, *value)?;
+        }
+
+        let mut values = vec![0; function.m_temps.len()];
+        for (parameter, (_, value)) in
+            function.m_parameters.iter().zip(arguments)
+        {
+            values[*parameter] = *value;
+        }
+        self.frames.push(Frame {
+            function,
+            values,
+            block: 0,
+            next: 0,
+            slots: slots.iter().map(|s| base + s).collect(),
+            va_list_state: base + va_list_state,
+            caller_stack,
+            call_dest: 0,
+            span: None,
+        });
+        return Ok(());
+    }
+
+    // Runs until the first call returns, giving its result
+    fn execute(&mut self, out: &mut dyn Write) -> Result<u64, RunError> {
+        loop {
+            let frame = match self.frames.last_mut() {
+                Some(f) => f,
+                None => panic!("Nothing to run"),
+            };
+            let function = frame.function;
+            let block = &function.m_blocks[frame.block];
+            if frame.next < block.m_instructions.len() {
+                let instruction = &block.m_instructions[frame.next];
+                frame.next += 1;
+                self.instruction(instruction, out)?;
+                continue;
+            }
+            match &block.m_terminator {
+                // Every argument is read before any parameter is written
+                Terminator::Jump { m_target, m_arguments } => {
+                    let values: Vec<u64> =
+                        m_arguments.iter().map(|a| frame.values[*a]).collect();
+                    let target = &function.m_blocks[*m_target];
+                    for (parameter, value) in
+                        target.m_parameters.iter().zip(values)
+                    {
+                        frame.values[*parameter] = value;
+                    }
+                    frame.block = *m_target;
+                    frame.next = 0;
+                }
+                Terminator::Branch { m_condition, m_true, m_false } => {
+                    frame.block = match frame.values[*m_condition] {
+                        0 => *m_false,
+                        _ => *m_true,
+                    };
+                    frame.next = 0;
+                }
+                Terminator::Return { m_value } => {
+                    let value = frame.values[*m_value];
+                    self.stack_pointer = frame.caller_stack;
+                    self.frames.pop();
+                    match self.frames.last_mut() {
+                        Some(caller) => caller.values[caller.call_dest] = value,
+                        None => return Ok(value),
+                    }
+                }
+            }
+        }
+    }
+
+    fn value(&self, temp: Temp) -> u64 {
+        match self.frames.last() {
+            Some(frame) => return frame.values[temp],
+            None => panic!("No frame for temporary {}", temp),
+        }
+    }
+
+    fn set(&mut self, temp: Temp, value: u64) {
+        match self.frames.last_mut() {
+            Some(frame) => frame.values[temp] = value,
+            None => panic!("No frame for temporary {}", temp),
+        }
+    }
+
+    fn frame(&self) -> &Frame<'a> {
+        match self.frames.last() {
+            Some(frame) => return frame,
+            None => panic!("No frame"),
+        }
+    }
+
+    fn instruction(
+        &mut self,
+        instruction: &'a Instruction,
+        out: &mut dyn Write,
+    ) -> Result<(), RunError> {
+        match instruction {
+            Instruction::Constant { m_dest, m_type, m_value } => {
+                self.set(*m_dest, truncate(*m_type, *m_value as u64));
+            }
+            Instruction::SlotAddress { m_dest, m_slot } => {
+                let address = self.frame().slots[*m_slot];
+                self.set(*m_dest, address as u64);
+            }
+            Instruction::GlobalAddress { m_dest, m_name } => {
+                let address = match self.addresses.get(m_name.as_str()) {
+                    Some(a) => *a,
+                    None => panic!("No static data named {}", m_name),
+                };
+                self.set(*m_dest, address as u64);
+            }
+            Instruction::Load { m_dest, m_type, m_address, m_volatile: _ } => {
+                let address = self.value(*m_address) as i64;
+                let value = self.memory.load(*m_type, address)?;
+                self.set(*m_dest, value);
+            }
+            Instruction::Store {
+                m_type,
+                m_address,
+                m_value,
+                m_volatile: _,
+            } => {
+                let address = self.value(*m_address) as i64;
+                let value = self.value(*m_value);
+                self.memory.store(*m_type, address, value)?;
+            }
+            Instruction::Unary { m_dest, m_op, m_type, m_operand } => {
+                let operand = self.value(*m_operand);
+                let value = match (m_op, m_type.is_floating()) {
+                    (_, true) => bits_of(*m_type, -float_of(*m_type, operand)),
+                    (UnaryOp::Negate, false) => operand.wrapping_neg(),
+                    (UnaryOp::Not, false) => !operand,
+                };
+                self.set(*m_dest, truncate(*m_type, value));
+            }
+            Instruction::Binary { m_dest, m_op, m_type, m_left, m_right } => {
+                let left = self.value(*m_left);
+                let right = self.value(*m_right);
+                let value = binary(*m_op, *m_type, left, right)?;
+                self.set(*m_dest, truncate(*m_type, value));
+            }
+            Instruction::Compare { m_dest, m_op, m_type, m_left, m_right } => {
+                let left = self.value(*m_left);
+                let right = self.value(*m_right);
+                let value = compare(*m_op, *m_type, left, right);
+                self.set(*m_dest, value as u64);
+            }
+            Instruction::Convert { m_dest, m_op, m_from, m_to, m_operand } => {
+                let operand = self.value(*m_operand);
+                let value = convert(*m_op, *m_from, *m_to, operand);
+                self.set(*m_dest, truncate(*m_to, value));
+            }
+            Instruction::Call {
+                m_dest,
+                m_function,
+                m_arguments,
+                m_variadic,
+            } => {
+                let frame = self.frame();
+                let arguments: Vec<(Type, u64)> = m_arguments
+                    .iter()
+                    .map(|a| (frame.function.m_temps[*a], frame.values[*a]))
+                    .collect();
+                let fixed = m_variadic.unwrap_or(arguments.len());
+                match self.functions.get(m_function.as_str()) {
+                    Some(function) => {
+                        let function = *function;
+                        match self.frames.last_mut() {
+                            Some(frame) => frame.call_dest = *m_dest,
+                            None => (),
+                        }
+                        self.call(function, &arguments, fixed)?;
+                    }
+                    None => {
+                        let value =
+                            self.builtin(m_function, &arguments, out)?;
+                        self.set(*m_dest, value as u32 as u64);
+                    }
+                }
+            }
+            // The va_list holds the address of the pointer to the next
+            // variable argument, so that va_arg can move it on
+            Instruction::VaStart { m_list } => {
+                let state = self.frame().va_list_state;
+                self.memory.store(Type::I64, state, (state + 8) as u64)?;
+                let list = self.value(*m_list) as i64;
+                self.memory.store(Type::I64, list, state as u64)?;
+            }
+            Instruction::VaArg { m_dest, m_type, m_list } => {
+                let state = self.value(*m_list) as i64;
+                let next = self.memory.load(Type::I64, state)? as i64;
+                let value = self.memory.load(*m_type, next)?;
+                self.memory.store(Type::I64, state, (next + 8) as u64)?;
+                self.set(*m_dest, value);
+            }
+            Instruction::Location { m_span } => match self.frames.last_mut() {
+                Some(frame) => frame.span = Some(m_span.clone()),
+                None => (),
+            },
+        }
+        return Ok(());
+    }
+
+    // Runs a builtin, giving its int result
+    fn builtin(
+        &mut self,
+        name: &str,
+        arguments: &[(Type, u64)],
+        out: &mut dyn Write,
+    ) -> Result<i32, RunError> {
+        let (bytes, result) = match name {
+            "putchar" => {
+                let c = arguments.first().map(|a| a.1 as u8).unwrap_or(0);
+                (vec![c], c as i32)
+            }
+            _ => {
+                let format = match arguments.first() {
+                    Some((_, address)) => {
+                        self.memory.string(*address as i64)?
+                    }
+                    None => Vec::new(),
+                };
+                let bytes = printf(&self.memory, &format, &arguments[1..])?;
+                let count = bytes.len() as i32;
+                (bytes, count)
+            }
+        };
+        match out.write_all(&bytes) {
+            Ok(_) => return Ok(result),
+            Err(e) => return Err(RunError::Output(e.to_string())),
+        }
+    }
+}
+
+fn binary(
+    op: BinaryOp,
+    t: Type,
+    left: u64,
+    right: u64,
+) -> Result<u64, RunError> {
+    if t.is_floating() {
+        let (a, b) = (float_of(t, left), float_of(t, right));
+        let value = match op {
+            BinaryOp::Add => a + b,
+            BinaryOp::Subtract => a - b,
+            BinaryOp::Multiply => a * b,
+            _ => a / b,
+        };
+        return Ok(bits_of(t, value));
+    }
+    let (a, b) = (sign_extend(t, left), sign_extend(t, right));
+    match op {
+        BinaryOp::Add => return Ok(left.wrapping_add(right)),
+        BinaryOp::Subtract => return Ok(left.wrapping_sub(right)),
+        BinaryOp::Multiply => return Ok(left.wrapping_mul(right)),
+        _ if right == 0 => return Err(RunError::DivisionByZero),
+        BinaryOp::Divide => return Ok(a.wrapping_div(b) as u64),
+        BinaryOp::Remainder => return Ok(a.wrapping_rem(b) as u64),
+        BinaryOp::UnsignedDivide => return Ok(left / right),
+        BinaryOp::UnsignedRemainder => return Ok(left % right),
+    }
+}
+
+fn compare(op: CompareOp, t: Type, left: u64, right: u64) -> bool {
+    if t.is_floating() {
+        let (a, b) = (float_of(t, left), float_of(t, right));
+        match op {
+            CompareOp::Equal => return a == b,
+            CompareOp::NotEqual => return a != b,
+            CompareOp::Less | CompareOp::UnsignedLess => return a < b,
+            CompareOp::LessOrEqual | CompareOp::UnsignedLessOrEqual => {
+                return a <= b
+            }
+            CompareOp::Greater | CompareOp::UnsignedGreater => return a > b,
+            CompareOp::GreaterOrEqual | CompareOp::UnsignedGreaterOrEqual => {
+                return a >= b
+            }
+        }
+    }
+    let (a, b) = (sign_extend(t, left), sign_extend(t, right));
+    match op {
+        CompareOp::Equal => return left == right,
+        CompareOp::NotEqual => return left != right,
+        CompareOp::Less => return a < b,
+        CompareOp::LessOrEqual => return a <= b,
+        CompareOp::Greater => return a > b,
+        CompareOp::GreaterOrEqual => return a >= b,
+        CompareOp::UnsignedLess => return left < right,
+        CompareOp::UnsignedLessOrEqual => return left <= right,
+        CompareOp::UnsignedGreater => return left > right,
+        CompareOp::UnsignedGreaterOrEqual => return left >= right,
+    }
+}
+
+// Conversions to floating round once, straight from the integer
+fn convert(op: ConvertOp, from: Type, to: Type, value: u64) -> u64 {
+    match op {
+        ConvertOp::SignExtend => return sign_extend(from, value) as u64,
+        ConvertOp::ZeroExtend | ConvertOp::Truncate => return value,
+        ConvertOp::SignedToFloat => {
+            let integer = sign_extend(from, value);
+            match to {
+                Type::F32 => return (integer as f32).to_bits() as u64,
+                _ => return (integer as f64).to_bits(),
+            }
+        }
+        ConvertOp::UnsignedToFloat => match to {
+            Type::F32 => return (value as f32).to_bits() as u64,
+            _ => return (value as f64).to_bits(),
+        },
+        ConvertOp::FloatToSigned => return float_of(from, value) as i64 as u64,
+        ConvertOp::FloatToUnsigned => {
+            let float = float_of(from, value);
+            match float < 0.0 {
+                true => return float as i64 as u64,
+                false => return float as u64,
+            }
+        }
+        ConvertOp::FloatToFloat => return bits_of(to, float_of(from, value)),
+    }
+}
+
+// The output of printf for the format and its variable arguments, which
+// have been promoted to int, long or double. Supports the flags - 0 + #
+// and space, a width, a precision, the lengths hh h l ll and z, and the
+// conversions d i u x X o c s p f and %. Any other conversion, or one
+// without an argument, is an error
+fn printf(
+    memory: &Memory,
+    format: &[u8],
+    arguments: &[(Type, u64)],
+) -> Result<Vec<u8>, RunError> {
+    let mut out: Vec<u8> = Vec::new();
+    let mut arguments = arguments.iter();
+    let mut i = 0;
+    while i < format.len() {
+        if format[i] != b'%' {
+            out.push(format[i]);
+            i += 1;
+            continue;
+        }
+        let start = i;
+        i += 1;
+        let mut flags: HashSet<u8> = HashSet::new();
+        while i < format.len() && b"-0+ #".contains(&format[i]) {
+            flags.insert(format[i]);
+            i += 1;
+        }
+        let mut width = 0;
+        while i < format.len() && format[i].is_ascii_digit() {
+            width = width * 10 + (format[i] - b'0') as usize;
+            i += 1;
+        }
+        let mut precision: Option<usize> = None;
+        if i < format.len() && format[i] == b'.' {
+            i += 1;
+            let mut p = 0;
+            while i < format.len() && format[i].is_ascii_digit() {
+                p = p * 10 + (format[i] - b'0') as usize;
+                i += 1;
+            }
+            precision = Some(p);
+        }
+        // Bytes of the integer argument
+        let mut size = 4;
+        while i < format.len() && b"hlz".contains(&format[i]) {
+            size = match (format[i], size) {
+                (b'h', 4) => 2,
+                (b'h', _) => 1,
+                _ => 8,
+            };
+            i += 1;
+        }
+        let conversion = match format.get(i) {
+            Some(c) => *c,
+            None => break,
+        };
+        i += 1;
+        if conversion == b'%' {
+            out.push(b'%');
+            continue;
+        }
+
+        if !b"diuxXopcsfF".contains(&conversion) {
+            // Up to the letter ending the conversion, as in "%*d"
+            let end = match format[i - 1..]
+                .iter()
+                .position(|c| c.is_ascii_alphabetic() && !b"hlz".contains(c))
+            {
+                Some(p) => i + p,
+                None => format.len(),
+            };
+            let text = String::from_utf8_lossy(&format[start..end]);
+            return Err(RunError::UnsupportedConversion(text.to_string()));
+        }
+        let bits = match arguments.next() {
+            Some((_, bits)) => *bits,
+            None => {
+                let text = String::from_utf8_lossy(&format[start..i]);
+                return Err(RunError::MissingArgument(text.to_string()));
+            }
+        };
+        let unsigned = match size {
+            8 => bits,
+            _ => bits & ((1u64 << (8 * size)) - 1),
+        };
+        let signed = ((unsigned << (64 - 8 * size)) as i64) >> (64 - 8 * size);
+        // Sign and digits, which the width pads between
+        let (sign, digits): (&str, String) = match conversion {
+            b'd' | b'i' => (
+                match (signed < 0, flags.contains(&b'+'), flags.contains(&b' '))
+                {
+                    (true, _, _) => "-",
+                    (false, true, _) => "+",
+                    (false, false, true) => " ",
+                    _ => "",
+                },
+                signed.unsigned_abs().to_string(),
+            ),
+            b'u' => ("", unsigned.to_string()),
+            // # marks the base of non-zero values
+            b'x' => match (flags.contains(&b'#'), unsigned) {
+                (true, 1..) => ("0x", format!("{:x}", unsigned)),
+                _ => ("", format!("{:x}", unsigned)),
+            },
+            b'X' => match (flags.contains(&b'#'), unsigned) {
+                (true, 1..) => ("0X", format!("{:X}", unsigned)),
+                _ => ("", format!("{:X}", unsigned)),
+            },
+            b'o' => match (flags.contains(&b'#'), unsigned) {
+                (true, 1..) => ("", format!("0{:o}", unsigned)),
+                _ => ("", format!("{:o}", unsigned)),
+            },
+            b'p' => match bits {
+                0 => ("", String::from("(nil)")),
+                _ => ("0x", format!("{:x}", bits)),
+            },
+            // Characters and strings are written as bytes, so are padded
+            // here
+            b'c' | b's' => {
+                let mut bytes = match conversion {
+                    b'c' => vec![bits as u8],
+                    _ => memory.string(bits as i64)?,
+                };
+                match precision {
+                    Some(p) if conversion == b's' => bytes.truncate(p),
+                    _ => (),
+                }
+                let padding = vec![b' '; width.saturating_sub(bytes.len())];
+                match flags.contains(&b'-') {
+                    true => {
+                        out.extend(bytes);
+                        out.extend(padding);
+                    }
+                    false => {
+                        out.extend(padding);
+                        out.extend(bytes);
+                    }
+                }
+                continue;
+            }
+            b'f' | b'F' => {
+                let float = f64::from_bits(bits);
+                let sign = match (
+                    float.is_sign_negative(),
+                    flags.contains(&b'+'),
+                    flags.contains(&b' '),
+                ) {
+                    (true, _, _) => "-",
+                    (false, true, _) => "+",
+                    (false, false, true) => " ",
+                    _ => "",
+                };
+                let magnitude = float.abs();
+                let digits = match (magnitude.is_nan(), magnitude.is_infinite())
+                {
+                    (true, _) => String::from("nan"),
+                    (_, true) => String::from("inf"),
+                    _ => format!("{:.*}", precision.unwrap_or(6), magnitude),
+                };
+                (sign, digits)
+            }
+            _ => panic!("Unsupported conversion {}", conversion as char),
+        };
+        // The precision of an integer is its least number of digits
+        let integer = b"diuxXo".contains(&conversion);
+        let digits = match precision {
+            Some(p) if integer && digits.len() < p => {
+                format!("{}{}", "0".repeat(p - digits.len()), digits)
+            }
+            _ => digits,
+        };
+        let length = sign.len() + digits.len();
+        let padding = width.saturating_sub(length);
+        if flags.contains(&b'-') {
+            out.extend_from_slice(sign.as_bytes());
+            out.extend_from_slice(digits.as_bytes());
+            out.extend(vec![b' '; padding]);
+        } else if flags.contains(&b'0')
+            && (b"fF".contains(&conversion) || integer && precision.is_none())
+        {
+            out.extend_from_slice(sign.as_bytes());
+            out.extend(vec![b'0'; padding]);
+            out.extend_from_slice(digits.as_bytes());
+        } else {
+            out.extend(vec![b' '; padding]);
+            out.extend_from_slice(sign.as_bytes());
+            out.extend_from_slice(digits.as_bytes());
+        }
+    }
+    return Ok(out);
+}
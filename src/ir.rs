@@ -0,0 +1,631 @@
+use std::fmt;
+
+use crate::parser::VarType;
+use crate::token::Span;
+
+// Three-address intermediate representation between the analysed program and
+// assembly. Each function is a list of basic blocks of instructions over
+// numbered temporaries, which are each assigned exactly once. Where control
+// flow joins, values are passed to the parameters of the next block instead
+// of merging with phi instructions. Variables live in memory, either stack
+// slots of the function or global data, and are only reached through Load
+// and Store
+
+// Numbers of a function's temporaries and blocks
+pub type Temp = usize;
+pub type BlockId = usize;
+
+// Machine level type of a temporary or a memory access. Signedness is not
+// part of the type but of the operations which depend on it. Addresses are
+// I64
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Type {
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl Type {
+    pub fn size(&self) -> i32 {
+        match self {
+            Type::I8 => return 1,
+            Type::I16 => return 2,
+            Type::I32 | Type::F32 => return 4,
+            Type::I64 | Type::F64 => return 8,
+        }
+    }
+
+    pub fn is_floating(&self) -> bool {
+        return *self == Type::F32 || *self == Type::F64;
+    }
+}
+
+// The IR type holding a value of a C type
+pub fn ir_type(var_type: &VarType) -> Type {
+    match var_type {
+        VarType::Char | VarType::SChar | VarType::UChar => return Type::I8,
+        VarType::Short | VarType::UShort => return Type::I16,
+        VarType::Int | VarType::UInt => return Type::I32,
+        VarType::Long
+        | VarType::ULong
+        | VarType::Pointer(_, _)
+        | VarType::VaList => return Type::I64,
+        VarType::Float => return Type::F32,
+        VarType::Double => return Type::F64,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    Negate,
+    // Bitwise complement, only of integers
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    // Division of floating values is always Divide
+    Divide,
+    UnsignedDivide,
+    Remainder,
+    UnsignedRemainder,
+}
+
+// Comparisons give an I32 of 0 or 1. Floating comparisons are false when an
+// operand is NaN, except NotEqual which is true
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CompareOp {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    UnsignedLess,
+    UnsignedLessOrEqual,
+    UnsignedGreater,
+    UnsignedGreaterOrEqual,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConvertOp {
+    // Integer to a wider integer
+    SignExtend,
+    ZeroExtend,
+    // Integer to a narrower integer, keeping the low bits
+    Truncate,
+    // Integer, read as signed or unsigned, to floating
+    SignedToFloat,
+    UnsignedToFloat,
+    // Floating to integer, rounding toward zero
+    FloatToSigned,
+    FloatToUnsigned,
+    // Between float and double
+    FloatToFloat,
+}
+
+#[derive(Debug, Clone)]
+pub enum Instruction {
+    // Floating constants hold the bits of the value, of an f32 for F32
+    Constant {
+        m_dest: Temp,
+        m_type: Type,
+        m_value: i64,
+    },
+    SlotAddress {
+        m_dest: Temp,
+        m_slot: usize,
+    },
+    // Address of a global variable or the label of a static local
+    GlobalAddress {
+        m_dest: Temp,
+        m_name: String,
+    },
+    Load {
+        m_dest: Temp,
+        m_type: Type,
+        m_address: Temp,
+        m_volatile: bool,
+    },
+    Store {
+        m_type: Type,
+        m_address: Temp,
+        m_value: Temp,
+        m_volatile: bool,
+    },
+    Unary {
+        m_dest: Temp,
+        m_op: UnaryOp,
+        m_type: Type,
+        m_operand: Temp,
+    },
+    Binary {
+        m_dest: Temp,
+        m_op: BinaryOp,
+        m_type: Type,
+        m_left: Temp,
+        m_right: Temp,
+    },
+    Compare {
+        m_dest: Temp,
+        m_op: CompareOp,
+        m_type: Type,
+        m_left: Temp,
+        m_right: Temp,
+    },
+    Convert {
+        m_dest: Temp,
+        m_op: ConvertOp,
+        m_from: Type,
+        m_to: Type,
+        m_operand: Temp,
+    },
+    // Arguments after the fixed parameters of a variadic function have been
+    // promoted already. For a variadic function m_variadic is how many of
+    // the arguments are for its fixed parameters
+    Call {
+        m_dest: Temp,
+        m_function: String,
+        m_arguments: Vec<Temp>,
+        m_variadic: Option<usize>,
+    },
+    // Sets up the argument state of the variadic function and stores its
+    // address in the va_list at m_list
+    VaStart {
+        m_list: Temp,
+    },
+    // Takes the next variable argument through the value of a va_list
+    VaArg {
+        m_dest: Temp,
+        m_type: Type,
+        m_list: Temp,
+    },
+    // The source position of the code which follows
+    Location {
+        m_span: Span,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum Terminator {
+    Jump { m_target: BlockId, m_arguments: Vec<Temp> },
+    // Goes to m_true when the integer condition isn't zero
+    Branch { m_condition: Temp, m_true: BlockId, m_false: BlockId },
+    Return { m_value: Temp },
+}
+
+// Blocks with parameters are only entered by Jump, which passes their values
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub m_parameters: Vec<Temp>,
+    pub m_instructions: Vec<Instruction>,
+    pub m_terminator: Terminator,
+}
+
+// Stack memory of a function, for a variable
+#[derive(Debug, Clone)]
+pub struct Slot {
+    pub m_name: String,
+    pub m_size: i32,
+    pub m_align: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub m_name: String,
+    // Visible outside the file
+    pub m_global: bool,
+    pub m_parameters: Vec<Temp>,
+    pub m_variadic: bool,
+    pub m_return_type: Type,
+    // Type of each temporary
+    pub m_temps: Vec<Type>,
+    pub m_slots: Vec<Slot>,
+    // Starts at the first block
+    pub m_blocks: Vec<Block>,
+    pub m_span: Span,
+}
+
+// What a variable with static storage duration starts as
+#[derive(Debug, Clone, PartialEq)]
+pub enum StaticValue {
+    // The bits of the value, as constants hold them
+    Constant(i64),
+    // The address of another variable plus a number of bytes, filled in
+    // when the program is linked
+    Address(String, i64),
+}
+
+// A variable with static storage duration. The value is None for zero
+#[derive(Debug, Clone)]
+pub struct Data {
+    pub m_name: String,
+    pub m_global: bool,
+    pub m_read_only: bool,
+    pub m_type: Type,
+    pub m_value: Option<StaticValue>,
+}
+
+// "symbol", "symbol+4" or "symbol-4", as assemblers write an address
+pub fn address_text(symbol: &str, offset: i64) -> String {
+    match offset {
+        0 => return String::from(symbol),
+        1.. => return format!("{}+{}", symbol, offset),
+        _ => return format!("{}{}", symbol, offset),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub m_functions: Vec<Function>,
+    pub m_data: Vec<Data>,
+}
+
+impl Program {
+    // The files the code came from, in the order they're first reached, so
+    // the backends can number them for .file before the first function
+    pub fn source_files(&self) -> Vec<String> {
+        let mut files: Vec<String> = Vec::new();
+        for function in &self.m_functions {
+            let spans = function.m_blocks.iter().flat_map(|b| {
+                b.m_instructions.iter().filter_map(|i| match i {
+                    Instruction::Location { m_span } => Some(m_span),
+                    _ => None,
+                })
+            });
+            for span in std::iter::once(&function.m_span).chain(spans) {
+                if !files.iter().any(|f| **f == *span.m_file) {
+                    files.push(span.m_file.to_string());
+                }
+            }
+        }
+        return files;
+    }
+}
+
+impl Instruction {
+    // The temporary the instruction defines
+    pub fn dest(&self) -> Option<Temp> {
+        match self {
+            Instruction::Constant { m_dest, .. }
+            | Instruction::SlotAddress { m_dest, .. }
+            | Instruction::GlobalAddress { m_dest, .. }
+            | Instruction::Load { m_dest, .. }
+            | Instruction::Unary { m_dest, .. }
+            | Instruction::Binary { m_dest, .. }
+            | Instruction::Compare { m_dest, .. }
+            | Instruction::Convert { m_dest, .. }
+            | Instruction::Call { m_dest, .. }
+            | Instruction::VaArg { m_dest, .. } => return Some(*m_dest),
+            Instruction::Store { .. }
+            | Instruction::VaStart { .. }
+            | Instruction::Location { .. } => return None,
+        }
+    }
+
+    pub fn dest_mut(&mut self) -> Option<&mut Temp> {
+        match self {
+            Instruction::Constant { m_dest, .. }
+            | Instruction::SlotAddress { m_dest, .. }
+            | Instruction::GlobalAddress { m_dest, .. }
+            | Instruction::Load { m_dest, .. }
+            | Instruction::Unary { m_dest, .. }
+            | Instruction::Binary { m_dest, .. }
+            | Instruction::Compare { m_dest, .. }
+            | Instruction::Convert { m_dest, .. }
+            | Instruction::Call { m_dest, .. }
+            | Instruction::VaArg { m_dest, .. } => return Some(m_dest),
+            Instruction::Store { .. }
+            | Instruction::VaStart { .. }
+            | Instruction::Location { .. } => return None,
+        }
+    }
+
+    // The temporaries the instruction reads
+    pub fn operands(&self) -> Vec<Temp> {
+        let mut instruction = self.clone();
+        return instruction.operands_mut().into_iter().map(|t| *t).collect();
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Temp> {
+        match self {
+            Instruction::Constant { .. }
+            | Instruction::SlotAddress { .. }
+            | Instruction::GlobalAddress { .. }
+            | Instruction::Location { .. } => return Vec::new(),
+            Instruction::Load { m_address, .. } => return vec![m_address],
+            Instruction::Store { m_address, m_value, .. } => {
+                return vec![m_address, m_value]
+            }
+            Instruction::Unary { m_operand, .. }
+            | Instruction::Convert { m_operand, .. } => return vec![m_operand],
+            Instruction::Binary { m_left, m_right, .. }
+            | Instruction::Compare { m_left, m_right, .. } => {
+                return vec![m_left, m_right]
+            }
+            Instruction::Call { m_arguments, .. } => {
+                return m_arguments.iter_mut().collect()
+            }
+            Instruction::VaStart { m_list } => return vec![m_list],
+            Instruction::VaArg { m_list, .. } => return vec![m_list],
+        }
+    }
+
+    // Whether the instruction does more than define its temporary, so has to
+    // stay even when the temporary is never used
+    pub fn has_side_effects(&self) -> bool {
+        match self {
+            Instruction::Load { m_volatile, .. } => return *m_volatile,
+            Instruction::Store { .. }
+            | Instruction::Call { .. }
+            | Instruction::VaStart { .. }
+            | Instruction::VaArg { .. }
+            | Instruction::Location { .. } => return true,
+            _ => return false,
+        }
+    }
+}
+
+impl Terminator {
+    // The blocks control can go to next
+    pub fn successors(&self) -> Vec<BlockId> {
+        let mut terminator = self.clone();
+        return terminator.successors_mut().into_iter().map(|b| *b).collect();
+    }
+
+    pub fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Terminator::Jump { m_target, .. } => return vec![m_target],
+            Terminator::Branch { m_true, m_false, .. } => {
+                return vec![m_true, m_false]
+            }
+            Terminator::Return { .. } => return Vec::new(),
+        }
+    }
+
+    pub fn operands(&self) -> Vec<Temp> {
+        let mut terminator = self.clone();
+        return terminator.operands_mut().into_iter().map(|t| *t).collect();
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Temp> {
+        match self {
+            Terminator::Jump { m_arguments, .. } => {
+                return m_arguments.iter_mut().collect()
+            }
+            Terminator::Branch { m_condition, .. } => return vec![m_condition],
+            Terminator::Return { m_value } => return vec![m_value],
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::I8 => write!(f, "i8"),
+            Type::I16 => write!(f, "i16"),
+            Type::I32 => write!(f, "i32"),
+            Type::I64 => write!(f, "i64"),
+            Type::F32 => write!(f, "f32"),
+            Type::F64 => write!(f, "f64"),
+        }
+    }
+}
+
+// Lower case names, as in the dump
+fn op_name<T: fmt::Debug>(op: &T) -> String {
+    let mut name = String::new();
+    for (i, c) in format!("{:?}", op).chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            name.push('_');
+        }
+        name.push(c.to_ascii_lowercase());
+    }
+    return name;
+}
+
+fn temp_list(temps: &Vec<Temp>) -> String {
+    let names: Vec<String> = temps.iter().map(|t| format!("%{}", t)).collect();
+    return names.join(", ");
+}
+
+// Floating constants are shown as their value
+fn constant_text(var_type: &Type, value: i64) -> String {
+    match var_type {
+        Type::F32 => return format!("{:?}", f32::from_bits(value as u32)),
+        Type::F64 => return format!("{:?}", f64::from_bits(value as u64)),
+        _ => return format!("{}", value),
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::Constant { m_dest, m_type, m_value } => write!(
+                f,
+                "%{} = const {} {}",
+                m_dest,
+                m_type,
+                constant_text(m_type, *m_value)
+            ),
+            Instruction::SlotAddress { m_dest, m_slot } => {
+                write!(f, "%{} = slot ${}", m_dest, m_slot)
+            }
+            Instruction::GlobalAddress { m_dest, m_name } => {
+                write!(f, "%{} = global @{}", m_dest, m_name)
+            }
+            Instruction::Load { m_dest, m_type, m_address, m_volatile } => {
+                let volatile = if *m_volatile { " volatile" } else { "" };
+                write!(f, "%{} = load{} {} %{}", m_dest, volatile, m_type, {
+                    m_address
+                })
+            }
+            Instruction::Store { m_type, m_address, m_value, m_volatile } => {
+                let volatile = if *m_volatile { " volatile" } else { "" };
+                write!(f, "store{} {} %{}, %{}", volatile, m_type, m_value, {
+                    m_address
+                })
+            }
+            Instruction::Unary { m_dest, m_op, m_type, m_operand } => write!(
+                f,
+                "%{} = {} {} %{}",
+                m_dest,
+                op_name(m_op),
+                m_type,
+                m_operand
+            ),
+            Instruction::Binary { m_dest, m_op, m_type, m_left, m_right } => {
+                write!(
+                    f,
+                    "%{} = {} {} %{}, %{}",
+                    m_dest,
+                    op_name(m_op),
+                    m_type,
+                    m_left,
+                    m_right
+                )
+            }
+            Instruction::Compare { m_dest, m_op, m_type, m_left, m_right } => {
+                write!(
+                    f,
+                    "%{} = cmp {} {} %{}, %{}",
+                    m_dest,
+                    op_name(m_op),
+                    m_type,
+                    m_left,
+                    m_right
+                )
+            }
+            Instruction::Convert { m_dest, m_op, m_from, m_to, m_operand } => {
+                write!(
+                    f,
+                    "%{} = {} {} %{} to {}",
+                    m_dest,
+                    op_name(m_op),
+                    m_from,
+                    m_operand,
+                    m_to
+                )
+            }
+            Instruction::Call {
+                m_dest,
+                m_function,
+                m_arguments,
+                m_variadic,
+            } => {
+                let variadic =
+                    if m_variadic.is_some() { " variadic" } else { "" };
+                write!(
+                    f,
+                    "%{} = call{} @{}({})",
+                    m_dest,
+                    variadic,
+                    m_function,
+                    temp_list(m_arguments)
+                )
+            }
+            Instruction::VaStart { m_list } => {
+                write!(f, "va_start %{}", m_list)
+            }
+            Instruction::VaArg { m_dest, m_type, m_list } => {
+                write!(f, "%{} = va_arg {} %{}", m_dest, m_type, m_list)
+            }
+            Instruction::Location { m_span } => write!(f, "; {}", m_span),
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Terminator::Jump { m_target, m_arguments } => {
+                if m_arguments.is_empty() {
+                    return write!(f, "jump b{}", m_target);
+                }
+                write!(f, "jump b{}({})", m_target, temp_list(m_arguments))
+            }
+            Terminator::Branch { m_condition, m_true, m_false } => {
+                write!(f, "branch %{}, b{}, b{}", m_condition, m_true, m_false)
+            }
+            Terminator::Return { m_value } => write!(f, "ret %{}", m_value),
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parameters: Vec<String> = self
+            .m_parameters
+            .iter()
+            .map(|p| format!("%{}: {}", p, self.m_temps[*p]))
+            .collect();
+        write!(
+            f,
+            "{}function @{}({}{}) -> {} {{\n",
+            if self.m_global { "global " } else { "" },
+            self.m_name,
+            parameters.join(", "),
+            if self.m_variadic { ", ..." } else { "" },
+            self.m_return_type
+        )?;
+        for (i, slot) in self.m_slots.iter().enumerate() {
+            write!(
+                f,
+                "  ${} = slot {} align {} ; {}\n",
+                i, slot.m_size, slot.m_align, slot.m_name
+            )?;
+        }
+        for (i, block) in self.m_blocks.iter().enumerate() {
+            write!(f, "b{}", i)?;
+            if !block.m_parameters.is_empty() {
+                let parameters: Vec<String> = block
+                    .m_parameters
+                    .iter()
+                    .map(|p| format!("%{}: {}", p, self.m_temps[*p]))
+                    .collect();
+                write!(f, "({})", parameters.join(", "))?;
+            }
+            write!(f, ":\n")?;
+            for instruction in &block.m_instructions {
+                write!(f, "  {}\n", instruction)?;
+            }
+            write!(f, "  {}\n", block.m_terminator)?;
+        }
+        write!(f, "}}\n")
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for data in &self.m_data {
+            let value = match &data.m_value {
+                Some(StaticValue::Constant(v)) => {
+                    constant_text(&data.m_type, *v)
+                }
+                Some(StaticValue::Address(symbol, offset)) => {
+                    format!("@{}", address_text(symbol, *offset))
+                }
+                None => constant_text(&data.m_type, 0),
+            };
+            write!(
+                f,
+                "{}{} @{}: {} = {}\n",
+                if data.m_global { "global " } else { "" },
+                if data.m_read_only { "const" } else { "data" },
+                data.m_name,
+                data.m_type,
+                value
+            )?;
+        }
+        for function in &self.m_functions {
+            write!(f, "\n{}", function)?;
+        }
+        return Ok(());
+    }
+}
@@ -1,25 +1,82 @@
-use crate::token::Token;
+use crate::token::{Span, Token};
+use std::rc::Rc;
 
 #[derive(Debug)]
 pub enum LexError {
     ExpectedToken,
-    NotImplemented,
+    NotImplemented(char),
+    InvalidLineMarker(String),
 }
 
-pub fn lex(input: &str) -> Result<Vec<Token>, LexError> {
+// Lexes the preprocessed source into tokens and the span of each token.
+// Line markers (`# 12 "file.h"`) and `#line` directives left in the input
+// set the file and line reported for the following lines
+pub fn lex(input: &str) -> Result<(Vec<Token>, Vec<Span>), (LexError, Span)> {
     let mut tokens = Vec::new();
+    let mut spans = Vec::new();
 
     let mut cur_token_string = String::new();
 
-    let break_chars = " \t\n{}();-~!+*/%<>&|=:?,";
-    let white_space = " \t\n";
+    let break_chars = " \t\r\n{}();-~!+*/%<>&|=:?,";
+    let white_space = " \t\r\n";
+
+    let mut file: Rc<str> = Rc::from("<input>");
+    let mut line: usize = 1;
+    let mut column: usize = 0;
+    let mut line_start = true;
+    let mut token_start =
+        Span { m_file: file.clone(), m_line: line, m_column: column };
 
-    let mut i = 0;
     let mut c_i = input.chars().peekable();
     while let Some(c) = c_i.next() {
+        column += 1;
+        let here =
+            Span { m_file: file.clone(), m_line: line, m_column: column };
+
+        if c == '#' && line_start {
+            let mut marker = String::new();
+            while let Some(&n) = c_i.peek() {
+                if n == '\n' {
+                    break;
+                }
+                marker.push(n);
+                c_i.next();
+            }
+            let mut parts = marker.split_whitespace().peekable();
+            if parts.peek() == Some(&"line") {
+                parts.next();
+            }
+            // The newline moves to line n, and there's no line 0
+            match parts.next().map(|n| n.parse::<usize>()) {
+                Some(Ok(n)) if n > 0 => line = n - 1,
+                _ => return Err((LexError::InvalidLineMarker(marker), here)),
+            }
+            match parts.next() {
+                Some(name) if name.starts_with('"') => {
+                    file = Rc::from(name.trim_matches('"'));
+                }
+                _ => (),
+            }
+            continue;
+        }
+        if c == '\n' {
+            line += 1;
+            column = 0;
+            line_start = true;
+        } else if !white_space.contains(c) {
+            line_start = false;
+        }
+
+        // The sign of an exponent belongs to the number: 1e-5, 0x1p+3
+        if (c == '+' || c == '-') && is_exponent_start(&cur_token_string) {
+            cur_token_string.push(c);
+            continue;
+        }
+
         if break_chars.contains(c) {
             // Lex the cur_token_string then lex the current char (break chars)
             if cur_token_string.len() > 0 {
+                spans.push(token_start.clone());
                 if cur_token_string == "return" {
                     tokens.push(Token::KeywordReturn);
                 } else if cur_token_string == "int" {
@@ -38,10 +95,52 @@ pub fn lex(input: &str) -> Result<Vec<Token>, LexError> {
                     tokens.push(Token::KeywordBreak);
                 } else if cur_token_string == "continue" {
                     tokens.push(Token::KeywordContinue);
+                } else if cur_token_string == "float" {
+                    tokens.push(Token::KeywordFloat);
+                } else if cur_token_string == "double" {
+                    tokens.push(Token::KeywordDouble);
+                } else if cur_token_string == "static" {
+                    tokens.push(Token::KeywordStatic);
+                } else if cur_token_string == "extern" {
+                    tokens.push(Token::KeywordExtern);
+                } else if cur_token_string == "auto" {
+                    tokens.push(Token::KeywordAuto);
+                } else if cur_token_string == "register" {
+                    tokens.push(Token::KeywordRegister);
+                } else if cur_token_string == "char" {
+                    tokens.push(Token::KeywordChar);
+                } else if cur_token_string == "short" {
+                    tokens.push(Token::KeywordShort);
+                } else if cur_token_string == "long" {
+                    tokens.push(Token::KeywordLong);
+                } else if cur_token_string == "signed" {
+                    tokens.push(Token::KeywordSigned);
+                } else if cur_token_string == "unsigned" {
+                    tokens.push(Token::KeywordUnsigned);
+                } else if cur_token_string == "sizeof" {
+                    tokens.push(Token::KeywordSizeof);
+                } else if cur_token_string == "_Alignof" {
+                    tokens.push(Token::KeywordAlignof);
+                } else if cur_token_string == "__builtin_va_list" {
+                    tokens.push(Token::KeywordVaList);
+                } else if cur_token_string == "__builtin_va_start" {
+                    tokens.push(Token::KeywordVaStart);
+                } else if cur_token_string == "__builtin_va_arg" {
+                    tokens.push(Token::KeywordVaArg);
+                } else if cur_token_string == "__builtin_va_end" {
+                    tokens.push(Token::KeywordVaEnd);
+                } else if cur_token_string == "..." {
+                    tokens.push(Token::Ellipsis);
+                } else if cur_token_string == "const" {
+                    tokens.push(Token::KeywordConst);
+                } else if cur_token_string == "volatile" {
+                    tokens.push(Token::KeywordVolatile);
                 } else {
                     // try parse to int then its an int literal
-                    if let Ok(i) = cur_token_string.parse::<i32>() {
-                        tokens.push(Token::IntLiteral(i));
+                    if let Some(t) = lex_integer(&cur_token_string) {
+                        tokens.push(t);
+                    } else if let Some(t) = lex_float(&cur_token_string) {
+                        tokens.push(t);
                     } else {
                         tokens
                             .push(Token::Identifier(cur_token_string.clone()));
@@ -53,6 +152,7 @@ pub fn lex(input: &str) -> Result<Vec<Token>, LexError> {
             if white_space.contains(c) {
                 continue;
             } else {
+                spans.push(here.clone());
                 match c {
                     '{' => tokens.push(Token::OpenBrace),
                     '}' => tokens.push(Token::CloseBrace),
@@ -77,7 +177,7 @@ pub fn lex(input: &str) -> Result<Vec<Token>, LexError> {
                             tokens.push(Token::OperatorAnd);
                             c_i.next();
                         }
-                        _ => return Err(LexError::NotImplemented),
+                        _ => tokens.push(Token::OperatorAmpersand),
                     },
                     '<' => match c_i.peek() {
                         Some('=') => {
@@ -98,7 +198,7 @@ pub fn lex(input: &str) -> Result<Vec<Token>, LexError> {
                             tokens.push(Token::OperatorOr);
                             c_i.next();
                         }
-                        _ => return Err(LexError::NotImplemented),
+                        _ => return Err((LexError::NotImplemented(c), here)),
                     },
                     '=' => match c_i.peek() {
                         Some('=') => {
@@ -110,13 +210,150 @@ pub fn lex(input: &str) -> Result<Vec<Token>, LexError> {
                     ':' => tokens.push(Token::Colon),
                     '?' => tokens.push(Token::QuestionMark),
                     ',' => tokens.push(Token::Comma),
-                    _ => return Err(LexError::NotImplemented),
+                    _ => return Err((LexError::NotImplemented(c), here)),
                 }
             }
         } else {
+            if cur_token_string.len() == 0 {
+                token_start = here;
+            }
             cur_token_string.push(c);
         }
     }
     tokens.push(Token::EndOfFile);
-    return Ok(tokens);
+    spans.push(Span { m_file: file, m_line: line, m_column: column + 1 });
+    return Ok((tokens, spans));
+}
+
+fn is_exponent_start(s: &str) -> bool {
+    let starts_number = match s.chars().next() {
+        Some(c) => c.is_ascii_digit() || c == '.',
+        None => false,
+    };
+    if !starts_number {
+        return false;
+    }
+    let hex = s.starts_with("0x") || s.starts_with("0X");
+    if hex {
+        return s.ends_with('p') || s.ends_with('P');
+    }
+    return s.ends_with('e') || s.ends_with('E');
+}
+
+// Decimal, octal (leading 0) and hexadecimal integer constants with u/U and
+// l/L/ll/LL suffixes. The type is the first one able to hold the value from
+// the candidates for the suffix, where octal and hexadecimal constants may
+// also be unsigned without a u suffix
+fn lex_integer(s: &str) -> Option<Token> {
+    if !s.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let digits_end = s
+        .find(|c| c == 'u' || c == 'U' || c == 'l' || c == 'L')
+        .unwrap_or(s.len());
+    let (digits, suffix) = s.split_at(digits_end);
+    let (unsigned, long) = match suffix.to_ascii_lowercase().as_str() {
+        "" => (false, false),
+        "u" => (true, false),
+        "l" | "ll" => (false, true),
+        "ul" | "lu" | "ull" | "llu" => (true, true),
+        _ => return None,
+    };
+
+    let (radix, body) = if digits.starts_with("0x") || digits.starts_with("0X")
+    {
+        (16, &digits[2..])
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        (8, &digits[1..])
+    } else {
+        (10, digits)
+    };
+    let value = match u64::from_str_radix(body, radix) {
+        Ok(v) => v,
+        Err(_) => return None,
+    };
+    let decimal = radix == 10;
+
+    if !unsigned && !long && value <= i32::MAX as u64 {
+        return Some(Token::IntLiteral(value as i32));
+    }
+    if !long && (unsigned || !decimal) && value <= u32::MAX as u64 {
+        return Some(Token::UnsignedLiteral(value as u32));
+    }
+    if !unsigned && value <= i64::MAX as u64 {
+        return Some(Token::LongLiteral(value as i64));
+    }
+    return Some(Token::UnsignedLongLiteral(value));
+}
+
+// Decimal (1.5, .5, 2e10) and hexadecimal (0x1.8p3) floating constants, with
+// an optional f/F suffix for float or l/L which is treated as double
+fn lex_float(s: &str) -> Option<Token> {
+    match s.chars().next() {
+        Some(c) if c.is_ascii_digit() || c == '.' => (),
+        _ => return None,
+    }
+
+    let (body, is_float) = if s.ends_with('f') || s.ends_with('F') {
+        (&s[..s.len() - 1], true)
+    } else if s.ends_with('l') || s.ends_with('L') {
+        (&s[..s.len() - 1], false)
+    } else {
+        (s, false)
+    };
+
+    let value = if body.starts_with("0x") || body.starts_with("0X") {
+        match parse_hex_float(&body[2..]) {
+            Some(v) => v,
+            None => return None,
+        }
+    } else {
+        if !body.contains(|c| c == '.' || c == 'e' || c == 'E') {
+            return None;
+        }
+        match body.parse::<f64>() {
+            Ok(v) => v,
+            Err(_) => return None,
+        }
+    };
+
+    if is_float {
+        return Some(Token::FloatLiteral(value as f32));
+    }
+    return Some(Token::DoubleLiteral(value));
+}
+
+fn parse_hex_float(s: &str) -> Option<f64> {
+    let p = match s.find(|c| c == 'p' || c == 'P') {
+        Some(p) => p,
+        None => return None, // the binary exponent is required
+    };
+    let exponent = match s[p + 1..].parse::<i32>() {
+        Ok(e) => e,
+        Err(_) => return None,
+    };
+
+    let mut mantissa: f64 = 0.0;
+    let mut scale: i32 = 0;
+    let mut seen_point = false;
+    let mut seen_digit = false;
+    for c in s[..p].chars() {
+        if c == '.' && !seen_point {
+            seen_point = true;
+            continue;
+        }
+        let digit = match c.to_digit(16) {
+            Some(d) => d,
+            None => return None,
+        };
+        seen_digit = true;
+        mantissa = mantissa * 16.0 + digit as f64;
+        if seen_point {
+            scale -= 4;
+        }
+    }
+    if !seen_digit {
+        return None;
+    }
+    return Some(mantissa * 2f64.powi(exponent + scale));
 }
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::elf::{Object, ObjectSection, RelocationKind, OBJECT_SECTIONS};
+
+#[derive(Debug)]
+pub enum LinkError {
+    UndefinedSymbol(String),
+    MultipleDefinition(String),
+    // A symbol too far from the code using it for a 32 bit displacement
+    OutOfRange(String),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkError::UndefinedSymbol(name) => {
+                write!(f, "undefined reference to `{}`", name)
+            }
+            LinkError::MultipleDefinition(name) => {
+                write!(f, "multiple definition of `{}`", name)
+            }
+            LinkError::OutOfRange(name) => {
+                write!(f, "`{}` is out of range of a 32 bit displacement", name)
+            }
+        }
+    }
+}
+
+// Where the executable is loaded, as gcc links programs which aren't
+// position independent
+const BASE_ADDRESS: u64 = 0x400000;
+const PAGE_SIZE: u64 = 0x1000;
+
+const HEADER_SIZE: u64 = 64;
+const PROGRAM_HEADER_SIZE: u64 = 56;
+const PROGRAM_HEADER_COUNT: u64 = 3;
+
+// Program header types and permissions
+const PT_LOAD: u32 = 1;
+const PT_GNU_STACK: u32 = 0x6474e551;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+fn align_to(value: u64, align: u64) -> u64 {
+    return value.div_ceil(align) * align;
+}
+
+struct ProgramHeader {
+    m_type: u32,
+    m_flags: u32,
+    m_offset: u64,
+    m_file_size: u64,
+    m_memory_size: u64,
+}
+
+impl ProgramHeader {
+    fn write(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.m_type.to_le_bytes());
+        bytes.extend_from_slice(&self.m_flags.to_le_bytes());
+        bytes.extend_from_slice(&self.m_offset.to_le_bytes());
+        // Loaded at the same distance from the base as it is in the file
+        let address = match self.m_type {
+            PT_LOAD => BASE_ADDRESS + self.m_offset,
+            _ => 0,
+        };
+        bytes.extend_from_slice(&address.to_le_bytes());
+        bytes.extend_from_slice(&address.to_le_bytes());
+        bytes.extend_from_slice(&self.m_file_size.to_le_bytes());
+        bytes.extend_from_slice(&self.m_memory_size.to_le_bytes());
+        let align = match self.m_type {
+            PT_LOAD => PAGE_SIZE,
+            _ => 16,
+        };
+        bytes.extend_from_slice(&align.to_le_bytes());
+    }
+}
+
+// Links the objects into a static ELF64 executable for x86-64 starting at
+// _start. Code and read only data go in one segment and writable data in
+// another, with each object's part of a section after the last object's
+//
+// Every symbol has to be defined by one of the objects, so programs linked
+// this way can't use the C library
+pub fn link(objects: &[Object]) -> Result<Vec<u8>, LinkError> {
+    // Where each object's sections start in the file, which is also their
+    // address less BASE_ADDRESS. Text and read only data come first, then
+    // data and .bss on a page of their own
+    let mut starts: Vec<[u64; 4]> = vec![[0; 4]; objects.len()];
+    let mut end = HEADER_SIZE + PROGRAM_HEADER_SIZE * PROGRAM_HEADER_COUNT;
+    let order = [
+        ObjectSection::Text,
+        ObjectSection::ReadOnly,
+        ObjectSection::Data,
+        ObjectSection::Bss,
+    ];
+    let mut code_end = 0;
+    let mut data_start = 0;
+    let mut data_end = 0;
+    for section in order {
+        if section == ObjectSection::Data {
+            code_end = end;
+            end = align_to(end, PAGE_SIZE);
+            data_start = end;
+        }
+        if section == ObjectSection::Bss {
+            data_end = end;
+        }
+        for (i, object) in objects.iter().enumerate() {
+            let position = section.position();
+            end = align_to(end, object.m_alignments[position].max(1));
+            starts[i][position] = end;
+            end += object.m_contents[position].len() as u64;
+        }
+    }
+    let memory_end = end;
+
+    let mut globals: HashMap<&str, u64> = HashMap::new();
+    let mut locals: Vec<HashMap<&str, u64>> = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        let mut defined: HashMap<&str, u64> = HashMap::new();
+        for symbol in &object.m_symbols {
+            let section = match symbol.m_section {
+                Some(s) => s,
+                None => continue,
+            };
+            let address =
+                BASE_ADDRESS + starts[i][section.position()] + symbol.m_offset;
+            defined.insert(&symbol.m_name, address);
+            if symbol.m_global
+                && globals.insert(&symbol.m_name, address).is_some()
+            {
+                return Err(LinkError::MultipleDefinition(
+                    symbol.m_name.clone(),
+                ));
+            }
+        }
+        locals.push(defined);
+    }
+
+    let mut bytes: Vec<u8> = vec![0; data_end as usize];
+    for (i, object) in objects.iter().enumerate() {
+        for section in OBJECT_SECTIONS {
+            if section == ObjectSection::Bss {
+                continue;
+            }
+            let start = starts[i][section.position()] as usize;
+            let contents = &object.m_contents[section.position()];
+            bytes[start..start + contents.len()].copy_from_slice(contents);
+        }
+
+        // Symbols defined in the object come before others of the name
+        for relocation in &object.m_relocations {
+            let name = relocation.m_symbol.as_str();
+            let target = match locals[i].get(name).or(globals.get(name)) {
+                Some(a) => *a,
+                None => {
+                    return Err(LinkError::UndefinedSymbol(String::from(name)))
+                }
+            };
+            let place = starts[i][relocation.m_section.position()]
+                + relocation.m_offset;
+            if relocation.m_kind == RelocationKind::Abs64 {
+                let value = target as i64 + relocation.m_addend;
+                bytes[place as usize..place as usize + 8]
+                    .copy_from_slice(&value.to_le_bytes());
+                continue;
+            }
+            // Calls go straight to the function, as nothing is shared
+            let value = target as i64 + relocation.m_addend
+                - (BASE_ADDRESS + place) as i64;
+            let value = match i32::try_from(value) {
+                Ok(v) => v,
+                Err(_) => {
+                    return Err(LinkError::OutOfRange(String::from(name)))
+                }
+            };
+            bytes[place as usize..place as usize + 4]
+                .copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    let entry = match globals.get("_start") {
+        Some(a) => *a,
+        None => return Err(LinkError::UndefinedSymbol(String::from("_start"))),
+    };
+
+    let mut header: Vec<u8> = Vec::new();
+    // Magic number, 64 bit, little endian, version 1, System V
+    header.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    header.extend_from_slice(&[0; 8]);
+    // Executable, x86-64, version 1
+    header.extend_from_slice(&2u16.to_le_bytes());
+    header.extend_from_slice(&62u16.to_le_bytes());
+    header.extend_from_slice(&1u32.to_le_bytes());
+    header.extend_from_slice(&entry.to_le_bytes());
+    // Program headers straight after this one, and no section headers
+    header.extend_from_slice(&HEADER_SIZE.to_le_bytes());
+    header.extend_from_slice(&0u64.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+    header.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    header.extend_from_slice(&(PROGRAM_HEADER_COUNT as u16).to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes());
+
+    let program_headers = [
+        // The headers themselves are loaded along with the code
+        ProgramHeader {
+            m_type: PT_LOAD,
+            m_flags: PF_R | PF_X,
+            m_offset: 0,
+            m_file_size: code_end,
+            m_memory_size: code_end,
+        },
+        // .bss is the part past the end of the file
+        ProgramHeader {
+            m_type: PT_LOAD,
+            m_flags: PF_R | PF_W,
+            m_offset: data_start,
+            m_file_size: data_end - data_start,
+            m_memory_size: memory_end - data_start,
+        },
+        // Keeps the stack from being executable
+        ProgramHeader {
+            m_type: PT_GNU_STACK,
+            m_flags: PF_R | PF_W,
+            m_offset: 0,
+            m_file_size: 0,
+            m_memory_size: 0,
+        },
+    ];
+    for program_header in &program_headers {
+        program_header.write(&mut header);
+    }
+    bytes[..header.len()].copy_from_slice(&header);
+    return Ok(bytes);
+}
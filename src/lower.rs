@@ -0,0 +1,1335 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::evaluator::{self, Constant};
+use crate::ir::{
+    self, BinaryOp, BlockId, CompareOp, ConvertOp, Instruction, Temp,
+    Terminator, Type, UnaryOp,
+};
+use crate::parser::{
+    AdditiveExpression, AdditiveOperator, BlockItem, ConditionalExpression,
+    Declaration, EqualityExpression, EqualityOperator, Expression, Factor,
+    Function, LogicalAndExpression, LogicalOrExpresson, MultiplicativeOperator,
+    Program, Qualifiers, RelationalExpression, RelationalOperator, Statement,
+    StorageClass, Term, TopLevelItem, UnaryOperator, VarType,
+};
+use crate::types::{self, TypeContext};
+
+// Where a variable is kept
+#[derive(Debug, Clone)]
+enum Storage {
+    Slot(usize),
+    // Global variables and static locals, by label
+    Global(String),
+}
+
+#[derive(Debug, Clone)]
+struct Variable {
+    storage: Storage,
+    var_type: VarType,
+    qualifiers: Qualifiers,
+}
+
+// Blocks that continue and break go to
+struct LoopTargets {
+    continue_block: BlockId,
+    break_block: BlockId,
+}
+
+// A block of the function being lowered, finished once it has a terminator
+struct BlockBuilder {
+    parameters: Vec<Temp>,
+    instructions: Vec<Instruction>,
+    terminator: Option<Terminator>,
+}
+
+// Turns the analysed program into IR. Every C level decision, conversions,
+// promotions, pointer scaling and evaluation order, is made here, so the
+// backends only deal with machine types
+pub struct Lowerer {
+    scopes: Vec<HashMap<String, Variable>>,
+    // Return and parameter types of every function in the program, and
+    // whether it is variadic
+    functions: HashMap<String, (VarType, Vec<VarType>, bool)>,
+    static_count: usize,
+    data: Vec<ir::Data>,
+    // The function being lowered
+    temps: Vec<Type>,
+    slots: Vec<ir::Slot>,
+    blocks: Vec<BlockBuilder>,
+    current: BlockId,
+    loops: Vec<LoopTargets>,
+    return_type: VarType,
+}
+
+impl TypeContext for Lowerer {
+    fn variable_type(&self, name: &String) -> Option<VarType> {
+        return self.query_var(name).map(|v| v.var_type);
+    }
+
+    fn function_type(&self, name: &String) -> Option<VarType> {
+        return self.functions.get(name).map(|f| f.0.clone());
+    }
+
+    fn variable_qualifiers(&self, name: &String) -> Qualifiers {
+        match self.query_var(name) {
+            Some(v) => return v.qualifiers,
+            None => return Qualifiers::default(),
+        }
+    }
+}
+
+// The bits of a constant of the type, as IR constants hold them
+fn constant_bits(value: &Constant, var_type: &VarType) -> i64 {
+    match value {
+        Constant::Int(v) => return *v,
+        Constant::Floating(v) if *var_type == VarType::Float => {
+            return (*v as f32).to_bits() as i64
+        }
+        Constant::Floating(v) => return v.to_bits() as i64,
+    }
+}
+
+impl Lowerer {
+    pub fn new() -> Self {
+        Lowerer {
+            scopes: Vec::new(),
+            functions: HashMap::new(),
+            static_count: 0,
+            data: Vec::new(),
+            temps: Vec::new(),
+            slots: Vec::new(),
+            blocks: Vec::new(),
+            current: 0,
+            loops: Vec::new(),
+            return_type: VarType::Int,
+        }
+    }
+
+    fn query_var(&self, name: &String) -> Option<Variable> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(v) = scope.get(name) {
+                return Some(v.clone());
+            }
+        }
+        return None;
+    }
+
+    fn add_var(&mut self, name: &String, variable: Variable) {
+        self.scopes.last_mut().unwrap().insert(name.clone(), variable);
+    }
+
+    fn new_temp(&mut self, temp_type: Type) -> Temp {
+        self.temps.push(temp_type);
+        return self.temps.len() - 1;
+    }
+
+    fn new_block(&mut self, parameters: Vec<Temp>) -> BlockId {
+        self.blocks.push(BlockBuilder {
+            parameters,
+            instructions: Vec::new(),
+            terminator: None,
+        });
+        return self.blocks.len() - 1;
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.blocks[self.current].instructions.push(instruction);
+    }
+
+    // Ends the current block, unless a return, break or continue already did
+    fn terminate(&mut self, terminator: Terminator) {
+        let block = &mut self.blocks[self.current];
+        if block.terminator.is_none() {
+            block.terminator = Some(terminator);
+        }
+    }
+
+    fn jump(&mut self, target: BlockId, arguments: Vec<Temp>) {
+        self.terminate(Terminator::Jump {
+            m_target: target,
+            m_arguments: arguments,
+        });
+    }
+
+    fn constant(&mut self, constant_type: Type, value: i64) -> Temp {
+        let dest = self.new_temp(constant_type);
+        self.emit(Instruction::Constant {
+            m_dest: dest,
+            m_type: constant_type,
+            m_value: value,
+        });
+        return dest;
+    }
+
+    fn zero(&mut self, var_type: &VarType) -> Temp {
+        let zero = constant_bits(&Constant::Floating(0.0), var_type);
+        let value = if var_type.is_floating() { zero } else { 0 };
+        return self.constant(ir::ir_type(var_type), value);
+    }
+
+    pub fn lower_program(&mut self, program: &Program) -> ir::Program {
+        // Names given internal linkage by a static declaration anywhere in
+        // the file
+        let mut internal: HashSet<String> = HashSet::new();
+        for item in &program.m_items {
+            match item {
+                // A declaration without a prototype doesn't replace one
+                // with a prototype
+                TopLevelItem::Function(function)
+                    if !function.m_prototype
+                        && function.m_items.is_none()
+                        && self.functions.contains_key(&function.m_id) => {}
+                TopLevelItem::Function(function) => {
+                    self.functions.insert(
+                        function.m_id.clone(),
+                        (
+                            function.m_type.clone(),
+                            function
+                                .m_params
+                                .iter()
+                                .map(|p| p.m_type.clone())
+                                .collect(),
+                            function.m_variadic,
+                        ),
+                    );
+                    if function.m_storage == Some(StorageClass::Static) {
+                        internal.insert(function.m_id.clone());
+                    }
+                }
+                TopLevelItem::Declaration(declaration) => {
+                    if declaration.m_storage == Some(StorageClass::Static) {
+                        internal.insert(declaration.m_id.clone());
+                    }
+                }
+            }
+        }
+
+        // File scope variables may be declared many times but are defined
+        // once, by the declaration with an initializer or else by any one
+        // without extern. Const ones go in read only memory
+        self.scopes.push(HashMap::new());
+        let mut functions: Vec<ir::Function> = Vec::new();
+        let mut definitions: Vec<(String, VarType, bool)> = Vec::new();
+        let mut initializers: HashMap<String, ir::StaticValue> = HashMap::new();
+        for item in &program.m_items {
+            match item {
+                TopLevelItem::Function(function) => {
+                    if function.m_items.is_some() {
+                        let global = !internal.contains(&function.m_id);
+                        functions.push(self.lower_function(function, global));
+                    }
+                }
+                TopLevelItem::Declaration(declaration) => {
+                    let id = &declaration.m_id;
+                    if self.query_var(id).is_none() {
+                        self.add_var(
+                            id,
+                            Variable {
+                                storage: Storage::Global(id.clone()),
+                                var_type: declaration.m_type.clone(),
+                                qualifiers: declaration.m_qualifiers.clone(),
+                            },
+                        );
+                    }
+                    let defines = declaration.m_value.is_some()
+                        || declaration.m_storage != Some(StorageClass::Extern);
+                    if defines && !definitions.iter().any(|d| &d.0 == id) {
+                        definitions.push((
+                            id.clone(),
+                            declaration.m_type.clone(),
+                            declaration.m_qualifiers.m_const,
+                        ));
+                    }
+                    match self.initial_value(declaration) {
+                        Some(v) => {
+                            initializers.insert(id.clone(), v);
+                        }
+                        None => (),
+                    }
+                }
+            }
+        }
+        self.scopes.pop();
+
+        let mut data: Vec<ir::Data> = Vec::new();
+        for (id, var_type, read_only) in &definitions {
+            data.push(ir::Data {
+                m_name: id.clone(),
+                m_global: !internal.contains(id),
+                m_read_only: *read_only,
+                m_type: ir::ir_type(var_type),
+                m_value: initializers.get(id).cloned(),
+            });
+        }
+        data.append(&mut self.data);
+
+        return ir::Program { m_functions: functions, m_data: data };
+    }
+
+    // Value of a constant initializer, converted to the declared type, or
+    // the address a pointer starts at
+    fn initial_value(
+        &self,
+        declaration: &Declaration,
+    ) -> Option<ir::StaticValue> {
+        let value = match &declaration.m_value {
+            Some(e) => e,
+            None => return None,
+        };
+        match evaluator::constant_value(self, value) {
+            Some(c) => {
+                let c = evaluator::convert_constant(
+                    &c,
+                    &types::expression_type(self, value),
+                    &declaration.m_type,
+                );
+                let bits = constant_bits(&c, &declaration.m_type);
+                return Some(ir::StaticValue::Constant(bits));
+            }
+            None => (),
+        }
+        let address = match evaluator::address_constant(self, value) {
+            Some(a) => a,
+            None => return None,
+        };
+        match self.query_var(&address.m_variable) {
+            Some(Variable { storage: Storage::Global(label), .. }) => {
+                return Some(ir::StaticValue::Address(label, address.m_offset))
+            }
+            _ => return None,
+        }
+    }
+
+    fn lower_function(
+        &mut self,
+        function: &Function,
+        global: bool,
+    ) -> ir::Function {
+        self.temps = Vec::new();
+        self.slots = Vec::new();
+        self.blocks = Vec::new();
+        self.current = self.new_block(Vec::new());
+        self.return_type = function.m_type.clone();
+        self.scopes.push(HashMap::new());
+
+        self.emit(Instruction::Location { m_span: function.m_span.clone() });
+        // Parameters arrive in temporaries and are kept in slots like any
+        // other variable, except unnamed ones, which can't be read
+        let mut parameters: Vec<Temp> = Vec::new();
+        for parameter in &function.m_params {
+            let temp = self.new_temp(ir::ir_type(&parameter.m_type));
+            parameters.push(temp);
+            let id = match &parameter.m_id {
+                Some(id) => id,
+                None => continue,
+            };
+            let address =
+                self.add_local(id, &parameter.m_type, &parameter.m_qualifiers);
+            self.emit(Instruction::Store {
+                m_type: ir::ir_type(&parameter.m_type),
+                m_address: address,
+                m_value: temp,
+                m_volatile: parameter.m_qualifiers.m_volatile,
+            });
+        }
+
+        match &function.m_items {
+            Some(items) => {
+                for block_item in items {
+                    self.lower_block_item(block_item);
+                }
+            }
+            None => (),
+        }
+        // Falling off the end returns 0
+        let return_type = self.return_type.clone();
+        let zero = self.zero(&return_type);
+        self.terminate(Terminator::Return { m_value: zero });
+        self.scopes.pop();
+
+        return ir::Function {
+            m_name: function.m_id.clone(),
+            m_global: global,
+            m_parameters: parameters,
+            m_variadic: function.m_variadic,
+            m_return_type: ir::ir_type(&function.m_type),
+            m_temps: std::mem::take(&mut self.temps),
+            m_slots: std::mem::take(&mut self.slots),
+            m_blocks: self.reachable_blocks(),
+            m_span: function.m_span.clone(),
+        };
+    }
+
+    // The finished blocks, leaving out those control never reaches, like
+    // code after a return
+    fn reachable_blocks(&mut self) -> Vec<ir::Block> {
+        let builders = std::mem::take(&mut self.blocks);
+        let mut reached = vec![false; builders.len()];
+        let mut work: Vec<BlockId> = vec![0];
+        while let Some(block) = work.pop() {
+            if reached[block] {
+                continue;
+            }
+            reached[block] = true;
+            match &builders[block].terminator {
+                Some(Terminator::Jump { m_target, m_arguments: _ }) => {
+                    work.push(*m_target)
+                }
+                Some(Terminator::Branch {
+                    m_condition: _,
+                    m_true,
+                    m_false,
+                }) => {
+                    work.push(*m_true);
+                    work.push(*m_false);
+                }
+                _ => (),
+            }
+        }
+
+        let mut numbers: Vec<BlockId> = Vec::new();
+        let mut count = 0;
+        for r in &reached {
+            numbers.push(count);
+            if *r {
+                count += 1;
+            }
+        }
+        let mut blocks: Vec<ir::Block> = Vec::new();
+        for (i, builder) in builders.into_iter().enumerate() {
+            if !reached[i] {
+                continue;
+            }
+            let terminator = match builder.terminator {
+                Some(Terminator::Jump { m_target, m_arguments }) => {
+                    Terminator::Jump {
+                        m_target: numbers[m_target],
+                        m_arguments,
+                    }
+                }
+                Some(Terminator::Branch { m_condition, m_true, m_false }) => {
+                    Terminator::Branch {
+                        m_condition,
+                        m_true: numbers[m_true],
+                        m_false: numbers[m_false],
+                    }
+                }
+                Some(t) => t,
+                None => panic!("Reachable block without a terminator"),
+            };
+            blocks.push(ir::Block {
+                m_parameters: builder.parameters,
+                m_instructions: builder.instructions,
+                m_terminator: terminator,
+            });
+        }
+        return blocks;
+    }
+
+    // Adds a variable kept in a new stack slot and gives its address
+    fn add_local(
+        &mut self,
+        name: &String,
+        var_type: &VarType,
+        qualifiers: &Qualifiers,
+    ) -> Temp {
+        self.slots.push(ir::Slot {
+            m_name: name.clone(),
+            m_size: types::size_of(var_type) as i32,
+            m_align: types::align_of(var_type) as i32,
+        });
+        let slot = self.slots.len() - 1;
+        self.add_var(
+            name,
+            Variable {
+                storage: Storage::Slot(slot),
+                var_type: var_type.clone(),
+                qualifiers: qualifiers.clone(),
+            },
+        );
+        let address = self.new_temp(Type::I64);
+        self.emit(Instruction::SlotAddress { m_dest: address, m_slot: slot });
+        return address;
+    }
+
+    fn lower_block_item(&mut self, block_item: &BlockItem) {
+        match block_item {
+            BlockItem::Declaration(declaration) => {
+                self.lower_declaration(declaration)
+            }
+            BlockItem::Statement(statement, span) => {
+                self.emit(Instruction::Location { m_span: span.clone() });
+                self.lower_statement(statement);
+            }
+        }
+    }
+
+    fn lower_declaration(&mut self, declaration: &Declaration) {
+        let global_name = match declaration.m_storage {
+            // Static locals live in data under a unique label
+            Some(StorageClass::Static) => {
+                let label =
+                    format!("{}.{}", declaration.m_id, self.static_count);
+                self.static_count += 1;
+                self.data.push(ir::Data {
+                    m_name: label.clone(),
+                    m_global: false,
+                    m_read_only: declaration.m_qualifiers.m_const,
+                    m_type: ir::ir_type(&declaration.m_type),
+                    m_value: self.initial_value(declaration),
+                });
+                Some(label)
+            }
+            Some(StorageClass::Extern) => Some(declaration.m_id.clone()),
+            _ => None,
+        };
+        match global_name {
+            Some(name) => {
+                self.add_var(
+                    &declaration.m_id,
+                    Variable {
+                        storage: Storage::Global(name),
+                        var_type: declaration.m_type.clone(),
+                        qualifiers: declaration.m_qualifiers.clone(),
+                    },
+                );
+                return;
+            }
+            None => (),
+        }
+
+        self.emit(Instruction::Location { m_span: declaration.m_span.clone() });
+        // Variables without an initializer start as zero. The initializer
+        // can't see the variable it initializes
+        let value = match &declaration.m_value {
+            Some(e) => {
+                let value = self.lower_expression(e);
+                let value_type = types::expression_type(self, e);
+                self.convert(value, &value_type, &declaration.m_type)
+            }
+            None => self.zero(&declaration.m_type),
+        };
+        let address = self.add_local(
+            &declaration.m_id,
+            &declaration.m_type,
+            &declaration.m_qualifiers,
+        );
+        self.emit(Instruction::Store {
+            m_type: ir::ir_type(&declaration.m_type),
+            m_address: address,
+            m_value: value,
+            m_volatile: declaration.m_qualifiers.m_volatile,
+        });
+    }
+
+    // Lowers the body of a loop, with continue and break going to the given
+    // blocks
+    fn lower_loop_body(
+        &mut self,
+        statement: &Statement,
+        continue_block: BlockId,
+        break_block: BlockId,
+    ) {
+        self.loops.push(LoopTargets { continue_block, break_block });
+        self.lower_statement(statement);
+        self.loops.pop();
+    }
+
+    // Branches on an expression used as a condition
+    fn lower_branch(
+        &mut self,
+        condition: &Expression,
+        true_block: BlockId,
+        false_block: BlockId,
+    ) {
+        let value = self.lower_expression(condition);
+        let condition_type = types::expression_type(self, condition);
+        let truth = self.truth_value(value, &condition_type);
+        self.terminate(Terminator::Branch {
+            m_condition: truth,
+            m_true: true_block,
+            m_false: false_block,
+        });
+    }
+
+    fn lower_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Continue => {
+                let target = self.loops.last().unwrap().continue_block;
+                self.jump(target, Vec::new());
+                self.current = self.new_block(Vec::new());
+            }
+            Statement::Break => {
+                let target = self.loops.last().unwrap().break_block;
+                self.jump(target, Vec::new());
+                self.current = self.new_block(Vec::new());
+            }
+            Statement::Return(expression) => {
+                let return_type = self.return_type.clone();
+                let value = match expression {
+                    Some(e) => {
+                        let value = self.lower_expression(e);
+                        let value_type = types::expression_type(self, e);
+                        self.convert(value, &value_type, &return_type)
+                    }
+                    None => self.zero(&return_type),
+                };
+                self.terminate(Terminator::Return { m_value: value });
+                self.current = self.new_block(Vec::new());
+            }
+            Statement::Expression(expression) => match expression {
+                Some(e) => {
+                    self.lower_expression(e);
+                }
+                None => (),
+            },
+            Statement::If {
+                m_condition,
+                m_true_statement,
+                m_else_statement,
+            } => {
+                let true_block = self.new_block(Vec::new());
+                let false_block = self.new_block(Vec::new());
+                let end_block = self.new_block(Vec::new());
+                self.lower_branch(m_condition, true_block, false_block);
+                self.current = true_block;
+                self.lower_statement(m_true_statement);
+                self.jump(end_block, Vec::new());
+                self.current = false_block;
+                match m_else_statement {
+                    Some(s) => self.lower_statement(s),
+                    None => (),
+                }
+                self.jump(end_block, Vec::new());
+                self.current = end_block;
+            }
+            Statement::Compound { m_block_items } => {
+                self.scopes.push(HashMap::new());
+                for block_item in m_block_items {
+                    self.lower_block_item(block_item);
+                }
+                self.scopes.pop();
+            }
+            Statement::While { m_condition, m_statement } => {
+                let condition_block = self.new_block(Vec::new());
+                let body_block = self.new_block(Vec::new());
+                let end_block = self.new_block(Vec::new());
+                self.jump(condition_block, Vec::new());
+                self.current = condition_block;
+                self.lower_branch(m_condition, body_block, end_block);
+                self.current = body_block;
+                self.lower_loop_body(m_statement, condition_block, end_block);
+                self.jump(condition_block, Vec::new());
+                self.current = end_block;
+            }
+            Statement::Do { m_statement, m_condition } => {
+                let body_block = self.new_block(Vec::new());
+                let condition_block = self.new_block(Vec::new());
+                let end_block = self.new_block(Vec::new());
+                self.jump(body_block, Vec::new());
+                self.current = body_block;
+                self.lower_loop_body(m_statement, condition_block, end_block);
+                self.jump(condition_block, Vec::new());
+                self.current = condition_block;
+                self.lower_branch(m_condition, body_block, end_block);
+                self.current = end_block;
+            }
+            Statement::For {
+                m_initial_expression,
+                m_condition,
+                m_post_expression,
+                m_statement,
+            } => {
+                match m_initial_expression {
+                    Some(e) => {
+                        self.lower_expression(e);
+                    }
+                    None => (),
+                }
+                self.lower_for(m_condition, m_post_expression, m_statement);
+            }
+            Statement::ForDecl {
+                m_initial_declaration,
+                m_condition,
+                m_post_expression,
+                m_statement,
+            } => {
+                self.scopes.push(HashMap::new());
+                self.lower_declaration(m_initial_declaration);
+                self.lower_for(m_condition, m_post_expression, m_statement);
+                self.scopes.pop();
+            }
+        }
+    }
+
+    // The loop of a for statement, after its initial clause
+    fn lower_for(
+        &mut self,
+        condition: &Expression,
+        post_expression: &Option<Expression>,
+        statement: &Statement,
+    ) {
+        let condition_block = self.new_block(Vec::new());
+        let body_block = self.new_block(Vec::new());
+        let post_block = self.new_block(Vec::new());
+        let end_block = self.new_block(Vec::new());
+        self.jump(condition_block, Vec::new());
+        self.current = condition_block;
+        self.lower_branch(condition, body_block, end_block);
+        self.current = body_block;
+        self.lower_loop_body(statement, post_block, end_block);
+        self.jump(post_block, Vec::new());
+        self.current = post_block;
+        match post_expression {
+            Some(e) => {
+                self.lower_expression(e);
+            }
+            None => (),
+        }
+        self.jump(condition_block, Vec::new());
+        self.current = end_block;
+    }
+
+    // Converts a value from one C type to another
+    fn convert(&mut self, value: Temp, from: &VarType, to: &VarType) -> Temp {
+        let (from_ir, to_ir) = (ir::ir_type(from), ir::ir_type(to));
+        if from_ir == to_ir {
+            return value;
+        }
+        let op = if from.is_floating() && to.is_floating() {
+            ConvertOp::FloatToFloat
+        } else if to.is_floating() {
+            match from.is_unsigned() {
+                true => ConvertOp::UnsignedToFloat,
+                false => ConvertOp::SignedToFloat,
+            }
+        } else if from.is_floating() {
+            match to.is_unsigned() {
+                true => ConvertOp::FloatToUnsigned,
+                false => ConvertOp::FloatToSigned,
+            }
+        } else if to_ir.size() < from_ir.size() {
+            ConvertOp::Truncate
+        } else if from.is_unsigned() {
+            ConvertOp::ZeroExtend
+        } else {
+            ConvertOp::SignExtend
+        };
+        let dest = self.new_temp(to_ir);
+        self.emit(Instruction::Convert {
+            m_dest: dest,
+            m_op: op,
+            m_from: from_ir,
+            m_to: to_ir,
+            m_operand: value,
+        });
+        return dest;
+    }
+
+    fn compare(
+        &mut self,
+        op: CompareOp,
+        operand_type: Type,
+        left: Temp,
+        right: Temp,
+    ) -> Temp {
+        let dest = self.new_temp(Type::I32);
+        self.emit(Instruction::Compare {
+            m_dest: dest,
+            m_op: op,
+            m_type: operand_type,
+            m_left: left,
+            m_right: right,
+        });
+        return dest;
+    }
+
+    fn binary(
+        &mut self,
+        op: BinaryOp,
+        operand_type: Type,
+        left: Temp,
+        right: Temp,
+    ) -> Temp {
+        let dest = self.new_temp(operand_type);
+        self.emit(Instruction::Binary {
+            m_dest: dest,
+            m_op: op,
+            m_type: operand_type,
+            m_left: left,
+            m_right: right,
+        });
+        return dest;
+    }
+
+    // A value tested as a condition: integers and pointers as they are, and
+    // floating values as 1 unless zero. NaN isn't equal to zero so is true
+    fn truth_value(&mut self, value: Temp, var_type: &VarType) -> Temp {
+        if !var_type.is_floating() {
+            return value;
+        }
+        let zero = self.zero(var_type);
+        return self.compare(
+            CompareOp::NotEqual,
+            ir::ir_type(var_type),
+            value,
+            zero,
+        );
+    }
+
+    // 1 for true and 0 for false, as && and || give
+    fn boolean_value(&mut self, value: Temp, var_type: &VarType) -> Temp {
+        let zero = self.zero(var_type);
+        return self.compare(
+            CompareOp::NotEqual,
+            ir::ir_type(var_type),
+            value,
+            zero,
+        );
+    }
+
+    // The address of the object designated by an lvalue, and whether it is
+    // volatile
+    fn lower_address(&mut self, target: &Factor) -> (Temp, bool) {
+        let volatile = match types::lvalue_qualifiers(self, target) {
+            Some(q) => q.m_volatile,
+            None => panic!("Address of a value which isn't an lvalue"),
+        };
+        match target {
+            Factor::Variable { m_var, m_span: _ } => {
+                let variable = match self.query_var(m_var) {
+                    Some(v) => v,
+                    None => panic!("Use of undeclared variable {}", m_var),
+                };
+                let address = self.new_temp(Type::I64);
+                match variable.storage {
+                    Storage::Slot(slot) => {
+                        self.emit(Instruction::SlotAddress {
+                            m_dest: address,
+                            m_slot: slot,
+                        })
+                    }
+                    Storage::Global(name) => {
+                        self.emit(Instruction::GlobalAddress {
+                            m_dest: address,
+                            m_name: name,
+                        })
+                    }
+                }
+                return (address, volatile);
+            }
+            Factor::UnaryOperation {
+                m_opertator: UnaryOperator::Dereference,
+                m_factor,
+            } => return (self.lower_factor(m_factor), volatile),
+            Factor::Braced { m_expression } => {
+                match types::single_factor(m_expression) {
+                    Some(f) => return self.lower_address(f),
+                    None => panic!("Address of a value which isn't an lvalue"),
+                }
+            }
+            _ => panic!("Address of a value which isn't an lvalue"),
+        }
+    }
+
+    fn lower_expression(&mut self, expression: &Expression) -> Temp {
+        match expression {
+            Expression::Assignment { m_target, m_value, m_span: _ } => {
+                // The value is worked out before the address
+                let value = self.lower_expression(m_value);
+                let target_type = types::factor_type(self, m_target);
+                let value_type = types::expression_type(self, m_value);
+                let value = self.convert(value, &value_type, &target_type);
+                let (address, volatile) = self.lower_address(m_target);
+                self.emit(Instruction::Store {
+                    m_type: ir::ir_type(&target_type),
+                    m_address: address,
+                    m_value: value,
+                    m_volatile: volatile,
+                });
+                return value;
+            }
+            Expression::Operation(conditional_expression) => {
+                // Worked out now unless it would trap or overflow, which is
+                // left to happen at run time
+                match evaluator::evaluate(self, expression) {
+                    Ok(evaluation) if !evaluation.m_overflow => {
+                        let var_type = types::conditional_type(
+                            self,
+                            conditional_expression,
+                        );
+                        let bits =
+                            constant_bits(&evaluation.m_value, &var_type);
+                        return self.constant(ir::ir_type(&var_type), bits);
+                    }
+                    _ => (),
+                }
+                return self
+                    .lower_conditional_expression(conditional_expression);
+            }
+        }
+    }
+
+    fn lower_conditional_expression(
+        &mut self,
+        conditional_expression: &ConditionalExpression,
+    ) -> Temp {
+        let condition = self
+            .lower_logical_or_expression(&conditional_expression.m_condition);
+        let true_expression = match &conditional_expression.m_true {
+            Some(e) => e,
+            None => return condition,
+        };
+        let false_expression = conditional_expression.m_false.as_ref().unwrap();
+        let result_type = types::conditional_type(self, conditional_expression);
+        let condition_type =
+            types::logical_or_type(self, &conditional_expression.m_condition);
+        let truth = self.truth_value(condition, &condition_type);
+
+        let true_block = self.new_block(Vec::new());
+        let false_block = self.new_block(Vec::new());
+        let result = self.new_temp(ir::ir_type(&result_type));
+        let end_block = self.new_block(vec![result]);
+        self.terminate(Terminator::Branch {
+            m_condition: truth,
+            m_true: true_block,
+            m_false: false_block,
+        });
+
+        self.current = true_block;
+        let value = self.lower_expression(true_expression);
+        let value_type = types::expression_type(self, true_expression);
+        let value = self.convert(value, &value_type, &result_type);
+        self.jump(end_block, vec![value]);
+
+        self.current = false_block;
+        let value = self.lower_conditional_expression(false_expression);
+        let value_type = types::conditional_type(self, false_expression);
+        let value = self.convert(value, &value_type, &result_type);
+        self.jump(end_block, vec![value]);
+
+        self.current = end_block;
+        return result;
+    }
+
+    // Short circuits: once the result is known the rest isn't evaluated.
+    // short_value is the result which stops evaluation, 1 for || and 0 for
+    // &&. Each operand given is lowered by lower_operand
+    fn lower_short_circuit<T>(
+        &mut self,
+        first: Temp,
+        first_type: &VarType,
+        rest: &Vec<T>,
+        short_value: i64,
+        lower_operand: fn(&mut Self, &T) -> (Temp, VarType),
+    ) -> Temp {
+        if rest.is_empty() {
+            return first;
+        }
+        let mut result = self.boolean_value(first, first_type);
+        for operand in rest {
+            let short_block = self.new_block(Vec::new());
+            let next_block = self.new_block(Vec::new());
+            let joined = self.new_temp(Type::I32);
+            let end_block = self.new_block(vec![joined]);
+            let (true_block, false_block) = match short_value {
+                0 => (next_block, short_block),
+                _ => (short_block, next_block),
+            };
+            self.terminate(Terminator::Branch {
+                m_condition: result,
+                m_true: true_block,
+                m_false: false_block,
+            });
+
+            self.current = short_block;
+            let known = self.constant(Type::I32, short_value);
+            self.jump(end_block, vec![known]);
+
+            self.current = next_block;
+            let (value, value_type) = lower_operand(self, operand);
+            let value = self.boolean_value(value, &value_type);
+            self.jump(end_block, vec![value]);
+
+            self.current = end_block;
+            result = joined;
+        }
+        return result;
+    }
+
+    fn lower_logical_or_expression(
+        &mut self,
+        logical_or_expression: &LogicalOrExpresson,
+    ) -> Temp {
+        let first =
+            self.lower_logical_and_expression(&logical_or_expression.m_first);
+        let first_type =
+            types::logical_and_type(self, &logical_or_expression.m_first);
+        return self.lower_short_circuit(
+            first,
+            &first_type,
+            &logical_or_expression.m_rest,
+            1,
+            |lowerer, operand| {
+                let value = lowerer.lower_logical_and_expression(operand);
+                (value, types::logical_and_type(lowerer, operand))
+            },
+        );
+    }
+
+    fn lower_logical_and_expression(
+        &mut self,
+        logical_and_expression: &LogicalAndExpression,
+    ) -> Temp {
+        let first =
+            self.lower_equality_expression(&logical_and_expression.m_first);
+        let first_type =
+            types::equality_type(self, &logical_and_expression.m_first);
+        return self.lower_short_circuit(
+            first,
+            &first_type,
+            &logical_and_expression.m_rest,
+            0,
+            |lowerer, operand| {
+                let value = lowerer.lower_equality_expression(operand);
+                (value, types::equality_type(lowerer, operand))
+            },
+        );
+    }
+
+    fn lower_equality_expression(
+        &mut self,
+        equality_expression: &EqualityExpression,
+    ) -> Temp {
+        let mut left =
+            self.lower_relational_expression(&equality_expression.m_first);
+        let mut left_type =
+            types::relational_type(self, &equality_expression.m_first);
+
+        for next_op in &equality_expression.m_rest {
+            let right = self.lower_relational_expression(&next_op.1);
+            let right_type = types::relational_type(self, &next_op.1);
+            let operand_type = types::common_type(&left_type, &right_type);
+            let left_value = self.convert(left, &left_type, &operand_type);
+            let right_value = self.convert(right, &right_type, &operand_type);
+            let op = match next_op.0 {
+                EqualityOperator::Equal => CompareOp::Equal,
+                EqualityOperator::NotEqual => CompareOp::NotEqual,
+            };
+            left = self.compare(
+                op,
+                ir::ir_type(&operand_type),
+                left_value,
+                right_value,
+            );
+            left_type = VarType::Int;
+        }
+
+        return left;
+    }
+
+    fn lower_relational_expression(
+        &mut self,
+        relational_expression: &RelationalExpression,
+    ) -> Temp {
+        let mut left =
+            self.lower_additive_expression(&relational_expression.m_first);
+        let mut left_type =
+            types::additive_type(self, &relational_expression.m_first);
+
+        for next_op in &relational_expression.m_rest {
+            let right = self.lower_additive_expression(&next_op.1);
+            let right_type = types::additive_type(self, &next_op.1);
+            let operand_type = types::common_type(&left_type, &right_type);
+            let left_value = self.convert(left, &left_type, &operand_type);
+            let right_value = self.convert(right, &right_type, &operand_type);
+            // Unsigned numbers and addresses compare without sign
+            let op = match (&next_op.0, operand_type.is_unsigned()) {
+                (RelationalOperator::Less, false) => CompareOp::Less,
+                (RelationalOperator::LessOrEqual, false) => {
+                    CompareOp::LessOrEqual
+                }
+                (RelationalOperator::Greater, false) => CompareOp::Greater,
+                (RelationalOperator::GreaterOrEqual, false) => {
+                    CompareOp::GreaterOrEqual
+                }
+                (RelationalOperator::Less, true) => CompareOp::UnsignedLess,
+                (RelationalOperator::LessOrEqual, true) => {
+                    CompareOp::UnsignedLessOrEqual
+                }
+                (RelationalOperator::Greater, true) => {
+                    CompareOp::UnsignedGreater
+                }
+                (RelationalOperator::GreaterOrEqual, true) => {
+                    CompareOp::UnsignedGreaterOrEqual
+                }
+            };
+            left = self.compare(
+                op,
+                ir::ir_type(&operand_type),
+                left_value,
+                right_value,
+            );
+            left_type = VarType::Int;
+        }
+        return left;
+    }
+
+    // An integer index scaled to a byte offset from a pointer to pointee
+    fn scale_index(
+        &mut self,
+        index: Temp,
+        index_type: &VarType,
+        pointee: &VarType,
+    ) -> Temp {
+        let index = self.convert(index, index_type, &VarType::Long);
+        let size = self.constant(Type::I64, types::size_of(pointee));
+        return self.binary(BinaryOp::Multiply, Type::I64, index, size);
+    }
+
+    fn lower_additive_expression(
+        &mut self,
+        additive_expression: &AdditiveExpression,
+    ) -> Temp {
+        let mut left = self.lower_term(&additive_expression.m_first_term);
+        let mut left_type =
+            types::term_type(self, &additive_expression.m_first_term);
+
+        for next_op in &additive_expression.m_rest {
+            let right = self.lower_term(&next_op.1);
+            let right_type = types::term_type(self, &next_op.1);
+            let operand_type = types::common_type(&left_type, &right_type);
+            let op = match next_op.0 {
+                AdditiveOperator::Addition => BinaryOp::Add,
+                AdditiveOperator::Minus => BinaryOp::Subtract,
+            };
+            // Pointer arithmetic counts in elements of the pointed to type
+            match (&left_type, &right_type) {
+                (VarType::Pointer(t, _), VarType::Pointer(_, _)) => {
+                    let bytes = self.binary(op, Type::I64, left, right);
+                    let size = self.constant(Type::I64, types::size_of(t));
+                    left =
+                        self.binary(BinaryOp::Divide, Type::I64, bytes, size);
+                    left_type = VarType::Long;
+                }
+                (VarType::Pointer(t, _), _) => {
+                    let t = *t.clone();
+                    let offset = self.scale_index(right, &right_type, &t);
+                    left = self.binary(op, Type::I64, left, offset);
+                }
+                (_, VarType::Pointer(t, _)) => {
+                    let t = *t.clone();
+                    let offset = self.scale_index(left, &left_type, &t);
+                    left = self.binary(op, Type::I64, offset, right);
+                    left_type = operand_type;
+                }
+                _ => {
+                    let left_value =
+                        self.convert(left, &left_type, &operand_type);
+                    let right_value =
+                        self.convert(right, &right_type, &operand_type);
+                    left = self.binary(
+                        op,
+                        ir::ir_type(&operand_type),
+                        left_value,
+                        right_value,
+                    );
+                    left_type = operand_type;
+                }
+            }
+        }
+
+        return left;
+    }
+
+    fn lower_term(&mut self, term: &Term) -> Temp {
+        let mut left = self.lower_factor(&term.m_first_factor);
+        let mut left_type = types::factor_type(self, &term.m_first_factor);
+
+        for next_op in &term.m_rest {
+            let right = self.lower_factor(&next_op.1);
+            let right_type = types::factor_type(self, &next_op.1);
+            let operand_type = types::common_type(&left_type, &right_type);
+            let left_value = self.convert(left, &left_type, &operand_type);
+            let right_value = self.convert(right, &right_type, &operand_type);
+            let unsigned = operand_type.is_unsigned();
+            let op = match (&next_op.0, unsigned) {
+                (MultiplicativeOperator::Multiplication, _) => {
+                    BinaryOp::Multiply
+                }
+                (MultiplicativeOperator::Division, false) => BinaryOp::Divide,
+                (MultiplicativeOperator::Division, true) => {
+                    BinaryOp::UnsignedDivide
+                }
+                (MultiplicativeOperator::Modulo, false) => BinaryOp::Remainder,
+                (MultiplicativeOperator::Modulo, true) => {
+                    BinaryOp::UnsignedRemainder
+                }
+            };
+            left = self.binary(
+                op,
+                ir::ir_type(&operand_type),
+                left_value,
+                right_value,
+            );
+            left_type = operand_type;
+        }
+
+        return left;
+    }
+
+    fn lower_factor(&mut self, factor: &Factor) -> Temp {
+        match factor {
+            Factor::FunCall { m_id, m_arguments, m_span: _ } => {
+                return self.lower_call(m_id, m_arguments)
+            }
+            Factor::Variable { m_var: _, m_span: _ } => {
+                let var_type = types::factor_type(self, factor);
+                let (address, volatile) = self.lower_address(factor);
+                let dest = self.new_temp(ir::ir_type(&var_type));
+                self.emit(Instruction::Load {
+                    m_dest: dest,
+                    m_type: ir::ir_type(&var_type),
+                    m_address: address,
+                    m_volatile: volatile,
+                });
+                return dest;
+            }
+            Factor::Constant { m_value, m_type } => {
+                return self.constant(ir::ir_type(m_type), *m_value)
+            }
+            Factor::FloatConstant { m_value, m_type } => {
+                let bits = constant_bits(&Constant::Floating(*m_value), m_type);
+                return self.constant(ir::ir_type(m_type), bits);
+            }
+            Factor::UnaryOperation {
+                m_opertator: UnaryOperator::AddressOf,
+                m_factor,
+            } => return self.lower_address(m_factor).0,
+            Factor::UnaryOperation {
+                m_opertator: UnaryOperator::Dereference,
+                m_factor: _,
+            } => {
+                let pointee = types::factor_type(self, factor);
+                let (address, volatile) = self.lower_address(factor);
+                let dest = self.new_temp(ir::ir_type(&pointee));
+                self.emit(Instruction::Load {
+                    m_dest: dest,
+                    m_type: ir::ir_type(&pointee),
+                    m_address: address,
+                    m_volatile: volatile,
+                });
+                return dest;
+            }
+            Factor::UnaryOperation { m_opertator, m_factor } => {
+                let value = self.lower_factor(m_factor);
+                let operand_type = types::factor_type(self, m_factor);
+                let op = match m_opertator {
+                    UnaryOperator::Negation => {
+                        let zero = self.zero(&operand_type);
+                        return self.compare(
+                            CompareOp::Equal,
+                            ir::ir_type(&operand_type),
+                            value,
+                            zero,
+                        );
+                    }
+                    UnaryOperator::Complement => UnaryOp::Not,
+                    _ => UnaryOp::Negate,
+                };
+                let result_type = types::factor_type(self, factor);
+                let value = self.convert(value, &operand_type, &result_type);
+                let dest = self.new_temp(ir::ir_type(&result_type));
+                self.emit(Instruction::Unary {
+                    m_dest: dest,
+                    m_op: op,
+                    m_type: ir::ir_type(&result_type),
+                    m_operand: value,
+                });
+                return dest;
+            }
+            Factor::Braced { m_expression } => {
+                return self.lower_expression(m_expression)
+            }
+            Factor::Cast { m_type, m_factor } => {
+                let value = self.lower_factor(m_factor);
+                let from = types::factor_type(self, m_factor);
+                return self.convert(value, &from, m_type);
+            }
+            // Known at compile time, and the operand of sizeof is never
+            // evaluated
+            Factor::SizeOfType { m_type: _ }
+            | Factor::SizeOfExpression { m_factor: _ }
+            | Factor::AlignOf { m_type: _ } => {
+                let size = match evaluator::constant_factor(self, factor) {
+                    Some(Constant::Int(v)) => v,
+                    _ => panic!("sizeof without a constant size"),
+                };
+                return self.constant(Type::I64, size);
+            }
+            Factor::VaStart { m_list, m_last: _ } => {
+                let list = match types::single_factor(m_list) {
+                    Some(f) => f,
+                    None => panic!("va_start on a value which isn't an lvalue"),
+                };
+                let (address, _) = self.lower_address(list);
+                self.emit(Instruction::VaStart { m_list: address });
+                return self.constant(Type::I32, 0);
+            }
+            Factor::VaArg { m_list, m_type } => {
+                let list = self.lower_expression(m_list);
+                let dest = self.new_temp(ir::ir_type(m_type));
+                self.emit(Instruction::VaArg {
+                    m_dest: dest,
+                    m_type: ir::ir_type(m_type),
+                    m_list: list,
+                });
+                return dest;
+            }
+            Factor::VaEnd { m_list: _ } => return self.constant(Type::I32, 0),
+        }
+    }
+
+    // Arguments are converted to the parameter types, or given the default
+    // argument promotions past the parameters of a variadic function.
+    // Integers narrower than int are passed extended to int, as the x86-64
+    // and AArch64 ABIs expect. They are evaluated last to first
+    fn lower_call(&mut self, id: &String, arguments: &Vec<Expression>) -> Temp {
+        let (return_type, param_types, variadic) = match self.functions.get(id)
+        {
+            Some(f) => f.clone(),
+            None => (VarType::Int, Vec::new(), false),
+        };
+        let mut values: Vec<Temp> = Vec::new();
+        for (i, argument) in arguments.iter().enumerate().rev() {
+            let value = self.lower_expression(argument);
+            let argument_type = types::expression_type(self, argument);
+            let param_type = match param_types.get(i) {
+                Some(t) => t.clone(),
+                None if argument_type == VarType::Float => VarType::Double,
+                None => types::promote(&argument_type),
+            };
+            let value = self.convert(value, &argument_type, &param_type);
+            let value = match param_type.is_integer()
+                && types::size_of(&param_type) < 4
+            {
+                true => self.convert(value, &param_type, &VarType::Int),
+                false => value,
+            };
+            values.push(value);
+        }
+        values.reverse();
+
+        let dest = self.new_temp(ir::ir_type(&return_type));
+        self.emit(Instruction::Call {
+            m_dest: dest,
+            m_function: id.clone(),
+            m_arguments: values,
+            m_variadic: match variadic {
+                true => Some(param_types.len()),
+                false => None,
+            },
+        });
+        return dest;
+    }
+}
@@ -1,73 +1,244 @@
 use std::env;
-use std::fs;
-use std::fs::write;
+use std::fs::{read, set_permissions, write, Permissions};
+use std::io::{stdout, BufWriter, Write};
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 use std::path::PathBuf;
-use std::process::Command;
-use token::Token;
+use std::process::{exit, Command};
+use token::{Span, Token};
 
+mod aarch64;
+mod allocator;
 mod analyser;
+mod assembly;
+mod elf;
+mod encoder;
+mod evaluator;
+mod frame;
 mod generator;
+mod interpreter;
+mod ir;
 mod lexer;
+mod linker;
+mod lower;
+mod optimiser;
 mod parser;
+mod peephole;
+mod preprocessor;
+mod riscv;
+mod runtime;
 mod token;
+mod types;
+mod wasm;
+
+// The machines ccc generates code for
+#[derive(PartialEq)]
+enum Target {
+    X86_64,
+    Aarch64,
+    Riscv64,
+    Wasm32,
+}
 
 fn main() {
     let debug = false;
 
     let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Requied path: usage ccc path");
-        return;
+
+    let mut in_path: Option<PathBuf> = None;
+    let mut preprocess_only = false;
+    let mut dump_ir = false;
+    // Cleared by -S, which leaves the assembly in place of a program
+    let mut compile = true;
+    // Set by -c, which makes an object file without running any other tools
+    let mut object_only = false;
+    // Set by --standalone, which links with the built-in linker and runtime
+    // instead of gcc
+    let mut standalone = false;
+    let mut optimisation_level = 0;
+    let mut syntax = assembly::Syntax::Att;
+    let mut target = Target::X86_64;
+    let mut include_paths: Vec<PathBuf> = Vec::new();
+    // (name or definition, is_define) in command line order
+    let mut macro_args: Vec<(String, bool)> = Vec::new();
+    // Object files and archives passed on to the linker
+    let mut link_paths: Vec<PathBuf> = Vec::new();
+
+    // `ccc run path` interprets the program instead of compiling it
+    let run = match args.get(1) {
+        Some(arg) => arg == "run",
+        None => false,
+    };
+
+    let mut arg_iter = args.iter().skip(match run {
+        true => 2,
+        false => 1,
+    });
+    while let Some(arg) = arg_iter.next() {
+        if arg == "-E" {
+            preprocess_only = true;
+        } else if arg == "--dump-ir" {
+            dump_ir = true;
+        } else if arg == "-S" {
+            compile = false;
+        } else if arg == "-c" {
+            object_only = true;
+        } else if arg == "--standalone" {
+            standalone = true;
+        } else if let Some(name) = arg.strip_prefix("-masm=") {
+            syntax = match name {
+                "att" => assembly::Syntax::Att,
+                "intel" => assembly::Syntax::Intel,
+                _ => {
+                    eprintln!("Unknown assembly syntax: {}", name);
+                    exit(1);
+                }
+            };
+        } else if let Some(name) = arg.strip_prefix("--target=") {
+            target = match name {
+                "x86_64-linux" => Target::X86_64,
+                "aarch64-linux" => Target::Aarch64,
+                "riscv64" => Target::Riscv64,
+                "wasm32" => Target::Wasm32,
+                _ => {
+                    eprintln!("Unknown target: {}", name);
+                    exit(1);
+                }
+            };
+        } else if let Some(level) = arg.strip_prefix("-O") {
+            // -O is -O1, and levels past the last one are treated as it
+            optimisation_level = match level {
+                "" => 1,
+                _ => match level.parse::<u32>() {
+                    Ok(l) => l.min(2),
+                    Err(_) => {
+                        eprintln!("Unknown optimisation level: {}", arg);
+                        exit(1);
+                    }
+                },
+            };
+        } else if arg == "-I" || arg == "-D" || arg == "-U" {
+            let value = match arg_iter.next() {
+                Some(v) => v.clone(),
+                None => {
+                    eprintln!("Missing argument to {}", arg);
+                    exit(1);
+                }
+            };
+            match arg.as_str() {
+                "-I" => include_paths.push(PathBuf::from(value)),
+                "-D" => macro_args.push((value, true)),
+                _ => macro_args.push((value, false)),
+            }
+        } else if let Some(dir) = arg.strip_prefix("-I") {
+            include_paths.push(PathBuf::from(dir));
+        } else if let Some(definition) = arg.strip_prefix("-D") {
+            macro_args.push((String::from(definition), true));
+        } else if let Some(name) = arg.strip_prefix("-U") {
+            macro_args.push((String::from(name), false));
+        } else if arg.starts_with('-') {
+            eprintln!("Unknown option: {}", arg);
+            exit(1);
+        } else if arg.ends_with(".o") || arg.ends_with(".a") {
+            link_paths.push(PathBuf::from(arg));
+        } else {
+            in_path = Some(PathBuf::from(arg));
+        }
+    }
+
+    if target != Target::X86_64
+        && (object_only || standalone || syntax != assembly::Syntax::Att)
+    {
+        eprintln!("-c, --standalone and -masm=intel only apply to x86-64");
+        exit(1);
+    }
+
+    // A WebAssembly module is the finished program, so there's nothing to
+    // assemble or link it with
+    if target == Target::Wasm32 {
+        if !link_paths.is_empty() {
+            eprintln!("Objects can't be linked into a WebAssembly module");
+            exit(1);
+        }
+        compile = false;
     }
 
-    let in_path = &args[1];
-    let in_path = PathBuf::from(in_path);
+    let in_path = match in_path {
+        Some(p) => p,
+        None => {
+            eprintln!(
+                "Requied path: usage ccc [run] [-E] [-S] [-c] [--standalone] [--dump-ir] [-O level] \
+                 [-masm=att|intel] [--target=name] [-I dir] [-D name[=value]] [-U name] path [objects]"
+            );
+            exit(1);
+        }
+    };
 
     let file_name = match in_path.file_name() {
         Some(name) => name.to_string_lossy().into_owned(),
         None => {
             eprintln!("Error: input path has no file name");
-            return;
+            exit(1);
         }
     };
 
     let dot_pos = match file_name.find('.') {
         Some(i) => i,
         None => {
-            return;
+            eprintln!("Error: input path has no extension");
+            exit(1);
         }
     };
 
     let program_name: String = file_name.chars().take(dot_pos).collect();
 
     let mut out_path = String::from(&program_name);
-    out_path.push_str(".s");
+    match target {
+        Target::Wasm32 => out_path.push_str(".wat"),
+        _ => out_path.push_str(".s"),
+    }
+
+    let mut preprocessor = preprocessor::Preprocessor::new(include_paths);
+    for (value, is_define) in &macro_args {
+        if *is_define {
+            preprocessor.define(value);
+        } else {
+            preprocessor.undefine(value);
+        }
+    }
 
-    let s = match fs::read_to_string(&in_path) {
+    let s = match preprocessor.preprocess_file(&in_path) {
         Ok(s) => s,
         Err(e) => {
-            println!("{:?}", e);
-            return;
+            eprintln!("Error preprocessing: {:?}", e);
+            exit(1);
         }
     };
 
-    let tokens: Vec<Token> = match lexer::lex(&s) {
+    if preprocess_only {
+        print!("{}", s);
+        return;
+    }
+
+    let (tokens, spans): (Vec<Token>, Vec<Span>) = match lexer::lex(&s) {
         Ok(v) => v,
-        Err(e) => panic!("Could not tokenize: {:?}", e),
+        Err((e, span)) => {
+            eprintln!("{}: Could not tokenize: {:?}", span, e);
+            exit(1);
+        }
     };
 
     if debug {
         println!("Tokens: {:?}", tokens);
     }
 
-    let program_result = parser::parse_program(&tokens);
+    let program_result = parser::parse_program(&tokens, &spans);
 
     let program = match program_result {
         Ok(prog) => prog,
-        Err(e) => {
-            eprintln!("Error parsing program: {:?}", e);
-            return;
+        Err((e, span)) => {
+            eprintln!("{}: Error parsing program: {:?}", span, e);
+            exit(1);
         }
     };
 
@@ -77,20 +248,143 @@ fn main() {
 
     let mut analyser = analyser::Analyser::new();
 
-    match analyser.analyse_program(&program) {
+    let analysis = analyser.analyse_program(&program);
+    for (warning, warning_span) in analyser.warnings() {
+        eprintln!("{}: warning: {}", warning_span, warning);
+    }
+    match analysis {
         Ok(_) => (),
-        Err(e) => panic!("{:?}", e),
+        Err((e, span)) => {
+            eprintln!("{}: Error analysing program: {:?}", span, e);
+            for (note, note_span) in analyser.notes() {
+                eprintln!("{}: note: {}", note_span, note);
+            }
+            exit(1);
+        }
     }
 
-    let mut generator = generator::Generator::new();
+    let mut ir_program = lower::Lowerer::new().lower_program(&program);
+    optimiser::optimise(&mut ir_program, optimisation_level);
 
-    let s_program = generator.generate(&program);
+    if dump_ir {
+        print!("{}", ir_program);
+        return;
+    }
+
+    // The exit status is main's result, as for a compiled program
+    if run {
+        let mut out = BufWriter::new(stdout());
+        let result = interpreter::run(&ir_program, &mut out);
+        match out.flush() {
+            Ok(_) => (),
+            Err(e) => eprintln!("Failed to write output: {}", e),
+        }
+        match result {
+            Ok(status) => exit(status),
+            Err((e, Some(span))) => {
+                eprintln!("{}: Error running program: {}", span, e)
+            }
+            Err((e, None)) => eprintln!("Error running program: {}", e),
+        }
+        exit(1);
+    }
+
+    let s_program = match target {
+        Target::Aarch64 => aarch64::Generator::new().generate(&ir_program),
+        Target::Riscv64 => riscv::Generator::new().generate(&ir_program),
+        Target::Wasm32 => wasm::Generator::new().generate(&ir_program),
+        Target::X86_64 => {
+            let mut generator = generator::Generator::new();
+
+            let mut lines = generator.generate(&ir_program);
+            if optimisation_level > 0 {
+                peephole::optimise(&mut lines);
+            }
+
+            if object_only && compile {
+                let object = elf::write(&encoder::encode(&lines));
+                let object_path = format!("{}.o", program_name);
+                match write(&object_path, object) {
+                    Ok(_) => (),
+                    Err(e) => {
+                        eprintln!("Failed to write {}: {}", object_path, e);
+                        exit(1);
+                    }
+                }
+                return;
+            }
+
+            if standalone && compile {
+                let mut objects =
+                    vec![runtime::object(), encoder::encode(&lines)];
+                for path in &link_paths {
+                    let bytes = match read(path) {
+                        Ok(b) => b,
+                        Err(e) => {
+                            eprintln!(
+                                "Failed to read {}: {}",
+                                path.display(),
+                                e
+                            );
+                            exit(1);
+                        }
+                    };
+                    match elf::read(&bytes) {
+                        Ok(object) => objects.push(object),
+                        Err(e) => {
+                            eprintln!(
+                                "Error reading {}: {}",
+                                path.display(),
+                                e
+                            );
+                            exit(1);
+                        }
+                    }
+                }
+                let executable = match linker::link(&objects) {
+                    Ok(e) => e,
+                    Err(e) => {
+                        eprintln!("Error linking: {}", e);
+                        exit(1);
+                    }
+                };
+                let dir = in_path.parent().unwrap_or(Path::new(""));
+                let executable_path = dir.join(&program_name);
+                match write(&executable_path, executable) {
+                    Ok(_) => (),
+                    Err(e) => {
+                        eprintln!(
+                            "Failed to write {}: {}",
+                            executable_path.display(),
+                            e
+                        );
+                        exit(1);
+                    }
+                }
+                let permissions = Permissions::from_mode(0o755);
+                match set_permissions(&executable_path, permissions) {
+                    Ok(_) => (),
+                    Err(e) => {
+                        eprintln!(
+                            "Failed to make {} executable: {}",
+                            executable_path.display(),
+                            e
+                        );
+                        exit(1);
+                    }
+                }
+                return;
+            }
+
+            assembly::print(&lines, syntax)
+        }
+    };
 
     match write(&out_path, &s_program) {
         Ok(_) => (),
         Err(e) => {
-            println!("{:?}", e);
-            return;
+            eprintln!("Failed to write {}: {}", out_path, e);
+            exit(1);
         }
     }
 
@@ -98,29 +392,36 @@ fn main() {
         println!("{:?}", s_program);
     }
 
-    let compile = true;
-
     if compile {
         let dir = in_path.parent().unwrap_or(Path::new(""));
         let gcc_out_path = dir.join(&program_name);
 
-        let gcc_output = Command::new("gcc")
+        // A cross compiler driver assembles and links for other targets
+        let driver = match target {
+            Target::X86_64 => "gcc",
+            Target::Aarch64 => "aarch64-linux-gnu-gcc",
+            Target::Riscv64 => "riscv64-linux-gnu-gcc",
+            Target::Wasm32 => return,
+        };
+        let gcc_output = Command::new(driver)
             .arg(&out_path)
+            .args(&link_paths)
             .arg("-o")
             .arg(&gcc_out_path)
             .output()
-            .expect("Failed to execute gcc");
+            .expect("Failed to execute the compiler driver");
 
         if !gcc_output.status.success() {
             eprintln!(
-                "gcc failed with output: \n{}",
+                "{} failed with output: \n{}",
+                driver,
                 String::from_utf8_lossy(&gcc_output.stderr)
             );
-            return;
+            exit(1);
         }
     }
 
-    let remove = !debug;
+    let remove = !debug && compile;
 
     if remove {
         let rm_output = Command::new("rm")
@@ -0,0 +1,1473 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::{
+    self, BinaryOp, BlockId, CompareOp, ConvertOp, Function, Instruction, Temp,
+    Terminator, Type, UnaryOp,
+};
+
+// Optimisation passes over the IR, chosen by the -O level. -O1 keeps
+// variables in temporaries instead of stack slots where their address isn't
+// taken, folds constants, propagates copies and removes dead code and
+// blocks. -O2 also inlines small functions, eliminates common
+// subexpressions and hoists invariant code out of loops. Every pass keeps
+// the program's behaviour, so -O0 output is the reference for them all
+
+// Functions with at most this many instructions are inlined at -O2
+const INLINE_LIMIT: usize = 40;
+// The -O1 passes run until nothing changes, or this many times
+const MAX_ROUNDS: usize = 10;
+
+pub fn optimise(program: &mut ir::Program, level: u32) {
+    if level == 0 {
+        return;
+    }
+    for function in &mut program.m_functions {
+        simplify(function);
+    }
+    if level < 2 {
+        return;
+    }
+    inline_calls(program);
+    remove_unused_functions(program);
+    for function in &mut program.m_functions {
+        // Slots of inlined functions can be promoted now
+        simplify(function);
+        eliminate_common_subexpressions(function);
+        hoist_loop_invariants(function);
+        simplify(function);
+    }
+}
+
+// The -O1 passes
+fn simplify(function: &mut Function) {
+    remove_unreachable_blocks(function);
+    promote_slots(function);
+    for _ in 0..MAX_ROUNDS {
+        let mut changed = fold_constants(function);
+        changed |= propagate_copies(function);
+        changed |= eliminate_dead_code(function);
+        changed |= simplify_control_flow(function);
+        if !changed {
+            break;
+        }
+    }
+    compact(function);
+}
+
+// The blocks jumping or branching to each block
+fn predecessors(function: &Function) -> Vec<Vec<BlockId>> {
+    let mut predecessors: Vec<Vec<BlockId>> =
+        vec![Vec::new(); function.m_blocks.len()];
+    for (i, block) in function.m_blocks.iter().enumerate() {
+        for successor in block.m_terminator.successors() {
+            if !predecessors[successor].contains(&i) {
+                predecessors[successor].push(i);
+            }
+        }
+    }
+    return predecessors;
+}
+
+// The blocks reachable from the entry, in reverse postorder, so each block
+// comes before its successors except along loop back edges
+fn reverse_postorder(function: &Function) -> Vec<BlockId> {
+    let mut visited = vec![false; function.m_blocks.len()];
+    let mut order: Vec<BlockId> = Vec::new();
+    // (block, successors already visited)
+    let mut stack: Vec<(BlockId, usize)> = vec![(0, 0)];
+    visited[0] = true;
+    while let Some((block, next)) = stack.pop() {
+        let successors = function.m_blocks[block].m_terminator.successors();
+        if next < successors.len() {
+            stack.push((block, next + 1));
+            let successor = successors[next];
+            if !visited[successor] {
+                visited[successor] = true;
+                stack.push((successor, 0));
+            }
+        } else {
+            order.push(block);
+        }
+    }
+    order.reverse();
+    return order;
+}
+
+// Follows a chain of replaced temporaries to the one standing for them all
+fn resolve(replacements: &HashMap<Temp, Temp>, temp: Temp) -> Temp {
+    let mut temp = temp;
+    while let Some(r) = replacements.get(&temp) {
+        temp = *r;
+    }
+    return temp;
+}
+
+// Replaces every use of a replaced temporary
+fn substitute(function: &mut Function, replacements: &HashMap<Temp, Temp>) {
+    if replacements.is_empty() {
+        return;
+    }
+    for block in &mut function.m_blocks {
+        for instruction in &mut block.m_instructions {
+            for operand in instruction.operands_mut() {
+                *operand = resolve(replacements, *operand);
+            }
+        }
+        for operand in block.m_terminator.operands_mut() {
+            *operand = resolve(replacements, *operand);
+        }
+    }
+}
+
+// Drops the parameters of each block at the given positions, with the
+// arguments jumps pass for them
+fn remove_parameters(
+    function: &mut Function,
+    removed: &HashMap<BlockId, HashSet<usize>>,
+) {
+    for (block, positions) in removed {
+        let parameters =
+            std::mem::take(&mut function.m_blocks[*block].m_parameters);
+        function.m_blocks[*block].m_parameters = parameters
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| !positions.contains(i))
+            .map(|(_, p)| p)
+            .collect();
+    }
+    for block in &mut function.m_blocks {
+        match &mut block.m_terminator {
+            Terminator::Jump { m_target, m_arguments } => {
+                match removed.get(m_target) {
+                    Some(positions) => {
+                        let arguments = std::mem::take(m_arguments);
+                        *m_arguments = arguments
+                            .into_iter()
+                            .enumerate()
+                            .filter(|(i, _)| !positions.contains(i))
+                            .map(|(_, a)| a)
+                            .collect();
+                    }
+                    None => (),
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+// Drops the blocks control never reaches, keeping the order of the rest
+fn remove_unreachable_blocks(function: &mut Function) -> bool {
+    let mut reached = vec![false; function.m_blocks.len()];
+    for block in reverse_postorder(function) {
+        reached[block] = true;
+    }
+    if reached.iter().all(|r| *r) {
+        return false;
+    }
+    let order: Vec<BlockId> =
+        (0..function.m_blocks.len()).filter(|b| reached[*b]).collect();
+    reorder_blocks(function, &order);
+    return true;
+}
+
+// Keeps only the given blocks, in the given order, which starts with the
+// entry
+fn reorder_blocks(function: &mut Function, order: &Vec<BlockId>) {
+    let mut numbers: Vec<BlockId> = vec![usize::MAX; function.m_blocks.len()];
+    for (i, block) in order.iter().enumerate() {
+        numbers[*block] = i;
+    }
+    let mut blocks: Vec<Option<ir::Block>> =
+        std::mem::take(&mut function.m_blocks).into_iter().map(Some).collect();
+    for block in order {
+        let mut block = blocks[*block].take().unwrap();
+        for successor in block.m_terminator.successors_mut() {
+            *successor = numbers[*successor];
+        }
+        function.m_blocks.push(block);
+    }
+}
+
+// Keeps variables in temporaries instead of stack slots, when the slot is
+// only ever loaded from and stored to directly. A variable's value at the
+// start of a block reached from more than one place comes in as a block
+// parameter, so jumps to it pass the values at their end
+fn promote_slots(function: &mut Function) {
+    // Temporaries holding the address of each slot, and its access type.
+    // A slot whose address is used any other way can't be promoted
+    let mut addresses: HashMap<Temp, usize> = HashMap::new();
+    for block in &function.m_blocks {
+        for instruction in &block.m_instructions {
+            match instruction {
+                Instruction::SlotAddress { m_dest, m_slot } => {
+                    addresses.insert(*m_dest, *m_slot);
+                }
+                _ => (),
+            }
+        }
+    }
+    let mut slot_types: Vec<Option<Type>> = vec![None; function.m_slots.len()];
+    let mut escaped = vec![false; function.m_slots.len()];
+    for block in &function.m_blocks {
+        for instruction in &block.m_instructions {
+            let (address, access_type, volatile, others) = match instruction {
+                Instruction::Load { m_type, m_address, m_volatile, .. } => {
+                    (Some(*m_address), *m_type, *m_volatile, Vec::new())
+                }
+                Instruction::Store {
+                    m_type,
+                    m_address,
+                    m_value,
+                    m_volatile,
+                } => (Some(*m_address), *m_type, *m_volatile, vec![*m_value]),
+                _ => (None, Type::I64, false, instruction.operands()),
+            };
+            for other in others {
+                match addresses.get(&other) {
+                    Some(slot) => escaped[*slot] = true,
+                    None => (),
+                }
+            }
+            let slot = match address.and_then(|a| addresses.get(&a)) {
+                Some(s) => *s,
+                None => continue,
+            };
+            let value_type = match instruction {
+                Instruction::Store { m_value, .. } => {
+                    function.m_temps[*m_value]
+                }
+                _ => access_type,
+            };
+            match slot_types[slot] {
+                Some(t) if t != access_type => escaped[slot] = true,
+                _ => slot_types[slot] = Some(access_type),
+            }
+            if volatile || value_type != access_type {
+                escaped[slot] = true;
+            }
+        }
+        let mut terminator = block.m_terminator.clone();
+        for operand in terminator.operands_mut() {
+            match addresses.get(operand) {
+                Some(slot) => escaped[*slot] = true,
+                None => (),
+            }
+        }
+    }
+    // Position of each promoted slot among them, and its type
+    let mut promoted: HashMap<usize, usize> = HashMap::new();
+    let mut promoted_types: Vec<Type> = Vec::new();
+    for slot in 0..function.m_slots.len() {
+        if !escaped[slot] {
+            promoted.insert(slot, promoted_types.len());
+            promoted_types.push(slot_types[slot].unwrap_or(Type::I64));
+        }
+    }
+    let predecessors = predecessors(function);
+    if promoted.is_empty() || !predecessors[0].is_empty() {
+        return;
+    }
+
+    // Blocks with many predecessors get a parameter for each variable
+    for (block, block_predecessors) in predecessors.iter().enumerate() {
+        if block_predecessors.len() < 2 {
+            continue;
+        }
+        for promoted_type in &promoted_types {
+            function.m_temps.push(*promoted_type);
+            let temp = function.m_temps.len() - 1;
+            function.m_blocks[block].m_parameters.push(temp);
+        }
+    }
+
+    // Variables read before they are written start as zero
+    let mut initial: Vec<Instruction> = Vec::new();
+    let mut entry_values: Vec<Temp> = Vec::new();
+    for promoted_type in &promoted_types {
+        function.m_temps.push(*promoted_type);
+        let temp = function.m_temps.len() - 1;
+        initial.push(Instruction::Constant {
+            m_dest: temp,
+            m_type: *promoted_type,
+            m_value: 0,
+        });
+        entry_values.push(temp);
+    }
+
+    let mut replacements: HashMap<Temp, Temp> = HashMap::new();
+    let mut exit_values: Vec<Vec<Temp>> =
+        vec![Vec::new(); function.m_blocks.len()];
+    for block in reverse_postorder(function) {
+        let mut values = if block == 0 {
+            entry_values.clone()
+        } else if predecessors[block].len() == 1 {
+            exit_values[predecessors[block][0]].clone()
+        } else {
+            let parameters = &function.m_blocks[block].m_parameters;
+            parameters[parameters.len() - promoted_types.len()..].to_vec()
+        };
+        let instructions =
+            std::mem::take(&mut function.m_blocks[block].m_instructions);
+        let mut kept: Vec<Instruction> = Vec::new();
+        if block == 0 {
+            kept.append(&mut initial);
+        }
+        for instruction in instructions {
+            let promoted_slot = |address: &Temp| {
+                addresses.get(address).and_then(|s| promoted.get(s)).cloned()
+            };
+            match &instruction {
+                Instruction::SlotAddress { m_slot, .. }
+                    if promoted.contains_key(m_slot) =>
+                {
+                    continue
+                }
+                Instruction::Load { m_dest, m_address, .. } => {
+                    match promoted_slot(m_address) {
+                        Some(k) => {
+                            replacements.insert(*m_dest, values[k]);
+                            continue;
+                        }
+                        None => (),
+                    }
+                }
+                Instruction::Store { m_address, m_value, .. } => {
+                    match promoted_slot(m_address) {
+                        Some(k) => {
+                            values[k] = resolve(&replacements, *m_value);
+                            continue;
+                        }
+                        None => (),
+                    }
+                }
+                _ => (),
+            }
+            kept.push(instruction);
+        }
+        function.m_blocks[block].m_instructions = kept;
+        exit_values[block] = values;
+    }
+
+    // Pass the values at the end of each block on to blocks with
+    // parameters. A branch to one goes through a new block which jumps
+    let block_count = function.m_blocks.len();
+    for block in 0..block_count {
+        let values = exit_values[block].clone();
+        let mut terminator = function.m_blocks[block].m_terminator.clone();
+        match &mut terminator {
+            Terminator::Jump { m_target, m_arguments } => {
+                if predecessors[*m_target].len() > 1 {
+                    m_arguments.extend(values);
+                }
+            }
+            Terminator::Branch { m_true, m_false, .. } => {
+                for target in [m_true, m_false] {
+                    if predecessors[*target].len() < 2 {
+                        continue;
+                    }
+                    function.m_blocks.push(ir::Block {
+                        m_parameters: Vec::new(),
+                        m_instructions: Vec::new(),
+                        m_terminator: Terminator::Jump {
+                            m_target: *target,
+                            m_arguments: values.clone(),
+                        },
+                    });
+                    *target = function.m_blocks.len() - 1;
+                }
+            }
+            Terminator::Return { .. } => (),
+        }
+        function.m_blocks[block].m_terminator = terminator;
+    }
+    substitute(function, &replacements);
+}
+
+// Sign extends the low bytes of an integer of the type, the form folded
+// constants are kept in
+fn normalise(value: i64, value_type: Type) -> i64 {
+    match value_type {
+        Type::I8 => return value as i8 as i64,
+        Type::I16 => return value as i16 as i64,
+        Type::I32 => return value as i32 as i64,
+        _ => return value,
+    }
+}
+
+// An integer of the type read as unsigned
+fn unsigned(value: i64, value_type: Type) -> u64 {
+    match value_type {
+        Type::I8 => return value as u8 as u64,
+        Type::I16 => return value as u16 as u64,
+        Type::I32 => return value as u32 as u64,
+        _ => return value as u64,
+    }
+}
+
+// The value of a floating constant, exact for both types
+fn float_value(bits: i64, value_type: Type) -> f64 {
+    match value_type {
+        Type::F32 => return f32::from_bits(bits as u32) as f64,
+        _ => return f64::from_bits(bits as u64),
+    }
+}
+
+// The bits of a floating value rounded to the type. Rounding the exact
+// result of + - * / on floats from a double gives the float result
+fn float_bits(value: f64, value_type: Type) -> i64 {
+    match value_type {
+        Type::F32 => return (value as f32).to_bits() as i64,
+        _ => return value.to_bits() as i64,
+    }
+}
+
+fn fold_unary(op: UnaryOp, value_type: Type, value: i64) -> i64 {
+    match (op, value_type) {
+        (UnaryOp::Negate, Type::F32) => return value ^ 0x80000000,
+        (UnaryOp::Negate, Type::F64) => return value ^ i64::MIN,
+        (UnaryOp::Negate, _) => {
+            return normalise(value.wrapping_neg(), value_type)
+        }
+        (UnaryOp::Not, _) => return normalise(!value, value_type),
+    }
+}
+
+// None for division by zero, and for signed overflow which traps on x86-64
+fn fold_binary(
+    op: BinaryOp,
+    value_type: Type,
+    left: i64,
+    right: i64,
+) -> Option<i64> {
+    if value_type.is_floating() {
+        let (left, right) =
+            (float_value(left, value_type), float_value(right, value_type));
+        let result = match op {
+            BinaryOp::Add => left + right,
+            BinaryOp::Subtract => left - right,
+            BinaryOp::Multiply => left * right,
+            _ => left / right,
+        };
+        return Some(float_bits(result, value_type));
+    }
+    let result = match op {
+        BinaryOp::Add => left.wrapping_add(right),
+        BinaryOp::Subtract => left.wrapping_sub(right),
+        BinaryOp::Multiply => left.wrapping_mul(right),
+        BinaryOp::Divide | BinaryOp::Remainder => {
+            let (left, right) =
+                (normalise(left, value_type), normalise(right, value_type));
+            let minimum = match value_type.size() {
+                4 => i32::MIN as i64,
+                _ => i64::MIN,
+            };
+            if right == 0 || (right == -1 && left == minimum) {
+                return None;
+            }
+            match op {
+                BinaryOp::Divide => left / right,
+                _ => left % right,
+            }
+        }
+        BinaryOp::UnsignedDivide | BinaryOp::UnsignedRemainder => {
+            let (left, right) =
+                (unsigned(left, value_type), unsigned(right, value_type));
+            if right == 0 {
+                return None;
+            }
+            match op {
+                BinaryOp::UnsignedDivide => (left / right) as i64,
+                _ => (left % right) as i64,
+            }
+        }
+    };
+    return Some(normalise(result, value_type));
+}
+
+fn fold_compare(op: CompareOp, value_type: Type, left: i64, right: i64) -> i64 {
+    if value_type.is_floating() {
+        let (left, right) =
+            (float_value(left, value_type), float_value(right, value_type));
+        let result = match op {
+            CompareOp::Equal => left == right,
+            CompareOp::NotEqual => left != right,
+            CompareOp::Less | CompareOp::UnsignedLess => left < right,
+            CompareOp::LessOrEqual | CompareOp::UnsignedLessOrEqual => {
+                left <= right
+            }
+            CompareOp::Greater | CompareOp::UnsignedGreater => left > right,
+            CompareOp::GreaterOrEqual | CompareOp::UnsignedGreaterOrEqual => {
+                left >= right
+            }
+        };
+        return result as i64;
+    }
+    let (signed_left, signed_right) =
+        (normalise(left, value_type), normalise(right, value_type));
+    let (unsigned_left, unsigned_right) =
+        (unsigned(left, value_type), unsigned(right, value_type));
+    let result = match op {
+        CompareOp::Equal => signed_left == signed_right,
+        CompareOp::NotEqual => signed_left != signed_right,
+        CompareOp::Less => signed_left < signed_right,
+        CompareOp::LessOrEqual => signed_left <= signed_right,
+        CompareOp::Greater => signed_left > signed_right,
+        CompareOp::GreaterOrEqual => signed_left >= signed_right,
+        CompareOp::UnsignedLess => unsigned_left < unsigned_right,
+        CompareOp::UnsignedLessOrEqual => unsigned_left <= unsigned_right,
+        CompareOp::UnsignedGreater => unsigned_left > unsigned_right,
+        CompareOp::UnsignedGreaterOrEqual => unsigned_left >= unsigned_right,
+    };
+    return result as i64;
+}
+
+// None for floating values out of the range of the integer type, which C
+// leaves undefined, so the conversion happens at run time as without -O
+fn fold_convert(
+    op: ConvertOp,
+    from: Type,
+    to: Type,
+    value: i64,
+) -> Option<i64> {
+    match op {
+        ConvertOp::SignExtend => {
+            return Some(normalise(normalise(value, from), to))
+        }
+        ConvertOp::ZeroExtend => {
+            return Some(normalise(unsigned(value, from) as i64, to))
+        }
+        ConvertOp::Truncate => return Some(normalise(value, to)),
+        ConvertOp::SignedToFloat => {
+            let value = normalise(value, from);
+            match to {
+                Type::F32 => return Some((value as f32).to_bits() as i64),
+                _ => return Some((value as f64).to_bits() as i64),
+            }
+        }
+        ConvertOp::UnsignedToFloat => {
+            let value = unsigned(value, from);
+            match to {
+                Type::F32 => return Some((value as f32).to_bits() as i64),
+                _ => return Some((value as f64).to_bits() as i64),
+            }
+        }
+        ConvertOp::FloatToSigned | ConvertOp::FloatToUnsigned => {
+            let value = float_value(value, from).trunc();
+            let bits = 8.0 * to.size() as f64;
+            let (minimum, limit) = match op {
+                ConvertOp::FloatToSigned => {
+                    (-(2f64.powf(bits - 1.0)), 2f64.powf(bits - 1.0))
+                }
+                _ => (0.0, 2f64.powf(bits)),
+            };
+            if !(value >= minimum && value < limit) {
+                return None;
+            }
+            match op {
+                ConvertOp::FloatToSigned => {
+                    return Some(normalise(value as i64, to))
+                }
+                _ => return Some(normalise(value as u64 as i64, to)),
+            }
+        }
+        ConvertOp::FloatToFloat => {
+            return Some(float_bits(float_value(value, from), to))
+        }
+    }
+}
+
+// Works out instructions whose operands are all constants, and turns
+// branches on a constant into jumps
+fn fold_constants(function: &mut Function) -> bool {
+    let mut constants: HashMap<Temp, i64> = HashMap::new();
+    for block in &function.m_blocks {
+        for instruction in &block.m_instructions {
+            match instruction {
+                Instruction::Constant { m_dest, m_value, .. } => {
+                    constants.insert(*m_dest, *m_value);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    let mut changed = false;
+    for block in &mut function.m_blocks {
+        for instruction in &mut block.m_instructions {
+            let value = |t: &Temp| constants.get(t).cloned();
+            let folded = match instruction {
+                Instruction::Unary { m_op, m_type, m_operand, .. } => {
+                    value(m_operand)
+                        .map(|v| (*m_type, fold_unary(*m_op, *m_type, v)))
+                }
+                Instruction::Binary {
+                    m_op, m_type, m_left, m_right, ..
+                } => match (value(m_left), value(m_right)) {
+                    (Some(l), Some(r)) => {
+                        fold_binary(*m_op, *m_type, l, r).map(|v| (*m_type, v))
+                    }
+                    _ => None,
+                },
+                Instruction::Compare {
+                    m_op, m_type, m_left, m_right, ..
+                } => match (value(m_left), value(m_right)) {
+                    (Some(l), Some(r)) => {
+                        Some((Type::I32, fold_compare(*m_op, *m_type, l, r)))
+                    }
+                    _ => None,
+                },
+                Instruction::Convert {
+                    m_op, m_from, m_to, m_operand, ..
+                } => value(m_operand)
+                    .and_then(|v| fold_convert(*m_op, *m_from, *m_to, v))
+                    .map(|v| (*m_to, v)),
+                _ => None,
+            };
+            match folded {
+                Some((folded_type, folded_value)) => {
+                    let dest = instruction.dest().unwrap();
+                    *instruction = Instruction::Constant {
+                        m_dest: dest,
+                        m_type: folded_type,
+                        m_value: folded_value,
+                    };
+                    constants.insert(dest, folded_value);
+                    changed = true;
+                }
+                None => (),
+            }
+        }
+        match block.m_terminator {
+            Terminator::Branch { m_condition, m_true, m_false } => {
+                match constants.get(&m_condition) {
+                    Some(v) => {
+                        let condition_type = function.m_temps[m_condition];
+                        let target = match normalise(*v, condition_type) {
+                            0 => m_false,
+                            _ => m_true,
+                        };
+                        block.m_terminator = Terminator::Jump {
+                            m_target: target,
+                            m_arguments: Vec::new(),
+                        };
+                        changed = true;
+                    }
+                    None => (),
+                }
+            }
+            _ => (),
+        }
+    }
+    return changed;
+}
+
+// Block parameters which are always passed the same value, or themselves
+// around a loop, are copies of that value, so their uses use it instead
+fn propagate_copies(function: &mut Function) -> bool {
+    // Values passed for each parameter
+    let mut incoming: HashMap<Temp, Vec<Temp>> = HashMap::new();
+    for block in &function.m_blocks {
+        match &block.m_terminator {
+            Terminator::Jump { m_target, m_arguments } => {
+                let parameters = &function.m_blocks[*m_target].m_parameters;
+                for (parameter, argument) in parameters.iter().zip(m_arguments)
+                {
+                    incoming.entry(*parameter).or_default().push(*argument);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let mut replacements: HashMap<Temp, Temp> = HashMap::new();
+    let mut removed: HashMap<BlockId, HashSet<usize>> = HashMap::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (b, block) in function.m_blocks.iter().enumerate() {
+            for (i, parameter) in block.m_parameters.iter().enumerate() {
+                if replacements.contains_key(parameter) {
+                    continue;
+                }
+                let mut value: Option<Temp> = None;
+                let mut copy = true;
+                for argument in incoming.get(parameter).unwrap_or(&Vec::new()) {
+                    let argument = resolve(&replacements, *argument);
+                    if argument == *parameter || value == Some(argument) {
+                        continue;
+                    }
+                    match value {
+                        None => value = Some(argument),
+                        Some(_) => copy = false,
+                    }
+                }
+                match value {
+                    Some(v) if copy => {
+                        replacements.insert(*parameter, v);
+                        removed.entry(b).or_default().insert(i);
+                        changed = true;
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+    if replacements.is_empty() {
+        return false;
+    }
+    remove_parameters(function, &removed);
+    substitute(function, &replacements);
+    return true;
+}
+
+// Removes instructions and block parameters whose values are never used
+// and which do nothing else
+fn eliminate_dead_code(function: &mut Function) -> bool {
+    // Where each temporary is defined
+    let mut instruction_of: HashMap<Temp, (BlockId, usize)> = HashMap::new();
+    let mut parameter_of: HashMap<Temp, (BlockId, usize)> = HashMap::new();
+    let mut incoming: HashMap<(BlockId, usize), Vec<Temp>> = HashMap::new();
+    let mut work: Vec<Temp> = Vec::new();
+    for (b, block) in function.m_blocks.iter().enumerate() {
+        for (i, parameter) in block.m_parameters.iter().enumerate() {
+            parameter_of.insert(*parameter, (b, i));
+        }
+        for (i, instruction) in block.m_instructions.iter().enumerate() {
+            match instruction.dest() {
+                Some(d) => {
+                    instruction_of.insert(d, (b, i));
+                }
+                None => (),
+            }
+            if instruction.has_side_effects() {
+                work.extend(instruction.operands());
+            }
+        }
+        match &block.m_terminator {
+            Terminator::Jump { m_target, m_arguments } => {
+                for (i, argument) in m_arguments.iter().enumerate() {
+                    incoming.entry((*m_target, i)).or_default().push(*argument);
+                }
+            }
+            Terminator::Branch { m_condition, .. } => work.push(*m_condition),
+            Terminator::Return { m_value } => work.push(*m_value),
+        }
+    }
+
+    let mut live: HashSet<Temp> = HashSet::new();
+    while let Some(temp) = work.pop() {
+        if !live.insert(temp) {
+            continue;
+        }
+        match instruction_of.get(&temp) {
+            Some((b, i)) => {
+                work.extend(function.m_blocks[*b].m_instructions[*i].operands())
+            }
+            None => (),
+        }
+        match parameter_of.get(&temp) {
+            Some(position) => match incoming.get(position) {
+                Some(arguments) => work.extend(arguments),
+                None => (),
+            },
+            None => (),
+        }
+    }
+
+    let mut changed = false;
+    for block in &mut function.m_blocks {
+        let count = block.m_instructions.len();
+        block.m_instructions.retain(|instruction| {
+            instruction.has_side_effects()
+                || instruction.dest().map_or(true, |d| live.contains(&d))
+        });
+        // A location with no code before the next one marks nothing
+        let instructions = std::mem::take(&mut block.m_instructions);
+        let mut kept: Vec<Instruction> = Vec::new();
+        for instruction in instructions {
+            match (kept.last(), &instruction) {
+                (
+                    Some(Instruction::Location { .. }),
+                    Instruction::Location { .. },
+                ) => {
+                    kept.pop();
+                }
+                _ => (),
+            }
+            kept.push(instruction);
+        }
+        block.m_instructions = kept;
+        changed |= block.m_instructions.len() != count;
+    }
+    let mut removed: HashMap<BlockId, HashSet<usize>> = HashMap::new();
+    for (b, block) in function.m_blocks.iter().enumerate() {
+        for (i, parameter) in block.m_parameters.iter().enumerate() {
+            if !live.contains(parameter) {
+                removed.entry(b).or_default().insert(i);
+            }
+        }
+    }
+    if !removed.is_empty() {
+        remove_parameters(function, &removed);
+        changed = true;
+    }
+    return changed;
+}
+
+// Skips blocks which only jump on, merges blocks into their only
+// predecessor and drops blocks which can't be reached
+fn simplify_control_flow(function: &mut Function) -> bool {
+    let mut changed = false;
+    for block in &mut function.m_blocks {
+        match block.m_terminator {
+            Terminator::Branch { m_condition: _, m_true, m_false }
+                if m_true == m_false =>
+            {
+                block.m_terminator = Terminator::Jump {
+                    m_target: m_true,
+                    m_arguments: Vec::new(),
+                };
+                changed = true;
+            }
+            _ => (),
+        }
+    }
+
+    // Where control ends up going through empty blocks. Their jumps pass no
+    // arguments, so neither do jumps to where they go
+    let empty_target = |function: &Function, block: BlockId| {
+        let block_ref = &function.m_blocks[block];
+        match &block_ref.m_terminator {
+            Terminator::Jump { m_target, m_arguments }
+                if block != 0
+                    && block_ref.m_parameters.is_empty()
+                    && block_ref.m_instructions.is_empty()
+                    && m_arguments.is_empty() =>
+            {
+                return Some(*m_target)
+            }
+            _ => return None,
+        }
+    };
+    let mut forward: Vec<BlockId> = Vec::new();
+    for block in 0..function.m_blocks.len() {
+        let mut target = block;
+        let mut seen: HashSet<BlockId> = HashSet::new();
+        while let Some(next) = empty_target(function, target) {
+            if !seen.insert(target) {
+                // A loop of empty blocks
+                target = block;
+                break;
+            }
+            target = next;
+        }
+        forward.push(target);
+    }
+    for block in &mut function.m_blocks {
+        for successor in block.m_terminator.successors_mut() {
+            if forward[*successor] != *successor {
+                *successor = forward[*successor];
+                changed = true;
+            }
+        }
+    }
+
+    loop {
+        let predecessors = predecessors(function);
+        let mut merge: Option<(BlockId, BlockId)> = None;
+        for (b, block) in function.m_blocks.iter().enumerate() {
+            match &block.m_terminator {
+                Terminator::Jump { m_target, .. }
+                    if *m_target != b
+                        && *m_target != 0
+                        && predecessors[*m_target].len() == 1 =>
+                {
+                    merge = Some((b, *m_target));
+                    break;
+                }
+                _ => (),
+            }
+        }
+        let (first, second) = match merge {
+            Some(m) => m,
+            None => break,
+        };
+        let arguments = match &function.m_blocks[first].m_terminator {
+            Terminator::Jump { m_arguments, .. } => m_arguments.clone(),
+            _ => Vec::new(),
+        };
+        let mut replacements: HashMap<Temp, Temp> = HashMap::new();
+        let parameters =
+            std::mem::take(&mut function.m_blocks[second].m_parameters);
+        for (parameter, argument) in parameters.iter().zip(arguments) {
+            replacements.insert(*parameter, argument);
+        }
+        let mut instructions =
+            std::mem::take(&mut function.m_blocks[second].m_instructions);
+        // Left to be dropped as unreachable
+        let terminator = std::mem::replace(
+            &mut function.m_blocks[second].m_terminator,
+            Terminator::Jump { m_target: second, m_arguments: Vec::new() },
+        );
+        function.m_blocks[first].m_instructions.append(&mut instructions);
+        function.m_blocks[first].m_terminator = terminator;
+        substitute(function, &replacements);
+        changed = true;
+    }
+
+    changed |= remove_unreachable_blocks(function);
+    return changed;
+}
+
+// Numbers the temporaries, slots and blocks still in use from 0, with the
+// blocks in reverse postorder so that most jumps go to the next block
+fn compact(function: &mut Function) {
+    let order = reverse_postorder(function);
+    reorder_blocks(function, &order);
+
+    let mut temp_numbers: HashMap<Temp, Temp> = HashMap::new();
+    let mut temps: Vec<Type> = Vec::new();
+    let mut number = |temp: &mut Temp| {
+        let next = temps.len();
+        let new = *temp_numbers.entry(*temp).or_insert(next);
+        if new == next {
+            temps.push(function.m_temps[*temp]);
+        }
+        *temp = new;
+    };
+    for parameter in &mut function.m_parameters {
+        number(parameter);
+    }
+    let mut slot_numbers: HashMap<usize, usize> = HashMap::new();
+    let mut slots: Vec<ir::Slot> = Vec::new();
+    for block in &mut function.m_blocks {
+        for parameter in &mut block.m_parameters {
+            number(parameter);
+        }
+        for instruction in &mut block.m_instructions {
+            for operand in instruction.operands_mut() {
+                number(operand);
+            }
+            match instruction.dest_mut() {
+                Some(d) => number(d),
+                None => (),
+            }
+            match instruction {
+                Instruction::SlotAddress { m_slot, .. } => {
+                    let next = slots.len();
+                    let new = *slot_numbers.entry(*m_slot).or_insert(next);
+                    if new == next {
+                        slots.push(function.m_slots[*m_slot].clone());
+                    }
+                    *m_slot = new;
+                }
+                _ => (),
+            }
+        }
+        for operand in block.m_terminator.operands_mut() {
+            number(operand);
+        }
+    }
+    function.m_temps = temps;
+    function.m_slots = slots;
+}
+
+// Copies small functions into the places they are called from. Each call is
+// inlined once, so calls in an inlined body stay calls and recursion ends
+fn inline_calls(program: &mut ir::Program) {
+    let candidates: HashMap<String, Function> = program
+        .m_functions
+        .iter()
+        .filter(|f| inlinable(f))
+        .map(|f| (f.m_name.clone(), f.clone()))
+        .collect();
+    for function in &mut program.m_functions {
+        let mut pending: Vec<BlockId> = (0..function.m_blocks.len()).collect();
+        while let Some(block) = pending.pop() {
+            let found = function.m_blocks[block]
+                .m_instructions
+                .iter()
+                .enumerate()
+                .find_map(|(i, instruction)| match instruction {
+                    Instruction::Call {
+                        m_dest,
+                        m_function,
+                        m_arguments,
+                        ..
+                    } => match candidates.get(m_function) {
+                        Some(callee)
+                            if *m_function != function.m_name
+                                && callee.m_parameters.len()
+                                    == m_arguments.len()
+                                && callee.m_return_type
+                                    == function.m_temps[*m_dest] =>
+                        {
+                            Some((i, callee))
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                });
+            match found {
+                Some((i, callee)) => {
+                    pending.push(inline_call(function, block, i, callee))
+                }
+                None => (),
+            }
+        }
+    }
+}
+
+fn inlinable(function: &Function) -> bool {
+    let mut count = 0;
+    for block in &function.m_blocks {
+        for instruction in &block.m_instructions {
+            match instruction {
+                Instruction::Location { .. } => continue,
+                Instruction::VaStart { .. } => return false,
+                Instruction::Call { m_function, .. }
+                    if *m_function == function.m_name =>
+                {
+                    return false
+                }
+                _ => count += 1,
+            }
+        }
+    }
+    return !function.m_variadic && count <= INLINE_LIMIT;
+}
+
+// Replaces the call at the index of the block with a copy of the callee's
+// blocks. The instructions after the call go to a new block, which the
+// callee's returns jump to, passing the result. Returns that block
+fn inline_call(
+    function: &mut Function,
+    block: BlockId,
+    index: usize,
+    callee: &Function,
+) -> BlockId {
+    let mut instructions =
+        std::mem::take(&mut function.m_blocks[block].m_instructions);
+    let rest = instructions.split_off(index + 1);
+    let (dest, arguments) = match instructions.pop() {
+        Some(Instruction::Call { m_dest, m_arguments, .. }) => {
+            (m_dest, m_arguments)
+        }
+        _ => panic!("Inlining something which isn't a call"),
+    };
+
+    let temp_base = function.m_temps.len();
+    let slot_base = function.m_slots.len();
+    let block_base = function.m_blocks.len();
+    let continuation = block_base + callee.m_blocks.len();
+    function.m_temps.extend(callee.m_temps.iter().cloned());
+    function.m_slots.extend(callee.m_slots.iter().cloned());
+
+    // Parameters are the arguments, which are passed extended to int when
+    // they are narrower
+    let mut temp_numbers: Vec<Temp> =
+        (0..callee.m_temps.len()).map(|t| temp_base + t).collect();
+    for (parameter, argument) in callee.m_parameters.iter().zip(arguments) {
+        let parameter_type = callee.m_temps[*parameter];
+        let argument_type = function.m_temps[argument];
+        if parameter_type == argument_type {
+            temp_numbers[*parameter] = argument;
+        } else {
+            instructions.push(Instruction::Convert {
+                m_dest: temp_base + parameter,
+                m_op: ConvertOp::Truncate,
+                m_from: argument_type,
+                m_to: parameter_type,
+                m_operand: argument,
+            });
+        }
+    }
+    function.m_blocks[block].m_instructions = instructions;
+    let terminator = std::mem::replace(
+        &mut function.m_blocks[block].m_terminator,
+        Terminator::Jump { m_target: block_base, m_arguments: Vec::new() },
+    );
+
+    for callee_block in &callee.m_blocks {
+        let mut copy = callee_block.clone();
+        for parameter in &mut copy.m_parameters {
+            *parameter = temp_numbers[*parameter];
+        }
+        for instruction in &mut copy.m_instructions {
+            for operand in instruction.operands_mut() {
+                *operand = temp_numbers[*operand];
+            }
+            match instruction.dest_mut() {
+                Some(d) => *d = temp_numbers[*d],
+                None => (),
+            }
+            match instruction {
+                Instruction::SlotAddress { m_slot, .. } => *m_slot += slot_base,
+                _ => (),
+            }
+        }
+        for operand in copy.m_terminator.operands_mut() {
+            *operand = temp_numbers[*operand];
+        }
+        for successor in copy.m_terminator.successors_mut() {
+            *successor += block_base;
+        }
+        match copy.m_terminator {
+            Terminator::Return { m_value } => {
+                copy.m_terminator = Terminator::Jump {
+                    m_target: continuation,
+                    m_arguments: vec![m_value],
+                }
+            }
+            _ => (),
+        }
+        function.m_blocks.push(copy);
+    }
+
+    function.m_blocks.push(ir::Block {
+        m_parameters: vec![dest],
+        m_instructions: rest,
+        m_terminator: terminator,
+    });
+    return continuation;
+}
+
+// Drops functions only visible in this file which are no longer called
+fn remove_unused_functions(program: &mut ir::Program) {
+    loop {
+        let mut called: HashSet<String> = HashSet::new();
+        for function in &program.m_functions {
+            for block in &function.m_blocks {
+                for instruction in &block.m_instructions {
+                    match instruction {
+                        Instruction::Call { m_function, .. }
+                            if *m_function != function.m_name =>
+                        {
+                            called.insert(m_function.clone());
+                        }
+                        _ => (),
+                    }
+                }
+            }
+        }
+        let count = program.m_functions.len();
+        program
+            .m_functions
+            .retain(|f| f.m_global || called.contains(&f.m_name));
+        if program.m_functions.len() == count {
+            return;
+        }
+    }
+}
+
+// Immediate dominator of each reachable block, the last block every path
+// from the entry to it goes through, by the iterative algorithm of Cooper,
+// Harvey and Kennedy. The entry is its own
+fn dominators(function: &Function) -> Vec<Option<BlockId>> {
+    let order = reverse_postorder(function);
+    let mut position = vec![usize::MAX; function.m_blocks.len()];
+    for (i, block) in order.iter().enumerate() {
+        position[*block] = i;
+    }
+    let predecessors = predecessors(function);
+    let mut dominators: Vec<Option<BlockId>> =
+        vec![None; function.m_blocks.len()];
+    dominators[0] = Some(0);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for block in order.iter().skip(1) {
+            let mut dominator: Option<BlockId> = None;
+            for predecessor in &predecessors[*block] {
+                if dominators[*predecessor].is_none() {
+                    continue;
+                }
+                dominator = match dominator {
+                    None => Some(*predecessor),
+                    Some(d) => {
+                        let (mut a, mut b) = (d, *predecessor);
+                        while a != b {
+                            while position[a] > position[b] {
+                                a = dominators[a].unwrap();
+                            }
+                            while position[b] > position[a] {
+                                b = dominators[b].unwrap();
+                            }
+                        }
+                        Some(a)
+                    }
+                };
+            }
+            if dominators[*block] != dominator {
+                dominators[*block] = dominator;
+                changed = true;
+            }
+        }
+    }
+    return dominators;
+}
+
+fn dominates(
+    dominators: &Vec<Option<BlockId>>,
+    a: BlockId,
+    b: BlockId,
+) -> bool {
+    let mut block = b;
+    loop {
+        if block == a {
+            return true;
+        }
+        match dominators[block] {
+            Some(d) if d != block => block = d,
+            _ => return false,
+        }
+    }
+}
+
+// What a pure instruction computes, so equal ones can share a temporary
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Expression {
+    Constant(Type, i64),
+    SlotAddress(usize),
+    GlobalAddress(String),
+    Unary(UnaryOp, Type, Temp),
+    Binary(BinaryOp, Type, Temp, Temp),
+    Compare(CompareOp, Type, Temp, Temp),
+    Convert(ConvertOp, Type, Type, Temp),
+}
+
+fn expression(instruction: &Instruction) -> Option<Expression> {
+    match instruction {
+        Instruction::Constant { m_type, m_value, .. } => {
+            let value = match m_type.is_floating() {
+                true => *m_value,
+                false => normalise(*m_value, *m_type),
+            };
+            return Some(Expression::Constant(*m_type, value));
+        }
+        Instruction::SlotAddress { m_slot, .. } => {
+            return Some(Expression::SlotAddress(*m_slot))
+        }
+        Instruction::GlobalAddress { m_name, .. } => {
+            return Some(Expression::GlobalAddress(m_name.clone()))
+        }
+        Instruction::Unary { m_op, m_type, m_operand, .. } => {
+            return Some(Expression::Unary(*m_op, *m_type, *m_operand))
+        }
+        Instruction::Binary { m_op, m_type, m_left, m_right, .. } => {
+            // Operands of commutative operations in a fixed order
+            let (left, right) = match m_op {
+                BinaryOp::Add | BinaryOp::Multiply if m_left > m_right => {
+                    (*m_right, *m_left)
+                }
+                _ => (*m_left, *m_right),
+            };
+            return Some(Expression::Binary(*m_op, *m_type, left, right));
+        }
+        Instruction::Compare { m_op, m_type, m_left, m_right, .. } => {
+            let (left, right) = match m_op {
+                CompareOp::Equal | CompareOp::NotEqual if m_left > m_right => {
+                    (*m_right, *m_left)
+                }
+                _ => (*m_left, *m_right),
+            };
+            return Some(Expression::Compare(*m_op, *m_type, left, right));
+        }
+        Instruction::Convert { m_op, m_from, m_to, m_operand, .. } => {
+            return Some(Expression::Convert(*m_op, *m_from, *m_to, *m_operand))
+        }
+        _ => return None,
+    }
+}
+
+// Reuses the result of an earlier pure instruction computing the same
+// thing, when that instruction dominates the later one
+fn eliminate_common_subexpressions(function: &mut Function) {
+    let dominators = dominators(function);
+    let mut children: Vec<Vec<BlockId>> =
+        vec![Vec::new(); function.m_blocks.len()];
+    for (block, dominator) in dominators.iter().enumerate() {
+        match dominator {
+            Some(d) if *d != block => children[*d].push(block),
+            _ => (),
+        }
+    }
+
+    let mut replacements: HashMap<Temp, Temp> = HashMap::new();
+    // (block, expressions available at its start)
+    let mut work: Vec<(BlockId, HashMap<Expression, Temp>)> =
+        vec![(0, HashMap::new())];
+    while let Some((block, mut available)) = work.pop() {
+        let instructions =
+            std::mem::take(&mut function.m_blocks[block].m_instructions);
+        let mut kept: Vec<Instruction> = Vec::new();
+        for mut instruction in instructions {
+            for operand in instruction.operands_mut() {
+                *operand = resolve(&replacements, *operand);
+            }
+            match expression(&instruction) {
+                Some(e) => {
+                    let dest = instruction.dest().unwrap();
+                    match available.get(&e) {
+                        Some(earlier) => {
+                            replacements.insert(dest, *earlier);
+                            continue;
+                        }
+                        None => {
+                            available.insert(e, dest);
+                        }
+                    }
+                }
+                None => (),
+            }
+            kept.push(instruction);
+        }
+        function.m_blocks[block].m_instructions = kept;
+        for child in &children[block] {
+            work.push((*child, available.clone()));
+        }
+    }
+    substitute(function, &replacements);
+}
+
+// Moves pure instructions whose operands don't change in a loop to a block
+// run once before it. Division can trap, so it only happens where the
+// program puts it
+fn hoist_loop_invariants(function: &mut Function) {
+    let dominators = dominators(function);
+    let predecessors = predecessors(function);
+
+    // Each loop is a header and the blocks which reach a jump back to it
+    // without going through it
+    let mut loops: Vec<(BlockId, HashSet<BlockId>)> = Vec::new();
+    for (block, block_predecessors) in predecessors.iter().enumerate() {
+        let mut body: HashSet<BlockId> = HashSet::new();
+        let mut work: Vec<BlockId> = Vec::new();
+        for predecessor in block_predecessors {
+            if dominators[*predecessor].is_some()
+                && dominates(&dominators, block, *predecessor)
+            {
+                work.push(*predecessor);
+            }
+        }
+        if work.is_empty() {
+            continue;
+        }
+        body.insert(block);
+        while let Some(b) = work.pop() {
+            if body.insert(b) {
+                work.extend(predecessors[b].iter().cloned());
+            }
+        }
+        loops.push((block, body));
+    }
+    // Inner loops first, so what leaves them can leave the outer ones too
+    loops.sort_by_key(|(_, body)| body.len());
+
+    for i in 0..loops.len() {
+        let (header, body) = loops[i].clone();
+        let preheader = make_preheader(function, header, &body);
+        for (other_header, other_body) in loops.iter_mut().skip(i + 1) {
+            if *other_header != header && other_body.contains(&header) {
+                other_body.insert(preheader);
+            }
+        }
+
+        let mut defined: HashSet<Temp> = HashSet::new();
+        for block in &body {
+            defined.extend(function.m_blocks[*block].m_parameters.iter());
+            for instruction in &function.m_blocks[*block].m_instructions {
+                defined.extend(instruction.dest());
+            }
+        }
+        let mut blocks: Vec<BlockId> = body.iter().cloned().collect();
+        blocks.sort();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in &blocks {
+                let instructions = std::mem::take(
+                    &mut function.m_blocks[*block].m_instructions,
+                );
+                let mut kept: Vec<Instruction> = Vec::new();
+                for instruction in instructions {
+                    let invariant = expression(&instruction).is_some()
+                        && !traps(&instruction)
+                        && instruction
+                            .operands()
+                            .iter()
+                            .all(|o| !defined.contains(o));
+                    if invariant {
+                        defined.remove(&instruction.dest().unwrap());
+                        function.m_blocks[preheader]
+                            .m_instructions
+                            .push(instruction);
+                        changed = true;
+                    } else {
+                        kept.push(instruction);
+                    }
+                }
+                function.m_blocks[*block].m_instructions = kept;
+            }
+        }
+    }
+}
+
+fn traps(instruction: &Instruction) -> bool {
+    match instruction {
+        Instruction::Binary { m_op, m_type, .. } => {
+            return !m_type.is_floating()
+                && !matches!(
+                    m_op,
+                    BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Multiply
+                )
+        }
+        _ => return false,
+    }
+}
+
+// A block which every entry to the loop from outside goes through, reusing
+// one which only jumps to the header
+fn make_preheader(
+    function: &mut Function,
+    header: BlockId,
+    body: &HashSet<BlockId>,
+) -> BlockId {
+    let outside: Vec<BlockId> = predecessors(function)[header]
+        .iter()
+        .filter(|p| !body.contains(p))
+        .cloned()
+        .collect();
+    if outside.len() == 1 {
+        match function.m_blocks[outside[0]].m_terminator {
+            Terminator::Jump { .. } => return outside[0],
+            _ => (),
+        }
+    }
+
+    let mut parameters: Vec<Temp> = Vec::new();
+    for parameter in function.m_blocks[header].m_parameters.clone() {
+        function.m_temps.push(function.m_temps[parameter]);
+        parameters.push(function.m_temps.len() - 1);
+    }
+    function.m_blocks.push(ir::Block {
+        m_parameters: parameters.clone(),
+        m_instructions: Vec::new(),
+        m_terminator: Terminator::Jump {
+            m_target: header,
+            m_arguments: parameters,
+        },
+    });
+    let preheader = function.m_blocks.len() - 1;
+    for predecessor in outside {
+        for successor in
+            function.m_blocks[predecessor].m_terminator.successors_mut()
+        {
+            if *successor == header {
+                *successor = preheader;
+            }
+        }
+    }
+    return preheader;
+}
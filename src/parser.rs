@@ -1,9 +1,72 @@
+use crate::token::Span;
 use crate::Token;
-use core::slice::Iter;
+use std::fmt;
 
 const DEBUG: bool = false;
 
-#[derive(Debug)]
+// Peekable iterator over the tokens which also knows their spans. The last
+// token looked at, by either next or peek, is remembered so that an error can
+// be reported at its location
+#[derive(Clone)]
+pub struct TokenIter<'a> {
+    tokens: &'a [&'a Token],
+    spans: &'a [Span],
+    position: usize,
+    last_seen: usize,
+}
+
+impl<'a> TokenIter<'a> {
+    fn new(tokens: &'a [&'a Token], spans: &'a [Span]) -> Self {
+        TokenIter { tokens, spans, position: 0, last_seen: 0 }
+    }
+
+    pub fn peek(&mut self) -> Option<&&'a Token> {
+        self.last_seen = self.position;
+        return self.tokens.get(self.position);
+    }
+
+    // Span of the next token
+    fn span(&self) -> Span {
+        return self.span_at(self.position);
+    }
+
+    // Span of the token most recently returned by next
+    fn last_span(&self) -> Span {
+        return self.span_at(self.position.saturating_sub(1));
+    }
+
+    fn error_span(&self) -> Span {
+        return self.span_at(self.last_seen);
+    }
+
+    fn span_at(&self, position: usize) -> Span {
+        match self.spans.get(position).or(self.spans.last()) {
+            Some(s) => return s.clone(),
+            None => {
+                return Span {
+                    m_file: "<input>".into(),
+                    m_line: 0,
+                    m_column: 0,
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for TokenIter<'a> {
+    type Item = &'a Token;
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.position).copied();
+        if token.is_some() {
+            self.last_seen = self.position;
+            self.position += 1;
+        }
+        return token;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum InFunction {
     ParseProgram,
     ParseFunction,
@@ -28,32 +91,394 @@ pub enum ParseError {
 
 #[derive(Debug)]
 pub struct Program {
-    // <program> ::= <function>
-    pub m_functions: Vec<Function>,
+    // <program> ::= { <function> | <declaration> }
+    pub m_items: Vec<TopLevelItem>,
 }
 
-#[derive(Debug, Clone)]
-pub enum FunctionType {
-    Int,
+#[derive(Debug)]
+pub enum TopLevelItem {
+    Function(Function),
+    Declaration(Declaration),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageClass {
+    Static,
+    Extern,
+    Auto,
+    Register,
+}
+
+// Type qualifiers. Every access to a volatile object has to happen as
+// written, so optimisations must not cache, merge or remove them
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Qualifiers {
+    pub m_const: bool,
+    pub m_volatile: bool,
+}
+
+impl Qualifiers {
+    // Whether these include every qualifier in other
+    pub fn contains(&self, other: &Qualifiers) -> bool {
+        return (self.m_const || !other.m_const)
+            && (self.m_volatile || !other.m_volatile);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum VarType {
+    Char,
+    SChar,
+    UChar,
+    Short,
+    UShort,
     Int,
+    UInt,
+    Long,
+    ULong,
+    Float,
+    Double,
+    // The type pointed to and its qualifiers
+    Pointer(Box<VarType>, Qualifiers),
+    // __builtin_va_list, which holds the address of the argument state set up
+    // by va_start, the way a va_list parameter is passed
+    VaList,
+}
+
+impl VarType {
+    pub fn is_floating(&self) -> bool {
+        match self {
+            VarType::Float | VarType::Double => return true,
+            _ => return false,
+        }
+    }
+
+    pub fn is_integer(&self) -> bool {
+        match self {
+            VarType::Float
+            | VarType::Double
+            | VarType::Pointer(_, _)
+            | VarType::VaList => return false,
+            _ => return true,
+        }
+    }
+
+    // Plain char is signed on x86-64
+    pub fn is_unsigned(&self) -> bool {
+        match self {
+            VarType::UChar
+            | VarType::UShort
+            | VarType::UInt
+            | VarType::ULong
+            | VarType::Pointer(_, _) => return true,
+            _ => return false,
+        }
+    }
+
+    pub fn is_pointer(&self) -> bool {
+        match self {
+            VarType::Pointer(_, _) => return true,
+            _ => return false,
+        }
+    }
+}
+
+// Written the way C spells it, with qualifiers after what they apply to
+impl fmt::Display for VarType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VarType::Char => write!(f, "char"),
+            VarType::SChar => write!(f, "signed char"),
+            VarType::UChar => write!(f, "unsigned char"),
+            VarType::Short => write!(f, "short"),
+            VarType::UShort => write!(f, "unsigned short"),
+            VarType::Int => write!(f, "int"),
+            VarType::UInt => write!(f, "unsigned int"),
+            VarType::Long => write!(f, "long"),
+            VarType::ULong => write!(f, "unsigned long"),
+            VarType::Float => write!(f, "float"),
+            VarType::Double => write!(f, "double"),
+            VarType::VaList => write!(f, "va_list"),
+            VarType::Pointer(t, q) => {
+                write!(f, "{}", t)?;
+                if q.m_const {
+                    write!(f, " const")?;
+                }
+                if q.m_volatile {
+                    write!(f, " volatile")?;
+                }
+                write!(f, " *")
+            }
+        }
+    }
+}
+
+fn is_type_specifier(token: &Token) -> bool {
+    match token {
+        Token::KeywordInt
+        | Token::KeywordFloat
+        | Token::KeywordDouble
+        | Token::KeywordChar
+        | Token::KeywordShort
+        | Token::KeywordLong
+        | Token::KeywordSigned
+        | Token::KeywordUnsigned
+        | Token::KeywordVaList => return true,
+        _ => return false,
+    }
+}
+
+// Combines the type keywords of a declaration, given in any order, into the
+// type they name. long long is the same type as long. None for combinations
+// that name no type, like long double or signed unsigned
+fn resolve_type_specifiers(specifiers: &Vec<Token>) -> Option<VarType> {
+    let (mut int, mut char, mut short, mut long) = (0, 0, 0, 0);
+    let (mut signed, mut unsigned, mut float, mut double) = (0, 0, 0, 0);
+    if let [Token::KeywordVaList] = specifiers.as_slice() {
+        return Some(VarType::VaList);
+    }
+    for s in specifiers {
+        match s {
+            Token::KeywordInt => int += 1,
+            Token::KeywordChar => char += 1,
+            Token::KeywordShort => short += 1,
+            Token::KeywordLong => long += 1,
+            Token::KeywordSigned => signed += 1,
+            Token::KeywordUnsigned => unsigned += 1,
+            Token::KeywordFloat => float += 1,
+            Token::KeywordDouble => double += 1,
+            _ => return None,
+        }
+    }
+
+    if int > 1 || long > 2 || signed + unsigned > 1 {
+        return None;
+    }
+    if char + short + float + double > 1 {
+        return None;
+    }
+    if float + double == 1 {
+        if int + long + signed + unsigned > 0 {
+            return None;
+        }
+        return Some(if float == 1 { VarType::Float } else { VarType::Double });
+    }
+    if char == 1 {
+        if int + long > 0 {
+            return None;
+        }
+        if signed == 1 {
+            return Some(VarType::SChar);
+        }
+        if unsigned == 1 {
+            return Some(VarType::UChar);
+        }
+        return Some(VarType::Char);
+    }
+    if short == 1 {
+        if long > 0 {
+            return None;
+        }
+        return Some(if unsigned == 1 {
+            VarType::UShort
+        } else {
+            VarType::Short
+        });
+    }
+    if long > 0 {
+        return Some(if unsigned == 1 {
+            VarType::ULong
+        } else {
+            VarType::Long
+        });
+    }
+    if specifiers.is_empty() {
+        return None;
+    }
+    return Some(if unsigned == 1 { VarType::UInt } else { VarType::Int });
+}
+
+fn storage_class(token: &Token) -> Option<StorageClass> {
+    match token {
+        Token::KeywordStatic => return Some(StorageClass::Static),
+        Token::KeywordExtern => return Some(StorageClass::Extern),
+        Token::KeywordAuto => return Some(StorageClass::Auto),
+        Token::KeywordRegister => return Some(StorageClass::Register),
+        _ => return None,
+    }
+}
+
+// Repeating a qualifier has the same effect as giving it once
+fn add_qualifier(token: &Token, qualifiers: &mut Qualifiers) {
+    match token {
+        Token::KeywordConst => qualifiers.m_const = true,
+        Token::KeywordVolatile => qualifiers.m_volatile = true,
+        _ => (),
+    }
+}
+
+fn is_type_qualifier(token: &Token) -> bool {
+    match token {
+        Token::KeywordConst | Token::KeywordVolatile => return true,
+        _ => return false,
+    }
+}
+
+// Whether the token can start the specifiers of a declaration
+fn is_declaration_start(token: &Token) -> bool {
+    return is_type_specifier(token)
+        || storage_class(token).is_some()
+        || is_type_qualifier(token);
+}
+
+// <specifiers> ::= { <storage-class> | <type> | <qualifier> }
+// In any order, with at most one storage class, and the type keywords have
+// to name a type
+fn parse_declaration_specifiers(
+    token_iter: &mut TokenIter,
+    in_function: InFunction,
+) -> Result<(Option<StorageClass>, VarType, Qualifiers), ParseError> {
+    let mut storage: Option<StorageClass> = None;
+    let mut specifiers: Vec<Token> = Vec::new();
+    let mut qualifiers = Qualifiers::default();
+
+    while let Some(&t) = token_iter.peek() {
+        if is_type_qualifier(t) {
+            add_qualifier(t, &mut qualifiers);
+        } else if let Some(s) = storage_class(t) {
+            if storage.is_some() {
+                return Err(ParseError::UnexpectedToken(
+                    t.clone(),
+                    in_function,
+                ));
+            }
+            storage = Some(s);
+        } else if is_type_specifier(t) {
+            specifiers.push(t.clone());
+        } else {
+            break;
+        }
+        token_iter.next();
+    }
+
+    match (resolve_type_specifiers(&specifiers), token_iter.peek()) {
+        (Some(v), _) => return Ok((storage, v, qualifiers)),
+        (None, _) if !specifiers.is_empty() => {
+            return Err(ParseError::UnexpectedToken(
+                specifiers[specifiers.len() - 1].clone(),
+                in_function,
+            ))
+        }
+        (None, Some(&t)) => {
+            return Err(ParseError::UnexpectedToken(t.clone(), in_function))
+        }
+        (None, None) => return Err(ParseError::ExpectedToken),
+    }
+}
+
+// <declarator> ::= { "*" { <qualifier> } } <id>
+// Parses the pointer levels in front of the name, giving the declared type
+// and its qualifiers
+fn parse_declarator(
+    token_iter: &mut TokenIter,
+    var_type: VarType,
+    qualifiers: Qualifiers,
+) -> (VarType, Qualifiers) {
+    let mut var_type = var_type;
+    let mut qualifiers = qualifiers;
+
+    while let Some(Token::OperatorMultiplication) = token_iter.peek() {
+        token_iter.next();
+        var_type = VarType::Pointer(Box::new(var_type), qualifiers);
+        qualifiers = Qualifiers::default();
+        while let Some(&t) = token_iter.peek() {
+            if !is_type_qualifier(t) {
+                break;
+            }
+            add_qualifier(t, &mut qualifiers);
+            token_iter.next();
+        }
+    }
+
+    return (var_type, qualifiers);
+}
+
+// <type-name> ::= { <type> | <qualifier> } { "*" { <qualifier> } }
+// The type in a cast, sizeof or _Alignof. Qualifiers of the named type itself
+// make no difference there and are dropped
+fn parse_type_name(
+    token_iter: &mut TokenIter,
+    in_function: InFunction,
+) -> Result<VarType, ParseError> {
+    let (storage, var_type, qualifiers) =
+        match parse_declaration_specifiers(token_iter, in_function) {
+            Ok(s) => s,
+            Err(e) => return Err(e),
+        };
+    if storage.is_some() {
+        return Err(ParseError::UnexpectedToken(
+            token_iter.clone().next().unwrap_or(&Token::EndOfFile).clone(),
+            in_function,
+        ));
+    }
+    let (var_type, _) = parse_declarator(token_iter, var_type, qualifiers);
+    return Ok(var_type);
+}
+
+// Whether the tokens after an open paren are a type name, making it a cast or
+// the operand of sizeof rather than a braced expression
+fn is_type_name_start(token: &Token) -> bool {
+    return is_type_specifier(token) || is_type_qualifier(token);
+}
+
+// Looks past the specifiers and name of a top level declaration to tell a
+// function from a variable
+fn is_function_declaration(token_iter: &TokenIter) -> bool {
+    let mut lookahead = token_iter.clone();
+    let (_, var_type, qualifiers) = match parse_declaration_specifiers(
+        &mut lookahead,
+        InFunction::ParseProgram,
+    ) {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+    parse_declarator(&mut lookahead, var_type, qualifiers);
+    match (lookahead.next(), lookahead.next()) {
+        (Some(Token::Identifier(_)), Some(Token::OpenParen)) => return true,
+        _ => return false,
+    }
 }
 
 #[derive(Debug)]
 pub struct Function {
-    // <function> ::= "int" <id> "(" ")" "{" <statement> "}"
-    pub m_type: FunctionType,
-    pub m_params: Vec<(VarType, String)>, // Change to struct when adding other types
+    // <function> ::= <type> <id> "(" [ "void" | <parameter> { "," <parameter> }
+    //                [ "," "..." ] ] ")" ( "{" <statement> "}" | ";" )
+    pub m_type: VarType,
+    pub m_storage: Option<StorageClass>,
+    pub m_params: Vec<Parameter>,
+    // Takes further arguments after the parameters: "," "..."
+    pub m_variadic: bool,
+    // Declared with a parameter list. An empty "()" isn't one before C23,
+    // while "(void)" is an empty list
+    pub m_prototype: bool,
     pub m_id: String,
     pub m_items: Option<Vec<BlockItem>>,
+    pub m_span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub m_type: VarType,
+    pub m_qualifiers: Qualifiers,
+    // Only ever register
+    pub m_storage: Option<StorageClass>,
+    // None when the declarator is abstract, as in "int f(int);"
+    pub m_id: Option<String>,
 }
 
 #[derive(Debug)]
 pub enum BlockItem {
-    Statement(Statement),
+    Statement(Statement, Span),
     Declaration(Declaration),
 }
 
@@ -98,13 +523,20 @@ pub enum Statement {
 
 #[derive(Debug)]
 pub struct Declaration {
+    pub m_type: VarType,
+    pub m_qualifiers: Qualifiers,
+    pub m_storage: Option<StorageClass>,
     pub m_id: String,
     pub m_value: Option<Expression>,
+    pub m_span: Span,
+    // Where the initializer starts, or the name without one
+    pub m_value_span: Span,
 }
 
 #[derive(Debug)]
 pub enum Expression {
-    Assignment { m_name: String, m_value: Box<Expression> },
+    // The target is a variable or a dereferenced pointer
+    Assignment { m_target: Box<Factor>, m_value: Box<Expression>, m_span: Span },
     Operation(ConditionalExpression),
 }
 
@@ -188,30 +620,45 @@ pub struct Term {
 
 #[derive(Debug)]
 pub enum UnaryOperator {
-    // <unary_op> ::= "!" | "~" | "-"
+    // <unary_op> ::= "!" | "~" | "-" | "*" | "&"
     Complement,
     Negation,
     Minus,
+    Dereference,
+    AddressOf,
 }
 
 #[derive(Debug)]
 pub enum Factor {
     // <factor> ::= "(" <exp> ")" | <unary_op> <factor> | <int>
-    FunCall { m_id: String, m_arguments: Vec<Expression> },
-    Constant { m_value: i32 },
+    FunCall { m_id: String, m_arguments: Vec<Expression>, m_span: Span },
+    Constant { m_value: i64, m_type: VarType },
+    FloatConstant { m_value: f64, m_type: VarType },
     UnaryOperation { m_opertator: UnaryOperator, m_factor: Box<Factor> },
     Braced { m_expression: Expression },
-    Variable { m_var: String },
+    Variable { m_var: String, m_span: Span },
+    Cast { m_type: VarType, m_factor: Box<Factor> },
+    SizeOfType { m_type: VarType },
+    SizeOfExpression { m_factor: Box<Factor> },
+    AlignOf { m_type: VarType },
+    // The variadic argument builtins behind <stdarg.h>
+    VaStart { m_list: Box<Expression>, m_last: String },
+    VaArg { m_list: Box<Expression>, m_type: VarType },
+    VaEnd { m_list: Box<Expression> },
 }
 
-pub fn parse_program(tokens: &Vec<Token>) -> Result<Program, ParseError> {
+pub fn parse_program(
+    tokens: &Vec<Token>,
+    spans: &Vec<Span>,
+) -> Result<Program, (ParseError, Span)> {
     if DEBUG {
         println!("Paring program from: {:?}", &tokens);
     }
 
-    let mut functions = Vec::new();
+    let mut items = Vec::new();
 
-    let mut token_iter = tokens.iter().peekable();
+    let token_refs: Vec<&Token> = tokens.iter().collect();
+    let mut token_iter = TokenIter::new(&token_refs, spans);
 
     while let Some(&next) = token_iter.peek() {
         match next {
@@ -219,27 +666,39 @@ pub fn parse_program(tokens: &Vec<Token>) -> Result<Program, ParseError> {
                 token_iter.next();
                 break;
             }
-            Token::KeywordInt => {
-                functions.push(match parse_function(&mut token_iter) {
-                    Ok(s) => s,
-                    Err(e) => return Err(e),
-                })
+            t if is_declaration_start(t) => {
+                if is_function_declaration(&token_iter) {
+                    items.push(TopLevelItem::Function(
+                        match parse_function(&mut token_iter) {
+                            Ok(s) => s,
+                            Err(e) => return Err((e, token_iter.error_span())),
+                        },
+                    ))
+                } else {
+                    items.push(TopLevelItem::Declaration(
+                        match parse_declaration(&mut token_iter) {
+                            Ok(d) => d,
+                            Err(e) => return Err((e, token_iter.error_span())),
+                        },
+                    ))
+                }
             }
             t => {
-                return Err(ParseError::UnexpectedToken(
-                    t.clone(),
-                    InFunction::ParseProgram,
+                return Err((
+                    ParseError::UnexpectedToken(
+                        t.clone(),
+                        InFunction::ParseProgram,
+                    ),
+                    token_iter.error_span(),
                 ))
             }
         }
     }
 
-    return Ok(Program { m_functions: functions });
+    return Ok(Program { m_items: items });
 }
 
-fn parse_function(
-    token_iter: &mut std::iter::Peekable<Iter<Token>>,
-) -> Result<Function, ParseError> {
+fn parse_function(token_iter: &mut TokenIter) -> Result<Function, ParseError> {
     if DEBUG {
         println!(
             "Parsing function from: {:?}",
@@ -248,22 +707,29 @@ fn parse_function(
     }
     //Token Iterator
 
-    let mut function = match token_iter.next() {
-        Some(Token::KeywordInt) => Function {
-            m_type: FunctionType::Int,
-            m_params: Vec::new(),
-            m_id: String::new(),
-            m_items: None,
-        },
-        Some(t) => {
-            return Err(ParseError::UnexpectedToken(
-                t.clone(),
-                InFunction::ParseFunction,
-            ))
-        }
-        None => return Err(ParseError::ExpectedToken),
+    let (storage, function_type, qualifiers) =
+        match parse_declaration_specifiers(
+            token_iter,
+            InFunction::ParseFunction,
+        ) {
+            Ok(s) => s,
+            Err(e) => return Err(e),
+        };
+    // Qualifiers on the returned value itself have no meaning
+    let (function_type, _) =
+        parse_declarator(token_iter, function_type, qualifiers);
+    let mut function = Function {
+        m_type: function_type,
+        m_storage: storage,
+        m_params: Vec::new(),
+        m_variadic: false,
+        m_prototype: true,
+        m_id: String::new(),
+        m_items: None,
+        m_span: token_iter.span(),
     };
 
+    function.m_span = token_iter.span();
     function.m_id = match token_iter.next() {
         Some(Token::Identifier(s)) => s.clone(),
         Some(t) => {
@@ -286,33 +752,21 @@ fn parse_function(
         None => return Err(ParseError::ExpectedToken),
     }
 
-    match token_iter.peek().cloned() {
-        Some(Token::CloseParen) => (),
-        Some(_) => {
-            let var_type: VarType;
-            match token_iter.next() {
-                Some(Token::KeywordInt) => var_type = VarType::Int,
-                Some(t) => {
-                    return Err(ParseError::UnexpectedToken(
-                        t.clone(),
-                        InFunction::ParseFunction,
-                    ))
-                }
-                None => return Err(ParseError::ExpectedToken),
-            }
-            let id = match token_iter.next() {
-                Some(Token::Identifier(s)) => s,
-                Some(t) => {
-                    return Err(ParseError::UnexpectedToken(
-                        t.clone(),
-                        InFunction::ParseFunction,
-                    ))
-                }
-                None => return Err(ParseError::ExpectedToken),
-            };
-            function.m_params.push((var_type, id.clone()));
+    let mut lookahead = token_iter.clone();
+    match (lookahead.next(), lookahead.next()) {
+        (Some(Token::CloseParen), _) => function.m_prototype = false,
+        (Some(Token::Identifier(s)), Some(Token::CloseParen))
+            if s == "void" =>
+        {
+            token_iter.next();
         }
-        None => return Err(ParseError::ExpectedToken),
+        (Some(_), _) => {
+            function.m_params.push(match parse_parameter(token_iter) {
+                Ok(p) => p,
+                Err(e) => return Err(e),
+            });
+        }
+        (None, _) => return Err(ParseError::ExpectedToken),
     }
 
     while let Some(next) = token_iter.peek().cloned() {
@@ -320,29 +774,21 @@ fn parse_function(
             Token::Comma => {
                 token_iter.next();
 
-                let var_type: VarType;
-
-                match token_iter.next() {
-                    Some(Token::KeywordInt) => var_type = VarType::Int,
-                    Some(t) => {
-                        return Err(ParseError::UnexpectedToken(
-                            t.clone(),
-                            InFunction::ParseFunction,
-                        ))
-                    }
-                    None => return Err(ParseError::ExpectedToken),
+                if let Some(Token::Ellipsis) = token_iter.peek() {
+                    token_iter.next();
+                    function.m_variadic = true;
+                    continue;
                 }
-                let id = match token_iter.next() {
-                    Some(Token::Identifier(s)) => s,
-                    Some(t) => {
-                        return Err(ParseError::UnexpectedToken(
-                            t.clone(),
-                            InFunction::ParseFunction,
-                        ))
-                    }
-                    None => return Err(ParseError::ExpectedToken),
-                };
-                function.m_params.push((var_type, id.clone()));
+                if function.m_variadic {
+                    return Err(ParseError::UnexpectedToken(
+                        Token::Comma,
+                        InFunction::ParseFunction,
+                    ));
+                }
+                function.m_params.push(match parse_parameter(token_iter) {
+                    Ok(p) => p,
+                    Err(e) => return Err(e),
+                });
             }
             Token::CloseParen => {
                 token_iter.next();
@@ -396,8 +842,49 @@ fn parse_function(
     return Ok(function);
 }
 
+// <parameter> ::= <specifiers> <declarator> [ <id> ]
+// "register" is the only storage class allowed on a parameter
+fn parse_parameter(
+    token_iter: &mut TokenIter,
+) -> Result<Parameter, ParseError> {
+    let (storage, var_type, qualifiers) = match parse_declaration_specifiers(
+        token_iter,
+        InFunction::ParseFunction,
+    ) {
+        Ok((None, v, q)) => (None, v, q),
+        Ok((Some(StorageClass::Register), v, q)) => {
+            (Some(StorageClass::Register), v, q)
+        }
+        Ok((Some(_), _, _)) => match token_iter.peek() {
+            Some(&t) => {
+                return Err(ParseError::UnexpectedToken(
+                    t.clone(),
+                    InFunction::ParseFunction,
+                ))
+            }
+            None => return Err(ParseError::ExpectedToken),
+        },
+        Err(e) => return Err(e),
+    };
+    let (var_type, qualifiers) =
+        parse_declarator(token_iter, var_type, qualifiers);
+    let id = match token_iter.peek() {
+        Some(Token::Identifier(s)) => Some(s.clone()),
+        _ => None,
+    };
+    if id.is_some() {
+        token_iter.next();
+    }
+    return Ok(Parameter {
+        m_type: var_type,
+        m_qualifiers: qualifiers,
+        m_storage: storage,
+        m_id: id,
+    });
+}
+
 fn parse_block_item(
-    token_iter: &mut std::iter::Peekable<Iter<Token>>,
+    token_iter: &mut TokenIter,
 ) -> Result<BlockItem, ParseError> {
     let block_item: BlockItem;
     if DEBUG {
@@ -408,7 +895,7 @@ fn parse_block_item(
     }
 
     match token_iter.peek().cloned() {
-        Some(Token::KeywordInt) => {
+        Some(t) if is_declaration_start(t) => {
             block_item =
                 BlockItem::Declaration(match parse_declaration(token_iter) {
                     Ok(d) => d,
@@ -416,11 +903,14 @@ fn parse_block_item(
                 })
         }
         Some(_) => {
-            block_item =
-                BlockItem::Statement(match parse_statement(token_iter) {
+            let span = token_iter.span();
+            block_item = BlockItem::Statement(
+                match parse_statement(token_iter) {
                     Ok(s) => s,
                     Err(e) => return Err(e),
-                })
+                },
+                span,
+            )
         }
         None => return Err(ParseError::ExpectedToken),
     }
@@ -433,7 +923,7 @@ fn parse_block_item(
 }
 
 fn parse_declaration(
-    token_iter: &mut std::iter::Peekable<Iter<Token>>,
+    token_iter: &mut TokenIter,
 ) -> Result<Declaration, ParseError> {
     let declaration: Declaration;
 
@@ -444,67 +934,72 @@ fn parse_declaration(
         );
     }
 
-    let token = match token_iter.next() {
-        Some(t) => t,
-        None => return Err(ParseError::ExpectedToken),
+    let (storage, var_type, qualifiers) = match parse_declaration_specifiers(
+        token_iter,
+        InFunction::ParseDeclaration,
+    ) {
+        Ok(s) => s,
+        Err(e) => return Err(e),
     };
+    let (var_type, qualifiers) =
+        parse_declarator(token_iter, var_type, qualifiers);
 
     let id: String;
     let expression: Option<Expression>;
+    let span = token_iter.span();
+    let mut value_span = span.clone();
 
-    match token {
-        Token::KeywordInt => {
-            let next_token = token_iter.next();
-            match next_token {
-                Some(Token::Identifier(s)) => id = s.clone(),
-                Some(t) => {
-                    return Err(ParseError::UnexpectedToken(
-                        t.clone(),
-                        InFunction::ParseDeclaration,
-                    ))
-                }
-                None => return Err(ParseError::ExpectedToken),
-            };
-
-            match token_iter.peek().cloned() {
-                Some(Token::SemiColon) => expression = None,
-                Some(Token::OperatorAssign) => {
-                    token_iter.next();
-                    expression = match parse_expression(token_iter) {
-                        Ok(e) => Some(e),
-                        Err(e) => return Err(e),
-                    }
-                }
-                Some(t) => {
-                    return Err(ParseError::UnexpectedToken(
-                        t.clone(),
-                        InFunction::ParseDeclaration,
-                    ))
-                }
-                None => return Err(ParseError::ExpectedToken),
-            }
+    match token_iter.next() {
+        Some(Token::Identifier(s)) => id = s.clone(),
+        Some(t) => {
+            return Err(ParseError::UnexpectedToken(
+                t.clone(),
+                InFunction::ParseDeclaration,
+            ))
+        }
+        None => return Err(ParseError::ExpectedToken),
+    };
 
-            match token_iter.next() {
-                Some(Token::SemiColon) => (),
-                Some(t) => {
-                    return Err(ParseError::UnexpectedToken(
-                        t.clone(),
-                        InFunction::ParseDeclaration,
-                    ))
-                }
-                None => return Err(ParseError::ExpectedToken),
+    match token_iter.peek().cloned() {
+        Some(Token::SemiColon) => expression = None,
+        Some(Token::OperatorAssign) => {
+            token_iter.next();
+            value_span = token_iter.span();
+            expression = match parse_expression(token_iter) {
+                Ok(e) => Some(e),
+                Err(e) => return Err(e),
             }
-
-            declaration = Declaration { m_id: id, m_value: expression };
         }
-        t => {
+        Some(t) => {
             return Err(ParseError::UnexpectedToken(
                 t.clone(),
                 InFunction::ParseDeclaration,
             ))
         }
+        None => return Err(ParseError::ExpectedToken),
     }
 
+    match token_iter.next() {
+        Some(Token::SemiColon) => (),
+        Some(t) => {
+            return Err(ParseError::UnexpectedToken(
+                t.clone(),
+                InFunction::ParseDeclaration,
+            ))
+        }
+        None => return Err(ParseError::ExpectedToken),
+    }
+
+    declaration = Declaration {
+        m_type: var_type,
+        m_qualifiers: qualifiers,
+        m_storage: storage,
+        m_id: id,
+        m_value: expression,
+        m_span: span,
+        m_value_span: value_span,
+    };
+
     if DEBUG {
         println!("Returning Declaration: {:?}", &declaration);
     }
@@ -513,7 +1008,7 @@ fn parse_declaration(
 }
 
 fn parse_statement(
-    token_iter: &mut std::iter::Peekable<Iter<Token>>,
+    token_iter: &mut TokenIter,
 ) -> Result<Statement, ParseError> {
     if DEBUG {
         println!(
@@ -545,7 +1040,7 @@ fn parse_statement(
                 None => return Err(ParseError::ExpectedToken),
             }
             match token_iter.peek().cloned() {
-                Some(Token::KeywordInt) => {
+                Some(t) if is_declaration_start(t) => {
                     initial_declaration = match parse_declaration(token_iter) {
                         Ok(e) => Some(e),
                         Err(e) => return Err(e),
@@ -578,9 +1073,10 @@ fn parse_statement(
             match token_iter.peek().cloned() {
                 Some(Token::SemiColon) => {
                     token_iter.next();
-                    let new_tokens =
-                        vec![Token::IntLiteral(1), Token::SemiColon];
-                    let mut new_iter = new_tokens.iter().peekable();
+                    let new_tokens = [&Token::IntLiteral(1), &Token::SemiColon];
+                    let new_spans =
+                        [token_iter.last_span(), token_iter.last_span()];
+                    let mut new_iter = TokenIter::new(&new_tokens, &new_spans);
                     condition = parse_expression(&mut new_iter).unwrap()
                 }
                 Some(_) => {
@@ -930,7 +1426,7 @@ fn parse_statement(
 }
 
 fn parse_expression(
-    token_iter: &mut std::iter::Peekable<Iter<Token>>,
+    token_iter: &mut TokenIter,
 ) -> Result<Expression, ParseError> {
     if DEBUG {
         println!(
@@ -939,47 +1435,37 @@ fn parse_expression(
         );
     }
     let expression;
+    let span = token_iter.span();
 
-    match token_iter.peek().cloned() {
-        Some(Token::Identifier(s)) => {
-            let mut next_iter = token_iter.clone();
-            next_iter.next();
+    let operation = match parse_conditional_expression(token_iter) {
+        Ok(e) => e,
+        Err(e) => return Err(e),
+    };
 
-            match next_iter.peek() {
-                Some(Token::OperatorAssign) => {
-                    token_iter.next();
-                    token_iter.next();
-                    let value = match parse_expression(token_iter) {
-                        Ok(e) => e,
-                        Err(e) => return Err(e),
-                    };
-                    expression = Expression::Assignment {
-                        m_name: s.clone(),
-                        m_value: Box::new(value),
-                    };
-                }
-                Some(_) => {
-                    expression = Expression::Operation(
-                        match parse_conditional_expression(token_iter) {
-                            Ok(l) => l,
-                            Err(e) => return Err(e),
-                        },
-                    )
+    match token_iter.peek().cloned() {
+        Some(Token::OperatorAssign) => {
+            // Only an expression without operators can be assigned to
+            let target = match into_factor(operation) {
+                Some(f) => f,
+                None => {
+                    return Err(ParseError::UnexpectedToken(
+                        Token::OperatorAssign,
+                        InFunction::ParseExpression,
+                    ))
                 }
-                None => return Err(ParseError::ExpectedToken),
+            };
+            token_iter.next();
+            let value = match parse_expression(token_iter) {
+                Ok(e) => e,
+                Err(e) => return Err(e),
+            };
+            expression = Expression::Assignment {
+                m_target: Box::new(target),
+                m_value: Box::new(value),
+                m_span: span,
             };
         }
-
-        Some(_) => {
-            expression = Expression::Operation(
-                match parse_conditional_expression(token_iter) {
-                    Ok(e) => e,
-                    Err(e) => return Err(e),
-                },
-            )
-        }
-
-        None => return Err(ParseError::ExpectedToken),
+        _ => expression = Expression::Operation(operation),
     }
 
     if DEBUG {
@@ -989,8 +1475,31 @@ fn parse_expression(
     return Ok(expression);
 }
 
+// The single factor making up an expression with no operators
+fn into_factor(conditional: ConditionalExpression) -> Option<Factor> {
+    if conditional.m_true.is_some() {
+        return None;
+    }
+    let logical_or = conditional.m_condition;
+    let logical_and = *logical_or.m_first;
+    let equality = *logical_and.m_first;
+    let relational = *equality.m_first;
+    let additive = *relational.m_first;
+    let term = *additive.m_first_term;
+    if logical_or.m_rest.len() > 0
+        || logical_and.m_rest.len() > 0
+        || equality.m_rest.len() > 0
+        || relational.m_rest.len() > 0
+        || additive.m_rest.len() > 0
+        || term.m_rest.len() > 0
+    {
+        return None;
+    }
+    return Some(*term.m_first_factor);
+}
+
 fn parse_conditional_expression(
-    token_iter: &mut std::iter::Peekable<Iter<Token>>,
+    token_iter: &mut TokenIter,
 ) -> Result<ConditionalExpression, ParseError> {
     if DEBUG {
         println!(
@@ -1056,7 +1565,7 @@ fn parse_conditional_expression(
 }
 
 fn parse_logical_or_expression(
-    token_iter: &mut std::iter::Peekable<Iter<Token>>,
+    token_iter: &mut TokenIter,
 ) -> Result<LogicalOrExpresson, ParseError> {
     if DEBUG {
         println!(
@@ -1099,7 +1608,7 @@ fn parse_logical_or_expression(
 }
 
 fn parse_logical_and_expression(
-    token_iter: &mut std::iter::Peekable<Iter<Token>>,
+    token_iter: &mut TokenIter,
 ) -> Result<LogicalAndExpression, ParseError> {
     if DEBUG {
         println!(
@@ -1141,7 +1650,7 @@ fn parse_logical_and_expression(
 }
 
 fn parse_equality_expression(
-    token_iter: &mut std::iter::Peekable<Iter<Token>>,
+    token_iter: &mut TokenIter,
 ) -> Result<EqualityExpression, ParseError> {
     if DEBUG {
         println!(
@@ -1187,7 +1696,7 @@ fn parse_equality_expression(
 }
 
 fn parse_relational_expression(
-    token_iter: &mut std::iter::Peekable<Iter<Token>>,
+    token_iter: &mut TokenIter,
 ) -> Result<RelationalExpression, ParseError> {
     if DEBUG {
         println!(
@@ -1253,7 +1762,7 @@ fn parse_relational_expression(
 }
 
 fn parse_additive_expression(
-    token_iter: &mut std::iter::Peekable<Iter<Token>>,
+    token_iter: &mut TokenIter,
 ) -> Result<AdditiveExpression, ParseError> {
     if DEBUG {
         println!(
@@ -1298,9 +1807,7 @@ fn parse_additive_expression(
     return Ok(additive_expression);
 }
 
-fn parse_term(
-    token_iter: &mut std::iter::Peekable<Iter<Token>>,
-) -> Result<Term, ParseError> {
+fn parse_term(token_iter: &mut TokenIter) -> Result<Term, ParseError> {
     if DEBUG {
         println!(
             "Parsing term from {:?}",
@@ -1355,9 +1862,7 @@ fn parse_term(
     return Ok(term);
 }
 
-fn parse_factor(
-    token_iter: &mut std::iter::Peekable<Iter<Token>>,
-) -> Result<Factor, ParseError> {
+fn parse_factor(token_iter: &mut TokenIter) -> Result<Factor, ParseError> {
     if DEBUG {
         println!(
             "Parsing factor from {:?}",
@@ -1369,6 +1874,7 @@ fn parse_factor(
         Some(t) => t,
         None => return Err(ParseError::ExpectedToken),
     };
+    let span = token_iter.last_span();
 
     match cur_token {
         Token::Identifier(s) => {
@@ -1415,12 +1921,154 @@ fn parse_factor(
                     factor = Factor::FunCall {
                         m_id: s.clone(),
                         m_arguments: arguments,
+                        m_span: span,
                     };
                 }
-                Some(_) => factor = Factor::Variable { m_var: s.clone() },
+                Some(_) => {
+                    factor = Factor::Variable { m_var: s.clone(), m_span: span }
+                }
                 None => return Err(ParseError::ExpectedToken),
             };
         }
+        Token::OpenParen
+            if token_iter.peek().is_some_and(|t| is_type_name_start(t)) =>
+        {
+            let cast_type =
+                match parse_type_name(token_iter, InFunction::ParseFactor) {
+                    Ok(t) => t,
+                    Err(e) => return Err(e),
+                };
+            match expect_close_paren(token_iter) {
+                Ok(_) => (),
+                Err(e) => return Err(e),
+            }
+            factor = Factor::Cast {
+                m_type: cast_type,
+                m_factor: match parse_factor(token_iter) {
+                    Ok(f) => Box::new(f),
+                    Err(e) => return Err(e),
+                },
+            };
+        }
+        Token::KeywordSizeof => {
+            let mut lookahead = token_iter.clone();
+            let type_operand = match (lookahead.next(), lookahead.peek()) {
+                (Some(Token::OpenParen), Some(t)) => is_type_name_start(t),
+                _ => false,
+            };
+            if type_operand {
+                token_iter.next();
+                let operand_type = match parse_type_name(
+                    token_iter,
+                    InFunction::ParseFactor,
+                ) {
+                    Ok(t) => t,
+                    Err(e) => return Err(e),
+                };
+                match expect_close_paren(token_iter) {
+                    Ok(_) => (),
+                    Err(e) => return Err(e),
+                }
+                factor = Factor::SizeOfType { m_type: operand_type };
+            } else {
+                factor = Factor::SizeOfExpression {
+                    m_factor: match parse_factor(token_iter) {
+                        Ok(f) => Box::new(f),
+                        Err(e) => return Err(e),
+                    },
+                };
+            }
+        }
+        Token::KeywordAlignof => {
+            match token_iter.next() {
+                Some(Token::OpenParen) => (),
+                Some(t) => {
+                    return Err(ParseError::UnexpectedToken(
+                        t.clone(),
+                        InFunction::ParseFactor,
+                    ))
+                }
+                None => return Err(ParseError::ExpectedToken),
+            }
+            let operand_type =
+                match parse_type_name(token_iter, InFunction::ParseFactor) {
+                    Ok(t) => t,
+                    Err(e) => return Err(e),
+                };
+            match expect_close_paren(token_iter) {
+                Ok(_) => (),
+                Err(e) => return Err(e),
+            }
+            factor = Factor::AlignOf { m_type: operand_type };
+        }
+        Token::KeywordVaStart => {
+            match expect_token(token_iter, Token::OpenParen) {
+                Ok(_) => (),
+                Err(e) => return Err(e),
+            }
+            let list = match parse_expression(token_iter) {
+                Ok(e) => Box::new(e),
+                Err(e) => return Err(e),
+            };
+            match expect_token(token_iter, Token::Comma) {
+                Ok(_) => (),
+                Err(e) => return Err(e),
+            }
+            let last = match token_iter.next() {
+                Some(Token::Identifier(s)) => s.clone(),
+                Some(t) => {
+                    return Err(ParseError::UnexpectedToken(
+                        t.clone(),
+                        InFunction::ParseFactor,
+                    ))
+                }
+                None => return Err(ParseError::ExpectedToken),
+            };
+            match expect_close_paren(token_iter) {
+                Ok(_) => (),
+                Err(e) => return Err(e),
+            }
+            factor = Factor::VaStart { m_list: list, m_last: last };
+        }
+        Token::KeywordVaArg => {
+            match expect_token(token_iter, Token::OpenParen) {
+                Ok(_) => (),
+                Err(e) => return Err(e),
+            }
+            let list = match parse_expression(token_iter) {
+                Ok(e) => Box::new(e),
+                Err(e) => return Err(e),
+            };
+            match expect_token(token_iter, Token::Comma) {
+                Ok(_) => (),
+                Err(e) => return Err(e),
+            }
+            let arg_type =
+                match parse_type_name(token_iter, InFunction::ParseFactor) {
+                    Ok(t) => t,
+                    Err(e) => return Err(e),
+                };
+            match expect_close_paren(token_iter) {
+                Ok(_) => (),
+                Err(e) => return Err(e),
+            }
+            factor = Factor::VaArg { m_list: list, m_type: arg_type };
+        }
+        Token::KeywordVaEnd => {
+            match expect_token(token_iter, Token::OpenParen) {
+                Ok(_) => (),
+                Err(e) => return Err(e),
+            }
+            let list = match parse_expression(token_iter) {
+                Ok(e) => Box::new(e),
+                Err(e) => return Err(e),
+            };
+            match expect_close_paren(token_iter) {
+                Ok(_) => (),
+                Err(e) => return Err(e),
+            }
+            factor = Factor::VaEnd { m_list: list };
+        }
         Token::OpenParen => {
             factor = Factor::Braced {
                 m_expression: match parse_expression(token_iter) {
@@ -1472,8 +2120,52 @@ fn parse_factor(
                 m_factor: next_factor,
             };
         }
+        Token::OperatorMultiplication => {
+            let next_factor = match parse_factor(token_iter) {
+                Ok(f) => Box::new(f),
+                Err(e) => return Err(e),
+            };
+            factor = Factor::UnaryOperation {
+                m_opertator: UnaryOperator::Dereference,
+                m_factor: next_factor,
+            };
+        }
+        Token::OperatorAmpersand => {
+            let next_factor = match parse_factor(token_iter) {
+                Ok(f) => Box::new(f),
+                Err(e) => return Err(e),
+            };
+            factor = Factor::UnaryOperation {
+                m_opertator: UnaryOperator::AddressOf,
+                m_factor: next_factor,
+            };
+        }
         Token::IntLiteral(val) => {
-            factor = Factor::Constant { m_value: val.clone() }
+            factor =
+                Factor::Constant { m_value: *val as i64, m_type: VarType::Int }
+        }
+        Token::LongLiteral(val) => {
+            factor = Factor::Constant { m_value: *val, m_type: VarType::Long }
+        }
+        Token::UnsignedLiteral(val) => {
+            factor =
+                Factor::Constant { m_value: *val as i64, m_type: VarType::UInt }
+        }
+        Token::UnsignedLongLiteral(val) => {
+            factor = Factor::Constant {
+                m_value: *val as i64,
+                m_type: VarType::ULong,
+            }
+        }
+        Token::FloatLiteral(val) => {
+            factor = Factor::FloatConstant {
+                m_value: *val as f64,
+                m_type: VarType::Float,
+            }
+        }
+        Token::DoubleLiteral(val) => {
+            factor =
+                Factor::FloatConstant { m_value: *val, m_type: VarType::Double }
         }
         t => {
             return Err(ParseError::UnexpectedToken(
@@ -1489,3 +2181,29 @@ fn parse_factor(
 
     return Ok(factor);
 }
+
+fn expect_close_paren(token_iter: &mut TokenIter) -> Result<(), ParseError> {
+    return expect_token(token_iter, Token::CloseParen);
+}
+
+// Takes the next token, which has to be the expected one
+fn expect_token(
+    token_iter: &mut TokenIter,
+    expected: Token,
+) -> Result<(), ParseError> {
+    match token_iter.next() {
+        Some(t)
+            if std::mem::discriminant(t)
+                == std::mem::discriminant(&expected) =>
+        {
+            return Ok(())
+        }
+        Some(t) => {
+            return Err(ParseError::UnexpectedToken(
+                t.clone(),
+                InFunction::ParseFactor,
+            ))
+        }
+        None => return Err(ParseError::ExpectedToken),
+    }
+}
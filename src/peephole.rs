@@ -0,0 +1,213 @@
+use crate::assembly::{Instruction, IntegerOp, Line, Operand, Register, Size};
+
+// Rewrites the generated assembly until none of these apply:
+// - instructions after an unconditional jump or return with no label
+//   before them are removed
+// - a push followed straight away by a pop becomes a move, or nothing when
+//   both name the same place
+// - a move followed by the move back is only the first move, unless the
+//   move back is a movl into a register, which zero extends it
+// - moves of a register to itself, other than movl which clears the upper
+//   half, are removed
+// - adding or subtracting 0 from %rsp is removed
+// - a jump to the label which follows it is removed
+pub fn optimise(lines: &mut Vec<Line>) {
+    let mut changed = true;
+    while changed {
+        changed = false;
+        changed |= remove_unreachable(lines);
+        changed |= fold_push_pop(lines);
+        changed |= remove_redundant_moves(lines);
+        changed |= remove_jumps_to_next(lines);
+    }
+}
+
+fn instruction(line: &Line) -> Option<&Instruction> {
+    match line {
+        Line::Instruction(instruction) => return Some(instruction),
+        _ => return None,
+    }
+}
+
+// Index of the line after i which isn't a directive
+fn next_line(lines: &Vec<Line>, i: usize) -> Option<usize> {
+    let mut j = i + 1;
+    while j < lines.len() && lines[j].is_directive() {
+        j += 1;
+    }
+    if j < lines.len() {
+        return Some(j);
+    }
+    return None;
+}
+
+fn remove_unreachable(lines: &mut Vec<Line>) -> bool {
+    let mut changed = false;
+    let mut reachable = true;
+    let mut i = 0;
+    while i < lines.len() {
+        match &lines[i] {
+            Line::Label(_) => reachable = true,
+            Line::Directive(_) => (),
+            Line::Instruction(instruction) => {
+                if !reachable {
+                    lines.remove(i);
+                    changed = true;
+                    continue;
+                }
+                if instruction.is_unconditional_jump() {
+                    reachable = false;
+                }
+            }
+        }
+        i += 1;
+    }
+    return changed;
+}
+
+fn fold_push_pop(lines: &mut Vec<Line>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i < lines.len() {
+        let j = match next_line(lines, i) {
+            Some(j) => j,
+            None => break,
+        };
+        let (source, destination) =
+            match (instruction(&lines[i]), instruction(&lines[j])) {
+                (
+                    Some(Instruction::Push(pushed)),
+                    Some(Instruction::Pop(popped)),
+                ) => (pushed.clone(), popped.clone()),
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+        if source == destination {
+            lines.remove(j);
+            lines.remove(i);
+            changed = true;
+            continue;
+        }
+        // There is no move from memory to memory
+        if source.is_memory() && destination.is_memory() {
+            i += 1;
+            continue;
+        }
+        lines[i] = Line::Instruction(Instruction::Mov(
+            Size::Quad,
+            source,
+            destination,
+        ));
+        lines.remove(j);
+        changed = true;
+    }
+    return changed;
+}
+
+// Whether an instruction changes nothing
+fn does_nothing(instruction: &Instruction) -> bool {
+    match instruction {
+        Instruction::Integer(
+            IntegerOp::Add | IntegerOp::Subtract,
+            _,
+            Operand::Immediate(0),
+            Operand::Register(Register::Rsp),
+        ) => return true,
+        Instruction::Mov(Size::Long, _, _) => return false,
+        Instruction::Mov(_, source, destination)
+        | Instruction::MovFloat(_, source, destination) => {
+            return source == destination
+        }
+        _ => return false,
+    }
+}
+
+fn remove_redundant_moves(lines: &mut Vec<Line>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i < lines.len() {
+        let current = match instruction(&lines[i]) {
+            Some(current) => current,
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+        if does_nothing(current) {
+            lines.remove(i);
+            changed = true;
+            continue;
+        }
+
+        // Only from a register, as a move from memory could change the
+        // base register of the address it came from
+        let reverse = match current {
+            Instruction::Mov(_, Operand::Register(_), _)
+            | Instruction::MovFloat(_, Operand::Register(_), _)
+            | Instruction::MovBits(_, Operand::Register(_), _) => {
+                current.reversed()
+            }
+            _ => None,
+        };
+        let back = match (reverse, next_line(lines, i)) {
+            // movl into a register clears its upper half, which the first
+            // move doesn't do for the register it came from
+            (
+                Some(Instruction::Mov(Size::Long, _, Operand::Register(_))),
+                _,
+            ) => None,
+            (Some(reverse), Some(j))
+                if instruction(&lines[j]) == Some(&reverse) =>
+            {
+                Some(j)
+            }
+            _ => None,
+        };
+        match back {
+            Some(j) => {
+                lines.remove(j);
+                changed = true;
+            }
+            None => i += 1,
+        }
+    }
+    return changed;
+}
+
+fn remove_jumps_to_next(lines: &mut Vec<Line>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i < lines.len() {
+        let target = match instruction(&lines[i]) {
+            Some(Instruction::Jump(target))
+            | Some(Instruction::JumpIf(_, target)) => target.clone(),
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        // Any of the labels before the next instruction is where control
+        // would go anyway
+        let mut j = i + 1;
+        let mut next = false;
+        while j < lines.len() {
+            match &lines[j] {
+                Line::Label(label) if *label == target => {
+                    next = true;
+                    break;
+                }
+                Line::Label(_) | Line::Directive(_) => j += 1,
+                Line::Instruction(_) => break,
+            }
+        }
+        if next {
+            lines.remove(i);
+            changed = true;
+        } else {
+            i += 1;
+        }
+    }
+    return changed;
+}
@@ -0,0 +1,1652 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const DEBUG: bool = false;
+
+const MAX_INCLUDE_DEPTH: usize = 200;
+
+// Headers that belong to the compiler rather than the C library, used when
+// no file of the same name is found on the include path
+const BUILTIN_HEADERS: [(&str, &str); 1] = [(
+    "stdarg.h",
+    "#ifndef _STDARG_H\n\
+     #define _STDARG_H\n\
+     #define va_list __builtin_va_list\n\
+     #define va_start(ap, last) __builtin_va_start(ap, last)\n\
+     #define va_arg(ap, type) __builtin_va_arg(ap, type)\n\
+     #define va_end(ap) __builtin_va_end(ap)\n\
+     #endif\n",
+)];
+
+#[derive(Debug)]
+pub enum PreprocessError {
+    // (location, message)
+    IoError(String, String),
+    IncludeNotFound(String, String),
+    InvalidDirective(String, String),
+    InvalidMacro(String, String),
+    InvalidExpression(String, String),
+    UnterminatedConditional(String, String),
+    UserError(String, String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum PpTokenKind {
+    Identifier,
+    Number,
+    CharLiteral,
+    StringLiteral,
+    Punctuator,
+}
+
+#[derive(Debug, Clone)]
+struct PpToken {
+    m_kind: PpTokenKind,
+    m_text: String,
+    m_leading_space: bool,
+    m_line: usize,
+    // Where the token starts on its line, counting from 1, or 0 for tokens
+    // which didn't come from there, such as most of a macro's replacement
+    m_column: usize,
+    m_hide_set: Vec<String>,
+}
+
+impl PpToken {
+    fn is(&self, text: &str) -> bool {
+        return self.m_kind == PpTokenKind::Punctuator && self.m_text == text;
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    m_params: Option<Vec<String>>, // None for object-like macros
+    m_variadic: bool,
+    m_body: Vec<PpToken>,
+}
+
+#[derive(Debug)]
+struct Conditional {
+    m_active: bool, // the current group is being emitted
+    m_taken: bool,  // some group of this #if chain was already emitted
+    m_parent_active: bool,
+    m_seen_else: bool,
+}
+
+// Per-file state: the presumed name and line offset can be changed by #line
+struct FileState {
+    m_dir: PathBuf,
+    m_presumed_name: String,
+    m_line_delta: isize,
+}
+
+impl FileState {
+    fn presumed_line(&self, line: usize) -> usize {
+        return (line as isize + self.m_line_delta) as usize;
+    }
+
+    fn location(&self, line: usize) -> String {
+        return format!(
+            "{}:{}",
+            self.m_presumed_name,
+            self.presumed_line(line)
+        );
+    }
+}
+
+pub struct Preprocessor {
+    include_paths: Vec<PathBuf>,
+    macros: HashMap<String, Macro>,
+    once_files: HashSet<PathBuf>,
+    include_depth: usize,
+}
+
+impl Preprocessor {
+    pub fn new(include_paths: Vec<PathBuf>) -> Self {
+        let mut preprocessor = Preprocessor {
+            include_paths,
+            macros: HashMap::new(),
+            once_files: HashSet::new(),
+            include_depth: 0,
+        };
+        preprocessor.define("__STDC__=1");
+        return preprocessor;
+    }
+
+    // Handles a command line definition: "NAME", "NAME=value" or "NAME(a,b)=body"
+    pub fn define(&mut self, definition: &str) {
+        let line = match definition.find('=') {
+            Some(i) => {
+                format!("{} {}", &definition[..i], &definition[i + 1..])
+            }
+            None => format!("{} 1", definition),
+        };
+        let tokens = tokenize_line(&line, 0);
+        let state = FileState {
+            m_dir: PathBuf::new(),
+            m_presumed_name: String::from("<command line>"),
+            m_line_delta: 0,
+        };
+        match self.define_macro(&tokens, &state, 0) {
+            Ok(_) => (),
+            Err(e) => eprintln!("Ignoring invalid definition: {:?}", e),
+        }
+    }
+
+    pub fn undefine(&mut self, name: &str) {
+        self.macros.remove(name);
+    }
+
+    pub fn preprocess_file(
+        &mut self,
+        path: &Path,
+    ) -> Result<String, PreprocessError> {
+        let source = match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) => {
+                return Err(PreprocessError::IoError(
+                    path.to_string_lossy().into_owned(),
+                    e.to_string(),
+                ))
+            }
+        };
+        let mut out = String::new();
+        match self.preprocess_source(&source, path, &mut out) {
+            Ok(_) => (),
+            Err(e) => return Err(e),
+        }
+        return Ok(out);
+    }
+
+    fn preprocess_source(
+        &mut self,
+        source: &str,
+        path: &Path,
+        out: &mut String,
+    ) -> Result<bool, PreprocessError> {
+        let mut state = FileState {
+            m_dir: path.parent().unwrap_or(Path::new("")).to_path_buf(),
+            m_presumed_name: path.to_string_lossy().into_owned(),
+            m_line_delta: 0,
+        };
+        let lines = logical_lines(&strip_comments(source));
+        let mut conditionals: Vec<Conditional> = Vec::new();
+        let mut out_line: usize = 1;
+
+        let flag = if self.include_depth > 0 { Some(1) } else { None };
+        line_marker(out, 1, &state.m_presumed_name, flag);
+
+        let mut i = 0;
+        while i < lines.len() {
+            let (line_no, text) = &lines[i];
+            let tokens = tokenize_line(text, *line_no);
+            let active = match conditionals.last() {
+                Some(c) => c.m_active,
+                None => true,
+            };
+
+            if tokens.len() > 0 && tokens[0].is("#") {
+                sync_line(out, &mut out_line, *line_no);
+                match self.directive(
+                    &tokens[1..],
+                    *line_no,
+                    active,
+                    &mut state,
+                    &mut conditionals,
+                    out,
+                    &mut out_line,
+                ) {
+                    Ok(_) => (),
+                    Err(e) => return Err(e),
+                }
+                i += 1;
+                continue;
+            }
+
+            if !active {
+                i += 1;
+                continue;
+            }
+
+            // Gather a run of text lines so that macro invocations may span
+            // several lines
+            let mut run: Vec<PpToken> = tokens;
+            let mut end_line = *line_no;
+            i += 1;
+            while i < lines.len() {
+                let next = tokenize_line(&lines[i].1, lines[i].0);
+                if next.len() > 0 && next[0].is("#") {
+                    break;
+                }
+                run.extend(next);
+                end_line = lines[i].0;
+                i += 1;
+            }
+
+            let expanded = match self.expand(run, &state) {
+                Ok(t) => t,
+                Err(e) => return Err(e),
+            };
+            emit_tokens(&expanded, &state, out, &mut out_line);
+            sync_line(out, &mut out_line, end_line);
+            out.push('\n');
+            out_line += 1;
+        }
+
+        match conditionals.last() {
+            Some(_) => {
+                return Err(PreprocessError::UnterminatedConditional(
+                    state.location(lines.last().map(|l| l.0).unwrap_or(1)),
+                    String::from("#if without #endif"),
+                ))
+            }
+            None => (),
+        }
+
+        return Ok(true);
+    }
+
+    fn directive(
+        &mut self,
+        tokens: &[PpToken],
+        line: usize,
+        active: bool,
+        state: &mut FileState,
+        conditionals: &mut Vec<Conditional>,
+        out: &mut String,
+        out_line: &mut usize,
+    ) -> Result<bool, PreprocessError> {
+        if DEBUG {
+            println!("Directive at {}: {:?}", state.location(line), tokens);
+        }
+
+        let name = match tokens.first() {
+            Some(t) => t.m_text.clone(),
+            None => String::new(), // null directive
+        };
+        let rest = if tokens.len() > 0 { &tokens[1..] } else { tokens };
+
+        match name.as_str() {
+            "if" | "ifdef" | "ifndef" => {
+                let condition = if !active {
+                    false
+                } else if name == "if" {
+                    match self.evaluate_condition(rest, state, line) {
+                        Ok(v) => v,
+                        Err(e) => return Err(e),
+                    }
+                } else {
+                    let id = match rest.first() {
+                        Some(t) if t.m_kind == PpTokenKind::Identifier => {
+                            &t.m_text
+                        }
+                        _ => {
+                            return Err(PreprocessError::InvalidDirective(
+                                state.location(line),
+                                format!("#{} expects an identifier", name),
+                            ))
+                        }
+                    };
+                    self.macros.contains_key(id) == (name == "ifdef")
+                };
+                conditionals.push(Conditional {
+                    m_active: active && condition,
+                    m_taken: condition,
+                    m_parent_active: active,
+                    m_seen_else: false,
+                });
+            }
+            "elif" | "else" => {
+                let cond = match conditionals.last() {
+                    Some(c) if !c.m_seen_else => c,
+                    _ => {
+                        return Err(PreprocessError::InvalidDirective(
+                            state.location(line),
+                            format!("#{} without matching #if", name),
+                        ))
+                    }
+                };
+                let parent_active = cond.m_parent_active;
+                let taken = cond.m_taken;
+                let condition = if !parent_active || taken {
+                    false
+                } else if name == "elif" {
+                    match self.evaluate_condition(rest, state, line) {
+                        Ok(v) => v,
+                        Err(e) => return Err(e),
+                    }
+                } else {
+                    true
+                };
+                let cond = conditionals.last_mut().unwrap();
+                cond.m_active = parent_active && condition;
+                cond.m_taken = taken || condition;
+                cond.m_seen_else = name == "else";
+            }
+            "endif" => match conditionals.pop() {
+                Some(_) => (),
+                None => {
+                    return Err(PreprocessError::InvalidDirective(
+                        state.location(line),
+                        String::from("#endif without matching #if"),
+                    ))
+                }
+            },
+            _ if !active => (),
+            "" => (),
+            "define" => match self.define_macro(rest, state, line) {
+                Ok(_) => (),
+                Err(e) => return Err(e),
+            },
+            "undef" => match rest.first() {
+                Some(t) if t.m_kind == PpTokenKind::Identifier => {
+                    self.macros.remove(&t.m_text);
+                }
+                _ => {
+                    return Err(PreprocessError::InvalidDirective(
+                        state.location(line),
+                        String::from("#undef expects an identifier"),
+                    ))
+                }
+            },
+            "include" => {
+                return self.include(rest, line, state, out, out_line);
+            }
+            "line" => {
+                let expanded = match self.expand(rest.to_vec(), state) {
+                    Ok(t) => t,
+                    Err(e) => return Err(e),
+                };
+                let new_line = match expanded.first() {
+                    Some(t) if t.m_kind == PpTokenKind::Number => {
+                        match t.m_text.parse::<usize>() {
+                            Ok(n) if n > 0 => n,
+                            _ => {
+                                return Err(PreprocessError::InvalidDirective(
+                                    state.location(line),
+                                    format!("invalid line number {}", t.m_text),
+                                ))
+                            }
+                        }
+                    }
+                    _ => {
+                        return Err(PreprocessError::InvalidDirective(
+                            state.location(line),
+                            String::from("#line expects a line number"),
+                        ))
+                    }
+                };
+                match expanded.get(1) {
+                    Some(t) if t.m_kind == PpTokenKind::StringLiteral => {
+                        state.m_presumed_name = unquote(&t.m_text);
+                    }
+                    _ => (),
+                }
+                // The line following the directive has number new_line
+                state.m_line_delta = new_line as isize - (line as isize + 1);
+                line_marker(out, new_line, &state.m_presumed_name, None);
+                *out_line = line + 1;
+                return Ok(true);
+            }
+            "error" | "warning" => {
+                let message = tokens_to_string(rest);
+                if name == "error" {
+                    return Err(PreprocessError::UserError(
+                        state.location(line),
+                        message,
+                    ));
+                }
+                eprintln!("{}: warning: {}", state.location(line), message);
+            }
+            "pragma" => match rest.first() {
+                Some(t) if t.m_text == "once" => (), // recorded on include
+                _ => (), // other pragmas are ignored
+            },
+            n => {
+                return Err(PreprocessError::InvalidDirective(
+                    state.location(line),
+                    format!("unknown directive #{}", n),
+                ))
+            }
+        }
+
+        out.push('\n');
+        *out_line += 1;
+        return Ok(true);
+    }
+
+    fn define_macro(
+        &mut self,
+        tokens: &[PpToken],
+        state: &FileState,
+        line: usize,
+    ) -> Result<bool, PreprocessError> {
+        let name = match tokens.first() {
+            Some(t) if t.m_kind == PpTokenKind::Identifier => t.m_text.clone(),
+            _ => {
+                return Err(PreprocessError::InvalidMacro(
+                    state.location(line),
+                    String::from("macro name must be an identifier"),
+                ))
+            }
+        };
+
+        let mut params: Option<Vec<String>> = None;
+        let mut variadic = false;
+        let mut body_start = 1;
+
+        // Function-like only when "(" follows the name without whitespace
+        match tokens.get(1) {
+            Some(t) if t.is("(") && !t.m_leading_space => {
+                let mut names = Vec::new();
+                let mut i = 2;
+                loop {
+                    match tokens.get(i) {
+                        Some(t) if t.is(")") => {
+                            i += 1;
+                            break;
+                        }
+                        Some(t) if t.is("...") => {
+                            variadic = true;
+                        }
+                        Some(t) if t.m_kind == PpTokenKind::Identifier => {
+                            names.push(t.m_text.clone());
+                        }
+                        Some(t) if t.is(",") && names.len() > 0 => (),
+                        _ => {
+                            return Err(PreprocessError::InvalidMacro(
+                                state.location(line),
+                                format!("invalid parameter list for {}", name),
+                            ))
+                        }
+                    }
+                    i += 1;
+                }
+                params = Some(names);
+                body_start = i;
+            }
+            _ => (),
+        }
+
+        let mut body: Vec<PpToken> =
+            tokens[body_start.min(tokens.len())..].iter().cloned().collect();
+        match body.first_mut() {
+            Some(t) => t.m_leading_space = false,
+            None => (),
+        }
+        if body.first().map_or(false, |t| t.is("##"))
+            || body.last().map_or(false, |t| t.is("##"))
+        {
+            return Err(PreprocessError::InvalidMacro(
+                state.location(line),
+                String::from("'##' cannot appear at either end of a macro"),
+            ));
+        }
+
+        self.macros.insert(
+            name,
+            Macro { m_params: params, m_variadic: variadic, m_body: body },
+        );
+        return Ok(true);
+    }
+
+    fn include(
+        &mut self,
+        tokens: &[PpToken],
+        line: usize,
+        state: &FileState,
+        out: &mut String,
+        out_line: &mut usize,
+    ) -> Result<bool, PreprocessError> {
+        let mut tokens = tokens.to_vec();
+        // #include MACRO
+        match tokens.first() {
+            Some(t) if t.m_kind == PpTokenKind::Identifier => {
+                tokens = match self.expand(tokens, state) {
+                    Ok(t) => t,
+                    Err(e) => return Err(e),
+                };
+            }
+            _ => (),
+        }
+
+        let (name, quoted) = match tokens.first() {
+            Some(t) if t.m_kind == PpTokenKind::StringLiteral => {
+                (unquote(&t.m_text), true)
+            }
+            Some(t) if t.is("<") => {
+                let mut name = String::new();
+                let mut closed = false;
+                for t in &tokens[1..] {
+                    if t.is(">") {
+                        closed = true;
+                        break;
+                    }
+                    if t.m_leading_space && name.len() > 0 {
+                        name.push(' ');
+                    }
+                    name.push_str(&t.m_text);
+                }
+                if !closed {
+                    return Err(PreprocessError::InvalidDirective(
+                        state.location(line),
+                        String::from("missing '>' in #include"),
+                    ));
+                }
+                (name, false)
+            }
+            _ => {
+                return Err(PreprocessError::InvalidDirective(
+                    state.location(line),
+                    String::from("#include expects \"FILENAME\" or <FILENAME>"),
+                ))
+            }
+        };
+
+        let mut candidates: Vec<PathBuf> = Vec::new();
+        if quoted {
+            candidates.push(state.m_dir.join(&name));
+        }
+        for dir in &self.include_paths {
+            candidates.push(dir.join(&name));
+        }
+        candidates.push(Path::new("/usr/local/include").join(&name));
+        candidates.push(Path::new("/usr/include").join(&name));
+
+        let (path, builtin) = match candidates.into_iter().find(|p| p.is_file())
+        {
+            Some(p) => (p, None),
+            None => match BUILTIN_HEADERS.iter().find(|h| h.0 == name) {
+                Some(h) => (Path::new("<built-in>").join(&name), Some(h.1)),
+                None => {
+                    return Err(PreprocessError::IncludeNotFound(
+                        state.location(line),
+                        name,
+                    ))
+                }
+            },
+        };
+
+        let canonical = fs::canonicalize(&path).unwrap_or(path.clone());
+        let entered = !self.once_files.contains(&canonical);
+        if entered {
+            let source = match builtin {
+                Some(text) => String::from(text),
+                None => match fs::read_to_string(&path) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        return Err(PreprocessError::IoError(
+                            state.location(line),
+                            e.to_string(),
+                        ))
+                    }
+                },
+            };
+            if has_pragma_once(&source) {
+                self.once_files.insert(canonical);
+            }
+            if self.include_depth >= MAX_INCLUDE_DEPTH {
+                return Err(PreprocessError::InvalidDirective(
+                    state.location(line),
+                    String::from("#include nested too deeply"),
+                ));
+            }
+            self.include_depth += 1;
+            let result = self.preprocess_source(&source, &path, out);
+            self.include_depth -= 1;
+            match result {
+                Ok(_) => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        if !entered {
+            out.push('\n');
+            *out_line += 1;
+            return Ok(true);
+        }
+
+        // Return to the including file at the line after the directive
+        line_marker(
+            out,
+            state.presumed_line(line) + 1,
+            &state.m_presumed_name,
+            Some(2),
+        );
+        *out_line = line + 1;
+        return Ok(true);
+    }
+
+    fn evaluate_condition(
+        &self,
+        tokens: &[PpToken],
+        state: &FileState,
+        line: usize,
+    ) -> Result<bool, PreprocessError> {
+        // Resolve defined before expansion so that the operand is not replaced
+        let mut resolved: Vec<PpToken> = Vec::new();
+        let mut i = 0;
+        while i < tokens.len() {
+            let t = &tokens[i];
+            if t.m_kind == PpTokenKind::Identifier && t.m_text == "defined" {
+                let (id, consumed) =
+                    match (tokens.get(i + 1), tokens.get(i + 2)) {
+                        (Some(p), Some(id)) if p.is("(") => {
+                            match tokens.get(i + 3) {
+                                Some(c) if c.is(")") => (id, 4),
+                                _ => {
+                                    return Err(
+                                        PreprocessError::InvalidExpression(
+                                            state.location(line),
+                                            String::from(
+                                                "missing ')' after defined",
+                                            ),
+                                        ),
+                                    )
+                                }
+                            }
+                        }
+                        (Some(id), _) => (id, 2),
+                        _ => {
+                            return Err(PreprocessError::InvalidExpression(
+                                state.location(line),
+                                String::from("defined expects an identifier"),
+                            ))
+                        }
+                    };
+                let mut value = t.clone();
+                value.m_kind = PpTokenKind::Number;
+                value.m_text = if self.macros.contains_key(&id.m_text) {
+                    String::from("1")
+                } else {
+                    String::from("0")
+                };
+                resolved.push(value);
+                i += consumed;
+            } else {
+                resolved.push(t.clone());
+                i += 1;
+            }
+        }
+
+        let expanded = match self.expand(resolved, state) {
+            Ok(t) => t,
+            Err(e) => return Err(e),
+        };
+
+        let mut evaluator = ConditionEvaluator {
+            tokens: &expanded,
+            position: 0,
+            location: state.location(line),
+            unevaluated: false,
+        };
+        let value = match evaluator.conditional() {
+            Ok(v) => v,
+            Err(e) => return Err(e),
+        };
+        if evaluator.position != expanded.len() {
+            return Err(PreprocessError::InvalidExpression(
+                state.location(line),
+                format!(
+                    "unexpected '{}' in #if",
+                    expanded[evaluator.position].m_text
+                ),
+            ));
+        }
+        return Ok(value.is_true());
+    }
+
+    // Macro expansion using hide sets: a token produced by expanding a macro
+    // carries the names of the macros it came from and is never expanded by
+    // those macros again
+    fn expand(
+        &self,
+        tokens: Vec<PpToken>,
+        state: &FileState,
+    ) -> Result<Vec<PpToken>, PreprocessError> {
+        let mut input: VecDeque<PpToken> = tokens.into();
+        let mut output: Vec<PpToken> = Vec::new();
+
+        while let Some(token) = input.pop_front() {
+            if token.m_kind != PpTokenKind::Identifier
+                || token.m_hide_set.contains(&token.m_text)
+            {
+                output.push(token);
+                continue;
+            }
+
+            match token.m_text.as_str() {
+                "__LINE__" => {
+                    let mut t = token.clone();
+                    t.m_kind = PpTokenKind::Number;
+                    t.m_text = state.presumed_line(token.m_line).to_string();
+                    output.push(t);
+                    continue;
+                }
+                "__FILE__" => {
+                    let mut t = token.clone();
+                    t.m_kind = PpTokenKind::StringLiteral;
+                    t.m_text = quote(&state.m_presumed_name);
+                    output.push(t);
+                    continue;
+                }
+                _ => (),
+            }
+
+            let definition = match self.macros.get(&token.m_text) {
+                Some(m) => m,
+                None => {
+                    output.push(token);
+                    continue;
+                }
+            };
+
+            let mut hide_set = token.m_hide_set.clone();
+            hide_set.push(token.m_text.clone());
+
+            let replacement = match &definition.m_params {
+                None => subst_object(&definition.m_body, &hide_set),
+                Some(params) => {
+                    match input.front() {
+                        Some(t) if t.is("(") => (),
+                        _ => {
+                            // A function-like macro name without arguments
+                            output.push(token);
+                            continue;
+                        }
+                    }
+                    input.pop_front();
+
+                    let (args, close) = match collect_arguments(&mut input) {
+                        Some(a) => a,
+                        None => {
+                            return Err(PreprocessError::InvalidMacro(
+                                state.location(token.m_line),
+                                format!(
+                                    "unterminated invocation of {}",
+                                    token.m_text
+                                ),
+                            ))
+                        }
+                    };
+                    let args = match normalise_arguments(
+                        args,
+                        params.len(),
+                        definition.m_variadic,
+                    ) {
+                        Some(a) => a,
+                        None => {
+                            return Err(PreprocessError::InvalidMacro(
+                                state.location(token.m_line),
+                                format!(
+                                    "wrong number of arguments to {}",
+                                    token.m_text
+                                ),
+                            ))
+                        }
+                    };
+
+                    let mut expanded_args = Vec::new();
+                    for arg in &args {
+                        expanded_args.push(
+                            match self.expand(arg.clone(), state) {
+                                Ok(t) => t,
+                                Err(e) => return Err(e),
+                            },
+                        );
+                    }
+
+                    // hide set is (HS(name) & HS(")")) + name
+                    let mut hide_set: Vec<String> = token
+                        .m_hide_set
+                        .iter()
+                        .filter(|n| close.m_hide_set.contains(n))
+                        .cloned()
+                        .collect();
+                    hide_set.push(token.m_text.clone());
+
+                    let mut names = params.clone();
+                    if definition.m_variadic {
+                        names.push(String::from("__VA_ARGS__"));
+                    }
+                    let params = Parameters {
+                        m_names: &names,
+                        m_raw: &args,
+                        m_expanded: &expanded_args,
+                    };
+                    match subst_function(
+                        &definition.m_body,
+                        &params,
+                        &hide_set,
+                        state,
+                        token.m_line,
+                    ) {
+                        Ok(t) => t,
+                        Err(e) => return Err(e),
+                    }
+                }
+            };
+
+            // Rescan the replacement together with the rest of the input
+            for (i, mut t) in replacement.into_iter().enumerate().rev() {
+                t.m_line = token.m_line;
+                t.m_column = 0;
+                if i == 0 {
+                    t.m_leading_space = token.m_leading_space;
+                    t.m_column = token.m_column;
+                }
+                input.push_front(t);
+            }
+        }
+
+        return Ok(output);
+    }
+}
+
+struct Parameters<'a> {
+    m_names: &'a [String],
+    m_raw: &'a [Vec<PpToken>],
+    m_expanded: &'a [Vec<PpToken>],
+}
+
+impl<'a> Parameters<'a> {
+    fn index(&self, token: &PpToken) -> Option<usize> {
+        if token.m_kind != PpTokenKind::Identifier {
+            return None;
+        }
+        return self.m_names.iter().position(|n| n == &token.m_text);
+    }
+}
+
+fn subst_object(body: &[PpToken], hide_set: &[String]) -> Vec<PpToken> {
+    let mut result: Vec<PpToken> = Vec::new();
+    let mut i = 0;
+    while i < body.len() {
+        if body[i].is("##") && result.len() > 0 && i + 1 < body.len() {
+            let left = result.pop().unwrap();
+            result.extend(glue(&left, &body[i + 1]));
+            i += 2;
+            continue;
+        }
+        result.push(body[i].clone());
+        i += 1;
+    }
+    for t in &mut result {
+        add_hide_set(t, hide_set);
+    }
+    return result;
+}
+
+fn subst_function(
+    body: &[PpToken],
+    params: &Parameters,
+    hide_set: &[String],
+    state: &FileState,
+    line: usize,
+) -> Result<Vec<PpToken>, PreprocessError> {
+    let mut result: Vec<PpToken> = Vec::new();
+    let mut i = 0;
+    while i < body.len() {
+        let t = &body[i];
+
+        // # param
+        if t.is("#") {
+            match body.get(i + 1).and_then(|p| params.index(p)) {
+                Some(p) => {
+                    let mut s = t.clone();
+                    s.m_kind = PpTokenKind::StringLiteral;
+                    s.m_text = stringize(&params.m_raw[p]);
+                    result.push(s);
+                    i += 2;
+                    continue;
+                }
+                None => {
+                    return Err(PreprocessError::InvalidMacro(
+                        state.location(line),
+                        String::from(
+                            "'#' is not followed by a macro parameter",
+                        ),
+                    ))
+                }
+            }
+        }
+
+        // x ## y
+        if t.is("##") {
+            let right = match body.get(i + 1) {
+                Some(r) => r,
+                None => break,
+            };
+            let right_tokens: Vec<PpToken> = match params.index(right) {
+                Some(p) => params.m_raw[p].clone(),
+                None => vec![right.clone()],
+            };
+            match (result.pop(), right_tokens.split_first()) {
+                (Some(left), Some((first, rest))) => {
+                    result.extend(glue(&left, first));
+                    result.extend(rest.iter().cloned());
+                }
+                (Some(left), None) => result.push(left),
+                (None, _) => result.extend(right_tokens.iter().cloned()),
+            }
+            i += 2;
+            continue;
+        }
+
+        match params.index(t) {
+            Some(p) => {
+                // Operands of ## are inserted without expansion
+                let raw = body.get(i + 1).map_or(false, |n| n.is("##"));
+                let arg =
+                    if raw { &params.m_raw[p] } else { &params.m_expanded[p] };
+                for (j, a) in arg.iter().enumerate() {
+                    let mut a = a.clone();
+                    if j == 0 {
+                        a.m_leading_space = t.m_leading_space;
+                    }
+                    result.push(a);
+                }
+            }
+            None => result.push(t.clone()),
+        }
+        i += 1;
+    }
+
+    for t in &mut result {
+        add_hide_set(t, hide_set);
+    }
+    return Ok(result);
+}
+
+fn add_hide_set(token: &mut PpToken, hide_set: &[String]) {
+    for name in hide_set {
+        if !token.m_hide_set.contains(name) {
+            token.m_hide_set.push(name.clone());
+        }
+    }
+}
+
+fn glue(left: &PpToken, right: &PpToken) -> Vec<PpToken> {
+    let text = format!("{}{}", left.m_text, right.m_text);
+    let mut tokens = tokenize_line(&text, left.m_line);
+    for t in &mut tokens {
+        t.m_hide_set = left.m_hide_set.clone();
+        t.m_column = 0;
+    }
+    match tokens.first_mut() {
+        Some(t) => {
+            t.m_leading_space = left.m_leading_space;
+            t.m_column = left.m_column;
+        }
+        None => (),
+    }
+    return tokens;
+}
+
+// Collects the arguments of a function-like macro invocation after the
+// opening parenthesis, returning them with the closing parenthesis
+fn collect_arguments(
+    input: &mut VecDeque<PpToken>,
+) -> Option<(Vec<Vec<PpToken>>, PpToken)> {
+    let mut args: Vec<Vec<PpToken>> = vec![Vec::new()];
+    let mut depth = 0;
+    while let Some(t) = input.pop_front() {
+        if t.is(")") && depth == 0 {
+            return Some((args, t));
+        }
+        if t.is(",") && depth == 0 {
+            args.push(Vec::new());
+            continue;
+        }
+        if t.is("(") {
+            depth += 1;
+        } else if t.is(")") {
+            depth -= 1;
+        }
+        args.last_mut().unwrap().push(t);
+    }
+    return None;
+}
+
+fn normalise_arguments(
+    mut args: Vec<Vec<PpToken>>,
+    num_params: usize,
+    variadic: bool,
+) -> Option<Vec<Vec<PpToken>>> {
+    // f() passes one empty argument
+    if num_params == 0 && !variadic {
+        if args.len() == 1 && args[0].len() == 0 {
+            return Some(Vec::new());
+        }
+        return None;
+    }
+    if variadic {
+        if args.len() < num_params {
+            if args.len() + 1 == num_params || num_params == 0 {
+                args.resize(num_params, Vec::new());
+            } else {
+                return None;
+            }
+        }
+        // Everything after the named parameters becomes __VA_ARGS__
+        let rest: Vec<Vec<PpToken>> = args.split_off(num_params);
+        let mut va_args: Vec<PpToken> = Vec::new();
+        for (i, arg) in rest.into_iter().enumerate() {
+            if i > 0 {
+                va_args.push(PpToken {
+                    m_kind: PpTokenKind::Punctuator,
+                    m_text: String::from(","),
+                    m_leading_space: false,
+                    m_line: 0,
+                    m_column: 0,
+                    m_hide_set: Vec::new(),
+                });
+            }
+            va_args.extend(arg);
+        }
+        args.push(va_args);
+        return Some(args);
+    }
+    if args.len() != num_params {
+        return None;
+    }
+    return Some(args);
+}
+
+fn stringize(tokens: &[PpToken]) -> String {
+    let mut s = String::new();
+    for (i, t) in tokens.iter().enumerate() {
+        if i > 0 && t.m_leading_space {
+            s.push(' ');
+        }
+        match t.m_kind {
+            PpTokenKind::StringLiteral | PpTokenKind::CharLiteral => {
+                for c in t.m_text.chars() {
+                    if c == '"' || c == '\\' {
+                        s.push('\\');
+                    }
+                    s.push(c);
+                }
+            }
+            _ => s.push_str(&t.m_text),
+        }
+    }
+    return format!("\"{}\"", s);
+}
+
+fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    return quoted;
+}
+
+fn unquote(s: &str) -> String {
+    let inner = s.trim_start_matches('"').trim_end_matches('"');
+    let mut unquoted = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some(n) => unquoted.push(n),
+                None => (),
+            }
+        } else {
+            unquoted.push(c);
+        }
+    }
+    return unquoted;
+}
+
+fn tokens_to_string(tokens: &[PpToken]) -> String {
+    let mut s = String::new();
+    for (i, t) in tokens.iter().enumerate() {
+        if i > 0 && t.m_leading_space {
+            s.push(' ');
+        }
+        s.push_str(&t.m_text);
+    }
+    return s;
+}
+
+// Writes a line marker in the form used by gcc: the next output line is line
+// `line` of `name`. Flag 1 marks entering an included file, 2 returning to
+// the includer
+fn line_marker(out: &mut String, line: usize, name: &str, flag: Option<u8>) {
+    match flag {
+        Some(f) => out.push_str(&format!("# {} {} {}\n", line, quote(name), f)),
+        None => out.push_str(&format!("# {} {}\n", line, quote(name))),
+    }
+}
+
+// Pads the output with newlines until it reaches the given source line
+fn sync_line(out: &mut String, out_line: &mut usize, line: usize) {
+    while *out_line < line {
+        out.push('\n');
+        *out_line += 1;
+    }
+}
+
+// Tokens are written at the column they had in the source, so the columns
+// the lexer sees are the original ones. When a macro's expansion is longer
+// than its invocation the line is continued after a line marker, which
+// starts the columns again
+fn emit_tokens(
+    tokens: &[PpToken],
+    state: &FileState,
+    out: &mut String,
+    out_line: &mut usize,
+) {
+    let mut column = match out.rfind('\n') {
+        Some(i) => out[i + 1..].chars().count(),
+        None => out.chars().count(),
+    };
+    for t in tokens {
+        if *out_line < t.m_line {
+            sync_line(out, out_line, t.m_line);
+            column = 0;
+        }
+        if t.m_column > 0 && column >= t.m_column {
+            out.push('\n');
+            let line = state.presumed_line(t.m_line);
+            line_marker(out, line, &state.m_presumed_name, None);
+            column = 0;
+        }
+        if column + 1 < t.m_column {
+            while column + 1 < t.m_column {
+                out.push(' ');
+                column += 1;
+            }
+        } else if column > 0 && t.m_leading_space {
+            out.push(' ');
+            column += 1;
+        }
+        out.push_str(&t.m_text);
+        column += t.m_text.chars().count();
+    }
+}
+
+fn has_pragma_once(source: &str) -> bool {
+    for line in source.lines() {
+        let tokens = tokenize_line(line, 0);
+        if tokens.len() >= 3
+            && tokens[0].is("#")
+            && tokens[1].m_text == "pragma"
+            && tokens[2].m_text == "once"
+        {
+            return true;
+        }
+    }
+    return false;
+}
+
+// Replaces comments with spaces, keeping newlines so line numbers are
+// unchanged
+fn strip_comments(source: &str) -> String {
+    let mut out = String::new();
+    let mut chars = source.chars().peekable();
+    let mut quote: Option<char> = None;
+
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) => {
+                out.push(c);
+                if c == '\\' {
+                    match chars.next() {
+                        Some(n) => out.push(n),
+                        None => (),
+                    }
+                } else if c == q || c == '\n' {
+                    quote = None;
+                }
+            }
+            None => match (c, chars.peek()) {
+                ('/', Some('/')) => {
+                    while let Some(&n) = chars.peek() {
+                        if n == '\n' {
+                            break;
+                        }
+                        chars.next();
+                    }
+                    out.push(' ');
+                }
+                ('/', Some('*')) => {
+                    // Blanked rather than removed, so the code after it
+                    // keeps its column
+                    chars.next();
+                    out.push_str("  ");
+                    let mut last = ' ';
+                    while let Some(n) = chars.next() {
+                        match n {
+                            '\n' => out.push('\n'),
+                            _ => out.push(' '),
+                        }
+                        if last == '*' && n == '/' {
+                            break;
+                        }
+                        last = n;
+                    }
+                }
+                ('"', _) | ('\'', _) => {
+                    quote = Some(c);
+                    out.push(c);
+                }
+                _ => out.push(c),
+            },
+        }
+    }
+    return out;
+}
+
+// Splits the source into logical lines, joining backslash-newline
+// continuations. Each line is paired with its starting physical line number
+fn logical_lines(source: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    let mut start = 1;
+    for (i, line) in source.split('\n').enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if current.len() == 0 {
+            start = i + 1;
+        }
+        match line.strip_suffix('\\') {
+            Some(l) => current.push_str(l),
+            None => {
+                current.push_str(line);
+                lines.push((start, current.clone()));
+                current.clear();
+            }
+        }
+    }
+    if current.len() > 0 {
+        lines.push((start, current));
+    }
+    // A trailing newline produces one empty final line
+    match lines.last() {
+        Some((_, l)) if l.len() == 0 && source.ends_with('\n') => {
+            lines.pop();
+        }
+        _ => (),
+    }
+    return lines;
+}
+
+const PUNCTUATORS: [&str; 23] = [
+    "...", "<<=", ">>=", "##", "->", "++", "--", "<<", ">>", "<=", ">=", "==",
+    "!=", "&&", "||", "*=", "/=", "%=", "+=", "-=", "&=", "^=", "|=",
+];
+
+fn tokenize_line(line: &str, line_no: usize) -> Vec<PpToken> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut leading_space = true;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            leading_space = true;
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        let kind;
+        if c.is_ascii_alphabetic() || c == '_' {
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric() || chars[i] == '_')
+            {
+                i += 1;
+            }
+            kind = PpTokenKind::Identifier;
+        } else if c.is_ascii_digit()
+            || (c == '.'
+                && i + 1 < chars.len()
+                && chars[i + 1].is_ascii_digit())
+        {
+            i += 1;
+            while i < chars.len() {
+                let n = chars[i];
+                if (n == '+' || n == '-') && "eEpP".contains(chars[i - 1]) {
+                    i += 1;
+                } else if n.is_ascii_alphanumeric() || n == '_' || n == '.' {
+                    i += 1;
+                } else {
+                    break;
+                }
+            }
+            kind = PpTokenKind::Number;
+        } else if c == '"' || c == '\'' {
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                if chars[i] == '\\' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            i = (i + 1).min(chars.len());
+            kind = if c == '"' {
+                PpTokenKind::StringLiteral
+            } else {
+                PpTokenKind::CharLiteral
+            };
+        } else {
+            let rest: String =
+                chars[i..chars.len().min(i + 3)].iter().collect();
+            let len = match PUNCTUATORS.iter().find(|p| rest.starts_with(*p)) {
+                Some(p) => p.len(),
+                None => 1,
+            };
+            i += len;
+            kind = PpTokenKind::Punctuator;
+        }
+
+        tokens.push(PpToken {
+            m_kind: kind,
+            m_text: chars[start..i].iter().collect(),
+            m_leading_space: leading_space,
+            m_line: line_no,
+            m_column: start + 1,
+            m_hide_set: Vec::new(),
+        });
+        leading_space = false;
+    }
+
+    return tokens;
+}
+
+// A value in an #if expression, which has the type intmax_t or uintmax_t
+#[derive(Debug, Clone, Copy)]
+struct Value {
+    m_bits: i64,
+    m_unsigned: bool,
+}
+
+impl Value {
+    fn signed(bits: i64) -> Self {
+        return Value { m_bits: bits, m_unsigned: false };
+    }
+
+    fn truth(condition: bool) -> Self {
+        return Value::signed(condition as i64);
+    }
+
+    fn is_true(&self) -> bool {
+        return self.m_bits != 0;
+    }
+}
+
+// Evaluates #if expressions with the usual C precedence and conversions, where
+// an operand is unsigned if either is. Operands which && || and ?: skip are
+// parsed without being evaluated, so they can't fail
+struct ConditionEvaluator<'a> {
+    tokens: &'a [PpToken],
+    position: usize,
+    location: String,
+    // Inside an operand which isn't evaluated
+    unevaluated: bool,
+}
+
+impl<'a> ConditionEvaluator<'a> {
+    fn peek(&self) -> Option<&PpToken> {
+        return self.tokens.get(self.position);
+    }
+
+    fn accept(&mut self, text: &str) -> bool {
+        match self.peek() {
+            Some(t) if t.is(text) => {
+                self.position += 1;
+                return true;
+            }
+            _ => return false,
+        }
+    }
+
+    fn error(&self, message: &str) -> PreprocessError {
+        return PreprocessError::InvalidExpression(
+            self.location.clone(),
+            String::from(message),
+        );
+    }
+
+    // Parses an operand, evaluating it only if the flag says so
+    fn operand(
+        &mut self,
+        evaluated: bool,
+        parse: impl FnOnce(&mut Self) -> Result<Value, PreprocessError>,
+    ) -> Result<Value, PreprocessError> {
+        let outer = self.unevaluated;
+        self.unevaluated = outer || !evaluated;
+        let value = parse(self);
+        self.unevaluated = outer;
+        return value;
+    }
+
+    fn conditional(&mut self) -> Result<Value, PreprocessError> {
+        let condition = match self.binary(0) {
+            Ok(v) => v,
+            Err(e) => return Err(e),
+        };
+        if !self.accept("?") {
+            return Ok(condition);
+        }
+        let true_value =
+            match self.operand(condition.is_true(), Self::conditional) {
+                Ok(v) => v,
+                Err(e) => return Err(e),
+            };
+        if !self.accept(":") {
+            return Err(self.error("expected ':' in conditional expression"));
+        }
+        let false_value =
+            match self.operand(!condition.is_true(), Self::conditional) {
+                Ok(v) => v,
+                Err(e) => return Err(e),
+            };
+        let unsigned = true_value.m_unsigned || false_value.m_unsigned;
+        let chosen = if condition.is_true() { true_value } else { false_value };
+        return Ok(Value { m_bits: chosen.m_bits, m_unsigned: unsigned });
+    }
+
+    // Precedence climbing over the binary operators, lowest level first
+    fn binary(&mut self, level: usize) -> Result<Value, PreprocessError> {
+        const LEVELS: [&[&str]; 10] = [
+            &["||"],
+            &["&&"],
+            &["|"],
+            &["^"],
+            &["&"],
+            &["==", "!="],
+            &["<", ">", "<=", ">="],
+            &["<<", ">>"],
+            &["+", "-"],
+            &["*", "/", "%"],
+        ];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+
+        let mut left = match self.binary(level + 1) {
+            Ok(v) => v,
+            Err(e) => return Err(e),
+        };
+        loop {
+            let op = match self.peek() {
+                Some(t)
+                    if t.m_kind == PpTokenKind::Punctuator
+                        && LEVELS[level].contains(&t.m_text.as_str()) =>
+                {
+                    t.m_text.clone()
+                }
+                _ => return Ok(left),
+            };
+            self.position += 1;
+
+            // The right operand of && and || is only evaluated when the left
+            // one doesn't decide the result
+            let evaluated = match op.as_str() {
+                "||" => !left.is_true(),
+                "&&" => left.is_true(),
+                _ => true,
+            };
+            let right = match self.operand(evaluated, |e| e.binary(level + 1)) {
+                Ok(v) => v,
+                Err(e) => return Err(e),
+            };
+            left = match self.apply(&op, left, right) {
+                Ok(v) => v,
+                Err(e) => return Err(e),
+            };
+        }
+    }
+
+    fn apply(
+        &self,
+        op: &str,
+        left: Value,
+        right: Value,
+    ) -> Result<Value, PreprocessError> {
+        let unsigned = left.m_unsigned || right.m_unsigned;
+        let (l, r) = (left.m_bits, right.m_bits);
+        let (ul, ur) = (l as u64, r as u64);
+        let bits = match op {
+            "||" => return Ok(Value::truth(left.is_true() || right.is_true())),
+            "&&" => return Ok(Value::truth(left.is_true() && right.is_true())),
+            "==" => return Ok(Value::truth(l == r)),
+            "!=" => return Ok(Value::truth(l != r)),
+            "<" | ">" | "<=" | ">=" => {
+                let ordering = if unsigned { ul.cmp(&ur) } else { l.cmp(&r) };
+                let result = match op {
+                    "<" => ordering.is_lt(),
+                    ">" => ordering.is_gt(),
+                    "<=" => ordering.is_le(),
+                    _ => ordering.is_ge(),
+                };
+                return Ok(Value::truth(result));
+            }
+            // A shift has the type of its left operand
+            "<<" => {
+                let bits = l.wrapping_shl(r as u32);
+                return Ok(Value { m_bits: bits, m_unsigned: left.m_unsigned });
+            }
+            ">>" => {
+                let bits = if left.m_unsigned {
+                    ul.wrapping_shr(r as u32) as i64
+                } else {
+                    l.wrapping_shr(r as u32)
+                };
+                return Ok(Value { m_bits: bits, m_unsigned: left.m_unsigned });
+            }
+            "|" => l | r,
+            "^" => l ^ r,
+            "&" => l & r,
+            "+" => l.wrapping_add(r),
+            "-" => l.wrapping_sub(r),
+            "*" => l.wrapping_mul(r),
+            "/" | "%" => {
+                if r == 0 {
+                    if self.unevaluated {
+                        return Ok(Value { m_bits: 0, m_unsigned: unsigned });
+                    }
+                    return Err(self.error("division by zero in #if"));
+                }
+                match (op, unsigned) {
+                    ("/", true) => (ul / ur) as i64,
+                    ("/", false) => l.wrapping_div(r),
+                    (_, true) => (ul % ur) as i64,
+                    (_, false) => l.wrapping_rem(r),
+                }
+            }
+            _ => l,
+        };
+        return Ok(Value { m_bits: bits, m_unsigned: unsigned });
+    }
+
+    fn unary(&mut self) -> Result<Value, PreprocessError> {
+        for op in ["-", "+", "~", "!"] {
+            if self.accept(op) {
+                let value = match self.unary() {
+                    Ok(v) => v,
+                    Err(e) => return Err(e),
+                };
+                let bits = match op {
+                    "-" => value.m_bits.wrapping_neg(),
+                    "~" => !value.m_bits,
+                    "!" => return Ok(Value::truth(!value.is_true())),
+                    _ => value.m_bits,
+                };
+                return Ok(Value {
+                    m_bits: bits,
+                    m_unsigned: value.m_unsigned,
+                });
+            }
+        }
+
+        if self.accept("(") {
+            let value = match self.conditional() {
+                Ok(v) => v,
+                Err(e) => return Err(e),
+            };
+            if !self.accept(")") {
+                return Err(self.error("expected ')' in #if"));
+            }
+            return Ok(value);
+        }
+
+        let token = match self.peek() {
+            Some(t) => t.clone(),
+            None => return Err(self.error("expected value in #if")),
+        };
+        self.position += 1;
+        match token.m_kind {
+            // Identifiers remaining after expansion evaluate to 0
+            PpTokenKind::Identifier => return Ok(Value::signed(0)),
+            PpTokenKind::Number => match parse_integer(&token.m_text) {
+                Some(v) => return Ok(v),
+                None => {
+                    return Err(self.error(&format!(
+                        "invalid integer constant {} in #if",
+                        token.m_text
+                    )))
+                }
+            },
+            PpTokenKind::CharLiteral => {
+                let inner = unquote(&token.m_text.replace('\'', ""));
+                let c = inner.chars().next().map_or(0, |c| c as i64);
+                return Ok(Value::signed(c));
+            }
+            _ => {
+                return Err(self
+                    .error(&format!("unexpected '{}' in #if", token.m_text)))
+            }
+        }
+    }
+}
+
+// An integer constant is unsigned with a u suffix, or when it's too big for
+// intmax_t
+fn parse_integer(text: &str) -> Option<Value> {
+    let digits = text.trim_end_matches(|c| "uUlL".contains(c));
+    let suffix = &text[digits.len()..];
+    let (digits, radix) =
+        if digits.starts_with("0x") || digits.starts_with("0X") {
+            (&digits[2..], 16)
+        } else if digits.len() > 1 && digits.starts_with('0') {
+            (&digits[1..], 8)
+        } else {
+            (digits, 10)
+        };
+    let value = match u64::from_str_radix(digits, radix) {
+        Ok(v) => v,
+        Err(_) => return None,
+    };
+    return Some(Value {
+        m_bits: value as i64,
+        m_unsigned: suffix.contains(['u', 'U']) || value > i64::MAX as u64,
+    });
+}
@@ -0,0 +1,783 @@
+use std::collections::HashMap;
+
+use crate::allocator::Allocation;
+use crate::frame;
+use crate::ir::{
+    self, BinaryOp, CompareOp, ConvertOp, StaticValue, Temp, Terminator, Type,
+    UnaryOp,
+};
+use crate::token::Span;
+
+// Integer arguments go in a0 to a7 and floating ones in fa0 to fa7
+const ARGUMENT_REGISTERS: usize = 8;
+
+// ra and the caller's s0 are saved at the top of the frame. A variadic
+// function saves a0 to a7 above them instead, directly below any arguments
+// passed on the stack, so that all of its variable arguments are in one
+// array. Under the saved registers is the pointer to the next variable
+// argument, which its va_list points at
+const RECORD_SIZE: i32 = 16;
+const SAVE_AREA: i32 = -64;
+const VARIADIC_RECORD_SIZE: i32 = 80;
+const VA_LIST_STATE: i32 = -88;
+
+// Generates RV64GC assembly from the IR, following the LP64D calling
+// convention Linux uses: LP64 with floating arguments in the floating point
+// registers. Every temporary is kept in the frame; instructions load their
+// operands into t0 and t1, or ft0 and ft1 if they're floating, and store
+// the result back. s0 is the frame pointer, at the stack pointer the
+// function was entered with, and t6 is used to reach far offsets
+pub struct Generator {
+    lines: Vec<String>,
+    file_numbers: HashMap<String, usize>,
+    // The last .loc of the current function, which isn't repeated
+    last_location: Option<(usize, usize, usize)>,
+    function_name: String,
+    local_labels: usize,
+    temps: Vec<Type>,
+    layout: frame::FrameLayout,
+    // Bytes at the top of the frame holding ra and the caller's s0
+    record_size: i32,
+    // s0 offset of the space block arguments are copied through
+    copy_area: i32,
+    // For a variadic function, the s0 offset of its first variable argument
+    va_start_state: Option<i32>,
+}
+
+// Whether a load extends a narrow integer with its sign or with zeros
+#[derive(Clone, Copy, PartialEq)]
+enum Extend {
+    Sign,
+    Zero,
+}
+
+// Load instruction for an integer of the type, extended to 64 bits.
+// Floating values are loaded as their bits
+fn load_of(value_type: &Type, extend: Extend) -> &'static str {
+    match (value_type, extend) {
+        (Type::I8, Extend::Sign) => return "lb",
+        (Type::I8, Extend::Zero) => return "lbu",
+        (Type::I16, Extend::Sign) => return "lh",
+        (Type::I16, Extend::Zero) => return "lhu",
+        (Type::I32 | Type::F32, Extend::Sign) => return "lw",
+        (Type::I32 | Type::F32, Extend::Zero) => return "lwu",
+        (Type::I64 | Type::F64, _) => return "ld",
+    }
+}
+
+// Store instruction for the bits of a value of the type
+fn store_of(value_type: &Type) -> &'static str {
+    match value_type.size() {
+        1 => return "sb",
+        2 => return "sh",
+        4 => return "sw",
+        _ => return "sd",
+    }
+}
+
+// Suffix of floating instructions for the type
+fn float_suffix(value_type: &Type) -> &'static str {
+    match value_type {
+        Type::F32 => return "s",
+        _ => return "d",
+    }
+}
+
+// Suffix of integer instructions on the low 32 bits for narrower types,
+// which give a sign extended result
+fn word_suffix(value_type: &Type) -> &'static str {
+    match value_type {
+        Type::I64 => return "",
+        _ => return "w",
+    }
+}
+
+fn fits_immediate(value: i64) -> bool {
+    return (-2048..2048).contains(&value);
+}
+
+// Storage for a variable with static storage duration
+fn static_data(data: &ir::Data) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    if data.m_global {
+        lines.push(format!("\t.globl\t{}", data.m_name));
+    }
+    let bytes = data.m_type.size();
+    // Addresses are relocated when a position independent program is
+    // loaded, so are kept writable, as gcc does in .data.rel.ro
+    let section = match data.m_value {
+        Some(StaticValue::Constant(_)) if data.m_read_only => {
+            ".section\t.rodata"
+        }
+        Some(_) => ".data",
+        None => ".bss",
+    };
+    lines.push(format!("\t{}", section));
+    lines.push(format!("\t.balign\t{}", bytes));
+    lines.push(format!("{}:", data.m_name));
+    let directive = match bytes {
+        1 => ".byte",
+        2 => ".half",
+        4 => ".word",
+        _ => ".dword",
+    };
+    match &data.m_value {
+        Some(StaticValue::Constant(v)) => {
+            lines.push(format!("\t{}\t{}", directive, v))
+        }
+        Some(StaticValue::Address(symbol, offset)) => lines.push(format!(
+            "\t{}\t{}",
+            directive,
+            ir::address_text(symbol, *offset)
+        )),
+        None => lines.push(format!("\t.zero\t{}", bytes)),
+    }
+    return lines;
+}
+
+impl Generator {
+    pub fn new() -> Self {
+        Generator {
+            lines: Vec::new(),
+            file_numbers: HashMap::new(),
+            last_location: None,
+            function_name: String::new(),
+            local_labels: 0,
+            temps: Vec::new(),
+            layout: frame::FrameLayout {
+                m_slots: Vec::new(),
+                m_temps: Vec::new(),
+                m_saves: Vec::new(),
+                m_size: 0,
+            },
+            record_size: 0,
+            copy_area: 0,
+            va_start_state: None,
+        }
+    }
+
+    fn emit(&mut self, instruction: String) {
+        self.lines.push(format!("\t{}", instruction));
+    }
+
+    fn label(&mut self, label: String) {
+        self.lines.push(format!("{}:", label));
+    }
+
+    fn generate_loc(&mut self, span: &Span) {
+        let file_number = match self.file_numbers.get(&*span.m_file) {
+            Some(&n) => n,
+            None => panic!("No .file for {}", span.m_file),
+        };
+        let location = (file_number, span.m_line, span.m_column);
+        if self.last_location == Some(location) {
+            return;
+        }
+        self.last_location = Some(location);
+        self.lines.push(format!(
+            "\t.loc\t{} {} {}",
+            file_number, span.m_line, span.m_column
+        ));
+    }
+
+    fn block_label(&self, block: usize) -> String {
+        return format!(".L{}.{}", self.function_name, block);
+    }
+
+    fn local_label(&mut self) -> String {
+        self.local_labels += 1;
+        return format!(".L{}.local{}", self.function_name, self.local_labels);
+    }
+
+    // destination = source + value. Immediates only reach 12 bits, so
+    // larger values go through t6
+    fn add_immediate(&mut self, destination: &str, source: &str, value: i64) {
+        if fits_immediate(value) {
+            self.emit(format!("addi\t{}, {}, {}", destination, source, value));
+            return;
+        }
+        self.emit(format!("li\tt6, {}", value));
+        self.emit(format!("add\t{}, {}, t6", destination, source));
+    }
+
+    // Address operand of the bytes at an offset from s0, going through t6
+    // when it's too far for a load or store to reach
+    fn frame(&mut self, offset: i32) -> String {
+        if fits_immediate(offset as i64) {
+            return format!("{}(s0)", offset);
+        }
+        self.add_immediate("t6", "s0", offset as i64);
+        return String::from("0(t6)");
+    }
+
+    fn home(&mut self, temp: Temp) -> String {
+        match self.layout.m_temps[temp] {
+            Some(offset) => return self.frame(offset),
+            None => panic!("Temporary {} has no home in the frame", temp),
+        }
+    }
+
+    // Loads an integer temporary into a register, extended to 64 bits.
+    // Floating values are loaded as their bits
+    fn load_integer(&mut self, temp: Temp, register: &str, extend: Extend) {
+        let mnemonic = load_of(&self.temps[temp], extend);
+        let home = self.home(temp);
+        self.emit(format!("{}\t{}, {}", mnemonic, register, home));
+    }
+
+    fn load_float(&mut self, temp: Temp, register: &str) {
+        let suffix = match self.temps[temp] {
+            Type::F32 => "w",
+            _ => "d",
+        };
+        let home = self.home(temp);
+        self.emit(format!("fl{}\t{}, {}", suffix, register, home));
+    }
+
+    // Stores the bits of the temporary's type from an integer register
+    fn store_bits(&mut self, temp: Temp, register: &str) {
+        let mnemonic = store_of(&self.temps[temp]);
+        let home = self.home(temp);
+        self.emit(format!("{}\t{}, {}", mnemonic, register, home));
+    }
+
+    fn store_float(&mut self, temp: Temp, register: &str) {
+        let suffix = match self.temps[temp] {
+            Type::F32 => "w",
+            _ => "d",
+        };
+        let home = self.home(temp);
+        self.emit(format!("fs{}\t{}, {}", suffix, register, home));
+    }
+
+    // Stores t0, or ft0 if the temporary is floating
+    fn store(&mut self, temp: Temp) {
+        match self.temps[temp].is_floating() {
+            true => self.store_float(temp, "ft0"),
+            false => self.store_bits(temp, "t0"),
+        }
+    }
+
+    pub fn generate(&mut self, program: &ir::Program) -> String {
+        for (i, file) in program.source_files().into_iter().enumerate() {
+            self.lines.push(format!("\t.file\t{} \"{}\"", i + 1, file));
+            self.file_numbers.insert(file, i + 1);
+        }
+        for function in &program.m_functions {
+            self.generate_function(function);
+        }
+        for data in &program.m_data {
+            self.lines.extend(static_data(data));
+        }
+        let mut text = std::mem::take(&mut self.lines).join("\n");
+        text.push('\n');
+        return text;
+    }
+
+    fn generate_function(&mut self, function: &ir::Function) {
+        self.function_name = function.m_name.clone();
+        self.local_labels = 0;
+        self.last_location = None;
+        self.temps = function.m_temps.clone();
+
+        // Block arguments are copied through the frame, below the saved
+        // registers and the va_list state
+        let mut copied = 0;
+        for block in &function.m_blocks {
+            match &block.m_terminator {
+                Terminator::Jump { m_arguments, .. } => {
+                    copied = copied.max(m_arguments.len() as i32)
+                }
+                _ => (),
+            }
+        }
+        let top = match function.m_variadic {
+            true => {
+                self.record_size = VARIADIC_RECORD_SIZE;
+                -VA_LIST_STATE
+            }
+            false => {
+                self.record_size = RECORD_SIZE;
+                RECORD_SIZE
+            }
+        };
+        self.copy_area = -(top + 8 * copied);
+        let allocation = Allocation {
+            m_registers: vec![None; function.m_temps.len()],
+            m_saved_across: HashMap::new(),
+        };
+        self.layout =
+            frame::layout_function(function, &allocation, top + 8 * copied);
+
+        self.lines.push(String::from("\t.text"));
+        if function.m_global {
+            self.lines.push(format!("\t.globl\t{}", function.m_name));
+        }
+        self.lines.push(String::from("\t.p2align\t2"));
+        self.lines.push(format!("\t.type\t{}, @function", function.m_name));
+        self.label(function.m_name.clone());
+        let record = self.record_size;
+        self.emit(format!("addi\tsp, sp, -{}", record));
+        self.emit(String::from("sd\tra, 8(sp)"));
+        self.emit(String::from("sd\ts0, 0(sp)"));
+        self.emit(format!("addi\ts0, sp, {}", record));
+        self.generate_loc(&function.m_span);
+        if self.layout.m_size > record {
+            self.add_immediate(
+                "sp",
+                "sp",
+                -(self.layout.m_size - record) as i64,
+            );
+        }
+        if function.m_variadic {
+            for i in 0..ARGUMENT_REGISTERS {
+                self.emit(format!(
+                    "sd\ta{}, {}(s0)",
+                    i,
+                    SAVE_AREA + 8 * i as i32
+                ));
+            }
+        }
+
+        // Floating parameters past fa7 take the integer registers, and
+        // parameters past those are on the stack from s0, 8 bytes each
+        let mut stack_offset = 0;
+        let mut int_index = 0;
+        let mut float_index = 0;
+        for parameter in &function.m_parameters {
+            if self.temps[*parameter].is_floating()
+                && float_index < ARGUMENT_REGISTERS
+            {
+                self.store_float(*parameter, &format!("fa{}", float_index));
+                float_index += 1;
+            } else if int_index < ARGUMENT_REGISTERS {
+                self.store_bits(*parameter, &format!("a{}", int_index));
+                int_index += 1;
+            } else {
+                let slot = self.frame(stack_offset);
+                self.emit(format!("ld\tt0, {}", slot));
+                self.store_bits(*parameter, "t0");
+                stack_offset += 8;
+            }
+        }
+        self.va_start_state = match function.m_variadic {
+            true if int_index < ARGUMENT_REGISTERS => {
+                Some(SAVE_AREA + 8 * int_index as i32)
+            }
+            true => Some(stack_offset),
+            false => None,
+        };
+
+        for (i, block) in function.m_blocks.iter().enumerate() {
+            if i > 0 {
+                self.label(self.block_label(i));
+            }
+            for instruction in &block.m_instructions {
+                self.generate_instruction(instruction);
+            }
+            self.generate_terminator(function, &block.m_terminator, i + 1);
+        }
+        self.lines.push(format!(
+            "\t.size\t{}, .-{}",
+            function.m_name, function.m_name
+        ));
+    }
+
+    fn generate_terminator(
+        &mut self,
+        function: &ir::Function,
+        terminator: &Terminator,
+        next_block: usize,
+    ) {
+        match terminator {
+            Terminator::Jump { m_target, m_arguments } => {
+                // A parameter may also be an argument, so every argument is
+                // copied out before any parameter is written
+                let parameters = &function.m_blocks[*m_target].m_parameters;
+                for (i, argument) in m_arguments.iter().enumerate() {
+                    self.load_integer(*argument, "t0", Extend::Zero);
+                    let slot = self.frame(self.copy_area + 8 * i as i32);
+                    self.emit(format!("sd\tt0, {}", slot));
+                }
+                for (i, parameter) in parameters.iter().enumerate() {
+                    let slot = self.frame(self.copy_area + 8 * i as i32);
+                    self.emit(format!("ld\tt0, {}", slot));
+                    self.store_bits(*parameter, "t0");
+                }
+                if *m_target != next_block {
+                    self.emit(format!("j\t{}", self.block_label(*m_target)));
+                }
+            }
+            // Conditional branches only reach 4KiB, so they skip over a
+            // jump to the block instead
+            Terminator::Branch { m_condition, m_true, m_false } => {
+                self.load_integer(*m_condition, "t0", Extend::Zero);
+                let skip = self.local_label();
+                self.emit(format!("beqz\tt0, {}", skip));
+                self.emit(format!("j\t{}", self.block_label(*m_true)));
+                self.label(skip);
+                if *m_false != next_block {
+                    self.emit(format!("j\t{}", self.block_label(*m_false)));
+                }
+            }
+            Terminator::Return { m_value } => {
+                match self.temps[*m_value].is_floating() {
+                    true => self.load_float(*m_value, "fa0"),
+                    false => self.load_integer(*m_value, "a0", Extend::Sign),
+                }
+                let record = self.record_size;
+                self.emit(format!("ld\tra, {}(s0)", 8 - record));
+                self.emit(format!("ld\tt0, {}(s0)", -record));
+                self.emit(String::from("mv\tsp, s0"));
+                self.emit(String::from("mv\ts0, t0"));
+                self.emit(String::from("ret"));
+            }
+        }
+    }
+
+    fn generate_instruction(&mut self, instruction: &ir::Instruction) {
+        match instruction {
+            // Floating constants are moved as their bits
+            ir::Instruction::Constant { m_dest, m_type: _, m_value } => {
+                self.emit(format!("li\tt0, {}", m_value));
+                self.store_bits(*m_dest, "t0");
+            }
+            ir::Instruction::SlotAddress { m_dest, m_slot } => {
+                let offset = self.layout.m_slots[*m_slot];
+                self.add_immediate("t0", "s0", offset as i64);
+                self.store(*m_dest);
+            }
+            // Always relative to the instruction, whether or not the code is
+            // position independent
+            ir::Instruction::GlobalAddress { m_dest, m_name } => {
+                self.emit(format!("lla\tt0, {}", m_name));
+                self.store(*m_dest);
+            }
+            // Each access is one instruction, so volatile ones need nothing
+            // more. Floating values are moved as their bits
+            ir::Instruction::Load {
+                m_dest,
+                m_type,
+                m_address,
+                m_volatile: _,
+            } => {
+                self.load_integer(*m_address, "t1", Extend::Zero);
+                let mnemonic = load_of(m_type, Extend::Zero);
+                self.emit(format!("{}\tt0, 0(t1)", mnemonic));
+                self.store_bits(*m_dest, "t0");
+            }
+            ir::Instruction::Store {
+                m_type,
+                m_address,
+                m_value,
+                m_volatile: _,
+            } => {
+                self.load_integer(*m_address, "t1", Extend::Zero);
+                self.load_integer(*m_value, "t0", Extend::Zero);
+                self.emit(format!("{}\tt0, 0(t1)", store_of(m_type)));
+            }
+            ir::Instruction::Unary { m_dest, m_op, m_type, m_operand } => {
+                if m_type.is_floating() {
+                    self.load_float(*m_operand, "ft0");
+                    self.emit(format!(
+                        "fneg.{}\tft0, ft0",
+                        float_suffix(m_type)
+                    ));
+                } else {
+                    self.load_integer(*m_operand, "t0", Extend::Zero);
+                    match m_op {
+                        UnaryOp::Negate => self.emit(format!(
+                            "neg{}\tt0, t0",
+                            word_suffix(m_type)
+                        )),
+                        UnaryOp::Not => self.emit(String::from("not\tt0, t0")),
+                    }
+                }
+                self.store(*m_dest);
+            }
+            ir::Instruction::Binary {
+                m_dest,
+                m_op,
+                m_type,
+                m_left,
+                m_right,
+            } => {
+                self.generate_binary(*m_op, m_type, *m_left, *m_right);
+                self.store(*m_dest);
+            }
+            ir::Instruction::Compare {
+                m_dest,
+                m_op,
+                m_type,
+                m_left,
+                m_right,
+            } => {
+                self.generate_compare(*m_op, m_type, *m_left, *m_right);
+                self.store(*m_dest);
+            }
+            ir::Instruction::Convert {
+                m_dest,
+                m_op,
+                m_from,
+                m_to,
+                m_operand,
+            } => {
+                self.generate_convert(*m_op, m_from, m_to, *m_operand);
+                self.store(*m_dest);
+            }
+            ir::Instruction::Call {
+                m_dest,
+                m_function,
+                m_arguments,
+                m_variadic,
+            } => {
+                self.generate_call(m_function, m_arguments, *m_variadic);
+                match self.temps[*m_dest].is_floating() {
+                    true => self.store_float(*m_dest, "fa0"),
+                    false => self.store_bits(*m_dest, "a0"),
+                }
+            }
+            // The va_list holds the address of the pointer to the next
+            // variable argument, so that va_arg can move it on
+            ir::Instruction::VaStart { m_list } => {
+                let first = match self.va_start_state {
+                    Some(s) => s,
+                    None => panic!("va_start outside a variadic function"),
+                };
+                self.add_immediate("t0", "s0", first as i64);
+                self.emit(format!("sd\tt0, {}(s0)", VA_LIST_STATE));
+                self.emit(format!("addi\tt0, s0, {}", VA_LIST_STATE));
+                self.load_integer(*m_list, "t1", Extend::Zero);
+                self.emit(String::from("sd\tt0, 0(t1)"));
+            }
+            // Variable arguments are all passed as 8 bytes, floating ones
+            // as their bits
+            ir::Instruction::VaArg { m_dest, m_type, m_list } => {
+                self.load_integer(*m_list, "t1", Extend::Zero);
+                self.emit(String::from("ld\tt2, 0(t1)"));
+                let mnemonic = load_of(m_type, Extend::Zero);
+                self.emit(format!("{}\tt0, 0(t2)", mnemonic));
+                self.emit(String::from("addi\tt2, t2, 8"));
+                self.emit(String::from("sd\tt2, 0(t1)"));
+                self.store_bits(*m_dest, "t0");
+            }
+            ir::Instruction::Location { m_span } => {
+                self.generate_loc(m_span);
+            }
+        }
+    }
+
+    // Leaves the result in t0 or ft0
+    fn generate_binary(
+        &mut self,
+        op: BinaryOp,
+        operand_type: &Type,
+        left: Temp,
+        right: Temp,
+    ) {
+        if operand_type.is_floating() {
+            self.load_float(left, "ft0");
+            self.load_float(right, "ft1");
+            let mnemonic = match op {
+                BinaryOp::Add => "fadd",
+                BinaryOp::Subtract => "fsub",
+                BinaryOp::Multiply => "fmul",
+                _ => "fdiv",
+            };
+            self.emit(format!(
+                "{}.{}\tft0, ft0, ft1",
+                mnemonic,
+                float_suffix(operand_type)
+            ));
+            return;
+        }
+        // Narrow operands of a division are extended by their signedness
+        let extend = match op {
+            BinaryOp::Divide | BinaryOp::Remainder => Extend::Sign,
+            _ => Extend::Zero,
+        };
+        self.load_integer(left, "t0", extend);
+        self.load_integer(right, "t1", extend);
+        let mnemonic = match op {
+            BinaryOp::Add => "add",
+            BinaryOp::Subtract => "sub",
+            BinaryOp::Multiply => "mul",
+            BinaryOp::Divide => "div",
+            BinaryOp::UnsignedDivide => "divu",
+            BinaryOp::Remainder => "rem",
+            BinaryOp::UnsignedRemainder => "remu",
+        };
+        self.emit(format!(
+            "{}{}\tt0, t0, t1",
+            mnemonic,
+            word_suffix(operand_type)
+        ));
+    }
+
+    // Leaves 0 or 1 in t0
+    fn generate_compare(
+        &mut self,
+        op: CompareOp,
+        operand_type: &Type,
+        left: Temp,
+        right: Temp,
+    ) {
+        if operand_type.is_floating() {
+            // Each compare is false when an operand is NaN, so != is the
+            // opposite of ==
+            self.load_float(left, "ft0");
+            self.load_float(right, "ft1");
+            let suffix = float_suffix(operand_type);
+            let (mnemonic, first, second) = match op {
+                CompareOp::Less => ("flt", "ft0", "ft1"),
+                CompareOp::LessOrEqual => ("fle", "ft0", "ft1"),
+                CompareOp::Greater => ("flt", "ft1", "ft0"),
+                CompareOp::GreaterOrEqual => ("fle", "ft1", "ft0"),
+                _ => ("feq", "ft0", "ft1"),
+            };
+            self.emit(format!(
+                "{}.{}\tt0, {}, {}",
+                mnemonic, suffix, first, second
+            ));
+            if op == CompareOp::NotEqual {
+                self.emit(String::from("xori\tt0, t0, 1"));
+            }
+            return;
+        }
+        // Values are compared as 64 bits, extended by the signedness of the
+        // compare. Sign extending 32 bit values keeps their unsigned order
+        let extend = match op {
+            CompareOp::UnsignedLess
+            | CompareOp::UnsignedLessOrEqual
+            | CompareOp::UnsignedGreater
+            | CompareOp::UnsignedGreaterOrEqual => Extend::Zero,
+            _ => Extend::Sign,
+        };
+        self.load_integer(left, "t0", extend);
+        self.load_integer(right, "t1", extend);
+        // a <= b is !(b < a), and a >= b is !(a < b)
+        let (instruction, inverted) = match op {
+            CompareOp::Equal => ("sub\tt0, t0, t1\n\tseqz\tt0, t0", false),
+            CompareOp::NotEqual => ("sub\tt0, t0, t1\n\tsnez\tt0, t0", false),
+            CompareOp::Less => ("slt\tt0, t0, t1", false),
+            CompareOp::LessOrEqual => ("slt\tt0, t1, t0", true),
+            CompareOp::Greater => ("slt\tt0, t1, t0", false),
+            CompareOp::GreaterOrEqual => ("slt\tt0, t0, t1", true),
+            CompareOp::UnsignedLess => ("sltu\tt0, t0, t1", false),
+            CompareOp::UnsignedLessOrEqual => ("sltu\tt0, t1, t0", true),
+            CompareOp::UnsignedGreater => ("sltu\tt0, t1, t0", false),
+            CompareOp::UnsignedGreaterOrEqual => ("sltu\tt0, t0, t1", true),
+        };
+        self.emit(String::from(instruction));
+        if inverted {
+            self.emit(String::from("xori\tt0, t0, 1"));
+        }
+    }
+
+    // Leaves the result in t0 or ft0
+    fn generate_convert(
+        &mut self,
+        op: ConvertOp,
+        from: &Type,
+        to: &Type,
+        operand: Temp,
+    ) {
+        match op {
+            ConvertOp::SignExtend => {
+                self.load_integer(operand, "t0", Extend::Sign)
+            }
+            // The low bytes already hold the narrower value
+            ConvertOp::ZeroExtend | ConvertOp::Truncate => {
+                self.load_integer(operand, "t0", Extend::Zero)
+            }
+            ConvertOp::SignedToFloat => {
+                self.load_integer(operand, "t0", Extend::Sign);
+                self.emit(format!("fcvt.{}.l\tft0, t0", float_suffix(to)));
+            }
+            ConvertOp::UnsignedToFloat => {
+                self.load_integer(operand, "t0", Extend::Zero);
+                self.emit(format!("fcvt.{}.lu\tft0, t0", float_suffix(to)));
+            }
+            // Rounding toward zero, not by the dynamic rounding mode
+            ConvertOp::FloatToSigned => {
+                self.load_float(operand, "ft0");
+                self.emit(format!(
+                    "fcvt.l.{}\tt0, ft0, rtz",
+                    float_suffix(from)
+                ));
+            }
+            ConvertOp::FloatToUnsigned => {
+                self.load_float(operand, "ft0");
+                self.emit(format!(
+                    "fcvt.lu.{}\tt0, ft0, rtz",
+                    float_suffix(from)
+                ));
+            }
+            ConvertOp::FloatToFloat => {
+                self.load_float(operand, "ft0");
+                self.emit(format!(
+                    "fcvt.{}.{}\tft0, ft0",
+                    float_suffix(to),
+                    float_suffix(from)
+                ));
+            }
+        }
+    }
+
+    // LP64D call: integer arguments go in a0 to a7 and floating ones in fa0
+    // to fa7, then in whichever integer registers are left, then on the
+    // stack in 8 bytes each with the first at the lowest address. Variable
+    // arguments are passed as integers. 32 bit integers are passed sign
+    // extended. Leaves the result in a0 or fa0
+    fn generate_call(
+        &mut self,
+        id: &String,
+        arguments: &Vec<Temp>,
+        variadic: Option<usize>,
+    ) {
+        let mut int_count = 0;
+        let mut float_count = 0;
+        // The register each argument goes in, if any
+        let mut registers: Vec<Option<String>> = Vec::new();
+        for (i, argument) in arguments.iter().enumerate() {
+            let fixed = match variadic {
+                Some(count) => i < count,
+                None => true,
+            };
+            if fixed
+                && self.temps[*argument].is_floating()
+                && float_count < ARGUMENT_REGISTERS
+            {
+                registers.push(Some(format!("fa{}", float_count)));
+                float_count += 1;
+            } else if int_count < ARGUMENT_REGISTERS {
+                registers.push(Some(format!("a{}", int_count)));
+                int_count += 1;
+            } else {
+                registers.push(None);
+            }
+        }
+
+        let stack_arguments = registers.iter().filter(|r| r.is_none()).count();
+        let stack_size = (8 * stack_arguments as i64 + 15) / 16 * 16;
+        if stack_size > 0 {
+            self.add_immediate("sp", "sp", -stack_size);
+        }
+        let mut stack_offset = 0;
+        for (i, argument) in arguments.iter().enumerate() {
+            match &registers[i] {
+                Some(r) if r.starts_with('f') => self.load_float(*argument, r),
+                Some(r) => self.load_integer(*argument, r, Extend::Sign),
+                None => {
+                    self.load_integer(*argument, "t0", Extend::Sign);
+                    self.emit(format!("sd\tt0, {}(sp)", stack_offset));
+                    stack_offset += 8;
+                }
+            }
+        }
+        self.emit(format!("call\t{}", id));
+        if stack_size > 0 {
+            self.add_immediate("sp", "sp", stack_size);
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use crate::assembly::{
+    Directive, Instruction, IntegerOp, Line, Operand, Register, Section, Size,
+};
+use crate::elf::Object;
+use crate::encoder;
+
+const SYS_EXIT: i64 = 60;
+
+// The entry point of programs linked without a C library. The kernel starts
+// _start with argc at the top of the stack and argv after it, and nothing
+// to return to, so main's result goes to the exit system call
+pub fn start() -> Vec<Line> {
+    let rsp = Operand::Memory { m_base: Register::Rsp, m_offset: 0 };
+    let argv = Operand::Memory { m_base: Register::Rsp, m_offset: 8 };
+    let instructions = vec![
+        // Marks the outermost frame for debuggers
+        Instruction::Integer(
+            IntegerOp::Xor,
+            Size::Long,
+            Operand::Register(Register::Rbp),
+            Operand::Register(Register::Rbp),
+        ),
+        Instruction::Mov(Size::Quad, rsp, Operand::Register(Register::Rdi)),
+        Instruction::Lea(argv, Operand::Register(Register::Rsi)),
+        Instruction::Integer(
+            IntegerOp::And,
+            Size::Quad,
+            Operand::Immediate(-16),
+            Operand::Register(Register::Rsp),
+        ),
+        Instruction::Call(String::from("main")),
+        Instruction::Mov(
+            Size::Long,
+            Operand::Register(Register::Rax),
+            Operand::Register(Register::Rdi),
+        ),
+        Instruction::Mov(
+            Size::Long,
+            Operand::Immediate(SYS_EXIT),
+            Operand::Register(Register::Rax),
+        ),
+        Instruction::SystemCall,
+    ];
+
+    let mut lines = vec![
+        Line::Directive(Directive::Section(Section::Text)),
+        Line::Directive(Directive::Global(String::from("_start"))),
+        Line::Label(String::from("_start")),
+    ];
+    lines.extend(instructions.into_iter().map(Line::Instruction));
+    return lines;
+}
+
+pub fn object() -> Object {
+    return encoder::encode(&start());
+}
@@ -1,3 +1,21 @@
+use std::fmt;
+use std::rc::Rc;
+
+// Where a token came from, after applying #line directives and preprocessor
+// line markers
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub m_file: Rc<str>,
+    pub m_line: usize,
+    pub m_column: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.m_file, self.m_line, self.m_column)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Token {
     KeywordInt,
@@ -8,6 +26,30 @@ pub enum Token {
     CloseBrace,
     KeywordReturn,
     IntLiteral(i32),
+    LongLiteral(i64),
+    UnsignedLiteral(u32),
+    UnsignedLongLiteral(u64),
+    FloatLiteral(f32),
+    DoubleLiteral(f64),
+    KeywordFloat,
+    KeywordDouble,
+    KeywordChar,
+    KeywordShort,
+    KeywordLong,
+    KeywordSigned,
+    KeywordUnsigned,
+    KeywordSizeof,
+    KeywordAlignof,
+    KeywordVaList,
+    KeywordVaStart,
+    KeywordVaArg,
+    KeywordVaEnd,
+    KeywordStatic,
+    KeywordExtern,
+    KeywordAuto,
+    KeywordRegister,
+    KeywordConst,
+    KeywordVolatile,
     SemiColon,
     OperatorMinus,
     OperatorComplement,
@@ -17,6 +59,7 @@ pub enum Token {
     OperatorDivision,
     OperatorModulo,
     OperatorAnd,
+    OperatorAmpersand,
     OperatorOr,
     OperatorEqual,
     OperatorNotEqual,
@@ -36,6 +79,7 @@ pub enum Token {
     KeywordBreak,
     KeywordContinue,
     Comma,
+    Ellipsis,
 }
 
 // // Incomplete debug formatter
@@ -0,0 +1,281 @@
+use crate::parser::{
+    AdditiveExpression, ConditionalExpression, EqualityExpression, Expression,
+    Factor, LogicalAndExpression, LogicalOrExpresson, Qualifiers,
+    RelationalExpression, Term, UnaryOperator, VarType,
+};
+
+// Type information needed to work out the type of an expression. Implemented
+// by both the analyser and the generator over their own scopes
+pub trait TypeContext {
+    fn variable_type(&self, name: &String) -> Option<VarType>;
+    fn function_type(&self, name: &String) -> Option<VarType>;
+
+    // Only needed where qualifiers are checked
+    fn variable_qualifiers(&self, _name: &String) -> Qualifiers {
+        return Qualifiers::default();
+    }
+}
+
+// Size and alignment in bytes of a value of the type in memory, following
+// the x86-64 System V ABI
+fn layout(var_type: &VarType) -> (i64, i64) {
+    match var_type {
+        VarType::Char | VarType::SChar | VarType::UChar => return (1, 1),
+        VarType::Short | VarType::UShort => return (2, 2),
+        VarType::Int | VarType::UInt | VarType::Float => return (4, 4),
+        VarType::Long
+        | VarType::ULong
+        | VarType::Double
+        | VarType::Pointer(_, _)
+        | VarType::VaList => return (8, 8),
+    }
+}
+
+pub fn size_of(var_type: &VarType) -> i64 {
+    return layout(var_type).0;
+}
+
+pub fn align_of(var_type: &VarType) -> i64 {
+    return layout(var_type).1;
+}
+
+// Integer conversion rank, where types of the same size share a rank
+fn integer_rank(var_type: &VarType) -> i64 {
+    return size_of(var_type);
+}
+
+// The integer promotions: anything narrower than int is used as an int
+pub fn promote(var_type: &VarType) -> VarType {
+    if var_type.is_integer() && integer_rank(var_type) < 4 {
+        return VarType::Int;
+    }
+    return var_type.clone();
+}
+
+// The usual arithmetic conversions for a binary operator
+pub fn common_type(a: &VarType, b: &VarType) -> VarType {
+    if a.is_pointer() {
+        return a.clone();
+    }
+    if b.is_pointer() {
+        return b.clone();
+    }
+    if *a == VarType::Double || *b == VarType::Double {
+        return VarType::Double;
+    }
+    if *a == VarType::Float || *b == VarType::Float {
+        return VarType::Float;
+    }
+
+    let a = promote(a);
+    let b = promote(b);
+    if a == b {
+        return a;
+    }
+    let (a_rank, b_rank) = (integer_rank(&a), integer_rank(&b));
+    if a.is_unsigned() == b.is_unsigned() {
+        return if a_rank > b_rank { a } else { b };
+    }
+    let (unsigned, signed) = if a.is_unsigned() { (a, b) } else { (b, a) };
+    // A wider signed type holds every value of the unsigned one
+    if integer_rank(&unsigned) >= integer_rank(&signed) {
+        return unsigned;
+    }
+    return signed;
+}
+
+pub fn expression_type(
+    ctx: &dyn TypeContext,
+    expression: &Expression,
+) -> VarType {
+    match expression {
+        Expression::Assignment { m_target, m_value: _, m_span: _ } => {
+            return factor_type(ctx, m_target)
+        }
+        Expression::Operation(c) => return conditional_type(ctx, c),
+    }
+}
+
+pub fn conditional_type(
+    ctx: &dyn TypeContext,
+    conditional_expression: &ConditionalExpression,
+) -> VarType {
+    match (&conditional_expression.m_true, &conditional_expression.m_false) {
+        (Some(t), Some(f)) => {
+            return common_type(
+                &expression_type(ctx, t),
+                &conditional_type(ctx, f),
+            )
+        }
+        _ => return logical_or_type(ctx, &conditional_expression.m_condition),
+    }
+}
+
+pub fn logical_or_type(
+    ctx: &dyn TypeContext,
+    logical_or_expression: &LogicalOrExpresson,
+) -> VarType {
+    if logical_or_expression.m_rest.len() > 0 {
+        return VarType::Int;
+    }
+    return logical_and_type(ctx, &logical_or_expression.m_first);
+}
+
+pub fn logical_and_type(
+    ctx: &dyn TypeContext,
+    logical_and_expression: &LogicalAndExpression,
+) -> VarType {
+    if logical_and_expression.m_rest.len() > 0 {
+        return VarType::Int;
+    }
+    return equality_type(ctx, &logical_and_expression.m_first);
+}
+
+pub fn equality_type(
+    ctx: &dyn TypeContext,
+    equality_expression: &EqualityExpression,
+) -> VarType {
+    if equality_expression.m_rest.len() > 0 {
+        return VarType::Int;
+    }
+    return relational_type(ctx, &equality_expression.m_first);
+}
+
+pub fn relational_type(
+    ctx: &dyn TypeContext,
+    relational_expression: &RelationalExpression,
+) -> VarType {
+    if relational_expression.m_rest.len() > 0 {
+        return VarType::Int;
+    }
+    return additive_type(ctx, &relational_expression.m_first);
+}
+
+pub fn additive_type(
+    ctx: &dyn TypeContext,
+    additive_expression: &AdditiveExpression,
+) -> VarType {
+    let mut var_type = term_type(ctx, &additive_expression.m_first_term);
+    for next in &additive_expression.m_rest {
+        let right_type = term_type(ctx, &next.1);
+        // The difference of two pointers is a number of elements
+        if var_type.is_pointer() && right_type.is_pointer() {
+            var_type = VarType::Long;
+        } else {
+            var_type = common_type(&var_type, &right_type);
+        }
+    }
+    return var_type;
+}
+
+pub fn term_type(ctx: &dyn TypeContext, term: &Term) -> VarType {
+    let mut var_type = factor_type(ctx, &term.m_first_factor);
+    for next in &term.m_rest {
+        var_type = common_type(&var_type, &factor_type(ctx, &next.1));
+    }
+    return var_type;
+}
+
+pub fn factor_type(ctx: &dyn TypeContext, factor: &Factor) -> VarType {
+    match factor {
+        Factor::FunCall { m_id, m_arguments: _, m_span: _ } => {
+            return ctx.function_type(m_id).unwrap_or(VarType::Int)
+        }
+        Factor::Constant { m_value: _, m_type } => return m_type.clone(),
+        Factor::FloatConstant { m_value: _, m_type } => return m_type.clone(),
+        Factor::UnaryOperation { m_opertator, m_factor } => match m_opertator {
+            UnaryOperator::Minus | UnaryOperator::Complement => {
+                return promote(&factor_type(ctx, m_factor))
+            }
+            UnaryOperator::Negation => return VarType::Int,
+            UnaryOperator::Dereference => match factor_type(ctx, m_factor) {
+                VarType::Pointer(t, _) => return *t,
+                _ => return VarType::Int,
+            },
+            UnaryOperator::AddressOf => {
+                return VarType::Pointer(
+                    Box::new(factor_type(ctx, m_factor)),
+                    lvalue_qualifiers(ctx, m_factor).unwrap_or_default(),
+                )
+            }
+        },
+        Factor::Braced { m_expression } => {
+            return expression_type(ctx, m_expression)
+        }
+        Factor::Variable { m_var, m_span: _ } => {
+            return ctx.variable_type(m_var).unwrap_or(VarType::Int)
+        }
+        Factor::Cast { m_type, m_factor: _ } => return m_type.clone(),
+        Factor::SizeOfType { m_type: _ }
+        | Factor::SizeOfExpression { m_factor: _ }
+        | Factor::AlignOf { m_type: _ } => return VarType::ULong,
+        Factor::VaArg { m_list: _, m_type } => return m_type.clone(),
+        // There is no void, these give an int which is always 0
+        Factor::VaStart { m_list: _, m_last: _ }
+        | Factor::VaEnd { m_list: _ } => return VarType::Int,
+    }
+}
+
+// Qualifiers of the object a factor designates, or None if it isn't an lvalue
+pub fn lvalue_qualifiers(
+    ctx: &dyn TypeContext,
+    factor: &Factor,
+) -> Option<Qualifiers> {
+    match factor {
+        Factor::Variable { m_var, m_span: _ } => {
+            return Some(ctx.variable_qualifiers(m_var))
+        }
+        Factor::UnaryOperation {
+            m_opertator: UnaryOperator::Dereference,
+            m_factor,
+        } => match factor_type(ctx, m_factor) {
+            VarType::Pointer(_, q) => return Some(q),
+            _ => return None,
+        },
+        Factor::Braced { m_expression } => match single_factor(m_expression) {
+            // A parenthesised lvalue is still an lvalue
+            Some(f) => return lvalue_qualifiers(ctx, f),
+            None => return None,
+        },
+        _ => return None,
+    }
+}
+
+// The additive expression making up an expression without any operators
+// of lower precedence
+pub fn single_additive(expression: &Expression) -> Option<&AdditiveExpression> {
+    let conditional = match expression {
+        Expression::Operation(c) => c,
+        Expression::Assignment { m_target: _, m_value: _, m_span: _ } => {
+            return None
+        }
+    };
+    if conditional.m_true.is_some() {
+        return None;
+    }
+    let logical_or = &conditional.m_condition;
+    let logical_and = &logical_or.m_first;
+    let equality = &logical_and.m_first;
+    let relational = &equality.m_first;
+    if logical_or.m_rest.len() > 0
+        || logical_and.m_rest.len() > 0
+        || equality.m_rest.len() > 0
+        || relational.m_rest.len() > 0
+    {
+        return None;
+    }
+    return Some(&relational.m_first);
+}
+
+// The factor making up an expression without any operators
+pub fn single_factor(expression: &Expression) -> Option<&Factor> {
+    let additive = match single_additive(expression) {
+        Some(a) => a,
+        None => return None,
+    };
+    let term = &additive.m_first_term;
+    if additive.m_rest.len() > 0 || term.m_rest.len() > 0 {
+        return None;
+    }
+    return Some(&term.m_first_factor);
+}
@@ -0,0 +1,866 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::{
+    self, BinaryOp, BlockId, CompareOp, ConvertOp, StaticValue, Temp,
+    Terminator, Type, UnaryOp,
+};
+
+// Static data starts past the first kilobyte so that no variable is at 0,
+// and the stack follows it, growing down from the end of memory
+const DATA_START: i64 = 1024;
+const STACK_SIZE: i64 = 64 * 1024;
+const PAGE_SIZE: i64 = 64 * 1024;
+
+// Whether a narrow integer is extended with its sign or with zeros
+#[derive(Clone, Copy, PartialEq)]
+enum Extend {
+    Sign,
+    Zero,
+}
+
+// The constructs around the code being generated, innermost last, which
+// br counts out through to find its target
+enum Enclosing {
+    // Branching to it goes back to the start of the loop's header block
+    Loop(BlockId),
+    // Branching to it goes on to the block following it
+    BlockFollowedBy(BlockId),
+    IfThenElse,
+}
+
+// The WebAssembly type holding a value of the type. Narrow integers are
+// held in an i32, with whatever is in the bits past them
+fn value_type(t: &Type) -> &'static str {
+    match t {
+        Type::I8 | Type::I16 | Type::I32 => return "i32",
+        Type::I64 => return "i64",
+        Type::F32 => return "f32",
+        Type::F64 => return "f64",
+    }
+}
+
+// Load instruction for a value of the type
+fn load_of(t: &Type) -> &'static str {
+    match t {
+        Type::I8 => return "i32.load8_u",
+        Type::I16 => return "i32.load16_u",
+        Type::I32 => return "i32.load",
+        Type::I64 => return "i64.load",
+        Type::F32 => return "f32.load",
+        Type::F64 => return "f64.load",
+    }
+}
+
+fn store_of(t: &Type) -> &'static str {
+    match t {
+        Type::I8 => return "i32.store8",
+        Type::I16 => return "i32.store16",
+        Type::I32 => return "i32.store",
+        Type::I64 => return "i64.store",
+        Type::F32 => return "f32.store",
+        Type::F64 => return "f64.store",
+    }
+}
+
+fn align_to(value: i64, align: i64) -> i64 {
+    return (value + align - 1) / align * align;
+}
+
+fn signature(parameters: &[Type], variadic: bool, result: &Type) -> String {
+    let mut text = String::new();
+    for parameter in parameters {
+        text.push_str(&format!(" (param {})", value_type(parameter)));
+    }
+    if variadic {
+        text.push_str(" (param i32)");
+    }
+    text.push_str(&format!(" (result {})", value_type(result)));
+    return text;
+}
+
+// The shape of a function's control flow, from which its structured
+// control flow is built. Without goto every loop has one entry, so each
+// edge either goes forward in reverse postorder or back to a loop header
+// dominating it
+struct Structure {
+    // Position of each block in reverse postorder, or None if unreachable
+    m_order: Vec<Option<usize>>,
+    m_loop_headers: HashSet<BlockId>,
+    // Blocks entered by more than one forward edge, which can't be placed
+    // inside the code of any one of them
+    m_merges: HashSet<BlockId>,
+    // Children of each block in the dominator tree which are merges
+    m_merge_children: Vec<Vec<BlockId>>,
+}
+
+fn successors(block: &ir::Block) -> Vec<BlockId> {
+    match &block.m_terminator {
+        Terminator::Jump { m_target, .. } => return vec![*m_target],
+        Terminator::Branch { m_true, m_false, .. } => {
+            return vec![*m_true, *m_false]
+        }
+        Terminator::Return { .. } => return Vec::new(),
+    }
+}
+
+impl Structure {
+    fn new(function: &ir::Function) -> Self {
+        let count = function.m_blocks.len();
+
+        // Postorder from a depth first search of the reachable blocks
+        let mut postorder: Vec<BlockId> = Vec::new();
+        let mut visited = vec![false; count];
+        let mut stack: Vec<(BlockId, usize)> = vec![(0, 0)];
+        visited[0] = true;
+        while let Some((block, next)) = stack.pop() {
+            let targets = successors(&function.m_blocks[block]);
+            if next < targets.len() {
+                stack.push((block, next + 1));
+                let target = targets[next];
+                if !visited[target] {
+                    visited[target] = true;
+                    stack.push((target, 0));
+                }
+            } else {
+                postorder.push(block);
+            }
+        }
+        let rpo: Vec<BlockId> = postorder.into_iter().rev().collect();
+        let mut order: Vec<Option<usize>> = vec![None; count];
+        for (i, block) in rpo.iter().enumerate() {
+            order[*block] = Some(i);
+        }
+
+        let mut predecessors: Vec<Vec<BlockId>> = vec![Vec::new(); count];
+        for block in &rpo {
+            for target in successors(&function.m_blocks[*block]) {
+                predecessors[target].push(*block);
+            }
+        }
+
+        // Immediate dominators, by Cooper, Harvey and Kennedy's iteration
+        // over reverse postorder
+        let mut dominators: Vec<Option<BlockId>> = vec![None; count];
+        dominators[0] = Some(0);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in rpo.iter().skip(1) {
+                let mut dominator: Option<BlockId> = None;
+                for predecessor in &predecessors[*block] {
+                    if dominators[*predecessor].is_none() {
+                        continue;
+                    }
+                    dominator = match dominator {
+                        None => Some(*predecessor),
+                        Some(d) => Some(intersect(
+                            &dominators,
+                            &order,
+                            d,
+                            *predecessor,
+                        )),
+                    };
+                }
+                if dominator != dominators[*block] {
+                    dominators[*block] = dominator;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut loop_headers: HashSet<BlockId> = HashSet::new();
+        let mut merges: HashSet<BlockId> = HashSet::new();
+        for block in &rpo {
+            let forward = predecessors[*block]
+                .iter()
+                .filter(|p| order[**p] < order[*block])
+                .count();
+            if forward < predecessors[*block].len() {
+                loop_headers.insert(*block);
+            }
+            if forward > 1 {
+                merges.insert(*block);
+            }
+        }
+
+        // In reverse postorder, so the last is the one placed last
+        let mut merge_children: Vec<Vec<BlockId>> = vec![Vec::new(); count];
+        for block in rpo.iter().skip(1) {
+            if merges.contains(block) {
+                match dominators[*block] {
+                    Some(d) => merge_children[d].push(*block),
+                    None => (),
+                }
+            }
+        }
+
+        Structure {
+            m_order: order,
+            m_loop_headers: loop_headers,
+            m_merges: merges,
+            m_merge_children: merge_children,
+        }
+    }
+}
+
+// The nearest common dominator of two blocks
+fn intersect(
+    dominators: &[Option<BlockId>],
+    order: &[Option<usize>],
+    mut a: BlockId,
+    mut b: BlockId,
+) -> BlockId {
+    while a != b {
+        while order[a] > order[b] {
+            a = dominators[a].unwrap_or(0);
+        }
+        while order[b] > order[a] {
+            b = dominators[b].unwrap_or(0);
+        }
+    }
+    return a;
+}
+
+// Generates a WebAssembly text module from the IR. Temporaries are locals
+// and variables are in linear memory, either static data or the frame of
+// their function on a stack kept in memory, which $__stack_pointer points
+// to the bottom of. Addresses stay I64 as in the IR and are wrapped to 32
+// bits to access memory, so the layout of types is the same as on x86-64.
+// The variable arguments of a call are stored in the caller's frame, 8
+// bytes each, and their address passed after the fixed arguments
+pub struct Generator {
+    lines: Vec<String>,
+    depth: usize,
+    temps: Vec<Type>,
+    structure: Structure,
+    enclosing: Vec<Enclosing>,
+    // Offset in the frame of each slot
+    slots: Vec<i64>,
+    frame_size: i64,
+    // Offset in the frame of the pointer to the next variable argument
+    va_list_state: i64,
+    addresses: HashMap<String, i64>,
+    // Parameter types, whether variadic, and result type of each function
+    signatures: HashMap<String, (Vec<Type>, bool, Type)>,
+}
+
+impl Generator {
+    pub fn new() -> Self {
+        Generator {
+            lines: Vec::new(),
+            depth: 0,
+            temps: Vec::new(),
+            structure: Structure {
+                m_order: Vec::new(),
+                m_loop_headers: HashSet::new(),
+                m_merges: HashSet::new(),
+                m_merge_children: Vec::new(),
+            },
+            enclosing: Vec::new(),
+            slots: Vec::new(),
+            frame_size: 0,
+            va_list_state: 0,
+            addresses: HashMap::new(),
+            signatures: HashMap::new(),
+        }
+    }
+
+    fn emit(&mut self, instruction: String) {
+        self.lines.push(format!("{}{}", "  ".repeat(self.depth), instruction));
+    }
+
+    fn open(&mut self, instruction: &str, enclosing: Enclosing) {
+        self.emit(String::from(instruction));
+        self.depth += 1;
+        self.enclosing.push(enclosing);
+    }
+
+    fn close(&mut self) {
+        self.enclosing.pop();
+        self.depth -= 1;
+        self.emit(String::from("end"));
+    }
+
+    fn get(&mut self, temp: Temp) {
+        self.emit(format!("local.get $t{}", temp));
+    }
+
+    fn set(&mut self, temp: Temp) {
+        self.emit(format!("local.set $t{}", temp));
+    }
+
+    // Gets a narrow integer with the bits past it filled
+    fn get_extended(&mut self, temp: Temp, extend: Extend) {
+        self.get(temp);
+        match (self.temps[temp], extend) {
+            (Type::I8, Extend::Sign) => {
+                self.emit(String::from("i32.extend8_s"))
+            }
+            (Type::I16, Extend::Sign) => {
+                self.emit(String::from("i32.extend16_s"))
+            }
+            (Type::I8, Extend::Zero) => {
+                self.emit(String::from("i32.const 255"));
+                self.emit(String::from("i32.and"));
+            }
+            (Type::I16, Extend::Zero) => {
+                self.emit(String::from("i32.const 65535"));
+                self.emit(String::from("i32.and"));
+            }
+            _ => (),
+        }
+    }
+
+    // Gets an address as a 32 bit memory index
+    fn get_address(&mut self, temp: Temp) {
+        self.get(temp);
+        self.emit(String::from("i32.wrap_i64"));
+    }
+
+    fn get_frame(&mut self, offset: i64) {
+        self.emit(String::from("local.get $frame"));
+        if offset != 0 {
+            self.emit(format!("i32.const {}", offset));
+            self.emit(String::from("i32.add"));
+        }
+    }
+
+    pub fn generate(&mut self, program: &ir::Program) -> String {
+        let mut data_end = DATA_START;
+        let mut segments: Vec<String> = Vec::new();
+        for data in &program.m_data {
+            let size = data.m_type.size() as i64;
+            data_end = align_to(data_end, size);
+            self.addresses.insert(data.m_name.clone(), data_end);
+            data_end += size;
+        }
+        // Addresses can be of data which comes later
+        for data in &program.m_data {
+            let value = match &data.m_value {
+                Some(StaticValue::Constant(v)) => *v,
+                Some(StaticValue::Address(symbol, offset)) => {
+                    match self.addresses.get(symbol) {
+                        Some(a) => *a + offset,
+                        None => panic!("No static data named {}", symbol),
+                    }
+                }
+                None => continue,
+            };
+            let size = data.m_type.size() as usize;
+            let bytes: String = value.to_le_bytes()[..size]
+                .iter()
+                .map(|b| format!("\\{:02x}", b))
+                .collect();
+            segments.push(format!(
+                "  (data (i32.const {}) \"{}\")",
+                self.addresses[&data.m_name], bytes
+            ));
+        }
+        let stack_top = align_to(data_end, 16) + STACK_SIZE;
+
+        for function in &program.m_functions {
+            let parameters = function
+                .m_parameters
+                .iter()
+                .map(|p| function.m_temps[*p])
+                .collect();
+            self.signatures.insert(
+                function.m_name.clone(),
+                (parameters, function.m_variadic, function.m_return_type),
+            );
+        }
+        // Functions which aren't defined are imported from the host, with
+        // the types of the first call to each
+        let mut imports: Vec<String> = Vec::new();
+        for function in &program.m_functions {
+            for block in &function.m_blocks {
+                for instruction in &block.m_instructions {
+                    match instruction {
+                        ir::Instruction::Call {
+                            m_dest,
+                            m_function,
+                            m_arguments,
+                            m_variadic,
+                        } if !self.signatures.contains_key(m_function) => {
+                            let fixed = m_variadic.unwrap_or(m_arguments.len());
+                            let parameters: Vec<Type> = m_arguments[..fixed]
+                                .iter()
+                                .map(|a| function.m_temps[*a])
+                                .collect();
+                            let result = function.m_temps[*m_dest];
+                            imports.push(format!(
+                                "  (import \"env\" \"{}\" (func ${}{}))",
+                                m_function,
+                                m_function,
+                                signature(
+                                    &parameters,
+                                    m_variadic.is_some(),
+                                    &result
+                                )
+                            ));
+                            self.signatures.insert(
+                                m_function.clone(),
+                                (parameters, m_variadic.is_some(), result),
+                            );
+                        }
+                        _ => (),
+                    }
+                }
+            }
+        }
+
+        self.lines.push(String::from("(module"));
+        self.lines.extend(imports);
+        self.lines.push(format!(
+            "  (memory (export \"memory\") {})",
+            align_to(stack_top, PAGE_SIZE) / PAGE_SIZE
+        ));
+        self.lines.push(format!(
+            "  (global $__stack_pointer (mut i32) (i32.const {}))",
+            stack_top
+        ));
+        self.lines.extend(segments);
+        for function in &program.m_functions {
+            self.generate_function(function);
+        }
+        self.lines.push(String::from(")"));
+        let mut text = std::mem::take(&mut self.lines).join("\n");
+        text.push('\n');
+        return text;
+    }
+
+    fn generate_function(&mut self, function: &ir::Function) {
+        self.temps = function.m_temps.clone();
+        self.structure = Structure::new(function);
+
+        // The frame holds the variable arguments of calls at the bottom,
+        // then the va_list state, then the slots
+        let mut call_arguments = 0;
+        let mut uses_va_arg = false;
+        for block in &function.m_blocks {
+            for instruction in &block.m_instructions {
+                match instruction {
+                    ir::Instruction::Call {
+                        m_arguments,
+                        m_variadic: Some(fixed),
+                        ..
+                    } => {
+                        let count = (m_arguments.len() - fixed) as i64;
+                        call_arguments = call_arguments.max(8 * count);
+                    }
+                    ir::Instruction::VaArg { .. } => uses_va_arg = true,
+                    _ => (),
+                }
+            }
+        }
+        let mut size = call_arguments;
+        self.va_list_state = size;
+        if function.m_variadic {
+            size += 8;
+        }
+        self.slots.clear();
+        for slot in &function.m_slots {
+            size = align_to(size, slot.m_align.max(1) as i64);
+            self.slots.push(size);
+            size += slot.m_size as i64;
+        }
+        self.frame_size = align_to(size, 16);
+
+        let mut header = format!("  (func ${}", function.m_name);
+        if function.m_global {
+            header.push_str(&format!(" (export \"{}\")", function.m_name));
+        }
+        for parameter in &function.m_parameters {
+            header.push_str(&format!(
+                " (param $t{} {})",
+                parameter,
+                value_type(&self.temps[*parameter])
+            ));
+        }
+        if function.m_variadic {
+            header.push_str(" (param $varargs i32)");
+        }
+        header.push_str(&format!(
+            " (result {})",
+            value_type(&function.m_return_type)
+        ));
+        self.lines.push(header);
+        self.depth = 2;
+
+        if self.frame_size > 0 {
+            self.emit(String::from("(local $frame i32)"));
+        }
+        if uses_va_arg {
+            self.emit(String::from("(local $scratch i64)"));
+        }
+        for (temp, temp_type) in function.m_temps.iter().enumerate() {
+            if !function.m_parameters.contains(&temp) {
+                self.emit(format!(
+                    "(local $t{} {})",
+                    temp,
+                    value_type(temp_type)
+                ));
+            }
+        }
+        if self.frame_size > 0 {
+            self.emit(String::from("global.get $__stack_pointer"));
+            self.emit(format!("i32.const {}", self.frame_size));
+            self.emit(String::from("i32.sub"));
+            self.emit(String::from("local.tee $frame"));
+            self.emit(String::from("global.set $__stack_pointer"));
+        }
+        if function.m_variadic {
+            self.get_frame(self.va_list_state);
+            self.emit(String::from("local.get $varargs"));
+            self.emit(String::from("i64.extend_i32_u"));
+            self.emit(String::from("i64.store"));
+        }
+
+        self.generate_node(function, 0);
+        // Every path has returned before here
+        self.emit(String::from("unreachable"));
+        self.lines.push(String::from("  )"));
+    }
+
+    // The code of the block and the blocks it dominates, in a loop if
+    // it's the header of one
+    fn generate_node(&mut self, function: &ir::Function, block: BlockId) {
+        let merges = self.structure.m_merge_children[block].clone();
+        if self.structure.m_loop_headers.contains(&block) {
+            self.open("loop", Enclosing::Loop(block));
+            self.generate_within(function, block, merges);
+            self.close();
+        } else {
+            self.generate_within(function, block, merges);
+        }
+    }
+
+    // Each merge the block dominates follows a wasm block holding the code
+    // which branches to it, with the last merge outermost
+    fn generate_within(
+        &mut self,
+        function: &ir::Function,
+        block: BlockId,
+        mut merges: Vec<BlockId>,
+    ) {
+        match merges.pop() {
+            Some(merge) => {
+                self.open("block", Enclosing::BlockFollowedBy(merge));
+                self.generate_within(function, block, merges);
+                self.close();
+                self.generate_node(function, merge);
+            }
+            None => {
+                for instruction in &function.m_blocks[block].m_instructions {
+                    self.generate_instruction(instruction);
+                }
+                self.generate_terminator(function, block);
+            }
+        }
+    }
+
+    fn generate_terminator(&mut self, function: &ir::Function, block: BlockId) {
+        match &function.m_blocks[block].m_terminator {
+            // Every argument is on the operand stack before any parameter
+            // is set, as a parameter may also be an argument
+            Terminator::Jump { m_target, m_arguments } => {
+                for argument in m_arguments {
+                    self.get(*argument);
+                }
+                let parameters = &function.m_blocks[*m_target].m_parameters;
+                for parameter in parameters.iter().rev() {
+                    self.set(*parameter);
+                }
+                self.generate_branch(function, block, *m_target);
+            }
+            Terminator::Branch { m_condition, m_true, m_false } => {
+                self.get(*m_condition);
+                self.open("if", Enclosing::IfThenElse);
+                self.generate_branch(function, block, *m_true);
+                self.depth -= 1;
+                self.emit(String::from("else"));
+                self.depth += 1;
+                self.generate_branch(function, block, *m_false);
+                self.close();
+            }
+            Terminator::Return { m_value } => {
+                if self.frame_size > 0 {
+                    self.get_frame(self.frame_size);
+                    self.emit(String::from("global.set $__stack_pointer"));
+                }
+                self.get(*m_value);
+                self.emit(String::from("return"));
+            }
+        }
+    }
+
+    // Goes back to a loop header or on to a merge with br, and otherwise
+    // places the target, which only this block goes to, right here
+    fn generate_branch(
+        &mut self,
+        function: &ir::Function,
+        source: BlockId,
+        target: BlockId,
+    ) {
+        let order = &self.structure.m_order;
+        let backward = order[target] <= order[source];
+        if !backward && !self.structure.m_merges.contains(&target) {
+            self.generate_node(function, target);
+            return;
+        }
+        let position = self.enclosing.iter().rposition(|e| match e {
+            Enclosing::Loop(header) => backward && *header == target,
+            Enclosing::BlockFollowedBy(merge) => !backward && *merge == target,
+            Enclosing::IfThenElse => false,
+        });
+        match position {
+            Some(p) => {
+                self.emit(format!("br {}", self.enclosing.len() - 1 - p))
+            }
+            None => panic!("No enclosing construct to branch to {}", target),
+        }
+    }
+
+    fn generate_instruction(&mut self, instruction: &ir::Instruction) {
+        match instruction {
+            // Floating constants are the bits of the value
+            ir::Instruction::Constant { m_dest, m_type, m_value } => {
+                match m_type {
+                    Type::I64 => self.emit(format!("i64.const {}", m_value)),
+                    Type::F64 => {
+                        self.emit(format!("i64.const {}", m_value));
+                        self.emit(String::from("f64.reinterpret_i64"));
+                    }
+                    Type::F32 => {
+                        self.emit(format!("i32.const {}", *m_value as i32));
+                        self.emit(String::from("f32.reinterpret_i32"));
+                    }
+                    _ => self.emit(format!("i32.const {}", *m_value as i32)),
+                }
+                self.set(*m_dest);
+            }
+            ir::Instruction::SlotAddress { m_dest, m_slot } => {
+                self.get_frame(self.slots[*m_slot]);
+                self.emit(String::from("i64.extend_i32_u"));
+                self.set(*m_dest);
+            }
+            ir::Instruction::GlobalAddress { m_dest, m_name } => {
+                let address = match self.addresses.get(m_name) {
+                    Some(a) => *a,
+                    None => panic!("No static data named {}", m_name),
+                };
+                self.emit(format!("i64.const {}", address));
+                self.set(*m_dest);
+            }
+            // Each access is one instruction, so volatile ones need nothing
+            // more
+            ir::Instruction::Load {
+                m_dest,
+                m_type,
+                m_address,
+                m_volatile: _,
+            } => {
+                self.get_address(*m_address);
+                self.emit(String::from(load_of(m_type)));
+                self.set(*m_dest);
+            }
+            ir::Instruction::Store {
+                m_type,
+                m_address,
+                m_value,
+                m_volatile: _,
+            } => {
+                self.get_address(*m_address);
+                self.get(*m_value);
+                self.emit(String::from(store_of(m_type)));
+            }
+            ir::Instruction::Unary { m_dest, m_op, m_type, m_operand } => {
+                let t = value_type(m_type);
+                match (m_op, m_type.is_floating()) {
+                    (_, true) => {
+                        self.get(*m_operand);
+                        self.emit(format!("{}.neg", t));
+                    }
+                    (UnaryOp::Negate, false) => {
+                        self.emit(format!("{}.const 0", t));
+                        self.get(*m_operand);
+                        self.emit(format!("{}.sub", t));
+                    }
+                    (UnaryOp::Not, false) => {
+                        self.get(*m_operand);
+                        self.emit(format!("{}.const -1", t));
+                        self.emit(format!("{}.xor", t));
+                    }
+                }
+                self.set(*m_dest);
+            }
+            ir::Instruction::Binary {
+                m_dest,
+                m_op,
+                m_type,
+                m_left,
+                m_right,
+            } => {
+                // Narrow operands of a division are extended by its
+                // signedness
+                let (operation, extend) = match (m_op, m_type.is_floating()) {
+                    (BinaryOp::Add, _) => ("add", Extend::Zero),
+                    (BinaryOp::Subtract, _) => ("sub", Extend::Zero),
+                    (BinaryOp::Multiply, _) => ("mul", Extend::Zero),
+                    (BinaryOp::Divide, true) => ("div", Extend::Zero),
+                    (BinaryOp::Divide, false) => ("div_s", Extend::Sign),
+                    (BinaryOp::UnsignedDivide, _) => ("div_u", Extend::Zero),
+                    (BinaryOp::Remainder, _) => ("rem_s", Extend::Sign),
+                    (BinaryOp::UnsignedRemainder, _) => ("rem_u", Extend::Zero),
+                };
+                self.get_extended(*m_left, extend);
+                self.get_extended(*m_right, extend);
+                self.emit(format!("{}.{}", value_type(m_type), operation));
+                self.set(*m_dest);
+            }
+            ir::Instruction::Compare {
+                m_dest,
+                m_op,
+                m_type,
+                m_left,
+                m_right,
+            } => {
+                let floating = m_type.is_floating();
+                let (operation, extend) = match (m_op, floating) {
+                    (CompareOp::Equal, _) => ("eq", Extend::Zero),
+                    (CompareOp::NotEqual, _) => ("ne", Extend::Zero),
+                    (CompareOp::Less, true) => ("lt", Extend::Sign),
+                    (CompareOp::LessOrEqual, true) => ("le", Extend::Sign),
+                    (CompareOp::Greater, true) => ("gt", Extend::Sign),
+                    (CompareOp::GreaterOrEqual, true) => ("ge", Extend::Sign),
+                    (CompareOp::Less, false) => ("lt_s", Extend::Sign),
+                    (CompareOp::LessOrEqual, false) => ("le_s", Extend::Sign),
+                    (CompareOp::Greater, false) => ("gt_s", Extend::Sign),
+                    (CompareOp::GreaterOrEqual, false) => {
+                        ("ge_s", Extend::Sign)
+                    }
+                    (CompareOp::UnsignedLess, _) => ("lt_u", Extend::Zero),
+                    (CompareOp::UnsignedLessOrEqual, _) => {
+                        ("le_u", Extend::Zero)
+                    }
+                    (CompareOp::UnsignedGreater, _) => ("gt_u", Extend::Zero),
+                    (CompareOp::UnsignedGreaterOrEqual, _) => {
+                        ("ge_u", Extend::Zero)
+                    }
+                };
+                self.get_extended(*m_left, extend);
+                self.get_extended(*m_right, extend);
+                self.emit(format!("{}.{}", value_type(m_type), operation));
+                self.set(*m_dest);
+            }
+            ir::Instruction::Convert {
+                m_dest,
+                m_op,
+                m_from,
+                m_to,
+                m_operand,
+            } => {
+                self.generate_convert(*m_op, m_from, m_to, *m_operand);
+                self.set(*m_dest);
+            }
+            ir::Instruction::Call {
+                m_dest,
+                m_function,
+                m_arguments,
+                m_variadic,
+            } => {
+                let fixed = m_variadic.unwrap_or(m_arguments.len());
+                for (i, argument) in m_arguments[fixed..].iter().enumerate() {
+                    self.get_frame(8 * i as i64);
+                    self.get(*argument);
+                    self.emit(String::from(store_of(&self.temps[*argument])));
+                }
+                for argument in &m_arguments[..fixed] {
+                    self.get(*argument);
+                }
+                if m_variadic.is_some() {
+                    self.get_frame(0);
+                }
+                self.emit(format!("call ${}", m_function));
+                self.set(*m_dest);
+            }
+            // The va_list holds the address of the pointer to the next
+            // variable argument, so that va_arg can move it on
+            ir::Instruction::VaStart { m_list } => {
+                self.get_address(*m_list);
+                self.get_frame(self.va_list_state);
+                self.emit(String::from("i64.extend_i32_u"));
+                self.emit(String::from("i64.store"));
+            }
+            ir::Instruction::VaArg { m_dest, m_type, m_list } => {
+                self.get_address(*m_list);
+                self.emit(String::from("i64.load"));
+                self.emit(String::from("local.set $scratch"));
+                self.emit(String::from("local.get $scratch"));
+                self.emit(String::from("i32.wrap_i64"));
+                self.emit(String::from(load_of(m_type)));
+                self.set(*m_dest);
+                self.get_address(*m_list);
+                self.emit(String::from("local.get $scratch"));
+                self.emit(String::from("i64.const 8"));
+                self.emit(String::from("i64.add"));
+                self.emit(String::from("i64.store"));
+            }
+            // The text format has no source positions
+            ir::Instruction::Location { .. } => (),
+        }
+    }
+
+    fn generate_convert(
+        &mut self,
+        op: ConvertOp,
+        from: &Type,
+        to: &Type,
+        operand: Temp,
+    ) {
+        let from_type = value_type(from);
+        let to_type = value_type(to);
+        match op {
+            ConvertOp::SignExtend | ConvertOp::ZeroExtend => {
+                let (extend, suffix) = match op {
+                    ConvertOp::SignExtend => (Extend::Sign, "s"),
+                    _ => (Extend::Zero, "u"),
+                };
+                self.get_extended(operand, extend);
+                if from_type != to_type {
+                    self.emit(format!("i64.extend_i32_{}", suffix));
+                }
+            }
+            ConvertOp::Truncate => {
+                self.get(operand);
+                if from_type != to_type {
+                    self.emit(String::from("i32.wrap_i64"));
+                }
+            }
+            ConvertOp::SignedToFloat => {
+                self.get_extended(operand, Extend::Sign);
+                self.emit(format!("{}.convert_{}_s", to_type, from_type));
+            }
+            ConvertOp::UnsignedToFloat => {
+                self.get_extended(operand, Extend::Zero);
+                self.emit(format!("{}.convert_{}_u", to_type, from_type));
+            }
+            // Out of range values saturate rather than trap
+            ConvertOp::FloatToSigned => {
+                self.get(operand);
+                self.emit(format!("{}.trunc_sat_{}_s", to_type, from_type));
+            }
+            ConvertOp::FloatToUnsigned => {
+                self.get(operand);
+                self.emit(format!("{}.trunc_sat_{}_u", to_type, from_type));
+            }
+            ConvertOp::FloatToFloat => {
+                self.get(operand);
+                match to {
+                    Type::F64 => self.emit(String::from("f64.promote_f32")),
+                    _ => self.emit(String::from("f32.demote_f64")),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,21 @@
+// AArch64 assembly assembles where a cross assembler or llvm-mc is
+// installed, and runs the same as on x86-64 where a cross compiler and
+// qemu-user are too
+mod common;
+
+use common::cross::{self, AARCH64};
+
+#[test]
+fn assembles() {
+    cross::assembles(&AARCH64, "\tstp\tx29, x30, [sp, #-16]!");
+}
+
+#[test]
+fn runs_under_qemu() {
+    cross::runs_under_qemu(&AARCH64);
+}
+
+#[test]
+fn x86_only_options() {
+    cross::x86_only_options(&AARCH64);
+}
@@ -0,0 +1,157 @@
+// Calls with many arguments between ccc and gcc compiled functions, in both
+// directions, for every mix of register and stack passed argument classes
+mod common;
+
+// Parameter lists to check, chosen to run out of integer registers, SSE
+// registers or both, with narrow and unsigned types on the stack
+const SIGNATURES: &[&[&str]] = &[
+    &["int", "int", "int", "int", "int", "int"],
+    &["int", "int", "int", "int", "int", "int", "int"],
+    &["long", "long", "long", "long", "long", "long", "long", "long", "long"],
+    &[
+        "char",
+        "short",
+        "int",
+        "long",
+        "unsigned char",
+        "unsigned short",
+        "unsigned int",
+        "unsigned long",
+        "signed char",
+        "short",
+        "unsigned char",
+    ],
+    &[
+        "double", "double", "double", "double", "double", "double", "double",
+        "double", "double", "double",
+    ],
+    &[
+        "float", "float", "float", "float", "float", "float", "float", "float",
+        "float",
+    ],
+    &[
+        "int", "double", "int", "double", "int", "double", "int", "double",
+        "int", "double", "int", "double", "int", "double", "int", "double",
+        "int", "double",
+    ],
+    &[
+        "long", "long", "long", "long", "long", "long", "long", "float",
+        "double", "float", "double", "float", "double", "float", "double",
+        "float", "char",
+    ],
+    &["double", "char", "float", "short", "double", "int"],
+];
+
+// A value of the given type for parameter i, away from zero and negative
+// where possible so that missing extensions show up
+fn argument(param_type: &str, i: usize) -> String {
+    let i = i as i64 + 1;
+    let sign = if i % 2 == 0 { -1 } else { 1 };
+    return match param_type {
+        "char" | "signed char" => format!("{}", sign * (100 + i)),
+        "unsigned char" => format!("{}", 200 + i),
+        "short" => format!("{}", sign * (30000 + i)),
+        "unsigned short" => format!("{}", 60000 + i),
+        "int" => format!("{}", sign * (2000000000 + i)),
+        "unsigned int" => format!("{}", 4000000000i64 + i),
+        "long" => format!("{}", sign * (5000000000 + i)),
+        "unsigned long" => format!("{}", 9000000000i64 + i),
+        _ => format!("{}.5", sign * i),
+    };
+}
+
+fn parameters(signature: &[&str]) -> String {
+    let parameters: Vec<String> = signature
+        .iter()
+        .enumerate()
+        .map(|(i, t)| format!("{} a{}", t, i))
+        .collect();
+    return parameters.join(", ");
+}
+
+fn arguments(signature: &[&str]) -> String {
+    let arguments: Vec<String> =
+        signature.iter().enumerate().map(|(i, t)| argument(t, i)).collect();
+    return arguments.join(", ");
+}
+
+// A function weighting each parameter by its position, so that swapped or
+// truncated arguments change the result. The sums stay exact in a double
+fn checksum(name: &str, signature: &[&str]) -> String {
+    let mut s = format!("double {}({}) {{\n    double r = 0.0;\n", name, {
+        parameters(signature)
+    });
+    for i in 0..signature.len() {
+        s.push_str(format!("    r = r + {} * a{};\n", i + 1, i).as_str());
+    }
+    s.push_str("    return r;\n}\n");
+    return s;
+}
+
+#[test]
+fn many_arguments() {
+    let mut helpers = String::new();
+    let mut source = String::new();
+    let mut checks = String::new();
+    for (k, signature) in SIGNATURES.iter().enumerate() {
+        // gcc defines gcc_k, the expected result, and a caller of ccc_k
+        helpers.push_str(&checksum(&format!("gcc_{}", k), signature));
+        helpers.push_str(
+            format!(
+                "double ccc_{0}({1});\n\
+                 double expect_{0}(void) {{ return gcc_{0}({2}); }}\n\
+                 double call_ccc_{0}(void) {{ return ccc_{0}({2}); }}\n",
+                k,
+                parameters(signature),
+                arguments(signature)
+            )
+            .as_str(),
+        );
+
+        source.push_str(
+            format!(
+                "double gcc_{0}({1});\n\
+                 double expect_{0}();\n\
+                 double call_ccc_{0}();\n",
+                k,
+                parameters(signature)
+            )
+            .as_str(),
+        );
+        source.push_str(&checksum(&format!("ccc_{}", k), signature));
+
+        // Exits with 10 * signature + direction on the first mismatch
+        checks.push_str(
+            format!(
+                "    expected = expect_{0}();\n\
+                 \x20   if (gcc_{0}({1}) != expected) return {2};\n\
+                 \x20   if (call_ccc_{0}() != expected) return {3};\n\
+                 \x20   if (ccc_{0}({1}) != expected) return {4};\n",
+                k,
+                arguments(signature),
+                10 * k + 1,
+                10 * k + 2,
+                10 * k + 3
+            )
+            .as_str(),
+        );
+    }
+    source.push_str(
+        format!(
+            "int main() {{\n    double expected;\n{}    return 0;\n}}\n",
+            { checks }
+        )
+        .as_str(),
+    );
+
+    let (_, code) =
+        common::run("arguments", "many_arguments", &helpers, &source);
+    assert_eq!(
+        code,
+        0,
+        "signature {} failed calling {}",
+        code / 10,
+        ["", "gcc from ccc", "ccc from gcc", "ccc from ccc"]
+            [(code % 10) as usize]
+    );
+}
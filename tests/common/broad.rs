@@ -0,0 +1,100 @@
+// A program using every kind of instruction the generators make, with the
+// helpers it calls and what it prints
+#![allow(dead_code)]
+
+pub const HELPERS: &str = r#"
+#include <stdio.h>
+
+int print_long(long i) {
+    printf("%ld\n", i);
+    return 0;
+}
+
+int print_double(double d) {
+    printf("%.6f\n", d);
+    return 0;
+}
+"#;
+
+// Conversions between every size and signedness, division, floating
+// comparisons with NaN, variadic and stack arguments and static data
+pub const SOURCE: &str = "
+#include <stdarg.h>
+
+int print_long(long i);
+int print_double(double d);
+
+static long counter = 3;
+const int limit = 7;
+double scale;
+
+double sum(int count, ...) {
+    va_list args;
+    va_start(args, count);
+    double total = 0;
+    for (int i = 0; i < count; i = i + 1) {
+        if (i % 2) total = total + va_arg(args, double);
+        else total = total + va_arg(args, long);
+    }
+    va_end(args);
+    return total;
+}
+
+long spread(long a, long b, long c, long d, long e, long f, long g,
+            double x, double y, double z, double w, double u, double v,
+            double s, double t, double r, float q) {
+    return a + b + c + d + e + f + g * 1000 + (long)(x + y + z + w + u + v + s
+        + t + r * 100 + q * 1000);
+}
+
+int main() {
+    signed char c = -100;
+    unsigned char uc = 200;
+    short s = -30000;
+    unsigned short us = 60000;
+    int i = -7;
+    unsigned int u = 4000000000u;
+    long l = -5000000000;
+    unsigned long ul = 18000000000000000000ul;
+    float f = 2.5f;
+    double d = -1.25;
+    print_long(c + uc + s + us);
+    print_long(i / 2 + i % 3 + (long)(u / 7u) + u % 9u);
+    print_long(l / 3 + l % 7 + (long)(ul / 11ul % 1000ul) + ul % 13ul);
+    print_long(-i + ~l + !i + (i < 3) + (u > 5u) + (l <= -1) + (ul >= 1ul));
+    print_double(-f + -d + f * d - f / d);
+    print_double((double)ul + (float)ul + (double)u + (float)i + (double)uc);
+    print_long((unsigned long)1.5e19 % 1000ul + (unsigned int)3.9e9 % 1000u);
+    print_long((long)(f * 3.0f) + (long)(double)f + (int)(float)d);
+    print_long((d == -1.25) + (d != 1.0) * 2 + (f < d) * 4 + (f <= 2.5f) * 8
+        + (d > -2.0) * 16 + (f >= 3.0f) * 32);
+    double nan = 0.0 / 0.0;
+    print_long((nan == nan) + (nan != nan) * 2 + (nan < 1.0) * 4);
+    scale = sum(4, 10l, 0.5, 20l, 0.25);
+    print_double(scale);
+    print_long(spread(1, 2, 3, 4, 5, 6, 7, 0.5, 0.5, 0.5, 0.5, 0.5, 0.5,
+        0.5, 0.5, 0.5, 0.25f));
+    long *p = &counter;
+    *p = *p * limit;
+    volatile short v = 9;
+    v = v + 1;
+    char bytes = (char)(counter + v);
+    return bytes;
+}
+";
+
+// As gcc prints it
+pub const EXPECTED: &str = "\
+30100
+571428571
+-1666666300
+5000000010
+-2.375000
+36000000408716255232.000000
+0
+8
+27
+2
+30.750000
+7325
+";
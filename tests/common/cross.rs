@@ -0,0 +1,167 @@
+// Checks shared by the backends for other architectures: the broad program
+// assembles, runs under qemu-user the same as on x86-64, and the x86-64 only
+// options are refused. Without the tools these checks are skipped, unless
+// CCC_CROSS_TOOLS is set, when a missing tool fails the test instead
+#![allow(dead_code)]
+
+use std::fs;
+use std::process::{Command, Output};
+
+use super::broad;
+
+pub struct Target {
+    // The --target option selecting the backend
+    pub m_option: &'static str,
+    pub m_name: &'static str,
+    // Prefix of the binutils and gcc cross tools, which is also where
+    // qemu-user finds the target's libraries
+    pub m_prefix: &'static str,
+    pub m_qemu: &'static str,
+    // Options for llvm-mc, used when there are no cross binutils
+    pub m_llvm_mc: &'static [&'static str],
+    // The architecture as llvm-mc --version lists it
+    pub m_llvm_arch: &'static str,
+}
+
+pub const AARCH64: Target = Target {
+    m_option: "--target=aarch64-linux",
+    m_name: "aarch64",
+    m_prefix: "aarch64-linux-gnu",
+    m_qemu: "qemu-aarch64",
+    m_llvm_mc: &["-triple=aarch64-linux-gnu"],
+    m_llvm_arch: "aarch64",
+};
+
+pub const RISCV64: Target = Target {
+    m_option: "--target=riscv64",
+    m_name: "riscv64",
+    m_prefix: "riscv64-linux-gnu",
+    m_qemu: "qemu-riscv64",
+    m_llvm_mc: &["-triple=riscv64", "-mattr=+m,+a,+f,+d,+c"],
+    m_llvm_arch: "riscv64",
+};
+
+pub fn available(tool: &str) -> bool {
+    return Command::new(tool).arg("--version").output().is_ok();
+}
+
+// Whether a test without the tools it needs has to fail rather than skip
+fn tools_required() -> bool {
+    return std::env::var_os("CCC_CROSS_TOOLS").is_some();
+}
+
+pub fn skip(reason: &str) {
+    assert!(!tools_required(), "{}, and CCC_CROSS_TOOLS is set", reason);
+    eprintln!("{}, skipping", reason);
+}
+
+fn check(output: Output, tool: &str) {
+    assert!(
+        output.status.success(),
+        "{} failed: {}",
+        tool,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+// Assembles a file with the cross assembler, or llvm-mc if it knows the
+// architecture, returning false when there's neither
+fn assemble(target: &Target, name: &str) -> bool {
+    let dir = super::directory(target.m_name);
+    let (source, object) = (format!("{}.s", name), format!("{}.o", name));
+    let assembler = format!("{}-as", target.m_prefix);
+    if available(&assembler) {
+        let output = Command::new(&assembler)
+            .args([&source, "-o", &object])
+            .current_dir(&dir)
+            .output()
+            .expect("Failed to run the assembler");
+        check(output, &assembler);
+        return true;
+    }
+    let architectures = match Command::new("llvm-mc").arg("--version").output()
+    {
+        Ok(o) => String::from_utf8_lossy(&o.stdout).into_owned(),
+        Err(_) => return false,
+    };
+    if !architectures.contains(target.m_llvm_arch) {
+        return false;
+    }
+    let output = Command::new("llvm-mc")
+        .args(target.m_llvm_mc)
+        .args(["-filetype=obj", &source, "-o", &object])
+        .current_dir(&dir)
+        .output()
+        .expect("Failed to run llvm-mc");
+    check(output, "llvm-mc");
+    return true;
+}
+
+// The broad program's assembly at each level has the instruction given and
+// nothing from x86-64, and assembles
+pub fn assembles(target: &Target, instruction: &str) {
+    for level in ["-O0", "-O1", "-O2"] {
+        let name = format!("broad{}", level.replace('-', "_"));
+        let options = [target.m_option, level];
+        let assembly =
+            super::assembly(target.m_name, &name, broad::SOURCE, &options);
+        assert!(assembly.contains(instruction), "{}", assembly);
+        assert!(!assembly.contains("%r"), "{}", assembly);
+        if !assemble(target, &name) {
+            skip(&format!("No assembler for {}", target.m_name));
+            return;
+        }
+    }
+}
+
+// The broad program linked with the cross compiler prints the same and exits
+// with the same status under qemu-user as it does on x86-64
+pub fn runs_under_qemu(target: &Target) {
+    let gcc = format!("{}-gcc", target.m_prefix);
+    if !available(&gcc) || !available(target.m_qemu) {
+        skip(&format!("No {} or {}", gcc, target.m_qemu));
+        return;
+    }
+    let dir = super::directory(target.m_name);
+    fs::write(dir.join("run_helpers.c"), broad::HELPERS)
+        .expect("Failed to write helpers");
+    let output = Command::new(&gcc)
+        .args(["-c", "run_helpers.c", "-o", "run_helpers.o"])
+        .current_dir(&dir)
+        .output()
+        .expect("Failed to execute the cross compiler");
+    check(output, &gcc);
+
+    for level in ["-O0", "-O1", "-O2"] {
+        let name = format!("run{}", level.replace('-', "_"));
+        let options = [target.m_option, level, "run_helpers.o"];
+        let ccc = super::compile(target.m_name, &name, broad::SOURCE, &options);
+        let stderr = String::from_utf8_lossy(&ccc.stderr);
+        assert!(ccc.status.success() && stderr.is_empty(), "{}", stderr);
+
+        let output = Command::new(target.m_qemu)
+            .args(["-L", &format!("/usr/{}", target.m_prefix)])
+            .arg(dir.join(&name))
+            .output()
+            .expect("Failed to run qemu-user");
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            broad::EXPECTED,
+            "at {}",
+            level
+        );
+        assert_eq!(output.status.code(), Some(31), "at {}", level);
+    }
+}
+
+// Objects and Intel syntax are only made for x86-64
+pub fn x86_only_options(target: &Target) {
+    for option in ["-c", "--standalone", "-masm=intel"] {
+        let source = "int main() { return 0; }\n";
+        let options = [target.m_option, option];
+        let ccc = super::compile(target.m_name, "options", source, &options);
+        let stderr = String::from_utf8_lossy(&ccc.stderr);
+        assert!(stderr.contains("only apply to x86-64"), "{}", stderr);
+        assert!(!ccc.status.success());
+    }
+}
@@ -0,0 +1,155 @@
+// Building and running programs compiled by ccc for the integration tests
+pub mod broad;
+pub mod cross;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+// Writes source to <name>.c in a directory per test group and runs ccc on
+// it with the arguments, from that directory
+pub fn compile(group: &str, name: &str, source: &str, args: &[&str]) -> Output {
+    let dir = directory(group);
+    let file = format!("{}.c", name);
+    fs::write(dir.join(&file), source).expect("Failed to write source");
+    return Command::new(env!("CARGO_BIN_EXE_ccc"))
+        .args(args)
+        .arg(&file)
+        .current_dir(&dir)
+        .output()
+        .expect("Failed to execute ccc");
+}
+
+pub fn directory(group: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(group);
+    fs::create_dir_all(&dir).expect("Failed to create test directory");
+    return dir;
+}
+
+// As compile, for a run of ccc that has to succeed without diagnostics
+fn compile_cleanly(
+    group: &str,
+    name: &str,
+    source: &str,
+    args: &[&str],
+) -> Output {
+    let ccc = compile(group, name, source, args);
+    let stderr = String::from_utf8_lossy(&ccc.stderr);
+    assert!(ccc.status.success() && stderr.is_empty(), "{}", stderr);
+    return ccc;
+}
+
+// Compiles source with ccc, linked against helpers compiled by gcc, runs it
+// and returns (stdout, exit code)
+#[allow(dead_code)]
+pub fn run(
+    group: &str,
+    name: &str,
+    helpers: &str,
+    source: &str,
+) -> (String, i32) {
+    return run_with_options(group, name, helpers, source, &[]);
+}
+
+// As run, passing extra options to ccc
+#[allow(dead_code)]
+pub fn run_with_options(
+    group: &str,
+    name: &str,
+    helpers: &str,
+    source: &str,
+    options: &[&str],
+) -> (String, i32) {
+    let dir = directory(group);
+    let helper_c = dir.join(format!("{}_helpers.c", name));
+    let helper_o = dir.join(format!("{}_helpers.o", name));
+    fs::write(&helper_c, helpers).expect("Failed to write helpers");
+    let gcc = Command::new("gcc")
+        .args(["-O0", "-c"])
+        .arg(&helper_c)
+        .arg("-o")
+        .arg(&helper_o)
+        .output()
+        .expect("Failed to execute gcc");
+    assert!(gcc.status.success(), "{}", String::from_utf8_lossy(&gcc.stderr));
+
+    let helper_o = helper_o.to_string_lossy().into_owned();
+    let mut args = options.to_vec();
+    args.push(&helper_o);
+    compile_cleanly(group, name, source, &args);
+
+    let output = Command::new(dir.join(name))
+        .output()
+        .expect("Failed to run compiled program");
+    let code = match output.status.code() {
+        Some(c) => c,
+        None => panic!("{} was killed: {:?}", name, output.status),
+    };
+    return (String::from_utf8_lossy(&output.stdout).into_owned(), code);
+}
+
+// Runs ccc --dump-ir with the options on source and returns what it prints
+#[allow(dead_code)]
+pub fn dump_ir(
+    group: &str,
+    name: &str,
+    source: &str,
+    options: &[&str],
+) -> String {
+    let args = [&["--dump-ir"], options].concat();
+    let ccc = compile_cleanly(group, name, source, &args);
+    return String::from_utf8_lossy(&ccc.stdout).into_owned();
+}
+
+// Runs ccc --dump-ir on source and returns the errors and warnings it prints
+#[allow(dead_code)]
+pub fn diagnostics(group: &str, name: &str, source: &str) -> String {
+    let ccc = compile(group, name, source, &["--dump-ir"]);
+    return String::from_utf8_lossy(&ccc.stderr).into_owned();
+}
+
+// Runs ccc -S with the options on source and returns the assembly it writes
+#[allow(dead_code)]
+pub fn assembly(
+    group: &str,
+    name: &str,
+    source: &str,
+    options: &[&str],
+) -> String {
+    compile_cleanly(group, name, source, &[&["-S"], options].concat());
+    return fs::read_to_string(directory(group).join(format!("{}.s", name)))
+        .expect("Failed to read assembly");
+}
+
+// Runs ccc -c with the options on source and returns the object it writes
+#[allow(dead_code)]
+pub fn object(
+    group: &str,
+    name: &str,
+    source: &str,
+    options: &[&str],
+) -> PathBuf {
+    compile_cleanly(group, name, source, &[&["-c"], options].concat());
+    return directory(group).join(format!("{}.o", name));
+}
+
+// Runs ccc run with the options on source and returns (stdout, stderr, exit
+// code)
+#[allow(dead_code)]
+pub fn interpret(
+    group: &str,
+    name: &str,
+    source: &str,
+    options: &[&str],
+) -> (String, String, i32) {
+    let ccc = compile(group, name, source, &[&["run"], options].concat());
+    let code = match ccc.status.code() {
+        Some(c) => c,
+        None => panic!("ccc run was killed: {:?}", ccc.status),
+    };
+    return (
+        String::from_utf8_lossy(&ccc.stdout).into_owned(),
+        String::from_utf8_lossy(&ccc.stderr).into_owned(),
+        code,
+    );
+}
@@ -0,0 +1,156 @@
+// Constant expressions worked out at compile time, in static initializers
+// and folded in functions, and the diagnostics for them
+mod common;
+
+const HELPERS: &str = r#"
+#include <stdio.h>
+
+int print_long(long l) {
+    printf("%ld\n", l);
+    return 0;
+}
+
+int print_double(double d) {
+    printf("%.6f\n", d);
+    return 0;
+}
+"#;
+
+#[test]
+fn static_initializers() {
+    let source = "
+int print_long(long l);
+int print_double(double d);
+
+long product = 3000000000 * 2 - 1;
+unsigned int wrapped = 0u - 1;
+int chosen = 1 ? 7 : 1 / 0;
+int shorted = 0 && 1 / 0;
+int either = 2 > 1 || 1 / 0;
+int unsigned_compare = -1 < 0u;
+double ratio = 1 / 2.0 + (float)1 / 3;
+int truncated = (char)(200 + 100) * -(3 % 2);
+long sizes = sizeof(long) * 2 + sizeof(int) - _Alignof(short);
+int *null = 1 - 1;
+
+int main() {
+    static int negative = -7 / 2 + -7 % 2;
+    print_long(product);
+    print_long(wrapped);
+    print_long(chosen + shorted + either);
+    print_long(unsigned_compare);
+    print_double(ratio);
+    print_long(truncated);
+    print_long(sizes);
+    print_long(negative);
+    return null == 0;
+}
+";
+    let (out, code) =
+        common::run("constant", "static_initializers", HELPERS, source);
+    assert_eq!(out, "5999999999\n4294967295\n8\n0\n0.833333\n-44\n18\n-4\n");
+    assert_eq!(code, 1);
+}
+
+// Pointers with static storage can start at the address of another static
+// variable, moved by a constant
+#[test]
+fn address_initializers() {
+    let source = "
+int print_long(long l);
+
+long table = 7;
+int x = 3;
+int *px = &x;
+long *pt = &(table);
+char *second = (char *)&x + 1;
+long *same = &table - 2 + 2;
+int y = 11;
+int *later = &y;
+
+int main() {
+    static int hidden = 5;
+    static int *ph = &hidden;
+    static int *const pc = &x;
+    print_long(*px + *pt + *ph + *pc + *later);
+    print_long(second - (char *)&x);
+    return same == &table;
+}
+";
+    let (out, code) =
+        common::run("constant", "address_initializers", HELPERS, source);
+    assert_eq!(out, "29\n1\n");
+    assert_eq!(code, 1);
+
+    let ir = common::dump_ir("constant", "addresses", source, &[]);
+    assert!(ir.contains("global data @second: i64 = @x+1\n"), "{}", ir);
+    assert!(ir.contains("data @ph.1: i64 = @hidden.0\n"), "{}", ir);
+
+    let cases = [
+        (
+            "automatic",
+            "int main() {\n    int l = 1;\n    static int *p = &l;\n\
+             \x20   return *p;\n}\n",
+            "automatic.c:3:17: Error analysing program: InitializerError(\"p\", \
+             \"Initializer element is not constant\")",
+        ),
+        (
+            "as_integer",
+            "int x;\nlong l = (long)&x;\n",
+            "as_integer.c:2:6: Error analysing program: InitializerError(\"l\", \
+             \"Initializer element is not constant\")",
+        ),
+    ];
+    for (name, source, expected) in cases {
+        let errors = common::diagnostics("constant", name, source);
+        assert!(errors.contains(expected), "{}", errors);
+    }
+}
+
+#[test]
+fn folded() {
+    let source = "
+int divide(int x) {
+    return x / 2;
+}
+
+int main() {
+    return (2 * 3 + 4 > 9) ? 10 / 5 : divide(1);
+}
+";
+    let ir = common::dump_ir("constant", "folded", source, &[]);
+    let main = &ir[ir.find("@main").unwrap()..];
+    assert!(main.contains("  %0 = const i32 2\n  ret %0\n"), "{}", ir);
+    assert!(!main.contains("call"), "{}", ir);
+    assert!(ir.contains("divide i32"), "{}", ir);
+}
+
+#[test]
+fn diagnostics() {
+    let source = "
+int big = 2147483647 + 1;
+
+int divide(int x) {
+    long fine = 2147483647L + 1;
+    return x / 0 + -(-2147483647 - 1);
+}
+";
+    let diagnostics = common::diagnostics("constant", "warnings", source);
+    let lines: Vec<&str> = diagnostics.lines().collect();
+    assert_eq!(lines.len(), 3, "{}", diagnostics);
+    // Initializers are warned about where they start
+    assert!(lines[0].ends_with("2:11: warning: integer overflow in expression"));
+    assert!(lines[1].ends_with("warning: division by zero"));
+    assert!(lines[2].ends_with("warning: integer overflow in expression"));
+
+    let source = "
+int zero = 0 && 1 / 0;
+int trap = 1 / (1 - 1);
+";
+    let diagnostics = common::diagnostics("constant", "errors", source);
+    assert!(
+        diagnostics.contains("Division by zero in initializer"),
+        "{}",
+        diagnostics
+    );
+}
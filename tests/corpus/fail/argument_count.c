@@ -0,0 +1,3 @@
+// expect: argument_count.c:3:21: Error analysing program: FunctionError("add"
+int add(int a, int b) { return a + b; }
+int main() { return add(1); }
@@ -0,0 +1,6 @@
+// expect: assign_const.c:4:5: Error analysing program: AssignmentError("=", "Assignment to const-qualified lvalue")
+int main() {
+    const int limit = 10;
+    limit = 11;
+    return limit;
+}
@@ -0,0 +1,5 @@
+// expect: Error analysing program: AssignmentError("=", "Expression is not assignable")
+int main() {
+    3 = 4;
+    return 0;
+}
@@ -0,0 +1,2 @@
+// expect: bitwise_or.c:2:23: Could not tokenize: NotImplemented('|')
+int main() { return 1 | 2; }
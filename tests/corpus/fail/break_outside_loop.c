@@ -0,0 +1,5 @@
+// expect: break_outside_loop.c:3:5: Error analysing program: JumpError("break", "'break' statement not in a loop")
+int main() {
+    break;
+    return 0;
+}
@@ -0,0 +1,6 @@
+// expect: continue_outside_loop.c:3:5: Error analysing program: JumpError("continue"
+int main(int argc) {
+    if (argc)
+        continue;
+    return 0;
+}
@@ -0,0 +1,5 @@
+// expect: Error analysing program: TypeError("Dereference", "Operand of * must be a pointer")
+int main() {
+    int x = 1;
+    return *x;
+}
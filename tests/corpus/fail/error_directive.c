@@ -0,0 +1,5 @@
+// expect: Error preprocessing: UserError("error_directive.c:3", "unsupported platform")
+#if !defined(__x86_64__) || 1
+#error unsupported platform
+#endif
+int main() { return 0; }
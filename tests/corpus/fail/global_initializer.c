@@ -0,0 +1,4 @@
+// expect: global_initializer.c:3:5: Error analysing program: InitializerError("global", "Initializer element is not constant")
+int f();
+int global = f();
+int main() { return global; }
@@ -0,0 +1,3 @@
+// expect: Error preprocessing: IncludeNotFound("missing_include.c:2", "missing.h")
+#include "missing.h"
+int main() { return 0; }
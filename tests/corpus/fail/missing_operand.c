@@ -0,0 +1,2 @@
+// expect: missing_operand.c:2:26: Error parsing program: UnexpectedToken(SemiColon, ParseFactor)
+int main() { return (1 + ; }
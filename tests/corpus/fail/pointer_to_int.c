@@ -0,0 +1,6 @@
+// expect: pointer_to_int.c:4:9: Error analysing program: TypeError("'int *' to 'int'", "Pointer converted to an integer")
+int main() {
+    int x = 1;
+    int y = &x;
+    return y;
+}
@@ -0,0 +1,6 @@
+// expect: redeclared_local.c:4:9: Error analysing program: DuplicateDeclaration("a", "Redeclaration in the same scope")
+int main() {
+    int a = 1;
+    int a = 2;
+    return a;
+}
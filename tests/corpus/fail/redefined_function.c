@@ -0,0 +1,4 @@
+// expect: redefined_function.c:3:5: Error analysing program: DuplicateDeclaration("f", "Redefinition of function")
+int f(int a) { return a; }
+int f(int a) { return a + 1; }
+int main() { return f(1); }
@@ -0,0 +1,4 @@
+// expect: static_extern.c:3:12: Error analysing program: LinkageError(
+int count;
+static int count;
+int main() { return count; }
@@ -0,0 +1,4 @@
+// expect: undeclared.c:3:12: Error analysing program: UndeclaredIdentifier("missing"
+int main() {
+    return missing;
+}
@@ -0,0 +1,3 @@
+// expect: Error preprocessing: UnterminatedConditional("unterminated_if.c:3", "#if without #endif")
+#if 1
+int main() { return 0; }
@@ -0,0 +1,68 @@
+// Integer arithmetic across every size and signedness: promotions, the usual
+// arithmetic conversions, wrapping of unsigned values and truncation
+int putchar(int c);
+
+int print_number(long n) {
+    if (n < 0) {
+        putchar(45);
+        n = -n;
+    }
+    if (n >= 10)
+        print_number(n / 10);
+    putchar(48 + (int)(n % 10));
+    return 0;
+}
+
+int line(long n) {
+    print_number(n);
+    putchar(10);
+    return 0;
+}
+
+int main() {
+    char c = 100;
+    signed char sc = -128;
+    unsigned char uc = 255;
+    short s = 32767;
+    unsigned short us = 65535;
+    int i = -2147483647 - 1;
+    unsigned int u = 3000000000u;
+    long l = 9000000000000000000;
+    unsigned long ul = 18446744073709551615ul;
+
+    line(c + c);
+    line((char)(c + c));
+    line(sc - 1);
+    line((signed char)(sc - 1));
+    line(uc + 1);
+    line((unsigned char)(uc + 1));
+    line(s + 1);
+    line((short)(s + 1));
+    line(us * 2);
+    line((unsigned short)(us + 2));
+    line(i / -3);
+    line(i % 7);
+    line(u + u);
+    line(u / 7u * 7u + u % 7u);
+    line(l / 1000000 + l % 999983);
+    line((long)(ul / 3ul));
+    line((long)(ul % 1000ul));
+    line(-7 / 2);
+    line(-7 % 2);
+    line(7 / -2);
+    line(7 % -2);
+    line(-1 < 0u);
+    line(-1 < 0l);
+    line(-1l < 0u);
+    line((unsigned char)-1 == 255);
+    line(~0);
+    line(~c);
+    line(~uc);
+    line(!0 + !5);
+    line(-(i + 1));
+    line(sizeof(c) + sizeof(s) * 10 + sizeof(i) * 100 + sizeof(l) * 1000);
+    line(_Alignof(short) + _Alignof(long) * 10);
+    line(0x7fffffff + 0x10l);
+    line(017 + 0xff);
+    return (int)(u % 256u);
+}
@@ -0,0 +1,101 @@
+// Loops, branches, break and continue, short circuits and the conditional
+// operator, with calls whose side effects show the order of evaluation
+int putchar(int c);
+
+int print_number(long n) {
+    if (n < 0) {
+        putchar(45);
+        n = -n;
+    }
+    if (n >= 10)
+        print_number(n / 10);
+    putchar(48 + (int)(n % 10));
+    return 0;
+}
+
+int line(long n) {
+    print_number(n);
+    putchar(10);
+    return 0;
+}
+
+int calls = 0;
+
+int noted(int value) {
+    calls = calls * 10 + value;
+    return value;
+}
+
+int classify(int n) {
+    if (n < 0)
+        return -1;
+    else if (n == 0)
+        return 0;
+    else if (n < 10)
+        return 1;
+    return 2;
+}
+
+int main() {
+    long total = 0;
+    for (int i = 0; i < 20; i = i + 1) {
+        if (i % 3 == 0)
+            continue;
+        for (int j = i; j > 0; j = j - 2) {
+            if (j == 5)
+                break;
+            total = total + j;
+        }
+        if (total > 500)
+            break;
+    }
+    line(total);
+
+    int n = 0;
+    while (n < 100)
+        n = n * 2 + 1;
+    line(n);
+
+    int k = 10;
+    do {
+        k = k - 3;
+    } while (k > 0);
+    line(k);
+
+    do
+        k = k + 1;
+    while (0);
+    line(k);
+
+    int x = noted(1) && noted(0) && noted(2);
+    line(x);
+    line(calls);
+    calls = 0;
+    x = noted(0) || noted(3) || noted(4);
+    line(x);
+    line(calls);
+    calls = 0;
+    x = noted(1) ? noted(5) : noted(6);
+    line(x);
+    line(calls);
+
+    line(classify(-5) + classify(0) * 10 + classify(7) * 100
+         + classify(70) * 1000);
+
+    int depth = 0;
+    while (1) {
+        depth = depth + 1;
+        if (depth < 5)
+            continue;
+        break;
+    }
+    line(depth);
+
+    int nested = 0;
+    for (int a = 0; a < 4; a = a + 1)
+        for (int b = 0; b < 4; b = b + 1)
+            for (int c = 0; c < 4; c = c + 1)
+                nested = nested + (a == b ? 1 : b == c ? 10 : 100);
+    line(nested);
+    return depth + nested % 50;
+}
@@ -0,0 +1,77 @@
+// Float and double arithmetic, conversions to and from every integer type
+// and comparisons, printed in thousandths
+int putchar(int c);
+
+int print_number(long n) {
+    if (n < 0) {
+        putchar(45);
+        n = -n;
+    }
+    if (n >= 10)
+        print_number(n / 10);
+    putchar(48 + (int)(n % 10));
+    return 0;
+}
+
+int line(long n) {
+    print_number(n);
+    putchar(10);
+    return 0;
+}
+
+int thousandths(double d) {
+    return line((long)(d * 1000.0));
+}
+
+double average(double a, double b, float c) {
+    return (a + b + c) / 3;
+}
+
+float halve(float f) {
+    return f / 2.0f;
+}
+
+int main() {
+    float f = 1.1f;
+    double d = 2.75;
+    thousandths(f);
+    thousandths(f * f);
+    thousandths(d / 3.0);
+    thousandths(-d);
+    thousandths(f + d);
+    thousandths(average(1.0, 2.0, 4.5f));
+    thousandths(halve(7.0f));
+    thousandths(1e-3 * 4.0);
+    thousandths(0x1.8p1);
+
+    line((int)3.99);
+    line((int)-3.99);
+    line((long)1e15);
+    line((unsigned char)200.7);
+    line((short)-1234.5);
+    line((unsigned int)4e9 / 1000u);
+    line((long)(unsigned long)1.8e19 / 1000000000);
+
+    unsigned long big = 18000000000000000000ul;
+    thousandths((double)big / 1e18);
+    thousandths((float)big / 1e18f);
+    long negative = -123456789012l;
+    thousandths((double)negative / 1e9);
+    unsigned int u = 4294967295u;
+    thousandths((double)u / 1e6);
+    char c = -7;
+    thousandths(c * 0.5);
+
+    line((f < d) + (f > d) * 2 + (d == 2.75) * 4 + (f != 1.1f) * 8);
+    line((f <= 1.1f) + (d >= 3.0) * 2);
+    double zero = 0.0;
+    double nan = zero / zero;
+    line((nan < 1.0) + (nan > 1.0) * 2 + (nan == nan) * 4
+         + (nan != nan) * 8 + (nan <= nan) * 16 + (nan >= nan) * 32);
+    double infinity = 1.0 / zero;
+    line(infinity > 1e308);
+    line(-infinity < -1e308);
+    line(!zero + !d * 2);
+    line(d ? 1 : 2);
+    return (int)(d * 10);
+}
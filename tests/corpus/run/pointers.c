@@ -0,0 +1,93 @@
+// Pointers to locals and globals, pointers to pointers, const and volatile
+// qualified pointers and static locals which keep their value across calls
+int putchar(int c);
+
+int print_number(long n) {
+    if (n < 0) {
+        putchar(45);
+        n = -n;
+    }
+    if (n >= 10)
+        print_number(n / 10);
+    putchar(48 + (int)(n % 10));
+    return 0;
+}
+
+int line(long n) {
+    print_number(n);
+    putchar(10);
+    return 0;
+}
+
+long shared = 40;
+extern int later;
+
+int swap(int *a, int *b) {
+    int t = *a;
+    *a = *b;
+    *b = t;
+    return 0;
+}
+
+int count() {
+    static int calls;
+    calls = calls + 1;
+    return calls;
+}
+
+int redirect(int **pp, int *target) {
+    *pp = target;
+    return **pp;
+}
+
+int main() {
+    int x = 3;
+    int y = 4;
+    swap(&x, &y);
+    line(x * 10 + y);
+
+    int *p = &x;
+    int **pp = &p;
+    **pp = 9;
+    line(x);
+    line(redirect(pp, &y));
+    *p = *p + 1;
+    line(y);
+
+    long *g = &shared;
+    *g = *g + 2;
+    line(shared);
+
+    const int *readonly = &y;
+    line(*readonly);
+    volatile int v = 5;
+    volatile int *vp = &v;
+    *vp = *vp * 3;
+    line(v);
+
+    count();
+    count();
+    line(count());
+
+    char c = 65;
+    char *cp = &c;
+    *cp = *cp + 1;
+    line(c);
+
+    unsigned short us = 1;
+    unsigned short *usp = &us;
+    *usp = *usp - 2;
+    line(us);
+
+    double d = 1.5;
+    double *dp = &d;
+    *dp = *dp * 4.0;
+    line((long)d);
+
+    line(p == &y);
+    line(p != 0);
+    line(later);
+    return x + y;
+}
+
+int later = 77;
@@ -0,0 +1,63 @@
+// Object and function-like macros, conditional inclusion and token pasting
+#include <stdarg.h>
+
+#define WIDTH 12
+#define HEIGHT (WIDTH / 2)
+#define AREA WIDTH * HEIGHT
+#define SQUARE(x) ((x) * (x))
+#define MAX(a, b) ((a) > (b) ? (a) : (b))
+#define PASTE(a, b) a##b
+#define NEWLINE putchar(10)
+
+int putchar(int c);
+
+int print_number(long n) {
+    if (n < 0) {
+        putchar(45);
+        n = -n;
+    }
+    if (n >= 10)
+        print_number(n / 10);
+    putchar(48 + (int)(n % 10));
+    return 0;
+}
+
+#if defined(WIDTH) && WIDTH > 10
+int wide = 1;
+#elif WIDTH > 5
+int wide = 2;
+#else
+int wide = 3;
+#endif
+
+#ifdef HEIGHT
+#undef HEIGHT
+#define HEIGHT 3
+#endif
+
+#ifndef DEPTH
+#define DEPTH (HEIGHT + 1)
+#endif
+
+#if 0
+this is never compiled
+#endif
+
+int main() {
+    int PASTE(value, 1) = SQUARE(WIDTH - 2);
+    print_number(value1);
+    NEWLINE;
+    print_number(AREA);
+    NEWLINE;
+    print_number(MAX(DEPTH, wide) + MAX(-3, -8));
+    NEWLINE;
+    print_number(__LINE__);
+    NEWLINE;
+#if WIDTH * 2 == 24
+    print_number(SQUARE(SQUARE(2)));
+#else
+    print_number(0);
+#endif
+    NEWLINE;
+    return wide;
+}
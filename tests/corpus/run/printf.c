@@ -0,0 +1,18 @@
+// printf with formats held in longs, as there are no string literals
+int printf(char *format, ...);
+
+int main() {
+    long format = 0x0a646c25;
+    printf((char *)&format, -1234567890123l);
+    format = 0x0a7835;
+    printf((char *)&format, 255);
+    format = 0x0a66332e25;
+    printf((char *)&format, 2.0 / 3.0);
+    format = 0x0a7525;
+    printf((char *)&format, 4000000000u);
+    format = 0x0a63253525;
+    printf((char *)&format, 7, 88);
+    long text = 0x0a6b6f;
+    format = 0x7325;
+    return printf((char *)&format, (char *)&text);
+}
@@ -0,0 +1,65 @@
+// Recursive and mutually recursive functions, with arguments that have to
+// survive the calls
+int putchar(int c);
+
+int print_number(long n) {
+    if (n < 0) {
+        putchar(45);
+        n = -n;
+    }
+    if (n >= 10)
+        print_number(n / 10);
+    putchar(48 + (int)(n % 10));
+    return 0;
+}
+
+int line(long n) {
+    print_number(n);
+    putchar(10);
+    return 0;
+}
+
+int is_odd(int n);
+
+int is_even(int n) {
+    return n == 0 ? 1 : is_odd(n - 1);
+}
+
+int is_odd(int n) {
+    return n == 0 ? 0 : is_even(n - 1);
+}
+
+long fibonacci(int n) {
+    return n < 2 ? n : fibonacci(n - 1) + fibonacci(n - 2);
+}
+
+long gcd(long a, long b) {
+    if (b == 0)
+        return a;
+    return gcd(b, a % b);
+}
+
+int ackermann(int m, int n) {
+    if (m == 0)
+        return n + 1;
+    if (n == 0)
+        return ackermann(m - 1, 1);
+    return ackermann(m - 1, ackermann(m, n - 1));
+}
+
+long power(long base, int exponent) {
+    if (exponent == 0)
+        return 1;
+    long half = power(base, exponent / 2);
+    return exponent % 2 ? half * half * base : half * half;
+}
+
+int main() {
+    line(fibonacci(20));
+    line(gcd(1071, 462) + gcd(17, 5) * 100);
+    line(ackermann(2, 3) + ackermann(3, 3) * 100);
+    line(is_even(10) + is_odd(7) * 10 + is_even(3) * 100);
+    line(power(3, 13));
+    line(power(-2, 31));
+    return (int)fibonacci(11);
+}
@@ -0,0 +1,66 @@
+// More values live at once than there are registers, across calls, so the
+// allocator has to spill and reload them
+int putchar(int c);
+
+int print_number(long n) {
+    if (n < 0) {
+        putchar(45);
+        n = -n;
+    }
+    if (n >= 10)
+        print_number(n / 10);
+    putchar(48 + (int)(n % 10));
+    return 0;
+}
+
+int line(long n) {
+    print_number(n);
+    putchar(10);
+    return 0;
+}
+
+long identity(long n) {
+    return n;
+}
+
+double scaled(double d) {
+    return d * 2;
+}
+
+int main() {
+    long a = identity(1);
+    long b = identity(2);
+    long c = identity(3);
+    long d = identity(4);
+    long e = identity(5);
+    long f = identity(6);
+    long g = identity(7);
+    long h = identity(8);
+    long i = identity(9);
+    long j = identity(10);
+    long k = identity(11);
+    long l = identity(12);
+    long m = identity(13);
+    long n = identity(14);
+    long o = identity(15);
+    long p = identity(16);
+    double x = scaled(0.5);
+    double y = scaled(1.5);
+    double z = scaled(2.5);
+    double w = scaled(3.5);
+    long first = a * b + c * d + e * f + g * h;
+    long second = i * j + k * l + m * n + o * p;
+    line(first);
+    line(second);
+    line(a + b + c + d + e + f + g + h + i + j + k + l + m + n + o + p);
+    line((long)(x + y * z - w));
+    long total = 0;
+    for (int r = 0; r < 5; r = r + 1) {
+        total = total + identity(a + r) * b - c + identity(d * r) + e - f
+                + g * identity(h) - i + j - k + l * identity(m) - n + o
+                + (long)scaled(x + r);
+    }
+    line(total);
+    line(a - p + b - o + c - n + d - m + e - l + f - k + g - j + h - i);
+    return (int)(total % 200);
+}
@@ -0,0 +1,65 @@
+// Static and global variables of every type, zero initialised, constant
+// initialised and read only, shared between functions
+int putchar(int c);
+
+int print_number(long n) {
+    if (n < 0) {
+        putchar(45);
+        n = -n;
+    }
+    if (n >= 10)
+        print_number(n / 10);
+    putchar(48 + (int)(n % 10));
+    return 0;
+}
+
+int line(long n) {
+    print_number(n);
+    putchar(10);
+    return 0;
+}
+
+char small = -5;
+unsigned char byte = 250;
+short half = -300;
+unsigned short uhalf = 65000;
+int number = 123456;
+unsigned int unumber = 4000000000u;
+long wide = -9000000000l;
+unsigned long uwide = 10000000000000000000ul;
+float ratio = 0.25f;
+double precise = 3.125;
+const long limit = 1000 * 1000;
+static int counter;
+long unset;
+double dunset;
+int *nowhere;
+
+int bump(int by) {
+    counter = counter + by;
+    return counter;
+}
+
+int next_id() {
+    static long id = 100;
+    id = id + 1;
+    return (int)id;
+}
+
+int main() {
+    line(small + byte + half + uhalf);
+    line(number + unumber / 1000u);
+    line(wide / 1000 + (long)(uwide / 1000000000000ul));
+    line((long)(ratio * 1000 + precise * 1000));
+    line(limit);
+    line(unset + (long)dunset + (nowhere == 0));
+    bump(3);
+    bump(4);
+    line(counter);
+    next_id();
+    line(next_id());
+    byte = byte + 10;
+    half = half * 200;
+    line(byte + half);
+    return counter + next_id();
+}
@@ -0,0 +1,78 @@
+// Variadic functions with int, long and double arguments, and calls with
+// more arguments than fit in registers
+#include <stdarg.h>
+
+int putchar(int c);
+
+int print_number(long n) {
+    if (n < 0) {
+        putchar(45);
+        n = -n;
+    }
+    if (n >= 10)
+        print_number(n / 10);
+    putchar(48 + (int)(n % 10));
+    return 0;
+}
+
+int line(long n) {
+    print_number(n);
+    putchar(10);
+    return 0;
+}
+
+// Each argument is preceded by its kind: 1 for int, 2 for long, 3 for double
+long mixed(int count, ...) {
+    va_list args;
+    va_start(args, count);
+    double total = 0;
+    for (int i = 0; i < count; i = i + 1) {
+        int kind = va_arg(args, int);
+        if (kind == 1)
+            total = total * 10 + va_arg(args, int);
+        else if (kind == 2)
+            total = total * 10 + va_arg(args, long);
+        else
+            total = total * 10 + va_arg(args, double);
+    }
+    va_end(args);
+    return (long)total;
+}
+
+int sum_ints(int count, ...) {
+    va_list args;
+    va_start(args, count);
+    int total = 0;
+    while (count > 0) {
+        total = total + va_arg(args, int);
+        count = count - 1;
+    }
+    va_end(args);
+    return total;
+}
+
+long many(long a, long b, long c, long d, long e, long f, long g, long h,
+          int i, int j) {
+    return a - b + c - d + e - f + g - h + i * 100 + j * 1000;
+}
+
+double many_doubles(double a, double b, double c, double d, double e,
+                    double f, double g, double h, double i, double j,
+                    long k, float l) {
+    return a + b * 2 + c * 3 + d * 4 + e * 5 + f * 6 + g * 7 + h * 8 + i * 9
+           + j * 10 + k * 11 + l * 12;
+}
+
+int main() {
+    line(mixed(3, 1, 4, 2, 5000000000l, 3, 2.5));
+    line(mixed(0));
+    line(sum_ints(10, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10));
+    char c = 3;
+    short s = -4;
+    float f = 1.5f;
+    line(mixed(3, 1, c, 1, s, 3, f));
+    line(many(1, 2, 3, 4, 5, 6, 7, 8, 9, 10));
+    line((long)many_doubles(1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0,
+                            1, 0.5f));
+    return sum_ints(2, 20, 22);
+}
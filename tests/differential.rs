@@ -0,0 +1,168 @@
+// Every program in tests/corpus/run is compiled by both gcc and ccc, at each
+// optimisation level and through ccc run, and has to print the same and exit
+// with the same status. Every program in tests/corpus/fail has to be
+// rejected with the diagnostic given on its first line, as
+// "// expect: <text>", and without producing an executable
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const LEVELS: [&str; 3] = ["-O0", "-O1", "-O2"];
+
+// The .c files of a corpus directory, in name order
+fn corpus(kind: &str) -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("corpus")
+        .join(kind);
+    let mut programs: Vec<PathBuf> = fs::read_dir(&dir)
+        .expect("Failed to read corpus")
+        .map(|entry| entry.expect("Failed to read corpus").path())
+        .filter(|path| path.extension().is_some_and(|e| e == "c"))
+        .collect();
+    programs.sort();
+    assert!(!programs.is_empty(), "No programs in {}", dir.display());
+    return programs;
+}
+
+// Copies a program into the test directory, as ccc writes its assembly to
+// the working directory, and returns its name without the extension
+fn prepare(dir: &Path, program: &Path) -> String {
+    fs::create_dir_all(dir).expect("Failed to create test directory");
+    let file = program.file_name().expect("Corpus entry without a name");
+    fs::copy(program, dir.join(file)).expect("Failed to copy program");
+    let name = program.file_stem().expect("Corpus entry without a name");
+    return name.to_string_lossy().into_owned();
+}
+
+fn ccc(dir: &Path, options: &[&str], file: &str) -> Output {
+    return Command::new(env!("CARGO_BIN_EXE_ccc"))
+        .args(options)
+        .arg(file)
+        .current_dir(dir)
+        .output()
+        .expect("Failed to execute ccc");
+}
+
+// Runs an executable and returns (stdout, exit code), or why it didn't exit
+fn execute(path: &Path) -> Result<(String, i32), String> {
+    let output = match Command::new(path).output() {
+        Ok(o) => o,
+        Err(e) => return Err(format!("couldn't be run: {}", e)),
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    match output.status.code() {
+        Some(code) => return Ok((stdout, code)),
+        None => return Err(format!("was killed: {:?}", output.status)),
+    }
+}
+
+// Compiles and runs a program with ccc in each way, and describes every way
+// whose result differs from gcc's
+fn compare(dir: &Path, program: &Path) -> Vec<String> {
+    let name = prepare(dir, program);
+    let file = format!("{}.c", name);
+    let mut failures = Vec::new();
+
+    let reference = dir.join(format!("{}_gcc", name));
+    let gcc = Command::new("gcc")
+        .args(["-w", "-o"])
+        .arg(&reference)
+        .arg(&file)
+        .current_dir(dir)
+        .output()
+        .expect("Failed to execute gcc");
+    assert!(gcc.status.success(), "{}", String::from_utf8_lossy(&gcc.stderr));
+    let expected = match execute(&reference) {
+        Ok(result) => result,
+        Err(e) => panic!("{} compiled by gcc {}", file, e),
+    };
+
+    for level in LEVELS {
+        let executable = dir.join(&name);
+        let _ = fs::remove_file(&executable);
+        let output = ccc(dir, &[level], &file);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !stderr.is_empty() {
+            failures.push(format!("{} {}: {}", file, level, stderr.trim_end()));
+            continue;
+        }
+        match execute(&executable) {
+            Ok(result) if result == expected => (),
+            Ok(result) => failures.push(format!(
+                "{} {}: got {:?}, gcc gave {:?}",
+                file, level, result, expected
+            )),
+            Err(e) => failures.push(format!("{} {}: {}", file, level, e)),
+        }
+    }
+
+    let output = ccc(dir, &["run", "-O2"], &file);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let result = (
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        output.status.code().unwrap_or(-1),
+    );
+    if !stderr.is_empty() {
+        failures.push(format!("{} run: {}", file, stderr.trim_end()));
+    } else if result != expected {
+        failures.push(format!(
+            "{} run: got {:?}, gcc gave {:?}",
+            file, result, expected
+        ));
+    }
+    return failures;
+}
+
+// Compiles a program which ccc has to reject, and describes what went wrong
+// if it wasn't rejected with the expected diagnostic
+fn reject(dir: &Path, program: &Path) -> Option<String> {
+    let name = prepare(dir, program);
+    let file = format!("{}.c", name);
+    let source = fs::read_to_string(program).expect("Failed to read program");
+    let expected = match source.lines().next() {
+        Some(l) => match l.strip_prefix("// expect: ") {
+            Some(e) => e,
+            None => panic!("{} has no expected diagnostic", file),
+        },
+        None => panic!("{} is empty", file),
+    };
+
+    let executable = dir.join(&name);
+    let _ = fs::remove_file(&executable);
+    let output = ccc(dir, &[], &file);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !stderr.contains(expected) {
+        return Some(format!(
+            "{}: expected {:?}, got {:?}",
+            file, expected, stderr
+        ));
+    }
+    if output.status.success() {
+        return Some(format!("{}: ccc exited successfully", file));
+    }
+    if executable.exists() {
+        return Some(format!("{}: produced an executable", file));
+    }
+    return None;
+}
+
+#[test]
+fn programs_match_gcc() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("differential");
+    let mut failures = Vec::new();
+    for program in corpus("run") {
+        failures.extend(compare(&dir, &program));
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn programs_are_rejected() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("rejected");
+    let mut failures = Vec::new();
+    for program in corpus("fail") {
+        failures.extend(reject(&dir, &program));
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
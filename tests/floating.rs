@@ -0,0 +1,155 @@
+// float and double: literals in each form, arithmetic and comparisons in SSE
+// registers, conversions to and from integers, and passing them in %xmm0-7
+// and on the stack to and from gcc compiled code
+mod common;
+
+const HELPERS: &str = r#"
+#include <stdio.h>
+
+int put_double(double d) {
+    return printf("%.4f\n", d);
+}
+
+int put_float(float f) {
+    return printf("%.4f\n", f);
+}
+
+int put_long(long l) {
+    return printf("%ld\n", l);
+}
+
+double sum(int a, double b, float c, long d, double e, float f, double g,
+           double h, double i, double j) {
+    return a + b + c + d + e + f + g + h + i + j;
+}
+"#;
+
+const DECLARATIONS: &str = "
+int put_double(double d);
+int put_float(float f);
+int put_long(long l);
+double sum(int a, double b, float c, long d, double e, float f, double g,
+           double h, double i, double j);
+";
+
+#[test]
+fn arithmetic() {
+    let source = format!(
+        "{}{}",
+        DECLARATIONS,
+        "
+int main() {
+    double a = 1.5e2;
+    double b = 0x1.8p1;
+    float c = 2.5f;
+    double d = .25;
+    double e = 1e-2;
+    put_double(a + b);
+    put_double(a - b);
+    put_double(a * d);
+    put_double(b / d);
+    put_double(e);
+    put_float(c * c);
+    put_long((a > b) + (a < b) * 2 + (a == 150.0) * 4 + (b != 3.0) * 8 +
+             (d <= 0.25) * 16 + (e >= 0.1) * 32);
+    if (e) {
+        put_long(1);
+    }
+    return a / 10;
+}
+"
+    );
+    let expected = "153.0000\n147.0000\n37.5000\n12.0000\n0.0100\n6.2500\n\
+                    21\n1\n";
+    for level in ["-O0", "-O1", "-O2"] {
+        let name = format!("arithmetic{}", level.replace('-', "_"));
+        let (out, code) = common::run_with_options(
+            "floating",
+            &name,
+            HELPERS,
+            &source,
+            &[level],
+        );
+        assert_eq!(out, expected, "at {}", level);
+        assert_eq!(code, 15, "at {}", level);
+    }
+}
+
+#[test]
+fn conversions() {
+    let source = format!(
+        "{}{}",
+        DECLARATIONS,
+        "
+int main() {
+    double a = 150.75;
+    int i = a;
+    long l = -a * 3;
+    double n = -7;
+    float m = i;
+    unsigned u = 3000000000u;
+    double v = u;
+    char c = 2.9;
+    put_long(i);
+    put_long(l);
+    put_double(n);
+    put_float(m);
+    put_double(v);
+    put_long(c);
+    return (float)a > i;
+}
+"
+    );
+    let (out, code) = common::run("floating", "conversions", HELPERS, &source);
+    assert_eq!(out, "150\n-452\n-7.0000\n150.0000\n3000000000.0000\n2\n");
+    assert_eq!(code, 1);
+}
+
+// Ten floating arguments with integers between them fill %xmm0-7 and go on
+// the stack, both calling gcc's code and being called with them
+#[test]
+fn passing() {
+    let source = format!(
+        "{}{}",
+        DECLARATIONS,
+        "
+double mix(int a, double b, float c, long d, double e, float f, double g,
+           double h, double i, double j) {
+    return a + b * c - d + e / f + g + h + i + j;
+}
+
+float half(float x) {
+    return x / 2;
+}
+
+int main() {
+    put_double(sum(1, 2.0, 3.0f, 4, 5.0, 6.0f, 7.0, 8.0, 9.0, 10.0));
+    put_double(mix(1, 2.0, 3.0f, 4, 5.0, 6.0f, 7.0, 8.0, 9.0, 10.0));
+    put_float(half(2.5f));
+    return 0;
+}
+"
+    );
+    let (out, code) = common::run("floating", "passing", HELPERS, &source);
+    assert_eq!(out, "55.0000\n37.8333\n1.2500\n");
+    assert_eq!(code, 0);
+}
+
+#[test]
+fn instructions() {
+    let source = "
+double scale(double d, int n) {
+    if (d < n) {
+        return d * n - 0.5;
+    }
+    return (float)d / 2;
+}
+";
+    let assembly = common::assembly("floating", "instructions", source, &[]);
+    for instruction in [
+        "movsd", "mulsd", "subsd", "divss", "ucomisd", "cvtsi2sd", "cvtsd2ss",
+        "%xmm0",
+    ] {
+        assert!(assembly.contains(instruction), "{}", assembly);
+    }
+}
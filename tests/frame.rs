@@ -0,0 +1,60 @@
+// Locals in the frame laid out up front, and leaving scopes with break and
+// continue
+mod common;
+
+#[test]
+fn loop_control() {
+    let source = "
+int main() {
+    int sum = 0;
+    for (int i = 0; i < 10; i = i + 1) {
+        int j = i * 2;
+        if (j == 6) continue;
+        if (j > 14) break;
+        sum = sum + j;
+    }
+    int k = 0;
+    while (1) {
+        long step = 1;
+        k = k + step;
+        if (k < 5) continue;
+        break;
+    }
+    int n = 0;
+    do {
+        n = n + 1;
+        if (n == 2) continue;
+        sum = sum + 100;
+    } while (n < 4);
+    return sum + k - 300;
+}
+";
+    let (_, code) = common::run("frame", "loop_control", "", source);
+    assert_eq!(code, 50 + 300 + 5 - 300);
+}
+
+#[test]
+fn sibling_scopes() {
+    let source = "
+int f(char c, double d, short s, float x) {
+    long total = c;
+    {
+        char a = 1;
+        double b = d;
+        total = total + a + (long)b;
+    }
+    {
+        int q = 9;
+        short r = s;
+        total = total + q + r + (int)x;
+    }
+    return (int)total;
+}
+
+int main() {
+    return f(-3, 7.9, 200, 4.5);
+}
+";
+    let (_, code) = common::run("frame", "sibling_scopes", "", source);
+    assert_eq!(code, -3 + 1 + 7 + 9 + 200 + 4);
+}
@@ -0,0 +1,192 @@
+// Compiles programs with ccc, links them against helpers built by gcc and
+// checks the output, so calls follow the System V ABI in both directions
+mod common;
+
+// Helpers compiled by gcc. aligned() reports whether %rsp was 16 byte
+// aligned at the call, the rest go through libc's printf, which uses SSE
+// instructions needing an aligned stack
+const HELPERS: &str = r#"
+#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+
+__attribute__((noinline)) int aligned(void) {
+    uintptr_t sp = (uintptr_t)__builtin_frame_address(0) + 16;
+    if (sp % 16 != 0) {
+        printf("misaligned\n");
+        return 0;
+    }
+    return 1;
+}
+
+int print_int(int i) {
+    printf("%d\n", i);
+    fflush(stdout);
+    return aligned();
+}
+
+int print_double(double d) {
+    printf("%.3f\n", d);
+    fflush(stdout);
+    return aligned();
+}
+
+int print_many(int a, int b, int c, int d, int e, int f, int g, int h,
+               double x, double y) {
+    printf("%d %d %d %d %d %d %d %d %.1f %.1f\n", a, b, c, d, e, f, g, h, x,
+           y);
+    fflush(stdout);
+    return aligned();
+}
+
+int print_sum(int count, ...) {
+    va_list args;
+    va_start(args, count);
+    long total = 0;
+    for (int i = 0; i < count; i++) {
+        total += va_arg(args, int);
+    }
+    va_end(args);
+    printf("%ld\n", total);
+    fflush(stdout);
+    return aligned();
+}
+
+int call_back(int (*f)(int), int i) {
+    return f(i);
+}
+"#;
+
+const DECLARATIONS: &str = "\
+int aligned();
+int print_int(int i);
+int print_double(double d);
+int print_many(int a, int b, int c, int d, int e, int f, int g, int h,
+               double x, double y);
+int print_sum(int count, ...);
+";
+
+fn run(name: &str, source: &str) -> (String, i32) {
+    let source = format!("{}{}", DECLARATIONS, source);
+    return common::run("interop", name, HELPERS, &source);
+}
+
+#[test]
+fn call_from_main() {
+    let (out, code) = run("call_from_main", "int main() { return aligned(); }");
+    assert_eq!(out, "");
+    assert_eq!(code, 1);
+}
+
+#[test]
+fn odd_variable_counts() {
+    let source = "
+int main() {
+    int a = 1;
+    int ok = print_int(a);
+    {
+        int b = 2;
+        ok = ok + print_int(a + b);
+        {
+            int c = 3;
+            ok = ok + print_int(a + b + c);
+        }
+    }
+    return ok;
+}
+";
+    let (out, code) = run("odd_variable_counts", source);
+    assert_eq!(out, "1\n3\n6\n");
+    assert_eq!(code, 3);
+}
+
+#[test]
+fn calls_with_pending_temporaries() {
+    let source = "
+int main() {
+    int x = 10;
+    int ok = 1 + (2 * (3 + print_int(x)));
+    ok = ok + (x - (x * print_double(2.5 * x)));
+    double d = 1.5 + (2.0 * print_double(0.25));
+    return ok + (int)d;
+}
+";
+    let (out, code) = run("calls_with_pending_temporaries", source);
+    assert_eq!(out, "10\n25.000\n0.250\n");
+    assert_eq!(code, 9 + 3);
+}
+
+#[test]
+fn nested_call_arguments() {
+    let source = "
+int add(int a, int b) { return print_int(a + b) + a + b; }
+
+int main() {
+    int x = 1;
+    return add(print_int(add(x, 2)), add(print_double(0.5), print_int(4)));
+}
+";
+    let (out, code) = run("nested_call_arguments", source);
+    // Arguments are evaluated last to first
+    assert_eq!(out, "4\n0.500\n2\n3\n4\n4\n");
+    assert_eq!(code, 5);
+}
+
+#[test]
+fn stack_arguments() {
+    let source = "
+int main() {
+    int a = 1;
+    int ok = print_many(1, 2, 3, 4, 5, 6, 7, 8, 0.5, 1.5);
+    {
+        int b = 2;
+        ok = ok + print_many(a, b, 3, 4, 5, 6, 7, 8 + print_int(9), 2.5, 3.5);
+    }
+    return ok;
+}
+";
+    let (out, code) = run("stack_arguments", source);
+    assert_eq!(out, "1 2 3 4 5 6 7 8 0.5 1.5\n9\n1 2 3 4 5 6 7 9 2.5 3.5\n");
+    assert_eq!(code, 2);
+}
+
+#[test]
+fn variadic_calls() {
+    let source = "
+int main() {
+    int x = 3;
+    int ok = print_sum(3, 1, 2, x);
+    ok = ok + 2 * print_sum(7, 1, 2, 3, 4, 5, 6, 7);
+    return ok + print_sum(8, 1, 2, 3, 4, 5, 6, 7, print_int(8));
+}
+";
+    let (out, code) = run("variadic_calls", source);
+    assert_eq!(out, "6\n28\n8\n29\n");
+    assert_eq!(code, 4);
+}
+
+#[test]
+fn variadic_definition() {
+    let source = "
+#include <stdarg.h>
+
+int total(int count, ...) {
+    va_list args;
+    va_start(args, count);
+    int sum = 0;
+    for (int i = 0; i < count; i = i + 1) {
+        sum = sum + va_arg(args, int);
+    }
+    va_end(args);
+    return sum + 100 * aligned();
+}
+
+int main() {
+    int a = 4;
+    return total(9, 1, 2, 3, a, 5, 6, 7, 8, total(1, 9) - 100);
+}
+";
+    let (out, code) = run("variadic_definition", source);
+    assert_eq!(out, "");
+    assert_eq!(code, 145);
+}
@@ -0,0 +1,95 @@
+// The IR printed by --dump-ir, and programs whose values cross blocks
+mod common;
+
+#[test]
+fn dump() {
+    let source = "
+static int limit = 3;
+
+int pick(int a, int b) {
+    return a > limit ? a : b;
+}
+";
+    let ir = common::dump_ir("ir", "dump", source, &[]);
+    let expected = [
+        "data @limit: i32 = 3",
+        "global function @pick(%0: i32, %2: i32) -> i32 {",
+        "  $0 = slot 4 align 4 ; a",
+        "  %6 = global @limit",
+        "  %8 = cmp greater i32 %5, %7",
+        "  branch %8, b1, b2",
+        "b3(%9: i32):",
+        "  ret %9",
+    ];
+    for line in expected {
+        assert!(ir.lines().any(|l| l == line), "missing {:?} in\n{}", line, ir);
+    }
+}
+
+#[test]
+fn joined_values() {
+    let source = "
+double half(long n) {
+    return n / 2.0;
+}
+
+int main() {
+    double big = 12345678.5;
+    double total = 0.0;
+    for (int i = 0; i < 4 && total < big; i = i + 1) {
+        total = total + (i % 2 ? half(i) : -1.25);
+    }
+    if (total != -2.5 + 0.5 + 1.5) return 1;
+    return (int)(big - 12345600.0);
+}
+";
+    let (_, code) = common::run("ir", "joined_values", "", source);
+    assert_eq!(code, 78);
+}
+
+#[test]
+fn jumps_outside_loops() {
+    for keyword in ["break", "continue"] {
+        let source = format!(
+            "
+int main(int argc) {{
+    while (argc) {{
+        argc = argc - 1;
+    }}
+    if (argc == 0)
+        {};
+    return 0;
+}}
+",
+            keyword
+        );
+        let errors = common::diagnostics("ir", keyword, &source);
+        let expected = format!(
+            "Error analysing program: JumpError(\"{0}\", \"'{0}' statement \
+             not in a loop\")",
+            keyword
+        );
+        assert!(errors.contains(&expected), "{}", errors);
+    }
+}
+
+#[test]
+fn redeclared_locals() {
+    let source = "
+int main(int a) {
+    for (int i = 0; i < 2; i = i + 1) {
+        int i = 3;
+    }
+    {
+        int a = 4;
+    }
+    int a = 2;
+    return a;
+}
+";
+    let errors = common::diagnostics("ir", "redeclared", source);
+    let expected = "redeclared.c:9:9: Error analysing program: \
+                    DuplicateDeclaration(\"a\", \"Redeclaration in the same \
+                    scope\")";
+    assert!(errors.contains(expected), "{}", errors);
+}
@@ -0,0 +1,140 @@
+// Object files from the built-in encoder link with gcc's and with each other
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+const HELPERS: &str = r#"
+#include <stdio.h>
+
+int print_long(long i) {
+    printf("%ld\n", i);
+    return 0;
+}
+"#;
+
+// Defines the data and functions the other object uses, with enough live
+// values to reach every register, byte sized arithmetic and doubles kept in
+// SSE registers across a call
+const LIBRARY: &str = "
+long total = 5;
+static int calls;
+
+char wrap(char c) {
+    char d = c + 100;
+    return d;
+}
+
+long mix(long a, long b, long c, long d, long e, long f, long g) {
+    long p = a * b;
+    long q = c - d;
+    long r = e + f;
+    long s = p / (g + 1);
+    long t = q * r;
+    long u = s - t;
+    calls = calls + 1;
+    total = total + p + q + r + s + t + u + calls;
+    return p + q + r + s + t + u;
+}
+
+double scale(double x) {
+    return x * 1.5;
+}
+
+double twice(double x) {
+    double a = scale(x);
+    double b = scale(a);
+    wrap(1);
+    return a + b;
+}
+
+long blend(long n) {
+    double a = scale(n);
+    double b = twice(a);
+    return a * 10 + b;
+}
+";
+
+const MAIN: &str = "
+int print_long(long i);
+char wrap(char c);
+long mix(long a, long b, long c, long d, long e, long f, long g);
+long blend(long n);
+extern long total;
+long *total_at = &total;
+
+int main() {
+    long sum = 0;
+    for (int i = 0; i < 300; i = i + 1) {
+        sum = sum + wrap(i);
+    }
+    print_long(sum);
+    print_long(mix(1, 2, 3, 4, 5, 6, 7));
+    print_long(mix(-9, 80, 700, 6000, 50000, 400000, 3000000));
+    print_long(*total_at);
+    print_long(blend(4));
+    return wrap(30);
+}
+";
+
+// Links the objects with the helpers, runs the program and returns
+// (stdout, exit code)
+fn link_and_run(name: &str, objects: &[PathBuf]) -> (String, i32) {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("object");
+    let helper_c = dir.join(format!("{}_helpers.c", name));
+    fs::write(&helper_c, HELPERS).expect("Failed to write helpers");
+    let program = dir.join(name);
+    let gcc = Command::new("gcc")
+        .arg(&helper_c)
+        .args(objects)
+        .arg("-o")
+        .arg(&program)
+        .output()
+        .expect("Failed to execute gcc");
+    assert!(gcc.status.success(), "{}", String::from_utf8_lossy(&gcc.stderr));
+
+    let output =
+        Command::new(&program).output().expect("Failed to run linked program");
+    let code = match output.status.code() {
+        Some(c) => c,
+        None => panic!("{} was killed: {:?}", name, output.status),
+    };
+    return (String::from_utf8_lossy(&output.stdout).into_owned(), code);
+}
+
+#[test]
+fn objects_link() {
+    for level in ["-O0", "-O1", "-O2"] {
+        let name = format!("program{}", level);
+        let library = common::object(
+            "object",
+            &format!("library{}", level),
+            LIBRARY,
+            &[level],
+        );
+        let main = common::object("object", &name, MAIN, &[level]);
+        let (output, code) = link_and_run(&name, &[library, main]);
+        assert_eq!(output, "1122\n12\n443980\n444000\n82\n", "at {}", level);
+        assert_eq!(code, 130, "at {}", level);
+    }
+}
+
+// The same source gives the same program through -c as through gcc
+#[test]
+fn matches_assembler() {
+    let library = common::object("object", "assembled_library", LIBRARY, &[]);
+    let main = common::object("object", "assembled_main", MAIN, &[]);
+    let from_objects = link_and_run("assembled_objects", &[library, main]);
+
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("object");
+    let library_s = common::assembly("object", "source_library", LIBRARY, &[]);
+    let main_s = common::assembly("object", "source_main", MAIN, &[]);
+    let library_path = dir.join("source_library.s");
+    let main_path = dir.join("source_main.s");
+    fs::write(&library_path, library_s).expect("Failed to write assembly");
+    fs::write(&main_path, main_s).expect("Failed to write assembly");
+    let from_assembly =
+        link_and_run("assembled_source", &[library_path, main_path]);
+    assert_eq!(from_objects, from_assembly);
+}
@@ -0,0 +1,226 @@
+// Programs built at each -O level behave as they do without optimisation,
+// and the optimisations show in the IR
+mod common;
+
+const HELPERS: &str = r#"
+#include <stdio.h>
+
+int print_int(int i) {
+    printf("%d\n", i);
+    return i;
+}
+
+int print_double(double d) {
+    printf("%.6f\n", d);
+    return (int)d;
+}
+"#;
+
+const DECLARATIONS: &str = "\
+int print_int(int i);
+int print_double(double d);
+";
+
+// Runs the program at every level and checks they all print and return
+// what -O0 does, which is returned
+fn run_all_levels(name: &str, source: &str) -> (String, i32) {
+    let source = format!("{}{}", DECLARATIONS, source);
+    let mut results: Vec<(String, i32)> = Vec::new();
+    for level in ["-O0", "-O1", "-O2"] {
+        let level_name = format!("{}{}", name, level.replace('-', "_"));
+        results.push(common::run_with_options(
+            "optimise",
+            &level_name,
+            HELPERS,
+            &source,
+            &[level],
+        ));
+    }
+    assert_eq!(results[1], results[0], "-O1 differs from -O0");
+    assert_eq!(results[2], results[0], "-O2 differs from -O0");
+    return results.remove(0);
+}
+
+#[test]
+fn arithmetic() {
+    let source = "
+int main() {
+    int big = 2147483647;
+    unsigned int u = 4000000000;
+    long l = -7;
+    print_int(big + 1);
+    print_int(u / 3u > 1000000000);
+    print_int(-7 / 2 + -7 % 2);
+    print_int((int)(l * 3000000000 / 1000000));
+    print_int((unsigned char)300 + (signed char)200);
+    print_int(~5 == -6);
+    print_double(1.0 / 3.0 + (float)0.1);
+    print_double((double)u + (float)-2.5);
+    print_int((int)-3.9 + (int)(unsigned long)1e19 / 1000000000);
+    return 2 * 3 + 4;
+}
+";
+    let (out, code) = run_all_levels("arithmetic", source);
+    assert_eq!(code, 10);
+    assert_eq!(out.lines().next(), Some("-2147483648"));
+}
+
+#[test]
+fn control_flow() {
+    let source = "
+int calls = 0;
+
+int count(int x) {
+    calls = calls + 1;
+    return x;
+}
+
+int main() {
+    int sum = 0;
+    for (int i = 0; i < 20; i = i + 1) {
+        if (i % 3 == 0) continue;
+        int j = 0;
+        while (j < i) {
+            j = j + 2;
+            if (j > 10) break;
+        }
+        sum = sum + (j > 5 ? j : -j);
+        if (sum > 1000) break;
+    }
+    int n = 0;
+    do {
+        n = n + (count(n) || count(1)) + (count(0) && count(1));
+    } while (n < 5);
+    print_int(sum);
+    print_int(calls);
+    return n;
+}
+";
+    run_all_levels("control_flow", source);
+}
+
+#[test]
+fn functions() {
+    let source = "
+static int square(int x) { return x * x; }
+
+int fib(int n) {
+    if (n < 2) return n;
+    return fib(n - 1) + fib(n - 2);
+}
+
+char wrap(char c) { return c + 1; }
+
+double scale(float f, double d, short s) { return f * d + s; }
+
+int main() {
+    int total = 0;
+    for (int i = 0; i < 10; i = i + 1) {
+        total = total + square(i) + fib(i);
+    }
+    print_int(total);
+    print_int(wrap(127));
+    print_int(wrap(300));
+    print_double(scale(0.5, 3.0, 70000));
+    return square(fib(7)) % 200;
+}
+";
+    run_all_levels("functions", source);
+}
+
+#[test]
+fn memory() {
+    let source = "
+static int counter = 5;
+
+int next() {
+    static int value = 10;
+    value = value + counter;
+    return value;
+}
+
+int set(int *p, int v) { *p = v; return v; }
+
+int main() {
+    int a = 1;
+    int *p = &a;
+    set(p, 7);
+    int b = a + *p;
+    volatile int v = 3;
+    v = v + 1;
+    long l = 2;
+    long *q = &l;
+    *q = *q * 21;
+    next();
+    print_int(next());
+    print_int(b);
+    return v + (int)l;
+}
+";
+    let (out, code) = run_all_levels("memory", source);
+    assert_eq!(out, "20\n14\n");
+    assert_eq!(code, 46);
+}
+
+#[test]
+fn traps_stay_guarded() {
+    // The division is invariant in the loop but must not run when d is 0
+    let source = "
+int divide(int n, int d) {
+    int total = 0;
+    for (int i = 0; i < n; i = i + 1) {
+        if (d != 0) total = total + 100 / d;
+    }
+    return total;
+}
+
+int main() {
+    print_int(divide(5, 0));
+    print_int(divide(3, 7));
+    int zero = 0;
+    if (zero) return 1 / zero;
+    return 0;
+}
+";
+    let (out, code) = run_all_levels("traps_stay_guarded", source);
+    assert_eq!(out, "0\n42\n");
+    assert_eq!(code, 0);
+}
+
+#[test]
+fn folded_ir() {
+    let source = "
+int main() {
+    int a = 2 * 3 + 4;
+    int b = a * 2;
+    if (b > 100) return 1;
+    return b - a;
+}
+";
+    let ir = common::dump_ir("optimise", "folded_ir", source, &["-O1"]);
+    assert!(ir.contains("  %0 = const i32 10\n  ret %0\n"), "{}", ir);
+    assert!(!ir.contains("slot"), "{}", ir);
+    assert!(!ir.contains("branch"), "{}", ir);
+}
+
+#[test]
+fn inlined_and_hoisted_ir() {
+    let source = "
+int square(int x) { return x * x; }
+
+int sum(int n, int k) {
+    int total = 0;
+    for (int i = 0; i < n; i = i + 1) {
+        total = total + square(k) + i;
+    }
+    return total;
+}
+";
+    let ir = common::dump_ir("optimise", "inlined_ir", source, &["-O2"]);
+    assert!(!ir.contains("call"), "{}", ir);
+    // The square of k is worked out once, before the loop
+    let sum = &ir[ir.find("@sum").unwrap()..];
+    let multiply = sum.find("multiply").unwrap();
+    let loop_start = sum.find("branch").unwrap();
+    assert!(multiply < loop_start, "{}", ir);
+}
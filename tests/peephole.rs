@@ -0,0 +1,127 @@
+// The peephole pass at -O1 and above tidies the generated assembly without
+// changing what programs do
+mod common;
+
+const HELPERS: &str = r#"
+#include <stdio.h>
+
+int print_int(int i) {
+    printf("%d\n", i);
+    return i;
+}
+"#;
+
+const SOURCE: &str = "
+int print_int(int i);
+
+int add(int a, int b) { return a + b; }
+
+int pick(int c, int a, int b) { return c ? a : b; }
+
+int main() {
+    int s = 0;
+    for (int i = 0; i < 10; i = i + 1) {
+        if (i % 2) s = s + add(i, 1);
+        else s = s - 1;
+        if (s > 100) break;
+    }
+    int n = 0;
+    while (n < 3) n = n + 1;
+    print_int(s);
+    print_int(pick(s > 20, n, -n));
+    return pick(0, 1, 2) + n;
+}
+";
+
+// The instructions of the assembly, without directives
+fn instructions(assembly: &str) -> Vec<&str> {
+    return assembly
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('.') || l.ends_with(':'))
+        .collect();
+}
+
+#[test]
+fn behaviour_kept() {
+    let mut results: Vec<(String, i32)> = Vec::new();
+    for level in ["-O0", "-O1", "-O2"] {
+        let name = format!("behaviour{}", level.replace('-', "_"));
+        results.push(common::run_with_options(
+            "peephole",
+            &name,
+            HELPERS,
+            SOURCE,
+            &[level],
+        ));
+    }
+    assert_eq!(results[0], (String::from("25\n3\n"), 5));
+    assert_eq!(results[1], results[0], "-O1 differs from -O0");
+    assert_eq!(results[2], results[0], "-O2 differs from -O0");
+}
+
+#[test]
+fn tidied() {
+    let unoptimised = common::assembly("peephole", "tidied_O0", SOURCE, &[]);
+    let assembly = common::assembly("peephole", "tidied", SOURCE, &["-O1"]);
+    let lines = instructions(&assembly);
+    assert!(lines.len() < instructions(&unoptimised).len(), "{}", assembly);
+    for pair in lines.windows(2) {
+        let (first, second) = (pair[0], pair[1]);
+        assert!(
+            !(first.starts_with("pushq") && second.starts_with("popq")),
+            "{}",
+            assembly
+        );
+        match first.strip_prefix("jmp") {
+            Some(target) => {
+                assert_ne!(
+                    format!("{}:", target.trim()),
+                    second,
+                    "{}",
+                    assembly
+                )
+            }
+            None => (),
+        }
+    }
+    assert!(!assembly.contains("$0, %rsp"), "{}", assembly);
+}
+
+// A movl back into the register a value was truncated from clears its upper
+// half, so it isn't removed as the reverse of the first move
+#[test]
+fn zero_extension_kept() {
+    let helpers = r#"
+#include <stdio.h>
+
+int print_unsigned_long(unsigned long u) {
+    printf("%lu\n", u);
+    return 0;
+}
+"#;
+    let source = "
+int print_unsigned_long(unsigned long u);
+
+unsigned long f(long x) {
+    unsigned int t = (unsigned int)x;
+    return t;
+}
+
+int main() {
+    print_unsigned_long(f(0x500000007));
+    return 0;
+}
+";
+    for level in ["-O0", "-O1", "-O2"] {
+        let name = format!("zero_extension{}", level.replace('-', "_"));
+        let result = common::run_with_options(
+            "peephole",
+            &name,
+            helpers,
+            source,
+            &[level],
+        );
+        assert_eq!(result, (String::from("7\n"), 0), "at {}", level);
+    }
+}
@@ -0,0 +1,239 @@
+// The built-in preprocessor: macros, conditional inclusion, #include with
+// search paths and the command line options, checked through -E
+mod common;
+
+use std::fs;
+
+// Runs ccc -E with the options on source and returns what it prints
+fn preprocess(name: &str, source: &str, options: &[&str]) -> String {
+    let args = [&["-E"], options].concat();
+    let ccc = common::compile("preprocessor", name, source, &args);
+    let stderr = String::from_utf8_lossy(&ccc.stderr);
+    assert!(ccc.status.success() && stderr.is_empty(), "{}", stderr);
+    return String::from_utf8_lossy(&ccc.stdout).into_owned();
+}
+
+// The output without line markers or whitespace, as tokens keep the columns
+// they had in the source and a line is continued after a marker when a
+// macro's expansion is longer than its invocation
+fn code(output: &str) -> String {
+    return output
+        .lines()
+        .filter(|l| !l.starts_with('#'))
+        .flat_map(|l| l.split_whitespace())
+        .collect();
+}
+
+fn squeezed(lines: &[&str]) -> String {
+    return lines.iter().flat_map(|l| l.split_whitespace()).collect();
+}
+
+#[test]
+fn macros() {
+    let source = "
+#define LIMIT 10
+#define TWICE LIMIT * 2
+#define SQUARE(x) ((x) * (x))
+#define MAX(a, b) ((a) > (b) ? (a) : (b))
+#define STR(x) #x
+#define CAT(a, b) a##b
+#define LOOP LOOP + 1
+int a = TWICE;
+int b = SQUARE(LIMIT + 1);
+int c = MAX(SQUARE(2), 3);
+char *d = STR(a  +   b);
+int CAT(var, 2) = CAT(0x, 1f);
+int e = LOOP;
+int f = SQUARE;
+#undef LIMIT
+int g = LIMIT;
+int h = __LINE__;
+char *i = __FILE__;
+";
+    let output = preprocess("macros", source, &[]);
+    let expected = [
+        "int a = 10 * 2;",
+        "int b = ((10 + 1) * (10 + 1));",
+        "int c = ((((2) * (2))) > (3) ? (((2) * (2))) : (3));",
+        "char *d = \"a + b\";",
+        "int var2 = 0x1f;",
+        "int e = LOOP + 1;",
+        "int f = SQUARE;",
+        "int g = LIMIT;",
+        "int h = 18;",
+        "char *i = \"macros.c\";",
+    ];
+    assert_eq!(code(&output), squeezed(&expected), "{}", output);
+    assert!(output.contains("\"a + b\""), "{}", output);
+}
+
+#[test]
+fn conditionals() {
+    let source = "
+#define ONE 1
+#if ONE + 1 == 2 && defined(ONE) && defined ONE
+int a;
+#endif
+#if 0
+int b;
+#elif ONE
+int c;
+#else
+int d;
+#endif
+#ifdef TWO
+int e;
+#elif !defined(TWO)
+int f;
+#endif
+#ifndef ONE
+int g;
+#else
+int h;
+#endif
+#if 0
+#if 1 / 0
+#error never read
+#endif
+#endif
+#if UNDEFINED == 0 && 'a' == 97 && (3 << 2) - 1 == 11 && -5 / 2 == -2
+int i;
+#endif
+";
+    let output = preprocess("conditionals", source, &[]);
+    assert_eq!(
+        code(&output),
+        squeezed(&["int a;", "int c;", "int f;", "int h;", "int i;"]),
+        "{}",
+        output
+    );
+}
+
+// The operands which && || and ?: don't evaluate can't fail, and unsigned
+// operands give uintmax_t arithmetic
+#[test]
+fn evaluation() {
+    let source = "
+#if 1 || 1 / 0
+int a;
+#endif
+#if 0 && 1 % 0
+#else
+int b;
+#endif
+#if 1 ? 2 : 1 / 0
+int c;
+#endif
+#if (0 ? 1 / 0 : 3) == 3
+int d;
+#endif
+#if 0xffffffffffffffff > 0
+int e;
+#endif
+#if -1 < 0u
+int f;
+#endif
+#if -1 > 0u && -1 / 2u == 0x7fffffffffffffff
+int g;
+#endif
+#if 18446744073709551615 == -1 && (0 ? 1u : -1) > 0
+int h;
+#endif
+#if -1 >> 63 == -1 && -1u >> 63 == 1
+int i;
+#endif
+";
+    let output = preprocess("evaluation", source, &[]);
+    assert_eq!(
+        code(&output),
+        squeezed(&[
+            "int a;", "int b;", "int c;", "int d;", "int e;", "int g;",
+            "int h;", "int i;"
+        ]),
+        "{}",
+        output
+    );
+
+    for (name, condition) in [("divide", "1 / 0"), ("rest", "0 || 1 % 0")] {
+        let source = format!("#if {}\n#endif\n", condition);
+        let errors = common::diagnostics("preprocessor", name, &source);
+        let expected = format!(
+            "Error preprocessing: InvalidExpression(\"{}.c:1\", \"division by \
+             zero in #if\")",
+            name
+        );
+        assert!(errors.contains(&expected), "{}", errors);
+    }
+}
+
+#[test]
+fn includes() {
+    let dir = common::directory("preprocessor").join("include");
+    fs::create_dir_all(&dir).expect("Failed to create include directory");
+    let header = "
+#pragma once
+#define TRIPLE(x) (3 * (x))
+int tripled(int x);
+";
+    fs::write(dir.join("triple.h"), header).expect("Failed to write header");
+    let source = "
+#include \"triple.h\"
+#include <triple.h>
+int tripled(int x) { return TRIPLE(x); }
+";
+    let output = preprocess("includes", source, &["-I", "include"]);
+    assert_eq!(
+        code(&output),
+        squeezed(&[
+            "int tripled(int x);",
+            "int tripled(int x) { return (3 * (x)); }"
+        ]),
+        "{}",
+        output
+    );
+
+    let source = "#include \"missing.h\"\n";
+    let errors = common::diagnostics("preprocessor", "missing", source);
+    let expected = "IncludeNotFound(\"missing.c:1\", \"missing.h\")";
+    assert!(errors.contains(expected), "{}", errors);
+}
+
+#[test]
+fn command_line() {
+    let source = "
+int a = SIZE;
+#ifdef FLAG
+int b = FLAG;
+#endif
+#ifdef GONE
+int c;
+#endif
+";
+    let options = ["-DSIZE=4 * 2", "-DFLAG", "-D", "GONE", "-UGONE"];
+    let output = preprocess("command_line", source, &options);
+    let expected = squeezed(&["int a = 4 * 2;", "int b = 1;"]);
+    assert_eq!(code(&output), expected, "{}", output);
+}
+
+// Macros reach the compiled program, and #error stops compilation
+#[test]
+fn compiled() {
+    let source = "
+#define ADD(a, b) ((a) + (b))
+#define BASE 40
+int main() {
+#if ADD(1, 1) == 2
+    return ADD(BASE, 2);
+#else
+    return 0;
+#endif
+}
+";
+    let (_, code) = common::run("preprocessor", "compiled", "", source);
+    assert_eq!(code, 42);
+
+    let source = "#if 1\n#error not supported here\n#endif\n";
+    let errors = common::diagnostics("preprocessor", "error", source);
+    let expected = "UserError(\"error.c:2\", \"not supported here\")";
+    assert!(errors.contains(expected), "{}", errors);
+}
@@ -0,0 +1,155 @@
+// Declarations of a function have to agree with each other and with its
+// definition, each error noting the earlier declaration, and a function can
+// only be called after it's declared
+mod common;
+
+#[test]
+fn compatible() {
+    let source = "
+int f(const int a);
+int f(int a) { return a * 3; }
+long g(long x, ...);
+long g(long x, ...) { return x; }
+int h();
+int main() {
+    return f(2) + g(4, 1.5) + h();
+}
+int h() { return 1; }
+";
+    let (_, code) = common::run("prototypes", "compatible", "", source);
+    assert_eq!(code, 11);
+}
+
+// Parameters can be left unnamed, "(void)" is an empty list, and "()"
+// declares a function without a prototype whose calls aren't checked
+#[test]
+fn without_names_or_prototypes() {
+    let source = "
+int f(int, long *);
+int f(int a, long *p) { return a + *p; }
+int g(void);
+int g(void) { return 2; }
+int h();
+int h(int a, int b) { return a * b; }
+int k(int a, int) { return a; }
+int m();
+int m(int);
+int m();
+int main() {
+    long l = 3;
+    return f(1, &l) + g() + h(2, 5) + k(7, 0) + m(4);
+}
+int m(int a) { return a * 10; }
+";
+    let (_, code) = common::run("prototypes", "unnamed", "", source);
+    assert_eq!(code, 4 + 2 + 10 + 7 + 40);
+}
+
+#[test]
+fn mismatches() {
+    let cases = [
+        (
+            "return_type",
+            "int f(int a);\nlong f(int a) { return a; }\n",
+            "return_type.c:2:6: Error analysing program: FunctionError(\"f\", \
+             \"Conflicting return type 'long', previously declared as \
+             'int'\")\n\
+             return_type.c:1:5: note: previous declaration of 'f' is here",
+        ),
+        (
+            "parameter_count",
+            "int f(int a);\nint f(int a, int b) { return a; }\n",
+            "parameter_count.c:2:5: Error analysing program: \
+             FunctionError(\"f\", \"Declared with 2 parameters, previously \
+             declared with 1\")\n\
+             parameter_count.c:1:5: note: previous declaration of 'f' is here",
+        ),
+        (
+            "variadic",
+            "int f(int a, ...);\nint f(int a) { return a; }\n",
+            "variadic.c:2:5: Error analysing program: FunctionError(\"f\", \
+             \"Declared with fixed arguments, previously declared with \
+             variadic\")\n\
+             variadic.c:1:5: note: previous declaration of 'f' is here",
+        ),
+        (
+            "parameter_type",
+            "int f(int a, long b);\nint f(int a, int *b) { return a; }\n",
+            "parameter_type.c:2:5: Error analysing program: \
+             FunctionError(\"f\", \"Conflicting type 'int *' for parameter 2, \
+             previously declared as 'long'\")\n\
+             parameter_type.c:1:5: note: previous declaration of 'f' is here",
+        ),
+        (
+            "unprototyped_variadic",
+            "int f();\nint f(int a, ...) { return a; }\n",
+            "unprototyped_variadic.c:2:5: Error analysing program: \
+             FunctionError(\"f\", \"Variadic, which a declaration without a \
+             prototype can't match\")\n\
+             unprototyped_variadic.c:1:5: note: previous declaration of 'f' is \
+             here",
+        ),
+        (
+            "unprototyped_char",
+            "int f();\nint f(char a) { return a; }\n",
+            "unprototyped_char.c:2:5: Error analysing program: \
+             FunctionError(\"f\", \"Parameter 1 has type 'char', which a \
+             declaration without a prototype can't match\")",
+        ),
+        (
+            "empty_definition",
+            "int f(int a);\nint f() { return 0; }\n",
+            "empty_definition.c:2:5: Error analysing program: \
+             FunctionError(\"f\", \"Declared with 0 parameters, previously \
+             declared with 1\")",
+        ),
+        (
+            "redefinition",
+            "int f(int a) { return a; }\nint f(int a) { return 1; }\n",
+            "redefinition.c:2:5: Error analysing program: \
+             DuplicateDeclaration(\"f\", \"Redefinition of function\")\n\
+             redefinition.c:1:5: note: previous definition of 'f' is here",
+        ),
+    ];
+    for (name, source, expected) in cases {
+        let errors = common::diagnostics("prototypes", name, source);
+        assert!(errors.contains(expected), "{}", errors);
+    }
+}
+
+#[test]
+fn calls() {
+    let cases = [
+        (
+            "later",
+            "int main() {\n    return later(1);\n}\n\
+             int later(int x) { return x; }\n",
+            "later.c:2:12: Error analysing program: FunctionError(\"later\", \
+             \"Called before it is declared, without a prototype\")\n\
+             later.c:4:5: note: 'later' is declared here",
+        ),
+        (
+            "undeclared",
+            "int main() {\n    return nowhere(1);\n}\n",
+            "undeclared.c:2:12: Error analysing program: \
+             FunctionError(\"nowhere\", \"Undefined identifier\")",
+        ),
+        (
+            "too_few",
+            "int f(int a, int b);\nint main() {\n    return f(1);\n}\n",
+            "too_few.c:3:12: Error analysing program: FunctionError(\"f\", \
+             \"Invalid Arguments\")\n\
+             too_few.c:1:5: note: 'f' is declared here",
+        ),
+        (
+            "kept_prototype",
+            "int f(int a);\nint f();\nint main() {\n    return f(1, 2);\n}\n",
+            "kept_prototype.c:4:12: Error analysing program: \
+             FunctionError(\"f\", \"Invalid Arguments\")",
+        ),
+    ];
+    for (name, source, expected) in cases {
+        let errors = common::diagnostics("prototypes", name, source);
+        assert!(errors.contains(expected), "{}", errors);
+    }
+}
@@ -0,0 +1,115 @@
+// const and volatile: writes through const lvalues and conversions which drop
+// qualifiers are rejected, const globals are read only, and volatile
+// accesses all survive optimisation
+mod common;
+
+#[test]
+fn qualified_code() {
+    let source = "
+const int limit = 7;
+const long table = 3;
+int f(const int *p);
+
+int main() {
+    int x = 1;
+    int *const fixed = &x;
+    *fixed = 2;
+    volatile int *v = &x;
+    const volatile int *w = v;
+    const int *r = fixed;
+    r = 0;
+    return limit + table + *w + f(&x);
+}
+
+int f(const int *p) {
+    return *p * 10;
+}
+";
+    let (_, code) = common::run("qualifiers", "qualified_code", "", source);
+    assert_eq!(code, 7 + 3 + 2 + 20);
+
+    let assembly = common::assembly("qualifiers", "sections", source, &[]);
+    let rodata = "\t.section\t.rodata\n\t.align\t4\nlimit:";
+    assert!(assembly.contains(rodata), "{}", assembly);
+    let rodata = "\t.section\t.rodata\n\t.align\t8\ntable:";
+    assert!(assembly.contains(rodata), "{}", assembly);
+}
+
+#[test]
+fn errors() {
+    let cases = [
+        (
+            "const_local",
+            "int main() {\n    const int x = 1;\n    x = 2;\n    return x;\n}\n",
+            "const_local.c:3:5: Error analysing program: \
+             AssignmentError(\"=\", \"Assignment to const-qualified lvalue\")",
+        ),
+        (
+            "through_pointer",
+            "int main() {\n    const int *p = 0;\n    *p = 1;\n    return 0;\n}\n",
+            "through_pointer.c:3:5: Error analysing program: \
+             AssignmentError(\"=\", \"Assignment to const-qualified lvalue\")",
+        ),
+        (
+            "const_pointer",
+            "int main() {\n    int x = 1;\n    int *const p = &x;\n    p = 0;\n\
+             \x20   return 0;\n}\n",
+            "const_pointer.c:4:5: Error analysing program: \
+             AssignmentError(\"=\", \"Assignment to const-qualified lvalue\")",
+        ),
+        (
+            "drops_const",
+            "int main() {\n    const int x = 1;\n    const int *p = &x;\n\
+             \x20   int *q = p;\n    return 0;\n}\n",
+            "drops_const.c:4:10: Error analysing program: TypeError(\"'int \
+             const *' to 'int *'\", \"Conversion discards qualifiers from \
+             pointer target type\")",
+        ),
+        (
+            "drops_volatile",
+            "int main() {\n    volatile int x = 1;\n    int *q = &x;\n\
+             \x20   return 0;\n}\n",
+            "drops_volatile.c:3:10: Error analysing program: TypeError(\"'int \
+             volatile *' to 'int *'\", \"Conversion discards qualifiers from \
+             pointer target type\")",
+        ),
+        (
+            "conflicting",
+            "const int g = 1;\nint g;\n",
+            "conflicting.c:2:5: Error analysing program: TypeError(\"g\", \
+             \"Conflicting types\")\n\
+             conflicting.c:1:11: note: previous declaration of 'g' is here",
+        ),
+    ];
+    for (name, source, expected) in cases {
+        let errors = common::diagnostics("qualifiers", name, source);
+        assert!(errors.contains(expected), "{}", errors);
+    }
+}
+
+// Each read and write of a volatile global is kept at -O2, in order
+#[test]
+fn volatile_accesses() {
+    let source = "
+volatile int flag = 0;
+
+int poll() {
+    int a = flag;
+    int b = flag;
+    flag = 1;
+    flag = 2;
+    return a + b;
+}
+";
+    let ir = common::dump_ir("qualifiers", "polled", source, &["-O2"]);
+    let accesses: Vec<&str> = ir
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| l.contains("volatile"))
+        .collect();
+    assert_eq!(accesses.len(), 4, "{}", ir);
+    assert!(accesses[0].ends_with("load volatile i32 %0"), "{}", ir);
+    assert!(accesses[1].ends_with("load volatile i32 %0"), "{}", ir);
+    assert!(accesses[2].starts_with("store volatile i32"), "{}", ir);
+    assert!(accesses[3].starts_with("store volatile i32"), "{}", ir);
+}
@@ -0,0 +1,157 @@
+// Temporaries kept in registers: more values live at once than there are
+// registers, values live across calls, and the callee saved registers
+// being kept for gcc code calling in
+mod common;
+
+// clobber() and clobber_double() overwrite every caller saved register ccc
+// hands out, as any call may. callee_saved_kept() calls mix() from
+// assembly with known values in the callee saved registers and returns
+// their sum afterwards
+const HELPERS: &str = r#"
+#include <stdio.h>
+
+#define CLOBBER                                                            \
+    __asm__ volatile("movq $-1, %%r10\n\t"                                 \
+                     "movq $-1, %%r11\n\t"                                 \
+                     "pcmpeqd %%xmm8, %%xmm8\n\t"                          \
+                     "pcmpeqd %%xmm9, %%xmm9\n\t"                          \
+                     "pcmpeqd %%xmm10, %%xmm10\n\t"                        \
+                     "pcmpeqd %%xmm11, %%xmm11\n\t"                        \
+                     "pcmpeqd %%xmm12, %%xmm12\n\t"                        \
+                     "pcmpeqd %%xmm13, %%xmm13\n\t"                        \
+                     "pcmpeqd %%xmm14, %%xmm14\n\t"                        \
+                     "pcmpeqd %%xmm15, %%xmm15\n\t" ::                     \
+                         : "r10", "r11", "xmm8", "xmm9", "xmm10", "xmm11", \
+                           "xmm12", "xmm13", "xmm14", "xmm15")
+
+long clobber(long x) {
+    CLOBBER;
+    return x;
+}
+
+double clobber_double(double x) {
+    CLOBBER;
+    return x;
+}
+
+long print_long(long l) {
+    printf("%ld\n", l);
+    return l;
+}
+
+int print_double(double d) {
+    printf("%.1f\n", d);
+    return 0;
+}
+
+long callee_saved_kept(long x) {
+    long sum;
+    __asm__ volatile("movq $11, %%rbx\n\t"
+                     "movq $12, %%r12\n\t"
+                     "movq $13, %%r13\n\t"
+                     "movq $14, %%r14\n\t"
+                     "movq $15, %%r15\n\t"
+                     "subq $128, %%rsp\n\t"
+                     "call mix\n\t"
+                     "addq $128, %%rsp\n\t"
+                     "movq %%rbx, %%rax\n\t"
+                     "addq %%r12, %%rax\n\t"
+                     "addq %%r13, %%rax\n\t"
+                     "addq %%r14, %%rax\n\t"
+                     "addq %%r15, %%rax\n\t"
+                     : "=a"(sum), "+D"(x)
+                     :
+                     : "rbx", "rcx", "rdx", "rsi", "r8", "r9", "r10", "r11",
+                       "r12", "r13", "r14", "r15", "memory", "xmm0", "xmm1",
+                       "xmm2", "xmm3", "xmm4", "xmm5", "xmm6", "xmm7", "xmm8",
+                       "xmm9", "xmm10", "xmm11", "xmm12", "xmm13", "xmm14",
+                       "xmm15");
+    return sum;
+}
+"#;
+
+const SOURCE: &str = "
+long clobber(long x);
+double clobber_double(double x);
+long print_long(long l);
+int print_double(double d);
+long callee_saved_kept(long x);
+
+long mix(long x) {
+    long a = x + 1;
+    long b = x * 2;
+    long c = x - 3;
+    long d = x * x;
+    long e = a + b;
+    long f = c * d;
+    long g = e - f;
+    long h = a * c;
+    long i = b + d;
+    long j = g + h;
+    return a + b + c + d + e + f + g + h + i + j;
+}
+
+long walk(long n) {
+    if (n == 0) return 0;
+    long k = n * 3;
+    long rest = walk(n - 1);
+    return k + rest;
+}
+
+int main() {
+    long a = clobber(1);
+    long b = clobber(2);
+    long c = clobber(3);
+    long d = clobber(4);
+    long e = clobber(5);
+    long f = clobber(6);
+    long g = clobber(7);
+    long h = clobber(8);
+    long i = clobber(9);
+    long j = clobber(10);
+    double x = clobber_double(0.5);
+    double y = clobber_double(1.5);
+    double z = clobber_double(2.5);
+    double w = clobber_double(3.5);
+    double v = clobber_double(4.5);
+    double u = clobber_double(5.5);
+    double t = clobber_double(6.5);
+    double s = clobber_double(7.5);
+    double r = clobber_double(8.5);
+    print_long(a);
+    print_long(b);
+    print_long(c);
+    print_long(d);
+    print_long(e);
+    print_long(f);
+    print_long(g);
+    print_long(h);
+    print_long(i);
+    print_long(j);
+    print_double(x + y + z + w + v + u + t + s + r);
+    print_double(r - s + t - u + v - w + z - y + x);
+    print_long(mix(5));
+    print_long(callee_saved_kept(5));
+    return walk(10) - 100;
+}
+";
+
+#[test]
+fn across_calls() {
+    for level in ["-O0", "-O1", "-O2"] {
+        let name = format!("across_calls{}", level.replace('-', "_"));
+        let (out, code) = common::run_with_options(
+            "registers",
+            &name,
+            HELPERS,
+            SOURCE,
+            &[level],
+        );
+        assert_eq!(
+            out, "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n40.5\n4.5\n100\n65\n",
+            "at {}",
+            level
+        );
+        assert_eq!(code, 65, "at {}", level);
+    }
+}
@@ -0,0 +1,21 @@
+// RISC-V assembly assembles where a cross assembler or llvm-mc is installed,
+// and runs the same as on x86-64 where a cross compiler and qemu-user are
+// too
+mod common;
+
+use common::cross::{self, RISCV64};
+
+#[test]
+fn assembles() {
+    cross::assembles(&RISCV64, "\taddi\ts0, sp, 16");
+}
+
+#[test]
+fn runs_under_qemu() {
+    cross::runs_under_qemu(&RISCV64);
+}
+
+#[test]
+fn x86_only_options() {
+    cross::x86_only_options(&RISCV64);
+}
@@ -0,0 +1,203 @@
+// ccc run interprets programs with builtin putchar and printf, giving the
+// same output and exit status as the compiled program
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use common::broad;
+
+// The helpers of the broad program written with printf. There are no string
+// literals, so each format is the bytes of a long
+const HELPERS: &str = "\
+int printf(char *format, ...);
+
+int print_long(long i) {
+    long format = 0x0a646c25;
+    printf((char *)&format, i);
+    return 0;
+}
+
+int print_double(double d) {
+    long format = 0x0a6625;
+    printf((char *)&format, d);
+    return 0;
+}
+";
+
+// A long holding the bytes of the format, null terminated
+fn packed(format: &str) -> String {
+    assert!(format.len() < 8);
+    let mut bytes = [0u8; 8];
+    bytes[..format.len()].copy_from_slice(format.as_bytes());
+    return format!("{:#x}", u64::from_le_bytes(bytes));
+}
+
+#[test]
+fn broad_program() {
+    // The broad program declares the helpers, which are defined here instead
+    let source = broad::SOURCE
+        .replace("int print_long(long i);\n", "")
+        .replace("int print_double(double d);\n", "");
+    for level in ["-O0", "-O2"] {
+        let name = format!("broad{}", level.replace('-', "_"));
+        let (stdout, stderr, code) = common::interpret(
+            "run",
+            &name,
+            &format!("{}\n{}", HELPERS, source),
+            &[level],
+        );
+        assert!(stderr.is_empty(), "{}", stderr);
+        assert_eq!(stdout, broad::EXPECTED);
+        assert_eq!(code, 31);
+    }
+}
+
+#[test]
+fn putchar() {
+    let source = "\
+int putchar(int c);
+
+int main() {
+    int c = 104;
+    putchar(c);
+    putchar(c + 1);
+    return putchar(10) + putchar(328);
+}
+";
+    let (stdout, stderr, code) =
+        common::interpret("run", "putchar", source, &[]);
+    assert!(stderr.is_empty(), "{}", stderr);
+    assert_eq!(stdout, "hi\nH");
+    assert_eq!(code, 82);
+}
+
+// Every printf conversion gives what the C library's does
+#[test]
+fn printf_matches_c_library() {
+    let cases = [
+        ("%d|", "-42"),
+        ("%5d|", "42"),
+        ("%-5d|", "42"),
+        ("%05d|", "-42"),
+        ("%+d|", "5"),
+        ("% d|", "5"),
+        ("%.3d|", "7"),
+        ("%i|", "-2147483647 - 1"),
+        ("%u|", "-1"),
+        ("%lu|", "-1L"),
+        ("%ld|", "-9000000000L"),
+        ("%hhd|", "300"),
+        ("%hu|", "-1"),
+        ("%x|", "255"),
+        ("%#x|", "0"),
+        ("%#06x|", "255"),
+        ("%#o|", "8"),
+        ("%X|", "48879"),
+        ("%lx|", "-1L"),
+        ("%o|", "8"),
+        ("%c|", "65"),
+        ("%3c|", "66"),
+        ("%f|", "3.14159"),
+        ("%.2f|", "-2.005"),
+        ("%8.3f|", "3.14159"),
+        ("%-8.1f|", "2.25"),
+        ("%08.2f|", "-1.5"),
+        ("%.0f|", "2.5"),
+        ("%f|", "1e20"),
+        ("%%%d|", "7"),
+        ("%p|", "(char *)0"),
+        ("%s|", "(char *)&text"),
+        ("%.1s|", "(char *)&text"),
+        ("%4s|", "(char *)&text"),
+    ];
+    let mut source = String::from(
+        "int printf(char *format, ...);\n\n\
+         int main() {\n    long text = 0x6968;\n    long format;\n",
+    );
+    for (format, argument) in cases {
+        source.push_str(&format!(
+            "    format = {};\n    printf((char *)&format, {});\n",
+            packed(format),
+            argument
+        ));
+    }
+    source.push_str("    return 0;\n}\n");
+
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("run");
+    fs::create_dir_all(&dir).expect("Failed to create test directory");
+    let path = dir.join("printf_gcc.c");
+    fs::write(&path, &source).expect("Failed to write source");
+    let gcc = Command::new("gcc")
+        .args(["-w", "-o"])
+        .arg(dir.join("printf_gcc"))
+        .arg(&path)
+        .output()
+        .expect("Failed to execute gcc");
+    assert!(gcc.status.success(), "{}", String::from_utf8_lossy(&gcc.stderr));
+    let expected = Command::new(dir.join("printf_gcc"))
+        .output()
+        .expect("Failed to run the program built by gcc");
+
+    let (stdout, stderr, code) =
+        common::interpret("run", "printf", &source, &[]);
+    assert!(stderr.is_empty(), "{}", stderr);
+    assert_eq!(stdout, String::from_utf8_lossy(&expected.stdout));
+    assert_eq!(code, 0);
+}
+
+#[test]
+fn runtime_errors() {
+    let cases = [
+        (
+            "division",
+            "int main() {\n    int zero = 0;\n    return 1 / zero;\n}\n",
+            "division.c:3:5: Error running program: integer division by zero",
+        ),
+        (
+            "null",
+            "int main() {\n    int *p = 0;\n    return *p;\n}\n",
+            "Error running program: bad memory access at 0x0",
+        ),
+        (
+            "recursion",
+            "int f(int n) {\n    return f(n + 1);\n}\n\n\
+             int main() {\n    return f(0);\n}\n",
+            "Error running program: stack overflow",
+        ),
+        (
+            "undefined",
+            "int missing(int n);\n\nint main() {\n    return missing(1);\n}\n",
+            "Error running program: undefined reference to `missing`",
+        ),
+    ];
+    for (name, source, message) in cases {
+        let (stdout, stderr, code) =
+            common::interpret("run", name, source, &[]);
+        assert!(stdout.is_empty(), "{}", stdout);
+        assert!(stderr.contains(message), "{}", stderr);
+        assert_eq!(code, 1);
+    }
+
+    // printf stops at a conversion it doesn't support or has no argument for
+    let cases = [
+        ("exponent", "%e", "1.5", "unsupported printf conversion `%e`"),
+        ("star", "%*d", "4, 2", "unsupported printf conversion `%*d`"),
+        ("missing", "%d%ld", "1", "missing printf argument for `%ld`"),
+    ];
+    for (name, format, arguments, message) in cases {
+        let source = format!(
+            "int printf(char *format, ...);\n\nint main() {{\n\
+             \x20   long format = {};\n\
+             \x20   return printf((char *)&format, {});\n}}\n",
+            packed(format),
+            arguments
+        );
+        let (_, stderr, code) = common::interpret("run", name, &source, &[]);
+        let expected =
+            format!("{}.c:5:5: Error running program: {}", name, message);
+        assert!(stderr.contains(&expected), "{}", stderr);
+        assert_eq!(code, 1);
+    }
+}
@@ -0,0 +1,106 @@
+// sizeof and _Alignof worked out from the type layout without evaluating the
+// operand, and casts truncating, sign extending or zero extending integers
+// and converting between integers, floating values and pointers
+mod common;
+
+const HELPERS: &str = r#"
+#include <stdio.h>
+
+int put_long(long l) {
+    return printf("%ld\n", l);
+}
+"#;
+
+#[test]
+fn layout() {
+    let source = "
+int put_long(long l);
+
+int main() {
+    char c = 1;
+    int i = 3;
+    long l = 4;
+    double d = 5;
+    int *p = &i;
+    put_long(sizeof c + sizeof(short) * 10 + sizeof i * 100 +
+             sizeof(long) * 1000);
+    put_long(sizeof(double) + sizeof(float) * 10 + sizeof p * 100 +
+             sizeof(char *) * 1000);
+    put_long(sizeof(unsigned char) + sizeof (i + l) * 10 +
+             sizeof(c + c) * 100 + sizeof d * 1000);
+    put_long(_Alignof(char) + _Alignof(short) * 10 + _Alignof(int) * 100 +
+             _Alignof(long) * 1000 + _Alignof(double) * 10000);
+    put_long(sizeof(i = 9) + i);
+    return 0;
+}
+";
+    let (out, code) = common::run("sizes", "layout", HELPERS, source);
+    assert_eq!(out, "8421\n8848\n8481\n88421\n7\n");
+    assert_eq!(code, 0);
+}
+
+#[test]
+fn casts() {
+    let source = "
+int put_long(long l);
+
+int main() {
+    char c = 1;
+    int i = 3;
+    int *p = &i;
+    put_long((char)300);
+    put_long((unsigned char)300);
+    put_long((short)70000);
+    put_long((unsigned short)-1);
+    put_long((int)4294967297);
+    put_long((unsigned int)-1);
+    put_long((long)(int)-5);
+    put_long((long)(unsigned int)-5);
+    put_long((signed char)200);
+    put_long((int)3.99 + (long)-2.5);
+    long q = (long)p;
+    int *back = (int *)q;
+    put_long(*back);
+    return (char)(c + 255);
+}
+";
+    let expected = "44\n44\n4464\n65535\n1\n4294967295\n-5\n4294967291\n\
+                    -56\n1\n3\n";
+    for level in ["-O0", "-O1", "-O2"] {
+        let name = format!("casts{}", level.replace('-', "_"));
+        let (out, code) =
+            common::run_with_options("sizes", &name, HELPERS, source, &[level]);
+        assert_eq!(out, expected, "at {}", level);
+        assert_eq!(code, 0, "at {}", level);
+    }
+}
+
+#[test]
+fn errors() {
+    let cases = [
+        (
+            "double_to_pointer",
+            "int main() {\n    double d = 1;\n    int *p = (int *)d;\n\
+             \x20   return 0;\n}\n",
+            "double_to_pointer.c:3:10: Error analysing program: \
+             TypeError(\"'double' to 'int *'\", \"Invalid cast\")",
+        ),
+        (
+            "pointer_to_float",
+            "int main() {\n    int *p = 0;\n    float f = (float)p;\n\
+             \x20   return 0;\n}\n",
+            "pointer_to_float.c:3:11: Error analysing program: \
+             TypeError(\"'int *' to 'float'\", \"Invalid cast\")",
+        ),
+        (
+            "unevaluated",
+            "int main() {\n    return sizeof(missing + 1);\n}\n",
+            "unevaluated.c:2:19: Error analysing program: \
+             UndeclaredIdentifier(\"missing\"",
+        ),
+    ];
+    for (name, source, expected) in cases {
+        let errors = common::diagnostics("sizes", name, source);
+        assert!(errors.contains(expected), "{}", errors);
+    }
+}
@@ -0,0 +1,128 @@
+// Diagnostics and .loc directives point at the file, line and column the code
+// was written at, through #include, #line, indentation and macros
+mod common;
+
+use std::fs;
+
+#[test]
+fn header_lines() {
+    let dir = common::directory("spans").join("include");
+    fs::create_dir_all(&dir).expect("Failed to create include directory");
+    let header = "
+int helper() {
+    return missing;
+}
+";
+    fs::write(dir.join("broken.h"), header).expect("Failed to write header");
+    let source = "#include \"include/broken.h\"\nint main() { return 0; }\n";
+    let errors = common::diagnostics("spans", "header", source);
+    let expected = "include/broken.h:3:12: Error analysing program: \
+                    UndeclaredIdentifier(\"missing\"";
+    assert!(errors.contains(expected), "{}", errors);
+}
+
+#[test]
+fn line_directives() {
+    let source = "
+int main() {
+#line 40 \"other.c\"
+    return nope;
+}
+";
+    let errors = common::diagnostics("spans", "line", source);
+    let expected = "other.c:40:12: Error analysing program: \
+                    UndeclaredIdentifier(\"nope\"";
+    assert!(errors.contains(expected), "{}", errors);
+
+    // A line continued after an expansion keeps the number #line gave it
+    let source = "
+#define SQUARE(x) ((x) * (x))
+int main() {
+#line 40 \"other.c\"
+    return SQUARE(2) + nope;
+}
+";
+    let errors = common::diagnostics("spans", "continued", source);
+    let expected = "other.c:40:24: Error analysing program: \
+                    UndeclaredIdentifier(\"nope\"";
+    assert!(errors.contains(expected), "{}", errors);
+
+    let source = "#line 0\nint main() { return 0; }\n";
+    let errors = common::diagnostics("spans", "line_zero", source);
+    let expected = "InvalidDirective(\"line_zero.c:1\", \"invalid line number \
+                    0\")";
+    assert!(errors.contains(expected), "{}", errors);
+}
+
+// Columns count the indentation and spacing of the source, also after a
+// comment or a macro whose expansion is shorter or longer than its
+// invocation
+#[test]
+fn columns() {
+    let source = "
+#define ONE 1
+#define SQUARE(x) ((x) * (x))
+int main() {
+    if (1) {
+            int a = ONE;
+        int b = SQUARE(2) +  unknown;
+    }
+    return 0;
+}
+";
+    let errors = common::diagnostics("spans", "columns", source);
+    let expected = "columns.c:7:30: Error analysing program: \
+                    UndeclaredIdentifier(\"unknown\"";
+    assert!(errors.contains(expected), "{}", errors);
+
+    let source = "
+int main() {
+    int a = /* one */ 1 + /* two
+    lines */ other;
+    return a;
+}
+";
+    let errors = common::diagnostics("spans", "comments", source);
+    let expected = "comments.c:4:14: Error analysing program: \
+                    UndeclaredIdentifier(\"other\"";
+    assert!(errors.contains(expected), "{}", errors);
+
+    let source = "int main() {\n\tint a = 1;\n\tbreak;\n}\n";
+    let errors = common::diagnostics("spans", "tabs", source);
+    assert!(errors.contains("tabs.c:3:2: "), "{}", errors);
+}
+
+#[test]
+fn locations() {
+    let dir = common::directory("spans").join("include");
+    fs::create_dir_all(&dir).expect("Failed to create include directory");
+    let header = "int helper() {\n    return 1;\n}\n";
+    fs::write(dir.join("helper.h"), header).expect("Failed to write header");
+    let source = "
+#include \"include/helper.h\"
+int main() {
+    int x = helper();
+    return x;
+}
+";
+    let assembly = common::assembly("spans", "locations", source, &[]);
+    let files = "\t.file\t1 \"include/helper.h\"\n\t.file\t2 \"locations.c\"\n";
+    assert!(assembly.starts_with(files), "{}", assembly);
+    for directive in ["\t.loc\t1 2 5", "\t.loc\t2 4 9", "\t.loc\t2 5 5"] {
+        assert!(assembly.contains(directive), "{}", assembly);
+    }
+    let locations: Vec<&str> =
+        assembly.lines().filter(|l| l.starts_with("\t.loc")).collect();
+    for pair in locations.windows(2) {
+        assert_ne!(pair[0], pair[1], "{}", assembly);
+    }
+}
+
+// Lexer errors are reported at the character the lexer stopped at
+#[test]
+fn lexer_errors() {
+    let source = "int main() {\n    return 1 | 2;\n}\n";
+    let errors = common::diagnostics("spans", "lexer", source);
+    let expected = "lexer.c:2:14: Could not tokenize: NotImplemented('|')";
+    assert!(errors.contains(expected), "{}", errors);
+}
@@ -0,0 +1,128 @@
+// Programs linked by ccc itself with its own runtime, without gcc's linker
+// or the C library
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+// Code, read only and writable data, a pointer to data, .bss and floating
+// point, and a function from another object called with doubles live
+const SOURCE: &str = "
+long square(long x);
+static long counter = 3;
+long *counted = &counter;
+const int limit = 7;
+long scratch;
+
+double half(double d) {
+    return d / 2;
+}
+
+double quarter(double d) {
+    double h = half(d);
+    square(1);
+    return half(h);
+}
+
+int main(int argc) {
+    scratch = 10;
+    for (int i = 0; i < limit; i = i + 1) {
+        counter = counter + square(i);
+    }
+    double h = half(9.0);
+    double q = quarter(3.0);
+    return *counted + scratch + argc + h + q;
+}
+";
+
+const LIBRARY: &str = "
+long square(long x) {
+    return x * x;
+}
+";
+
+fn directory() -> PathBuf {
+    return common::directory("standalone");
+}
+
+// Runs ccc --standalone on source with the options and objects
+fn link(
+    name: &str,
+    source: &str,
+    options: &[&str],
+    objects: &[PathBuf],
+) -> Output {
+    let objects: Vec<String> =
+        objects.iter().map(|o| o.to_string_lossy().into_owned()).collect();
+    let mut args = vec!["--standalone"];
+    args.extend(options);
+    args.extend(objects.iter().map(|o| o.as_str()));
+    return common::compile("standalone", name, source, &args);
+}
+
+// Links and runs the program, returning its exit code
+fn run(name: &str, source: &str, options: &[&str], objects: &[PathBuf]) -> i32 {
+    let ccc = link(name, source, options, objects);
+    let stderr = String::from_utf8_lossy(&ccc.stderr);
+    assert!(ccc.status.success() && stderr.is_empty(), "{}", stderr);
+    let output = Command::new(directory().join(name))
+        .output()
+        .expect("Failed to run linked program");
+    match output.status.code() {
+        Some(c) => return c,
+        None => panic!("{} was killed: {:?}", name, output.status),
+    }
+}
+
+#[test]
+fn with_ccc_objects() {
+    for level in ["-O0", "-O1", "-O2"] {
+        let library = common::object(
+            "standalone",
+            &format!("library{}", level),
+            LIBRARY,
+            &[level],
+        );
+        let name = format!("program{}", level);
+        assert_eq!(
+            run(&name, SOURCE, &[level], &[library]),
+            110,
+            "at {}",
+            level
+        );
+    }
+}
+
+// Objects from gcc link too, leaving out their unwinding tables
+#[test]
+fn with_gcc_objects() {
+    let library_c = directory().join("gcc_library.c");
+    let library_o = directory().join("gcc_library.o");
+    fs::write(&library_c, LIBRARY).expect("Failed to write source");
+    let gcc = Command::new("gcc")
+        .args(["-O2", "-c"])
+        .arg(&library_c)
+        .arg("-o")
+        .arg(&library_o)
+        .output()
+        .expect("Failed to execute gcc");
+    assert!(gcc.status.success(), "{}", String::from_utf8_lossy(&gcc.stderr));
+    assert_eq!(run("gcc_program", SOURCE, &[], &[library_o]), 110);
+}
+
+#[test]
+fn undefined_symbol() {
+    let source = "
+int putchar(int c);
+
+int main() {
+    putchar(65);
+    return 0;
+}
+";
+    let ccc = link("undefined", source, &[], &[]);
+    let stderr = String::from_utf8_lossy(&ccc.stderr);
+    assert!(stderr.contains("undefined reference to `putchar`"), "{}", stderr);
+    assert!(!directory().join("undefined").exists());
+}
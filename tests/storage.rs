@@ -0,0 +1,161 @@
+// Storage classes: static functions and globals stay local to their file,
+// static locals keep their value between calls, extern refers to a definition
+// elsewhere, and declarations have to agree on linkage
+mod common;
+
+// The helpers have their own hidden and twice, which only link next to
+// ours because both have internal linkage
+const HELPERS: &str = r#"
+#include <stdio.h>
+
+long shared = 40;
+static int hidden = 100;
+
+static int twice(int x) {
+    return x * 200;
+}
+
+int put_long(long l) {
+    return printf("%ld\n", l);
+}
+
+int helper_hidden(void) {
+    return twice(hidden);
+}
+"#;
+
+#[test]
+fn linkage() {
+    let source = "
+int put_long(long l);
+int helper_hidden();
+extern long shared;
+static int hidden = 5;
+static int twice(int x);
+
+int counter() {
+    static int count;
+    static long step = 2;
+    count = count + step;
+    return count;
+}
+
+static int twice(int x) {
+    return x * 2;
+}
+
+int bump() {
+    extern long shared;
+    shared = shared + 1;
+    return shared;
+}
+
+int main() {
+    auto int a = 1;
+    register int b = 2;
+    counter();
+    counter();
+    put_long(counter());
+    put_long(bump());
+    put_long(shared);
+    put_long(twice(hidden) + a + b);
+    put_long(helper_hidden());
+    return 0;
+}
+";
+    for level in ["-O0", "-O2"] {
+        let name = format!("linkage{}", level.replace('-', "_"));
+        let (out, code) = common::run_with_options(
+            "storage",
+            &name,
+            HELPERS,
+            source,
+            &[level],
+        );
+        assert_eq!(out, "6\n41\n41\n13\n20000\n", "at {}", level);
+        assert_eq!(code, 0, "at {}", level);
+    }
+
+    let assembly = common::assembly("storage", "symbols", source, &[]);
+    for directive in ["\t.globl\tcounter", "\t.globl\tbump", "\t.globl\tmain"] {
+        assert!(assembly.contains(directive), "{}", assembly);
+    }
+    for local in ["twice", "hidden", "count", "step", "shared"] {
+        let directive = format!("\t.globl\t{}\n", local);
+        assert!(!assembly.contains(&directive), "{}", assembly);
+    }
+}
+
+#[test]
+fn errors() {
+    let cases = [
+        (
+            "static_after",
+            "int f();\nstatic int f() { return 0; }\n",
+            "static_after.c:2:12: Error analysing program: LinkageError(\"f\", \
+             \"static declaration follows non-static declaration\")\n\
+             static_after.c:1:5: note: previous declaration of 'f' is here",
+        ),
+        (
+            "non_static_after",
+            "static int g;\nint g;\n",
+            "non_static_after.c:2:5: Error analysing program: \
+             LinkageError(\"g\", \"non-static declaration follows static \
+             declaration\")\n\
+             non_static_after.c:1:12: note: previous declaration of 'g' is here",
+        ),
+        (
+            "file_scope_auto",
+            "auto int g;\n",
+            "file_scope_auto.c:1:10: Error analysing program: \
+             StorageClassError(\"g\", \"auto and register are not allowed at \
+             file scope\")",
+        ),
+        (
+            "for_static",
+            "int main() {\n    for (static int i = 0; i < 1; i = i + 1) {}\n\
+             \x20   return 0;\n}\n",
+            "for_static.c:2:21: Error analysing program: \
+             StorageClassError(\"i\", \"Only auto and register are allowed in \
+             a for loop declaration\")",
+        ),
+        (
+            "register_address",
+            "int main() {\n    register int r = 1;\n    int *p = &r;\n\
+             \x20   return *p;\n}\n",
+            "register_address.c:3:10: Error analysing program: \
+             StorageClassError(\"r\", \"Address of register variable \
+             requested\")",
+        ),
+        (
+            "register_parameter",
+            "int f(register int a) {\n    return *&(a);\n}\n",
+            "register_parameter.c:2:5: Error analysing program: \
+             StorageClassError(\"a\", \"Address of register variable \
+             requested\")",
+        ),
+    ];
+    for (name, source, expected) in cases {
+        let errors = common::diagnostics("storage", name, source);
+        assert!(errors.contains(expected), "{}", errors);
+    }
+
+    // extern after static keeps the internal linkage
+    let source = "static int g = 3;\nextern int g;\nint main() { return g; }\n";
+    let (_, code) = common::run("storage", "extern_after", "", source);
+    assert_eq!(code, 3);
+
+    // A variable which isn't register can shadow one which is
+    let source = "
+int main() {
+    register int r = 4;
+    {
+        int r = 2;
+        int *p = &r;
+        return *p + r;
+    }
+}
+";
+    let (_, code) = common::run("storage", "shadowed_register", "", source);
+    assert_eq!(code, 4);
+}
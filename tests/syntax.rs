@@ -0,0 +1,79 @@
+// Programs assembled from Intel syntax behave as they do from AT&T, using
+// every kind of instruction the generator makes
+mod common;
+
+use common::broad;
+
+#[test]
+fn same_behaviour() {
+    for syntax in ["-masm=att", "-masm=intel"] {
+        for level in ["-O0", "-O1", "-O2"] {
+            let name = format!(
+                "{}{}",
+                syntax.trim_start_matches("-masm="),
+                level.replace('-', "_")
+            );
+            let result = common::run_with_options(
+                "syntax",
+                &name,
+                broad::HELPERS,
+                broad::SOURCE,
+                &[syntax, level],
+            );
+            assert_eq!(result, (String::from(broad::EXPECTED), 31), "{}", name);
+        }
+    }
+}
+
+#[test]
+fn intel_output() {
+    let assembly = common::assembly(
+        "syntax",
+        "intel_output",
+        broad::SOURCE,
+        &["-masm=intel"],
+    );
+    assert!(assembly.starts_with("\t.intel_syntax noprefix\n"), "{}", assembly);
+    assert!(!assembly.contains('%'), "{}", assembly);
+    assert!(!assembly.contains('$'), "{}", assembly);
+    assert!(assembly.contains("\tmovsxd\t"), "{}", assembly);
+    assert!(assembly.contains("DWORD PTR [rbp-"), "{}", assembly);
+}
+
+// Doubles kept in SSE registers across calls are saved and restored around
+// them, which GNU as only accepts with SSE moves in Intel syntax
+#[test]
+fn sse_saves() {
+    let source = "
+int print_double(double d);
+
+double half(double d) {
+    return d / 2;
+}
+
+double quarter(double d) {
+    double h = half(d);
+    print_double(h);
+    return half(h);
+}
+
+int main() {
+    double h = half(9.0);
+    double q = quarter(3.0);
+    print_double(h + q);
+    return h * q;
+}
+";
+    for syntax in ["-masm=att", "-masm=intel"] {
+        let name = format!("sse_saves_{}", syntax.trim_start_matches("-masm="));
+        let result = common::run_with_options(
+            "syntax",
+            &name,
+            broad::HELPERS,
+            source,
+            &[syntax, "-O1"],
+        );
+        let expected = String::from("1.500000\n5.250000\n");
+        assert_eq!(result, (expected, 3), "{}", name);
+    }
+}
@@ -0,0 +1,138 @@
+// Variadic functions: calls set %al to the number of SSE registers used,
+// definitions read integer and floating arguments with va_arg past the
+// registers onto the stack, and the va_list builtins are checked
+mod common;
+
+const HELPERS: &str = r#"
+#include <stdio.h>
+#include <stdarg.h>
+
+int show(int count, ...) {
+    va_list args;
+    va_start(args, count);
+    for (int i = 0; i < count; i++) {
+        printf("%.2f\n", va_arg(args, double));
+    }
+    va_end(args);
+    return count;
+}
+
+long add_longs(int count, ...) {
+    va_list args;
+    va_start(args, count);
+    long sum = 0;
+    for (int i = 0; i < count; i++) {
+        sum += va_arg(args, long);
+    }
+    va_end(args);
+    return sum;
+}
+"#;
+
+const SOURCE: &str = "
+#include <stdarg.h>
+int show(int count, ...);
+long add_longs(int count, ...);
+
+double mean(int count, ...) {
+    va_list args;
+    va_start(args, count);
+    double sum = 0;
+    for (int i = 0; i < count; i = i + 1) {
+        if (i % 2) {
+            sum = sum + va_arg(args, double);
+        } else {
+            sum = sum + va_arg(args, long);
+        }
+    }
+    va_end(args);
+    return sum / count;
+}
+
+int main() {
+    show(3, 1.5, 2.25, mean(4, 1l, 2.0, 3l, 4.0));
+    show(10, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0);
+    return add_longs(8, 1l, 2l, 3l, 4l, 5l, 6l, 7l, 8l) +
+           mean(10, 1l, 2.0, 3l, 4.0, 5l, 6.0, 7l, 8.0, 9l, 10.0);
+}
+";
+
+#[test]
+fn calls_and_definitions() {
+    let expected = "1.50\n2.25\n2.50\n1.00\n2.00\n3.00\n4.00\n5.00\n6.00\n\
+                    7.00\n8.00\n9.00\n10.00\n";
+    for level in ["-O0", "-O1", "-O2"] {
+        let name = format!("mixed{}", level.replace('-', "_"));
+        let (out, code) = common::run_with_options(
+            "variadic",
+            &name,
+            HELPERS,
+            SOURCE,
+            &[level],
+        );
+        assert_eq!(out, expected, "at {}", level);
+        assert_eq!(code, 36 + 5, "at {}", level);
+    }
+}
+
+// %al holds the number of SSE registers used, at most 8
+#[test]
+fn vector_count() {
+    let assembly = common::assembly("variadic", "count", SOURCE, &[]);
+    for (count, callee) in [(3, "show"), (8, "show"), (0, "add_longs")] {
+        let call = format!("\tmovl\t${}, %eax\n\tcall\t{}\n", count, callee);
+        assert!(assembly.contains(&call), "{}", assembly);
+    }
+}
+
+#[test]
+fn errors() {
+    let cases = [
+        (
+            "fixed",
+            "#include <stdarg.h>\nint f(int a) {\n    va_list l;\n\
+             \x20   va_start(l, a);\n    return 0;\n}\n",
+            "fixed.c:4:5: Error analysing program: FunctionError(\"va_start\", \
+             \"va_start used in a function with fixed arguments\")",
+        ),
+        (
+            "not_last",
+            "#include <stdarg.h>\nint f(int a, int b, ...) {\n    va_list l;\n\
+             \x20   va_start(l, a);\n    return 0;\n}\n",
+            "not_last.c:4:5: Error analysing program: \
+             FunctionError(\"va_start\", \"Second argument of va_start is not \
+             the last named parameter\")",
+        ),
+        (
+            "too_few",
+            "int f(int a, int b, ...);\nint main() {\n    return f(1);\n}\n",
+            "too_few.c:3:12: Error analysing program: FunctionError(\"f\", \
+             \"Too few arguments for the fixed parameters\")\n\
+             too_few.c:1:5: note: 'f' is declared here",
+        ),
+        (
+            "not_a_list",
+            "#include <stdarg.h>\nint f(int a, ...) {\n    int l = 0;\n\
+             \x20   return va_arg(l, int);\n}\n",
+            "not_a_list.c:4:5: Error analysing program: TypeError(\"'int'\", \
+             \"Argument is not a va_list\")",
+        ),
+        (
+            "converted",
+            "#include <stdarg.h>\nint f(int a, ...) {\n    va_list l;\n\
+             \x20   int x = l;\n    return 0;\n}\n",
+            "converted.c:4:9: Error analysing program: TypeError(\"'va_list' \
+             to 'int'\", \"Incompatible conversion of a va_list\")",
+        ),
+        (
+            "no_fixed",
+            "int f(...);\n",
+            "no_fixed.c:1:7: Error parsing program: UnexpectedToken(Ellipsis, \
+             ParseFunction)",
+        ),
+    ];
+    for (name, source, expected) in cases {
+        let errors = common::diagnostics("variadic", name, source);
+        assert!(errors.contains(expected), "{}", errors);
+    }
+}
@@ -0,0 +1,125 @@
+// WebAssembly text is generated for the broad program, and runs the same as
+// on x86-64 where wat2wasm and node are installed
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use common::broad;
+use common::cross::{available, skip};
+
+const TARGET: &str = "--target=wasm32";
+
+// Runs main with the helpers of the broad program as imports, writing what
+// they print and then main's result
+const RUNNER: &str = r#"
+const fs = require('fs');
+let out = '';
+const env = {
+  print_long: (x) => { out += x.toString() + '\n'; return 0; },
+  print_double: (x) => { out += x.toFixed(6) + '\n'; return 0; },
+};
+const compiled = new WebAssembly.Module(fs.readFileSync(process.argv[2]));
+const instance = new WebAssembly.Instance(compiled, { env });
+const result = instance.exports.main();
+process.stdout.write(out + result + '\n');
+"#;
+
+fn compile(dir: &PathBuf, name: &str, level: &str) -> String {
+    fs::create_dir_all(dir).expect("Failed to create test directory");
+    let path = dir.join(format!("{}.c", name));
+    fs::write(&path, broad::SOURCE).expect("Failed to write source");
+    let ccc = Command::new(env!("CARGO_BIN_EXE_ccc"))
+        .args([TARGET, level])
+        .arg(&path)
+        .current_dir(dir)
+        .output()
+        .expect("Failed to execute ccc");
+    let stderr = String::from_utf8_lossy(&ccc.stderr);
+    assert!(ccc.status.success() && stderr.is_empty(), "{}", stderr);
+    return fs::read_to_string(dir.join(format!("{}.wat", name)))
+        .expect("Failed to read the module");
+}
+
+#[test]
+fn generates_module() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("wasm32");
+    for level in ["-O0", "-O2"] {
+        let name = format!("broad{}", level.replace('-', "_"));
+        let module = compile(&dir, &name, level);
+        assert!(module.starts_with("(module\n"), "{}", module);
+        let import = "(import \"env\" \"print_long\" \
+                      (func $print_long (param i64) (result i32)))";
+        assert!(module.contains(import), "{}", module);
+        assert!(
+            module.contains("(func $main (export \"main\") (result i32)"),
+            "{}",
+            module
+        );
+        // Loops stay loops rather than being dispatched through a table
+        assert!(module.lines().any(|l| l.trim() == "loop"), "{}", module);
+        assert!(!module.contains("br_table"), "{}", module);
+        assert!(!dir.join(&name).exists());
+    }
+}
+
+#[test]
+fn runs_under_node() {
+    if !available("wat2wasm") || !available("node") {
+        skip("No wat2wasm or node");
+        return;
+    }
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("wasm32");
+    fs::create_dir_all(&dir).expect("Failed to create test directory");
+    let runner = dir.join("runner.js");
+    fs::write(&runner, RUNNER).expect("Failed to write runner");
+    for level in ["-O0", "-O2"] {
+        let name = format!("run{}", level.replace('-', "_"));
+        compile(&dir, &name, level);
+        let binary = dir.join(format!("{}.wasm", name));
+        let wat2wasm = Command::new("wat2wasm")
+            .arg(dir.join(format!("{}.wat", name)))
+            .arg("-o")
+            .arg(&binary)
+            .output()
+            .expect("Failed to execute wat2wasm");
+        assert!(
+            wat2wasm.status.success(),
+            "{}",
+            String::from_utf8_lossy(&wat2wasm.stderr)
+        );
+
+        let output = Command::new("node")
+            .arg(&runner)
+            .arg(&binary)
+            .output()
+            .expect("Failed to run node");
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            format!("{}31\n", broad::EXPECTED)
+        );
+    }
+}
+
+#[test]
+fn rejects_objects() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("wasm32");
+    fs::create_dir_all(&dir).expect("Failed to create test directory");
+    let path = dir.join("objects.c");
+    fs::write(&path, "int main() { return 0; }\n")
+        .expect("Failed to write source");
+    let ccc = Command::new(env!("CARGO_BIN_EXE_ccc"))
+        .arg(TARGET)
+        .arg(&path)
+        .arg("helpers.o")
+        .current_dir(&dir)
+        .output()
+        .expect("Failed to execute ccc");
+    let stderr = String::from_utf8_lossy(&ccc.stderr);
+    assert!(
+        stderr.contains("can't be linked into a WebAssembly module"),
+        "{}",
+        stderr
+    );
+}